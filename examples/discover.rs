@@ -106,6 +106,7 @@ async fn main() -> Result<(), PolarisError> {
         ttl: 5,
         // 这里开启心跳的自动上报能力
         auto_heartbeat: true,
+        health_check: None,
     };
     let _ret = provider.register(req).await;
     match _ret {
@@ -162,8 +163,10 @@ async fn main() -> Result<(), PolarisError> {
         criteria: Criteria{
             policy: "random".to_string(),
             hash_key: "".to_string(),
+            ..Default::default()
         },
         route_info: route_info,
+        chain_override: None,
     }).await;
 
     match ret {
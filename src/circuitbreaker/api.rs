@@ -16,12 +16,14 @@
 use std::{sync::Arc, time::Duration};
 
 use crate::core::{
-    flow::CircuitBreakerFlow,
+    flow::{CircuitBreakerFlow, RouterFlow},
     model::{
         circuitbreaker::{CallAbortedError, CheckResult, MethodResource, Resource, ResourceStat, RetStatus, ServiceResource},
         error::PolarisError,
+        naming::Instance,
     },
 };
+use crate::discovery::req::ServiceCallResult;
 
 use super::req::{RequestContext, ResponseContext};
 
@@ -40,6 +42,9 @@ where
         &self,
         req: RequestContext,
     ) -> Result<Arc<InvokeHandler>, PolarisError>;
+
+    /// shutdown 关闭 CircuitBreakerAPI 实例，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
 
 /// InvokeHandler .
@@ -48,15 +53,17 @@ pub struct InvokeHandler {
     req_ctx: RequestContext,
     // flow: 熔断器流程
     flow: Arc<CircuitBreakerFlow>,
+    // router_flow: 路由流程，用于将实例调用结果上报给负载均衡器
+    router_flow: Arc<RouterFlow>,
 }
 
 impl InvokeHandler {
-    pub fn new(req_ctx: RequestContext, flow: Arc<CircuitBreakerFlow>) -> Self {
-        InvokeHandler { req_ctx, flow }
+    pub fn new(req_ctx: RequestContext, flow: Arc<CircuitBreakerFlow>, router_flow: Arc<RouterFlow>) -> Self {
+        InvokeHandler { req_ctx, flow, router_flow }
     }
 
     /// acquire_permission 检查当前请求是否可放通
-    async fn acquire_permission(&self) -> Result<(), CallAbortedError> {
+    pub async fn acquire_permission(&self) -> Result<(), CallAbortedError> {
         let svc_res = ServiceResource::new_waith_caller(
             self.req_ctx.caller_service.clone(), 
         self.req_ctx.callee_service.clone());
@@ -77,7 +84,8 @@ impl InvokeHandler {
         }
     }
 
-    async fn on_success(&self, rsp: ResponseContext) -> Result<(), PolarisError> {
+    /// on_success 上报调用成功
+    pub async fn on_success(&self, rsp: ResponseContext) -> Result<(), PolarisError> {
         let cost = rsp.duration.clone();
         let mut code = -1 as i32;
         let mut status = RetStatus::RetSuccess;
@@ -91,21 +99,36 @@ impl InvokeHandler {
                 status = RetStatus::RetReject;
             }
         }
-        self.common_report(cost, code, status).await
+        self.common_report(cost, code, status, rsp.instance).await
     }
 
-    async fn on_error(&self, rsp: ResponseContext) -> Result<(), PolarisError> {
+    /// on_error 上报调用失败
+    pub async fn on_error(&self, rsp: ResponseContext) -> Result<(), PolarisError> {
         let cost = rsp.duration.clone();
         let mut code = 0 as i32;
         let mut status = RetStatus::RetUnknown;
 
-        if let Some(r) = &self.req_ctx.result_to_code {
-            code = r.on_success(rsp.result.unwrap());
+        if let (Some(r), Some(err)) = (&self.req_ctx.result_to_code, rsp.error) {
+            code = r.on_error(err);
         }
-        self.common_report(cost, code, status).await
+        self.common_report(cost, code, status, rsp.instance).await
     }
 
-    async fn common_report(&self, cost: Duration, code: i32, status: RetStatus) -> Result<(), PolarisError> {
+    async fn common_report(&self, cost: Duration, code: i32, status: RetStatus, instance: Option<Instance>) -> Result<(), PolarisError> {
+        if let Some(instance) = instance {
+            let result = ServiceCallResult {
+                flow_id: String::new(),
+                namespace: self.req_ctx.callee_service.namespace.clone(),
+                service: self.req_ctx.callee_service.name.clone(),
+                instance,
+                method: self.req_ctx.method.clone(),
+                ret_status: status.clone(),
+                ret_code: code,
+                delay: cost,
+            };
+            self.router_flow.report_call_result(&result).await;
+        }
+
         let stat = ResourceStat {
             resource: Resource::ServiceResource(ServiceResource::new_waith_caller(
                 self.req_ctx.caller_service.clone(), 
@@ -14,10 +14,11 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::{
     context::SDKContext,
-    flow::CircuitBreakerFlow,
+    flow::{CircuitBreakerFlow, RouterFlow},
     model::{
         circuitbreaker::{CheckResult, Resource, ResourceStat},
         error::PolarisError,
@@ -36,6 +37,8 @@ pub struct DefaultCircuitBreakerAPI {
     manage_sdk: bool,
     // flow: 熔断器流程
     flow: Arc<CircuitBreakerFlow>,
+    // router_flow: 路由流程，InvokeHandler 通过其上报实例调用结果
+    router_flow: Arc<RouterFlow>,
 }
 
 impl DefaultCircuitBreakerAPI {
@@ -45,7 +48,8 @@ impl DefaultCircuitBreakerAPI {
         Self {
             context: ctx,
             manage_sdk: true,
-            flow: Arc::new(CircuitBreakerFlow::new(extensions)),
+            flow: Arc::new(CircuitBreakerFlow::new(extensions.clone())),
+            router_flow: Arc::new(RouterFlow::new(extensions)),
         }
     }
 
@@ -54,7 +58,8 @@ impl DefaultCircuitBreakerAPI {
         Self {
             context,
             manage_sdk: false,
-            flow: Arc::new(CircuitBreakerFlow::new(extensions)),
+            flow: Arc::new(CircuitBreakerFlow::new(extensions.clone())),
+            router_flow: Arc::new(RouterFlow::new(extensions)),
         }
     }
 }
@@ -93,6 +98,17 @@ impl CircuitBreakerAPI for DefaultCircuitBreakerAPI {
         req: RequestContext,
     ) -> Result<Arc<InvokeHandler>, PolarisError> {
         // Implement the method logic here
-        Ok(Arc::new(InvokeHandler::new(req, self.flow.clone())))
+        Ok(Arc::new(InvokeHandler::new(
+            req,
+            self.flow.clone(),
+            self.router_flow.clone(),
+        )))
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context.shutdown(timeout).await
     }
 }
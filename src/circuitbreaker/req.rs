@@ -15,7 +15,7 @@
 
 use std::{any::Any, time::Duration};
 
-use crate::core::model::naming::ServiceKey;
+use crate::core::model::naming::{Instance, ServiceKey};
 
 pub trait ResultToErrorCode
 where
@@ -36,6 +36,8 @@ pub struct RequestContext {
 
 pub struct ResponseContext {
     pub duration: Duration,
+    // instance 本次调用的被调实例，设置后调用结果会同时上报给负载均衡器
+    pub instance: Option<Instance>,
     pub result: Option<Box<dyn Any>>,
     pub error: Option<Box<dyn Any>>,
 }
@@ -14,20 +14,27 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
 
 use crate::{config::default::{DefaultConfigFileAPI, DefaultConfigGroupAPI}, core::{
     context::SDKContext,
     model::{
-        config::{ConfigFile, ConfigGroup},
+        config::{ConfigFile, ConfigFileChangeEvent, ConfigGroup, ConfigGroupChangeEvent},
         error::PolarisError,
+        watch::WatchStream,
     },
 }, info};
 
 use super::req::{
     CreateConfigFileRequest, GetConfigFileRequest, GetConfigGroupRequest, PublishConfigFileRequest,
     UpdateConfigFileRequest, UpsertAndPublishConfigFileRequest, WatchConfigFileRequest,
-    WatchConfigFileResponse, WatchConfigGroupRequest, WatchConfigGroupResponse,
+    WatchConfigFileResponse, WatchConfigFileStreamRequest, WatchConfigGroupRequest,
+    WatchConfigGroupResponse, WatchConfigGroupStreamRequest, WatchConfigKeysRequest,
 };
+use super::typed::TypedConfig;
+use super::view::ConfigView;
 
 /// new_config_file_api
 pub fn new_config_file_api() -> Result<impl ConfigFileAPI, PolarisError> {
@@ -79,6 +86,34 @@ where
         &self,
         req: WatchConfigFileRequest,
     ) -> Result<WatchConfigFileResponse, PolarisError>;
+
+    /// watch_config_file_stream 以事件流的方式监听配置文件变更，首个事件为当前的配置文件，drop 事件流时自动取消监听
+    async fn watch_config_file_stream(
+        &self,
+        req: WatchConfigFileStreamRequest,
+    ) -> Result<WatchStream<ConfigFileChangeEvent>, PolarisError>;
+
+    /// get_config_view 获取配置文件并按照文件格式解析为 ConfigView
+    async fn get_config_view(&self, req: GetConfigFileRequest) -> Result<ConfigView, PolarisError>;
+
+    /// watch_config_keys 监听配置文件中配置项的变更，配置文件重新发布后回调发生变化的配置项及其新旧值
+    async fn watch_config_keys(
+        &self,
+        req: WatchConfigKeysRequest,
+    ) -> Result<WatchConfigFileResponse, PolarisError>;
+
+    /// watch_typed 监听配置文件并按照文件格式解析为 T，返回的句柄始终持有最近一次解析成功的配置，
+    /// 解析失败时保留上一次的配置并通过句柄上报错误，drop 所有句柄后自动取消监听
+    async fn watch_typed<T>(
+        &self,
+        req: GetConfigFileRequest,
+    ) -> Result<TypedConfig<T>, PolarisError>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+        Self: Sized;
+
+    /// shutdown 关闭 ConfigFileAPI 实例，取消所有监听，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
 
 /// new_config_group_api
@@ -116,4 +151,23 @@ where
         &self,
         req: WatchConfigGroupRequest,
     ) -> Result<WatchConfigGroupResponse, PolarisError>;
+
+    /// watch_publish_config_files_stream 以事件流的方式监听发布的配置文件变更，首个事件为当前的配置文件列表，drop 事件流时自动取消监听
+    async fn watch_publish_config_files_stream(
+        &self,
+        req: WatchConfigGroupStreamRequest,
+    ) -> Result<WatchStream<ConfigGroupChangeEvent>, PolarisError>;
+
+    /// watch_typed 监听配置分组，将分组下的所有配置文件按照文件名顺序合并后解析为 T，
+    /// 返回的句柄始终持有最近一次解析成功的配置，drop 所有句柄后自动取消监听
+    async fn watch_typed<T>(
+        &self,
+        req: GetConfigGroupRequest,
+    ) -> Result<TypedConfig<T>, PolarisError>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+        Self: Sized;
+
+    /// shutdown 关闭 ConfigGroupAPI 实例，取消所有监听，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
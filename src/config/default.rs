@@ -19,8 +19,10 @@ use std::{
         atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
+use serde::de::DeserializeOwned;
 use tokio::sync::RwLock;
 
 use crate::core::{
@@ -29,6 +31,7 @@ use crate::core::{
         cache::{EventType, ServerEvent},
         config::{ConfigFile, ConfigFileChangeEvent, ConfigGroup, ConfigGroupChangeEvent},
         error::PolarisError,
+        watch::{channel, WatchStream},
     },
     plugin::cache::{Action, ResourceListener},
 };
@@ -38,9 +41,12 @@ use super::{
     req::{
         CreateConfigFileRequest, GetConfigFileRequest, GetConfigGroupRequest,
         PublishConfigFileRequest, UpdateConfigFileRequest, UpsertAndPublishConfigFileRequest,
-        WatchConfigFileRequest, WatchConfigFileResponse, WatchConfigGroupRequest,
-        WatchConfigGroupResponse,
+        WatchConfigFileRequest, WatchConfigFileResponse, WatchConfigFileStreamRequest,
+        WatchConfigGroupRequest, WatchConfigGroupResponse, WatchConfigGroupStreamRequest,
+        WatchConfigKeysRequest,
     },
+    typed::{parse_file, parse_group, TypedConfig, TypedConfigUpdater},
+    view::ConfigView,
 };
 
 struct ConfigFileWatcher {
@@ -219,6 +225,157 @@ impl ConfigFileAPI for DefaultConfigFileAPI {
             self.watchers.clone(),
         ))
     }
+
+    async fn watch_config_file_stream(
+        &self,
+        req: WatchConfigFileStreamRequest,
+    ) -> Result<WatchStream<ConfigFileChangeEvent>, PolarisError> {
+        let (sender, stream) = channel(req.option);
+        let sender = Arc::new(sender);
+        let watch_sender = sender.clone();
+        let watch_rsp = self
+            .watch_config_file(WatchConfigFileRequest {
+                namespace: req.namespace.clone(),
+                group: req.group.clone(),
+                file: req.file.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_sender.send(event);
+                }),
+            })
+            .await?;
+        let stream =
+            stream.with_cancel(watch_rsp.into_canceler(self.context.get_engine().get_executor()));
+
+        // 拉取一次配置文件，确保资源已经被订阅，同时作为首个事件下发
+        let config_file = self
+            .get_config_file(GetConfigFileRequest {
+                namespace: req.namespace,
+                group: req.group,
+                file: req.file,
+                timeout: req.timeout,
+            })
+            .await?;
+        sender.send_initial(ConfigFileChangeEvent { config_file });
+        Ok(stream)
+    }
+
+    async fn get_config_view(&self, req: GetConfigFileRequest) -> Result<ConfigView, PolarisError> {
+        let config_file = self.get_config_file(req).await?;
+        ConfigView::new(config_file, &self.context.conf.config)
+    }
+
+    async fn watch_config_keys(
+        &self,
+        req: WatchConfigKeysRequest,
+    ) -> Result<WatchConfigFileResponse, PolarisError> {
+        // last 上一次解析成功的配置视图，用于计算配置项的变更
+        let last: Arc<std::sync::Mutex<Option<ConfigView>>> = Arc::new(std::sync::Mutex::new(None));
+        let watch_last = last.clone();
+        let conf = self.context.conf.clone();
+        let watch_req = req.clone();
+        let watch_rsp = self
+            .watch_config_file(WatchConfigFileRequest {
+                namespace: req.namespace.clone(),
+                group: req.group.clone(),
+                file: req.file.clone(),
+                call_back: Arc::new(move |event| {
+                    let view = match ConfigView::new(event.config_file, &conf.config) {
+                        Ok(view) => view,
+                        Err(e) => {
+                            crate::warn!(
+                                "[polaris][config] parse config file {} fail, keep last view: {}",
+                                watch_req.file,
+                                e.get_err_msg()
+                            );
+                            return;
+                        }
+                    };
+                    let mut change = {
+                        let mut last = watch_last.lock().unwrap();
+                        let change = last.as_ref().map(|old| view.diff(old));
+                        *last = Some(view);
+                        match change {
+                            Some(change) => change,
+                            None => return,
+                        }
+                    };
+                    change.changes.retain(|c| watch_req.match_key(&c.key));
+                    if !change.changes.is_empty() {
+                        (watch_req.call_back)(change);
+                    }
+                }),
+            })
+            .await?;
+
+        // 拉取一次配置文件作为计算变更的基线，监听期间已经收到的推送以推送为准
+        let view = match self
+            .get_config_view(GetConfigFileRequest {
+                namespace: req.namespace,
+                group: req.group,
+                file: req.file,
+                timeout: req.timeout,
+            })
+            .await
+        {
+            Ok(view) => view,
+            Err(e) => {
+                watch_rsp.cancel_watch().await;
+                return Err(e);
+            }
+        };
+        let mut last = last.lock().unwrap();
+        if last.is_none() {
+            *last = Some(view);
+        }
+        Ok(watch_rsp)
+    }
+
+    async fn watch_typed<T>(
+        &self,
+        req: GetConfigFileRequest,
+    ) -> Result<TypedConfig<T>, PolarisError>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+        Self: Sized,
+    {
+        let updater = TypedConfigUpdater::<T>::new();
+        let watch_updater = updater.clone();
+        let conf = self.context.conf.clone();
+        let watch_rsp = self
+            .watch_config_file(WatchConfigFileRequest {
+                namespace: req.namespace.clone(),
+                group: req.group.clone(),
+                file: req.file.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_updater.update(parse_file(event.config_file, &conf.config));
+                }),
+            })
+            .await?;
+
+        // 首次加载失败时没有可以使用的配置，直接返回错误
+        let ret = match self.get_config_file(req).await {
+            Ok(config_file) => parse_file::<T>(config_file, &self.context.conf.config),
+            Err(e) => Err(e),
+        };
+        match ret {
+            Ok(value) => {
+                let canceler = watch_rsp.into_canceler(self.context.get_engine().get_executor());
+                Ok(updater.init(value, canceler))
+            }
+            Err(e) => {
+                watch_rsp.cancel_watch().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        self.watchers.watchers.write().await.clear();
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context.shutdown(timeout).await
+    }
 }
 
 /// DefaultConfigGroupAPI
@@ -317,6 +474,88 @@ impl ConfigGroupAPI for DefaultConfigGroupAPI {
             self.watchers.clone(),
         ))
     }
+
+    async fn watch_publish_config_files_stream(
+        &self,
+        req: WatchConfigGroupStreamRequest,
+    ) -> Result<WatchStream<ConfigGroupChangeEvent>, PolarisError> {
+        let (sender, stream) = channel(req.option);
+        let sender = Arc::new(sender);
+        let watch_sender = sender.clone();
+        let watch_rsp = self
+            .watch_publish_config_files(WatchConfigGroupRequest {
+                flow_id: req.flow_id.clone(),
+                timeout: req.timeout,
+                namespace: req.namespace.clone(),
+                group: req.group.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_sender.send(event);
+                }),
+            })
+            .await?;
+        let stream =
+            stream.with_cancel(watch_rsp.into_canceler(self.context.get_engine().get_executor()));
+
+        // 拉取一次配置分组，确保资源已经被订阅，同时作为首个事件下发
+        let config_group = self
+            .get_publish_config_files(GetConfigGroupRequest {
+                flow_id: req.flow_id,
+                timeout: req.timeout,
+                namespace: req.namespace,
+                group: req.group,
+            })
+            .await?;
+        sender.send_initial(ConfigGroupChangeEvent { config_group });
+        Ok(stream)
+    }
+
+    async fn watch_typed<T>(
+        &self,
+        req: GetConfigGroupRequest,
+    ) -> Result<TypedConfig<T>, PolarisError>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+        Self: Sized,
+    {
+        let updater = TypedConfigUpdater::<T>::new();
+        let watch_updater = updater.clone();
+        let conf = self.context.conf.clone();
+        let watch_rsp = self
+            .watch_publish_config_files(WatchConfigGroupRequest {
+                flow_id: req.flow_id.clone(),
+                timeout: req.timeout,
+                namespace: req.namespace.clone(),
+                group: req.group.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_updater.update(parse_group(event.config_group, &conf.config));
+                }),
+            })
+            .await?;
+
+        // 首次加载失败时没有可以使用的配置，直接返回错误
+        let ret = match self.get_publish_config_files(req).await {
+            Ok(config_group) => parse_group::<T>(config_group, &self.context.conf.config),
+            Err(e) => Err(e),
+        };
+        match ret {
+            Ok(value) => {
+                let canceler = watch_rsp.into_canceler(self.context.get_engine().get_executor());
+                Ok(updater.init(value, canceler))
+            }
+            Err(e) => {
+                watch_rsp.cancel_watch().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        self.watchers.watchers.write().await.clear();
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context.shutdown(timeout).await
+    }
 }
 
 struct ConfigGroupWatcher {
@@ -370,6 +609,183 @@ impl ResourceListener for ConfigGroupResourceListener {
 
     // 获取监听的key
     fn watch_key(&self) -> EventType {
-        EventType::ConfigFile
+        EventType::ConfigGroup
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polaris_specification::v1::config_discover_response::ConfigDiscoverResponseType;
+    use polaris_specification::v1::{ClientConfigFileInfo, ConfigDiscoverResponse};
+
+    use super::*;
+    use crate::core::context::tests::new_context;
+    use crate::core::engine::tests::{push_remote_data, MockConnector};
+    use crate::core::model::cache::{RemoteData, ResourceEventKey};
+    use crate::core::model::config::ConfigKeyChangeType;
+
+    fn new_group_data(group: &str, revision: &str, files: &[&str]) -> RemoteData {
+        let info = |name: &str| ClientConfigFileInfo {
+            namespace: Some("default".to_string()),
+            group: Some(group.to_string()),
+            name: Some(name.to_string()),
+            file_name: Some(name.to_string()),
+            version: Some(1),
+            content: Some(String::new()),
+            ..Default::default()
+        };
+        let mut rsp = ConfigDiscoverResponse {
+            revision: revision.to_string(),
+            config_file: Some(info("")),
+            config_file_names: files.iter().map(|name| info(name)).collect(),
+            ..Default::default()
+        };
+        rsp.set_type(ConfigDiscoverResponseType::ConfigFileNames);
+        RemoteData {
+            event_key: ResourceEventKey {
+                namespace: "default".to_string(),
+                event_type: EventType::ConfigGroup,
+                filter: HashMap::from([("group".to_string(), group.to_string())]),
+            },
+            discover_value: None,
+            config_value: Some(rsp),
+        }
+    }
+
+    fn new_file_data(version: u64, content: &str) -> RemoteData {
+        let mut rsp = ConfigDiscoverResponse {
+            revision: version.to_string(),
+            config_file: Some(ClientConfigFileInfo {
+                namespace: Some("default".to_string()),
+                group: Some("app".to_string()),
+                file_name: Some("app.yaml".to_string()),
+                name: Some("app.yaml".to_string()),
+                version: Some(version),
+                content: Some(content.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        rsp.set_type(ConfigDiscoverResponseType::ConfigFile);
+        RemoteData {
+            event_key: ResourceEventKey {
+                namespace: "default".to_string(),
+                event_type: EventType::ConfigFile,
+                filter: HashMap::from([
+                    ("group".to_string(), "app".to_string()),
+                    ("file".to_string(), "app.yaml".to_string()),
+                ]),
+            },
+            discover_value: None,
+            config_value: Some(rsp),
+        }
+    }
+
+    #[test]
+    fn test_watch_config_keys() {
+        let connector = MockConnector::default();
+        let handlers = connector.handlers.clone();
+        let (rt, ctx) = new_context(connector);
+        let api = Arc::new(DefaultConfigFileAPI::new(ctx));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watch_api = api.clone();
+        let watch = rt.spawn(async move {
+            watch_api
+                .watch_config_keys(WatchConfigKeysRequest {
+                    namespace: "default".to_string(),
+                    group: "app".to_string(),
+                    file: "app.yaml".to_string(),
+                    timeout: Duration::from_secs(5),
+                    keys: vec!["server".to_string()],
+                    call_back: Arc::new(move |event| {
+                        let _ = tx.send(event);
+                    }),
+                })
+                .await
+        });
+        // 监听时拉取的配置文件作为计算变更的基线
+        push_remote_data(
+            &handlers,
+            new_file_data(
+                1,
+                "name: a\nserver:\n  port: 8080\n  host: h1\nservers: 1\n",
+            ),
+        );
+        let _watch = rt.block_on(watch).unwrap().unwrap();
+
+        // 只通知 server 下的配置项，前缀相同的 servers 以及其他配置项不会通知
+        push_remote_data(
+            &handlers,
+            new_file_data(
+                2,
+                "name: b\nserver:\n  port: 9090\n  host: h1\nservers: 2\n",
+            ),
+        );
+        let event = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(2, event.version);
+        assert_eq!(1, event.changes.len());
+        let change = &event.changes[0];
+        assert_eq!("server.port", change.key);
+        assert_eq!(ConfigKeyChangeType::Modified, change.change_type);
+        assert_eq!(Some("8080"), change.old_value.as_deref());
+        assert_eq!(Some("9090"), change.new_value.as_deref());
+
+        // 关注的配置项没有变化时不会通知
+        push_remote_data(
+            &handlers,
+            new_file_data(
+                3,
+                "name: c\nserver:\n  port: 9090\n  host: h1\nservers: 3\n",
+            ),
+        );
+        assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn test_watch_config_group() {
+        let connector = MockConnector::default();
+        let handlers = connector.handlers.clone();
+        let (rt, ctx) = new_context(connector);
+        let api = Arc::new(DefaultConfigGroupAPI::new(ctx));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        rt.block_on(api.watch_publish_config_files(WatchConfigGroupRequest {
+            flow_id: String::new(),
+            timeout: Duration::from_secs(1),
+            namespace: "default".to_string(),
+            group: "app".to_string(),
+            call_back: Arc::new(move |event| {
+                let _ = tx.send(event.config_group.revision);
+            }),
+        }))
+        .unwrap();
+
+        let load_api = api.clone();
+        let load = rt.spawn(async move {
+            load_api
+                .get_publish_config_files(GetConfigGroupRequest {
+                    flow_id: String::new(),
+                    timeout: Duration::from_secs(5),
+                    namespace: "default".to_string(),
+                    group: "app".to_string(),
+                })
+                .await
+        });
+        push_remote_data(&handlers, new_group_data("app", "1", &["a.yaml"]));
+        let group = rt.block_on(load).unwrap().unwrap();
+        assert_eq!(1, group.files.len());
+
+        // 配置分组变更事件会通知到配置分组的监听器
+        push_remote_data(&handlers, new_group_data("app", "2", &["a.yaml", "b.yaml"]));
+        let mut revisions = Vec::new();
+        while let Ok(revision) = rx.recv_timeout(Duration::from_secs(2)) {
+            let done = revision == "2";
+            revisions.push(revision);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(Some("2"), revisions.last().map(String::as_str));
     }
 }
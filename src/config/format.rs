@@ -0,0 +1,276 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use serde_json::{Map, Value};
+
+use crate::core::model::config::ConfigFile;
+use crate::core::model::error::{ErrorCode, PolarisError};
+
+/// CONFIG_FILE_LABEL_FORMAT 配置文件标签中指定文件格式的 key，优先级高于文件扩展名
+pub static CONFIG_FILE_LABEL_FORMAT: &str = "format";
+
+/// ConfigFormat 配置文件内容格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+    Properties,
+    Toml,
+    // Text 无法识别的格式，整个文件内容作为一个字符串
+    Text,
+}
+
+impl ConfigFormat {
+    /// detect 根据配置文件标签或者文件扩展名识别文件格式
+    pub fn detect(file: &ConfigFile) -> Self {
+        if let Some(format) = file
+            .labels
+            .get(CONFIG_FILE_LABEL_FORMAT)
+            .and_then(|format| Self::parse(format))
+        {
+            return format;
+        }
+        file.name
+            .rsplit_once('.')
+            .and_then(|(_, ext)| Self::parse(ext))
+            .unwrap_or(ConfigFormat::Text)
+    }
+
+    /// parse 根据格式名称或扩展名解析格式，例如 yaml、yml、json
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            "properties" => Some(ConfigFormat::Properties),
+            "toml" => Some(ConfigFormat::Toml),
+            "text" | "txt" => Some(ConfigFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// parse_content 将配置内容解析为统一的树形结构，properties 文件解析为扁平的 key-value
+    pub fn parse_content(&self, content: &str) -> Result<Value, PolarisError> {
+        let ret = match self {
+            ConfigFormat::Yaml => serde_yaml::from_str::<serde_yaml::Value>(content)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_json::to_value(value).map_err(|e| e.to_string())),
+            ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Properties => Ok(parse_properties(content)),
+            ConfigFormat::Toml => toml::from_str::<toml::Value>(content)
+                .map(toml_to_json)
+                .map_err(|e| e.to_string()),
+            ConfigFormat::Text => return Ok(Value::String(content.to_string())),
+        };
+        match ret {
+            // 空文件按照空对象处理
+            Ok(Value::Null) => Ok(Value::Object(Map::new())),
+            Ok(value) => Ok(value),
+            Err(e) => Err(PolarisError::new(
+                ErrorCode::InvalidConfig,
+                format!("parse {:?} config content fail: {}", self, e),
+            )),
+        }
+    }
+}
+
+// parse_properties 解析 java properties 格式，支持 = : 以及空白分隔符、行尾 \ 续行与常见转义
+fn parse_properties(content: &str) -> Value {
+    let mut map = Map::new();
+    let mut logical = String::new();
+    for line in content.lines() {
+        let line = line.trim_start();
+        if logical.is_empty() && (line.is_empty() || line.starts_with('#') || line.starts_with('!'))
+        {
+            continue;
+        }
+        // 行尾奇数个 \ 表示续行
+        let slashes = line.chars().rev().take_while(|c| *c == '\\').count();
+        if slashes % 2 == 1 {
+            logical.push_str(&line[..line.len() - 1]);
+            continue;
+        }
+        logical.push_str(line);
+        let (key, value) = split_property(&logical);
+        map.insert(key, Value::String(value));
+        logical.clear();
+    }
+    if !logical.is_empty() {
+        let (key, value) = split_property(&logical);
+        map.insert(key, Value::String(value));
+    }
+    Value::Object(map)
+}
+
+fn split_property(line: &str) -> (String, String) {
+    let mut key = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    key.push(unescape_char(next, &mut chars));
+                }
+            }
+            '=' | ':' => break,
+            c if c.is_whitespace() => {
+                // 空白之后允许再出现一个 = 或 : 分隔符
+                let rest = chars.as_str().trim_start();
+                chars = match rest.strip_prefix(['=', ':']) {
+                    Some(rest) => rest.chars(),
+                    None => rest.chars(),
+                };
+                break;
+            }
+            c => key.push(c),
+        }
+    }
+    let mut value = String::new();
+    let mut chars = chars.as_str().trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    value.push(unescape_char(next, &mut chars));
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    (key, value)
+}
+
+fn unescape_char(c: char, chars: &mut std::str::Chars) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'f' => '\u{c}',
+        'u' => {
+            let hex: String = chars.by_ref().take(4).collect();
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or('\u{fffd}')
+        }
+        c => c,
+    }
+}
+
+// toml_to_json 将 TOML 的值转换为统一的树形结构，日期时间按照字符串处理
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(v) => Value::String(v),
+        toml::Value::Integer(v) => Value::from(v),
+        toml::Value::Float(v) => serde_json::Number::from_f64(v)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        toml::Value::Boolean(v) => Value::Bool(v),
+        toml::Value::Datetime(v) => Value::String(v.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(k, v)| (k, toml_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        let mut file = ConfigFile {
+            name: "conf/app.yml".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(ConfigFormat::Yaml, ConfigFormat::detect(&file));
+        file.labels
+            .insert(CONFIG_FILE_LABEL_FORMAT.to_string(), "toml".to_string());
+        assert_eq!(ConfigFormat::Toml, ConfigFormat::detect(&file));
+        file.labels.clear();
+        file.name = "app".to_string();
+        assert_eq!(ConfigFormat::Text, ConfigFormat::detect(&file));
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let content =
+            "# comment\nserver.port = 8080\nserver.name:polaris\\\n  -rust\nmsg hello\\nworld\n";
+        let value = ConfigFormat::Properties.parse_content(content).unwrap();
+        assert_eq!(
+            json!({
+                "server.port": "8080",
+                "server.name": "polaris-rust",
+                "msg": "hello\nworld",
+            }),
+            value
+        );
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let content = r#"
+title = "polaris" # comment
+[server]
+port = 8_080
+ratio = 0.5
+hosts = [
+  "a",
+  'b',
+]
+tls.enable = true
+
+[[routes]]
+name = "r1"
+[[routes]]
+name = "r2"
+meta = { env = "test" }
+desc = """
+multi
+line"""
+updated = 1979-05-27T07:32:00Z
+"#;
+        let value = ConfigFormat::Toml.parse_content(content).unwrap();
+        assert_eq!(
+            json!({
+                "title": "polaris",
+                "server": {
+                    "port": 8080,
+                    "ratio": 0.5,
+                    "hosts": ["a", "b"],
+                    "tls": {"enable": true},
+                },
+                "routes": [
+                    {"name": "r1"},
+                    {
+                        "name": "r2",
+                        "meta": {"env": "test"},
+                        "desc": "multi\nline",
+                        "updated": "1979-05-27T07:32:00Z",
+                    },
+                ],
+            }),
+            value
+        );
+
+        assert!(ConfigFormat::Toml.parse_content("a = \"x").is_err());
+        assert!(ConfigFormat::Toml.parse_content("a = 1\na = 2").is_err());
+    }
+}
@@ -16,4 +16,7 @@
 pub mod req;
 
 pub mod api;
+pub mod format;
+pub mod typed;
+pub mod view;
 mod default;
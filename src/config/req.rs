@@ -17,9 +17,12 @@ use std::{sync::Arc, time::Duration};
 
 use crate::core::model::config::{
     ConfigFile, ConfigFileChangeEvent, ConfigFileRelease, ConfigFileRequest,
-    ConfigGroupChangeEvent, ConfigPublishRequest, ConfigReleaseRequest,
+    ConfigGroupChangeEvent, ConfigKeyChangeEvent, ConfigPublishRequest, ConfigReleaseRequest,
 };
 
+use crate::core::model::watch::WatchStreamOption;
+use tokio::runtime::Runtime;
+
 use super::default::{ConfigFileResourceListener, ConfigGroupResourceListener};
 
 #[derive(Clone, Debug)]
@@ -152,6 +155,49 @@ impl WatchConfigFileResponse {
             .cancel_watch(&self.watch_key, self.watch_id)
             .await;
     }
+
+    /// into_canceler 转换为可以在同步上下文中执行的取消监听动作
+    pub(crate) fn into_canceler(self, executor: Arc<Runtime>) -> Box<dyn FnOnce() + Send> {
+        Box::new(move || {
+            executor.spawn(async move {
+                self.cancel_watch().await;
+            });
+        })
+    }
+}
+
+/// WatchConfigFileStreamRequest 以事件流的方式监听配置文件变更
+#[derive(Clone, Debug)]
+pub struct WatchConfigFileStreamRequest {
+    pub namespace: String,
+    pub group: String,
+    pub file: String,
+    pub timeout: Duration,
+    // option 事件流的缓冲区配置
+    pub option: WatchStreamOption,
+}
+
+/// WatchConfigKeysRequest 监听配置文件中配置项的变更
+#[derive(Clone)]
+pub struct WatchConfigKeysRequest {
+    pub namespace: String,
+    pub group: String,
+    pub file: String,
+    pub timeout: Duration,
+    // keys 关注的配置项，支持 a.b 匹配其下的所有配置项，为空时关注所有配置项
+    pub keys: Vec<String>,
+    pub call_back: Arc<dyn Fn(ConfigKeyChangeEvent) + Send + Sync>,
+}
+
+impl WatchConfigKeysRequest {
+    /// match_key 判断配置项是否为关注的配置项
+    pub fn match_key(&self, key: &str) -> bool {
+        self.keys.is_empty()
+            || self.keys.iter().any(|prefix| {
+                key == prefix
+                    || (key.starts_with(prefix.as_str()) && key[prefix.len()..].starts_with('.'))
+            })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -201,4 +247,26 @@ impl WatchConfigGroupResponse {
             .cancel_watch(&self.watch_key, self.watch_id)
             .await;
     }
+
+    /// into_canceler 转换为可以在同步上下文中执行的取消监听动作
+    pub(crate) fn into_canceler(self, executor: Arc<Runtime>) -> Box<dyn FnOnce() + Send> {
+        Box::new(move || {
+            executor.spawn(async move {
+                self.cancel_watch().await;
+            });
+        })
+    }
+}
+
+/// WatchConfigGroupStreamRequest 以事件流的方式监听配置分组下发布的配置文件变更
+#[derive(Clone, Debug)]
+pub struct WatchConfigGroupStreamRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    // namespace 命名空间
+    pub namespace: String,
+    // group 配置分组
+    pub group: String,
+    // option 事件流的缓冲区配置
+    pub option: WatchStreamOption,
 }
@@ -0,0 +1,360 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::config::view::{convert, ConfigView};
+use crate::core::config::config_file::ConfigFileConfig;
+use crate::core::model::config::{ConfigFile, ConfigGroup};
+use crate::core::model::error::PolarisError;
+use crate::core::model::watch::{channel, WatchSender, WatchStream, WatchStreamOption};
+
+/// TypedConfigEvent 配置热加载事件
+#[derive(Clone, Debug)]
+pub enum TypedConfigEvent<T> {
+    // Updated 配置解析成功并已经替换为新值
+    Updated(Arc<T>),
+    // ParseFailed 配置解析失败，句柄继续持有上一次解析成功的值
+    ParseFailed(PolarisError),
+}
+
+/// TypedConfig 配置热加载句柄，始终持有最近一次解析成功的配置，所有 clone 都被 drop 后自动取消监听
+pub struct TypedConfig<T> {
+    inner: Arc<TypedConfigInner<T>>,
+}
+
+impl<T> Clone for TypedConfig<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> TypedConfig<T> {
+    /// load 获取当前的配置，返回的 Arc<T> 不受后续配置变更的影响
+    pub fn load(&self) -> Arc<T> {
+        self.inner.value.read().unwrap().clone()
+    }
+
+    /// last_error 最近一次解析失败的错误，解析成功后清空
+    pub fn last_error(&self) -> Option<PolarisError> {
+        self.inner.last_error.read().unwrap().clone()
+    }
+
+    /// changes 订阅配置热加载事件流，每次调用返回一个独立的事件流
+    pub fn changes(&self, option: WatchStreamOption) -> WatchStream<TypedConfigEvent<T>> {
+        let (sender, stream) = channel(option);
+        let mut subscribers = self.inner.subscribers.lock().unwrap();
+        subscribers.retain(|sender| !sender.is_closed());
+        subscribers.push(sender);
+        stream
+    }
+}
+
+struct TypedConfigInner<T> {
+    value: RwLock<Arc<T>>,
+    last_error: RwLock<Option<PolarisError>>,
+    subscribers: Mutex<Vec<WatchSender<TypedConfigEvent<T>>>>,
+    // canceler 取消底层的配置监听
+    canceler: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl<T> TypedConfigInner<T> {
+    fn publish(&self, event: TypedConfigEvent<T>)
+    where
+        T: Send + Sync,
+    {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|sender| !sender.is_closed());
+        for sender in subscribers.iter() {
+            let event = match &event {
+                TypedConfigEvent::Updated(value) => TypedConfigEvent::Updated(value.clone()),
+                TypedConfigEvent::ParseFailed(e) => TypedConfigEvent::ParseFailed(e.clone()),
+            };
+            sender.send(event);
+        }
+    }
+}
+
+impl<T> Drop for TypedConfigInner<T> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.canceler.get_mut().unwrap().take() {
+            cancel();
+        }
+    }
+}
+
+/// TypedConfigUpdater 配置监听回调使用的更新器，只持有句柄的弱引用，句柄初始化之前收到的最新变更在初始化时生效
+pub(crate) struct TypedConfigUpdater<T> {
+    state: Arc<Mutex<UpdaterState<T>>>,
+}
+
+enum UpdaterState<T> {
+    // Pending 句柄还没有初始化，保存期间收到的最新变更
+    Pending(Option<Result<T, PolarisError>>),
+    Ready(Weak<TypedConfigInner<T>>),
+}
+
+impl<T> Clone for TypedConfigUpdater<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync> TypedConfigUpdater<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(UpdaterState::Pending(None))),
+        }
+    }
+
+    /// init 使用首次解析成功的配置创建句柄，初始化之前收到的变更比首次加载的配置更新，优先使用
+    pub(crate) fn init(&self, value: T, canceler: Box<dyn FnOnce() + Send>) -> TypedConfig<T> {
+        let mut state = self.state.lock().unwrap();
+        let (value, last_error) = match &mut *state {
+            UpdaterState::Pending(pending) => match pending.take() {
+                Some(Ok(pending)) => (pending, None),
+                Some(Err(e)) => (value, Some(e)),
+                None => (value, None),
+            },
+            UpdaterState::Ready(_) => (value, None),
+        };
+        let inner = Arc::new(TypedConfigInner {
+            value: RwLock::new(Arc::new(value)),
+            last_error: RwLock::new(last_error),
+            subscribers: Mutex::new(Vec::new()),
+            canceler: Mutex::new(Some(canceler)),
+        });
+        *state = UpdaterState::Ready(Arc::downgrade(&inner));
+        TypedConfig { inner }
+    }
+
+    /// update 处理配置变更，解析失败时保留上一次解析成功的值
+    pub(crate) fn update(&self, ret: Result<T, PolarisError>) {
+        let inner = {
+            let mut state = self.state.lock().unwrap();
+            match &mut *state {
+                UpdaterState::Pending(pending) => {
+                    *pending = Some(ret);
+                    return;
+                }
+                UpdaterState::Ready(inner) => match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                },
+            }
+        };
+        match ret {
+            Ok(value) => {
+                let value = Arc::new(value);
+                *inner.value.write().unwrap() = value.clone();
+                *inner.last_error.write().unwrap() = None;
+                inner.publish(TypedConfigEvent::Updated(value));
+            }
+            Err(e) => {
+                crate::warn!(
+                    "[polaris][config] parse config fail, keep last value: {}",
+                    e.get_err_msg()
+                );
+                *inner.last_error.write().unwrap() = Some(e.clone());
+                inner.publish(TypedConfigEvent::ParseFailed(e));
+            }
+        }
+    }
+}
+
+/// parse_file 按照配置文件的格式将配置文件解析为 T
+pub(crate) fn parse_file<T: DeserializeOwned>(
+    file: ConfigFile,
+    conf: &ConfigFileConfig,
+) -> Result<T, PolarisError> {
+    ConfigView::new(file, conf)?.deserialize()
+}
+
+/// parse_group 将配置分组下的所有配置文件按照文件名顺序深度合并后解析为 T，
+/// 文件名靠后的配置覆盖靠前的配置，内容不是对象的文件以文件名作为 key
+pub(crate) fn parse_group<T: DeserializeOwned>(
+    group: ConfigGroup,
+    conf: &ConfigFileConfig,
+) -> Result<T, PolarisError> {
+    let mut files = group.files;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut root = Map::new();
+    for file in files {
+        let name = file.name.clone();
+        match ConfigView::new(file, conf)?.to_tree() {
+            Value::Object(map) => merge(&mut root, map),
+            value => {
+                root.insert(name, value);
+            }
+        }
+    }
+    convert(Value::Object(root))
+}
+
+fn merge(target: &mut Map<String, Value>, source: Map<String, Value>) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Object(target)), Value::Object(source)) => merge(target, source),
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::StreamExt;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::core::config::config_file::ConfigFilter;
+    use crate::core::model::error::ErrorCode;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct App {
+        port: u16,
+        name: String,
+    }
+
+    fn new_conf() -> ConfigFileConfig {
+        ConfigFileConfig {
+            properties_value_cache_size: 100,
+            properties_value_expire_time: 60000,
+            config_filter: ConfigFilter {
+                enable: false,
+                chain: Vec::new(),
+                plugin: HashMap::new(),
+            },
+        }
+    }
+
+    fn new_file(name: &str, content: &str) -> ConfigFile {
+        ConfigFile {
+            name: name.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typed_config_update() {
+        let updater = TypedConfigUpdater::<App>::new();
+        // 句柄初始化之前的最新变更比首次加载的配置更新，初始化时优先使用
+        updater.update(Err(PolarisError::new(
+            ErrorCode::InvalidConfig,
+            String::new(),
+        )));
+        updater.update(parse_file(
+            new_file("app.yaml", "port: 7070\nname: a"),
+            &new_conf(),
+        ));
+
+        let handle = updater.init(
+            parse_file(new_file("app.yaml", "port: 8080\nname: a"), &new_conf()).unwrap(),
+            Box::new(|| {}),
+        );
+        assert_eq!(7070, handle.load().port);
+        assert!(handle.last_error().is_none());
+        let mut changes = handle.changes(WatchStreamOption::default());
+
+        updater.update(parse_file(new_file("app.yaml", "port: x"), &new_conf()));
+        assert_eq!(7070, handle.load().port);
+        assert!(handle.last_error().is_some());
+        assert!(matches!(
+            changes.next().await,
+            Some(TypedConfigEvent::ParseFailed(_))
+        ));
+
+        updater.update(parse_file(
+            new_file("app.yaml", "port: 9090\nname: b"),
+            &new_conf(),
+        ));
+        assert_eq!(9090, handle.load().port);
+        assert!(handle.last_error().is_none());
+        match changes.next().await {
+            Some(TypedConfigEvent::Updated(value)) => assert_eq!("b", value.name),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_failed_before_init() {
+        let updater = TypedConfigUpdater::<App>::new();
+        updater.update(parse_file(new_file("app.yaml", "port: x"), &new_conf()));
+        // 初始化之前的变更解析失败时使用首次加载的配置，同时保留解析错误
+        let handle = updater.init(
+            App {
+                port: 8080,
+                name: "a".to_string(),
+            },
+            Box::new(|| {}),
+        );
+        assert_eq!(8080, handle.load().port);
+        assert!(handle.last_error().is_some());
+    }
+
+    #[test]
+    fn test_parse_group() {
+        let group = ConfigGroup {
+            namespace: "default".to_string(),
+            group: "app".to_string(),
+            files: vec![
+                new_file("b.properties", "app.port=9090"),
+                new_file("a.yaml", "app:\n  port: 8080\n  name: polaris"),
+            ],
+            revision: String::new(),
+        };
+        #[derive(Deserialize)]
+        struct Root {
+            app: App,
+        }
+        let root: Root = parse_group(group, &new_conf()).unwrap();
+        assert_eq!(
+            App {
+                port: 9090,
+                name: "polaris".to_string(),
+            },
+            root.app
+        );
+    }
+
+    #[test]
+    fn test_cancel_on_drop() {
+        let canceled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = canceled.clone();
+        let updater = TypedConfigUpdater::<u32>::new();
+        let handle = updater.init(
+            1,
+            Box::new(move || flag.store(true, std::sync::atomic::Ordering::SeqCst)),
+        );
+        let other = handle.clone();
+        drop(handle);
+        assert!(!canceled.load(std::sync::atomic::Ordering::SeqCst));
+        drop(other);
+        assert!(canceled.load(std::sync::atomic::Ordering::SeqCst));
+        // 句柄释放后的变更不再处理
+        updater.update(Ok(2));
+    }
+}
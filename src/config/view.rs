@@ -0,0 +1,580 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::config::format::ConfigFormat;
+use crate::core::config::config_file::ConfigFileConfig;
+use crate::core::model::config::{
+    ConfigFile, ConfigKeyChange, ConfigKeyChangeEvent, ConfigKeyChangeType,
+};
+use crate::core::model::error::{ErrorCode, PolarisError};
+
+/// ConfigKV 按照 a.b.c 形式的 key 读取配置项
+pub trait ConfigKV {
+    /// get_value 获取 key 对应的原始配置值，数组元素使用下标访问，例如 a.0.b
+    fn get_value(&self, key: &str) -> Option<&Value>;
+
+    /// contains 判断 key 是否存在
+    fn contains(&self, key: &str) -> bool {
+        self.get_value(key).is_some()
+    }
+
+    /// get_string 获取 key 对应配置值的字符串形式
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.get_value(key).map(render_value)
+    }
+}
+
+/// ConfigView 配置文件的结构化视图，根据文件格式解析配置内容，支持按 key 读取以及反序列化为结构体
+pub struct ConfigView {
+    file: ConfigFile,
+    format: ConfigFormat,
+    root: Value,
+    cache: ValueCache,
+}
+
+impl ConfigView {
+    /// new 解析配置文件，cache 的大小以及过期时间使用 config.propertiesValueCacheSize、config.propertiesValueExpireTime
+    pub fn new(file: ConfigFile, conf: &ConfigFileConfig) -> Result<Self, PolarisError> {
+        let format = ConfigFormat::detect(&file);
+        Self::new_with_format(file, format, conf)
+    }
+
+    /// new_with_format 使用指定的格式解析配置文件
+    pub fn new_with_format(
+        file: ConfigFile,
+        format: ConfigFormat,
+        conf: &ConfigFileConfig,
+    ) -> Result<Self, PolarisError> {
+        let root = format.parse_content(&file.content)?;
+        Ok(Self {
+            file,
+            format,
+            root,
+            cache: ValueCache::new(
+                conf.properties_value_cache_size as usize,
+                Duration::from_millis(u64::from(conf.properties_value_expire_time)),
+            ),
+        })
+    }
+
+    pub fn file(&self) -> &ConfigFile {
+        &self.file
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        self.format
+    }
+
+    /// get 获取 key 对应的配置值并转换为 T，字符串与数字、布尔值之间会自动转换，key 不存在或者转换失败时返回 None
+    pub fn get<T>(&self, key: &str) -> Option<T>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        match self.try_get(key) {
+            Ok(value) => value,
+            Err(e) => {
+                crate::debug!(
+                    "[polaris][config] get config value {} from {} fail: {}",
+                    key,
+                    self.file.name,
+                    e.get_err_msg()
+                );
+                None
+            }
+        }
+    }
+
+    /// get_or 获取 key 对应的配置值，key 不存在或者转换失败时返回 default
+    pub fn get_or<T>(&self, key: &str, default: T) -> T
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// try_get 获取 key 对应的配置值并转换为 T，key 不存在时返回 Ok(None)，转换失败时返回错误
+    pub fn try_get<T>(&self, key: &str) -> Result<Option<T>, PolarisError>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        if let Some(value) = self.cache.get::<T>(key) {
+            return Ok(Some(value));
+        }
+        let value = match self.get_value(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let value: T = convert(value.clone())?;
+        self.cache.put(key, value.clone());
+        Ok(Some(value))
+    }
+
+    /// deserialize 将整个配置文件反序列化为 T，properties 文件中的 a.b.c 会展开为嵌套结构
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, PolarisError> {
+        convert(self.to_tree())
+    }
+
+    // to_tree 配置内容的树形结构，properties 文件中的 a.b.c 会展开为嵌套结构
+    pub(crate) fn to_tree(&self) -> Value {
+        match self.format {
+            ConfigFormat::Properties => expand_keys(&self.root),
+            _ => self.root.clone(),
+        }
+    }
+
+    /// keys 所有叶子配置项的 key 以及字符串形式的值
+    pub fn keys(&self) -> BTreeMap<String, String> {
+        let mut ret = BTreeMap::new();
+        flatten("", &self.root, &mut ret);
+        ret
+    }
+
+    /// diff 计算从 old 变更为当前视图时发生变化的配置项
+    pub fn diff(&self, old: &ConfigView) -> ConfigKeyChangeEvent {
+        let old_keys = old.keys();
+        let new_keys = self.keys();
+        let mut changes = Vec::new();
+        for (key, old_value) in old_keys.iter() {
+            match new_keys.get(key) {
+                None => changes.push(ConfigKeyChange {
+                    key: key.clone(),
+                    old_value: Some(old_value.clone()),
+                    new_value: None,
+                    change_type: ConfigKeyChangeType::Deleted,
+                }),
+                Some(new_value) if new_value != old_value => changes.push(ConfigKeyChange {
+                    key: key.clone(),
+                    old_value: Some(old_value.clone()),
+                    new_value: Some(new_value.clone()),
+                    change_type: ConfigKeyChangeType::Modified,
+                }),
+                _ => {}
+            }
+        }
+        for (key, new_value) in new_keys.iter() {
+            if !old_keys.contains_key(key) {
+                changes.push(ConfigKeyChange {
+                    key: key.clone(),
+                    old_value: None,
+                    new_value: Some(new_value.clone()),
+                    change_type: ConfigKeyChangeType::Added,
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        ConfigKeyChangeEvent {
+            namespace: self.file.namespace.clone(),
+            group: self.file.group.clone(),
+            file: self.file.name.clone(),
+            version: self.file.version,
+            changes,
+        }
+    }
+}
+
+impl ConfigKV for ConfigView {
+    fn get_value(&self, key: &str) -> Option<&Value> {
+        lookup(&self.root, key)
+    }
+}
+
+// lookup 按照 a.b.c 查找配置值，优先匹配包含 . 的完整 key，兼容 properties 的扁平结构
+fn lookup<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    if key.is_empty() {
+        return Some(value);
+    }
+    match value {
+        Value::Object(map) => {
+            if let Some(value) = map.get(key) {
+                return Some(value);
+            }
+            key.match_indices('.').find_map(|(index, _)| {
+                map.get(&key[..index])
+                    .and_then(|value| lookup(value, &key[index + 1..]))
+            })
+        }
+        Value::Array(items) => {
+            let (head, rest) = key.split_once('.').unwrap_or((key, ""));
+            head.parse::<usize>()
+                .ok()
+                .and_then(|index| items.get(index))
+                .and_then(|value| lookup(value, rest))
+        }
+        _ => None,
+    }
+}
+
+// expand_keys 将 properties 中 a.b.c 形式的 key 展开为嵌套结构，存在冲突时保留扁平的 key
+fn expand_keys(root: &Value) -> Value {
+    let flat = match root {
+        Value::Object(map) => map,
+        other => return other.clone(),
+    };
+    let mut ret = Map::new();
+    for (key, value) in flat.iter() {
+        let parts: Vec<&str> = key.split('.').collect();
+        if !insert_nested(&mut ret, &parts, value) {
+            ret.insert(key.clone(), value.clone());
+        }
+    }
+    Value::Object(ret)
+}
+
+fn insert_nested(map: &mut Map<String, Value>, parts: &[&str], value: &Value) -> bool {
+    if parts.len() == 1 {
+        map.insert(parts[0].to_string(), value.clone());
+        return true;
+    }
+    match map
+        .entry(parts[0].to_string())
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        Value::Object(child) => insert_nested(child, &parts[1..], value),
+        _ => false,
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, ret: &mut BTreeMap<String, String>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map.iter() {
+                flatten(&join(key), value, ret);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                flatten(&join(&index.to_string()), value, ret);
+            }
+        }
+        _ => {
+            ret.insert(prefix.to_string(), render_value(value));
+        }
+    }
+}
+
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+pub(crate) fn convert<T: DeserializeOwned>(value: Value) -> Result<T, PolarisError> {
+    T::deserialize(LenientValue(value)).map_err(|e| {
+        PolarisError::new(
+            ErrorCode::InvalidConfig,
+            format!("convert config value fail: {}", e),
+        )
+    })
+}
+
+// LenientValue 宽松的反序列化，properties 等格式中的值都是字符串，需要按照目标类型转换为数字或布尔值，反之亦然
+struct LenientValue(Value);
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for LenientValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident => $ty:ty, $visit:ident;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                if let Value::String(s) = &self.0 {
+                    if let Ok(v) = s.trim().parse::<$ty>() {
+                        return visitor.$visit(v);
+                    }
+                }
+                self.deserialize_any(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for LenientValue {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(items) => {
+                let mut seq = SeqDeserializer::new(items.into_iter().map(LenientValue));
+                let ret = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(ret)
+            }
+            Value::Object(map) => {
+                let mut map =
+                    MapDeserializer::new(map.into_iter().map(|(k, v)| (k, LenientValue(v))));
+                let ret = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(ret)
+            }
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_number! {
+        deserialize_bool => bool, visit_bool;
+        deserialize_i8 => i64, visit_i64;
+        deserialize_i16 => i64, visit_i64;
+        deserialize_i32 => i64, visit_i64;
+        deserialize_i64 => i64, visit_i64;
+        deserialize_u8 => u64, visit_u64;
+        deserialize_u16 => u64, visit_u64;
+        deserialize_u32 => u64, visit_u64;
+        deserialize_u64 => u64, visit_u64;
+        deserialize_f32 => f64, visit_f64;
+        deserialize_f64 => f64, visit_f64;
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Number(n) => visitor.visit_string(n.to_string()),
+            Value::Bool(b) => visitor.visit_string(b.to_string()),
+            other => other.deserialize_str(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        char bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+// ValueCache 类型转换结果的缓存，超过容量时优先淘汰过期的数据，其次淘汰最早写入的数据
+struct ValueCache {
+    capacity: usize,
+    expire: Duration,
+    entries: Mutex<HashMap<(String, TypeId), CacheEntry>>,
+    seq: AtomicU64,
+}
+
+struct CacheEntry {
+    create_time: Instant,
+    // seq 写入顺序
+    seq: u64,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl ValueCache {
+    fn new(capacity: usize, expire: Duration) -> Self {
+        Self {
+            capacity,
+            expire,
+            entries: Mutex::new(HashMap::new()),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    fn get<T: Clone + Send + Sync + 'static>(&self, key: &str) -> Option<T> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(&(key.to_string(), TypeId::of::<T>()))?;
+        if entry.create_time.elapsed() >= self.expire {
+            return None;
+        }
+        entry.value.downcast_ref::<T>().cloned()
+    }
+
+    fn put<T: Clone + Send + Sync + 'static>(&self, key: &str, value: T) {
+        if self.capacity == 0 || self.expire.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.retain(|_, entry| entry.create_time.elapsed() < self.expire);
+        }
+        if entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.seq)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            (key.to_string(), TypeId::of::<T>()),
+            CacheEntry {
+                create_time: Instant::now(),
+                seq: self.seq.fetch_add(1, Ordering::Relaxed),
+                value: Arc::new(value),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::core::config::config_file::ConfigFilter;
+
+    fn new_view(name: &str, content: &str) -> ConfigView {
+        let file = ConfigFile {
+            name: name.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let conf = ConfigFileConfig {
+            properties_value_cache_size: 2,
+            properties_value_expire_time: 60000,
+            config_filter: ConfigFilter {
+                enable: false,
+                chain: Vec::new(),
+                plugin: HashMap::new(),
+            },
+        };
+        ConfigView::new(file, &conf).unwrap()
+    }
+
+    #[derive(Deserialize, Clone, Debug, PartialEq)]
+    struct Server {
+        port: u16,
+        name: String,
+        debug: Option<bool>,
+    }
+
+    #[test]
+    fn test_get_value() {
+        let view = new_view(
+            "app.yaml",
+            "server:\n  port: 8080\n  name: 1.0\n  hosts:\n    - a\n    - b\n",
+        );
+        assert_eq!(Some(8080), view.get::<u32>("server.port"));
+        assert_eq!(Some("8080".to_string()), view.get::<String>("server.port"));
+        assert_eq!(Some("b".to_string()), view.get::<String>("server.hosts.1"));
+        assert_eq!(9090, view.get_or("server.admin_port", 9090));
+        assert!(view.try_get::<u32>("server.hosts").is_err());
+        assert_eq!(
+            Server {
+                port: 8080,
+                name: "1.0".to_string(),
+                debug: None,
+            },
+            view.get::<Server>("server").unwrap()
+        );
+
+        let view = new_view(
+            "app.properties",
+            "server.port=8080\nserver.name=polaris\nserver.debug=true\n",
+        );
+        assert_eq!(Some(true), view.get::<bool>("server.debug"));
+        #[derive(Deserialize)]
+        struct App {
+            server: Server,
+        }
+        let app: App = view.deserialize().unwrap();
+        assert_eq!(8080, app.server.port);
+        assert_eq!(Some(true), app.server.debug);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = new_view("app.json", r#"{"a": {"b": 1, "c": "x"}, "d": [1]}"#);
+        let new = new_view("app.json", r#"{"a": {"b": 2}, "d": [1], "e": true}"#);
+        let changes: Vec<(String, Option<String>, Option<String>, ConfigKeyChangeType)> = new
+            .diff(&old)
+            .changes
+            .into_iter()
+            .map(|c| (c.key, c.old_value, c.new_value, c.change_type))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "a.b".to_string(),
+                    Some("1".to_string()),
+                    Some("2".to_string()),
+                    ConfigKeyChangeType::Modified
+                ),
+                (
+                    "a.c".to_string(),
+                    Some("x".to_string()),
+                    None,
+                    ConfigKeyChangeType::Deleted
+                ),
+                (
+                    "e".to_string(),
+                    None,
+                    Some("true".to_string()),
+                    ConfigKeyChangeType::Added
+                ),
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn test_value_cache() {
+        let cache = ValueCache::new(2, Duration::from_secs(60));
+        cache.put("a", 1u32);
+        cache.put("b", 2u32);
+        cache.put("c", 3u32);
+        assert_eq!(None, cache.get::<u32>("a"));
+        assert_eq!(Some(3), cache.get::<u32>("c"));
+        // 同一个 key 不同类型分别缓存
+        assert_eq!(None, cache.get::<String>("c"));
+    }
+}
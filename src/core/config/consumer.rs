@@ -15,6 +15,7 @@
 
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::time::Duration;
 
 use super::global::LocalCacheConfig;
 
@@ -33,6 +34,21 @@ pub struct ServiceRouterConfig {
     pub before_chain: Vec<ServiceRouterPluginConfig>,
     pub core_chain: Vec<ServiceRouterPluginConfig>,
     pub after_chain: Vec<ServiceRouterPluginConfig>,
+    // callee_chains 针对特定被调服务的路由链配置，优先于全局路由链
+    #[serde(default)]
+    pub callee_chains: Vec<CalleeRouterChainConfig>,
+}
+
+/// CalleeRouterChainConfig 被调服务维度的路由链，service 为 * 时匹配命名空间下的所有服务，
+/// 某个阶段的路由链不配置时沿用全局配置
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CalleeRouterChainConfig {
+    pub namespace: String,
+    pub service: String,
+    pub before_chain: Option<Vec<String>>,
+    pub core_chain: Option<Vec<String>>,
+    pub after_chain: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -47,6 +63,130 @@ pub struct ServiceRouterPluginConfig {
 pub struct LoadBalancerConfig {
     pub default_policy: String,
     pub plugins: Option<Vec<String>>,
+    // options 负载均衡插件的配置项，key 为插件名
+    #[serde(default)]
+    pub options: HashMap<String, HashMap<String, String>>,
+    // dynamic_weight 动态权重配置
+    #[serde(default)]
+    pub dynamic_weight: DynamicWeightConfig,
+    // warmup 新实例预热配置
+    #[serde(default)]
+    pub warmup: WarmupConfig,
+    // hash_key 一致性哈希 key 的来源，例如 header.x-user-id，请求未指定 hash_key 时使用
+    #[serde(default)]
+    pub hash_key: String,
+}
+
+/// WarmupConfig 新实例预热配置，新上线实例的权重在预热窗口内从较小的比例逐步增加到 100%
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WarmupConfig {
+    pub enable: bool,
+    // window 预热窗口
+    #[serde(with = "serde_duration_ext", default = "default_warmup_window")]
+    pub window: Duration,
+    // min_weight_percent 预热开始时的权重百分比
+    #[serde(default = "default_dynamic_weight_min_percent")]
+    pub min_weight_percent: u32,
+    // aggression 预热曲线的陡峭程度，1.0 为线性增长，越大前期增长越慢
+    #[serde(default = "default_warmup_aggression")]
+    pub aggression: f64,
+    // start_metadata_key 实例 metadata 中记录预热开始时间 (unix 毫秒) 的 key，未设置时使用实例在服务端的创建时间，其次使用实例被发现的时间
+    #[serde(default = "default_warmup_start_key")]
+    pub start_metadata_key: String,
+    // server_utc_offset 北极星服务端所在时区，例如 +08:00，服务端下发的实例创建时间不带时区，
+    // 只有配置了时区时才使用实例创建时间计算预热进度
+    #[serde(default)]
+    pub server_utc_offset: Option<String>,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            window: default_warmup_window(),
+            min_weight_percent: default_dynamic_weight_min_percent(),
+            aggression: default_warmup_aggression(),
+            start_metadata_key: default_warmup_start_key(),
+            server_utc_offset: None,
+        }
+    }
+}
+
+fn default_warmup_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_warmup_aggression() -> f64 {
+    1.0
+}
+
+fn default_warmup_start_key() -> String {
+    "polaris.warmup.start".to_string()
+}
+
+/// DynamicWeightConfig 动态权重配置，根据实例最近的调用成功率、耗时以及实例上报的负载调整实例权重
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DynamicWeightConfig {
+    pub enable: bool,
+    // window 统计窗口
+    #[serde(with = "serde_duration_ext", default = "default_dynamic_weight_window")]
+    pub window: Duration,
+    // min_request 统计窗口内的调用次数达到该值后，才根据成功率和耗时调整权重
+    #[serde(default = "default_dynamic_weight_min_request")]
+    pub min_request: u64,
+    // min_weight_percent 调整后的权重不低于静态权重的百分比
+    #[serde(default = "default_dynamic_weight_min_percent")]
+    pub min_weight_percent: u32,
+    // load_metadata_key 实例 metadata 中记录实例负载 (0-100) 的 key
+    #[serde(default = "default_dynamic_weight_load_key")]
+    pub load_metadata_key: String,
+}
+
+impl Default for DynamicWeightConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            window: default_dynamic_weight_window(),
+            min_request: default_dynamic_weight_min_request(),
+            min_weight_percent: default_dynamic_weight_min_percent(),
+            load_metadata_key: default_dynamic_weight_load_key(),
+        }
+    }
+}
+
+fn default_dynamic_weight_window() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_dynamic_weight_min_request() -> u64 {
+    10
+}
+
+fn default_dynamic_weight_min_percent() -> u32 {
+    10
+}
+
+fn default_dynamic_weight_load_key() -> String {
+    "polaris.load".to_string()
+}
+
+impl LoadBalancerConfig {
+    /// get_plugin_config 获取负载均衡插件的配置
+    pub fn get_plugin_config(&self, name: &str) -> LoadBalancerPluginConfig {
+        LoadBalancerPluginConfig {
+            name: name.to_string(),
+            options: self.options.get(name).cloned(),
+        }
+    }
+}
+
+/// LoadBalancerPluginConfig 负载均衡插件配置
+#[derive(Debug, Clone, Default)]
+pub struct LoadBalancerPluginConfig {
+    pub name: String,
+    pub options: Option<HashMap<String, String>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -24,6 +24,9 @@ pub struct ProviderConfig {
     #[serde(with = "serde_duration_ext", default = "default_min_register_interval")]
     pub min_register_interval: Duration,
     pub heartbeat_worker_size: Option<u32>,
+    // enrich 注册实例时自动补全实例信息
+    #[serde(default)]
+    pub enrich: InstanceEnrichConfig,
 }
 
 fn default_min_register_interval() -> Duration {
@@ -46,7 +49,7 @@ pub struct RateLimitConfig {
     pub report_metrics: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct LosslessConfig {
     pub enable: bool,
@@ -56,4 +59,59 @@ pub struct LosslessConfig {
     pub delay_register_interval: Duration,
     #[serde(with = "serde_duration_ext")]
     pub health_check_interval: Duration,
+    // drain_interval 优雅下线时，反注册之后等待存量请求处理完成的时间
+    #[serde(with = "serde_duration_ext", default = "default_drain_interval")]
+    pub drain_interval: Duration,
+}
+
+fn default_drain_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InstanceEnrichConfig {
+    // enable 是否在注册实例时自动补全实例信息
+    #[serde(default)]
+    pub enable: bool,
+    // kubernetes 是否从 Kubernetes 环境变量以及 downward API 中读取 pod 信息写入实例 metadata
+    #[serde(default = "default_true")]
+    pub kubernetes: bool,
+    // downward_api_path downward API 挂载目录，读取其中的 labels 文件写入实例 metadata
+    #[serde(default = "default_downward_api_path")]
+    pub downward_api_path: String,
+    // client_labels 是否将 global.client.labels 写入实例 metadata
+    #[serde(default = "default_true")]
+    pub client_labels: bool,
+    // version 实例版本，pod 的 version 标签优先
+    #[serde(default)]
+    pub version: String,
+    // protocol 实例协议
+    #[serde(default)]
+    pub protocol: String,
+    // vpc_id 实例所在的 VPC
+    #[serde(default)]
+    pub vpc_id: String,
+}
+
+impl Default for InstanceEnrichConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            kubernetes: default_true(),
+            downward_api_path: default_downward_api_path(),
+            client_labels: default_true(),
+            version: String::new(),
+            protocol: String::new(),
+            vpc_id: String::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_downward_api_path() -> String {
+    "/etc/podinfo".to_string()
 }
@@ -13,7 +13,9 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use crate::core::config::config::{load_default, Configuration};
 use crate::core::engine::Engine;
 use crate::core::model::error::{ErrorCode, PolarisError};
@@ -73,11 +75,117 @@ impl SDKContext {
     pub fn get_engine(&self) -> Arc<Engine> {
         self.engine.clone()
     }
+
+    /// shutdown 关闭 SDK：反注册当前进程注册的所有实例，停止心跳、上报以及数据同步任务，
+    /// timeout 为等待反注册完成的最长时间，重复调用时直接返回
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        self.engine.shutdown(timeout).await
+    }
+
+    /// is_shutdown 是否已经关闭
+    pub fn is_shutdown(&self) -> bool {
+        self.engine.is_closed()
+    }
+
+    /// register_shutdown_hook 收到 SIGTERM 或者 SIGINT 时执行 shutdown，完成后调用 on_shutdown，
+    /// 注册信号监听后进程不会再因为信号直接退出，需要在 on_shutdown 中结束进程
+    pub fn register_shutdown_hook<F>(self: &Arc<Self>, timeout: Duration, on_shutdown: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shutdown_on(wait_shutdown_signal(), timeout, on_shutdown);
+    }
+
+    // shutdown_on signal 完成时执行 shutdown，完成后调用 on_shutdown
+    fn shutdown_on<S, F>(self: &Arc<Self>, signal: S, timeout: Duration, on_shutdown: F)
+    where
+        S: Future<Output = ()> + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let ctx = self.clone();
+        self.engine.get_executor().spawn(async move {
+            signal.await;
+            info!("[polaris][context] receive shutdown signal");
+            if let Err(e) = ctx.shutdown(timeout).await {
+                crate::error!("[polaris][context] shutdown fail: {}", e);
+            }
+            on_shutdown();
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn wait_shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(e) => {
+            crate::error!("[polaris][context] listen SIGTERM fail: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
 }
 
-mod tests {
+#[cfg(not(unix))]
+async fn wait_shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::runtime::Runtime;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::core::engine::tests::{new_engine, new_register_request, MockConnector};
+
+    /// new_context 创建使用 MockConnector 的 SDKContext，参见 new_engine
+    pub(crate) fn new_context(connector: MockConnector) -> (Runtime, Arc<SDKContext>) {
+        let (rt, engine) = new_engine(connector);
+        let ctx = SDKContext {
+            conf: engine.get_extensions().conf.clone(),
+            engine,
+        };
+        (rt, Arc::new(ctx))
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_shutdown_on_signal() {
+        let connector = MockConnector::default();
+        let calls = connector.calls.clone();
+        let (rt, ctx) = new_context(connector);
+        rt.block_on(ctx.get_engine().register_instance(new_register_request(8080)))
+            .unwrap();
+
+        let (signal_tx, signal_rx) = oneshot::channel::<()>();
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        ctx.shutdown_on(
+            async move {
+                let _ = signal_rx.await;
+            },
+            Duration::from_secs(1),
+            move || done_tx.send(()).unwrap(),
+        );
+        // 收到信号之前不会关闭
+        assert!(done_rx.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(!ctx.is_shutdown());
+
+        signal_tx.send(()).unwrap();
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ctx.is_shutdown());
+        assert_eq!(
+            vec!["register:127.0.0.1:8080", "deregister:127.0.0.1:8080"],
+            *calls.lock().unwrap()
+        );
+    }
 }
@@ -14,7 +14,9 @@
 // specific language governing permissions and limitations under the License.
 
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use tokio::runtime::{Builder, Runtime};
 
@@ -30,14 +32,14 @@ use crate::config::req::{
     UpdateConfigFileRequest, UpsertAndPublishConfigFileRequest,
 };
 use crate::core::config::config::Configuration;
-use crate::core::model::cache::{EventType, ResourceEventKey};
-use crate::core::model::error::PolarisError;
+use crate::core::model::cache::{EventType, ResourceEventKey, ServiceInstancesCacheItem};
+use crate::core::model::error::{ErrorCode, PolarisError};
 use crate::core::model::naming::InstanceRequest;
 use crate::core::plugin::plugins::Extensions;
 use crate::discovery::req::{
-    GetAllInstanceRequest, GetServiceRuleRequest, InstanceDeregisterRequest,
+    GetAllInstanceRequest, GetServiceRuleRequest, GetServicesRequest, InstanceDeregisterRequest,
     InstanceHeartbeatRequest, InstanceRegisterRequest, InstanceRegisterResponse, InstancesResponse,
-    ReportServiceContractRequest, ServiceRuleResponse,
+    ReportServiceContractRequest, ServiceRuleResponse, ServicesResponse,
 };
 
 pub struct Engine
@@ -51,6 +53,9 @@ where
     location_provider: Arc<LocationProvider>,
     client_ctx: Arc<ClientContext>,
     client_flow: ClientFlow,
+    // registered 通过当前进程注册的实例，shutdown 时统一反注册，key 为 namespace#service#ip#port
+    registered: Mutex<HashMap<String, InstanceDeregisterRequest>>,
+    closed: AtomicBool,
 }
 
 impl Engine {
@@ -89,6 +94,8 @@ impl Engine {
             location_provider: location_provider,
             client_ctx: client_ctx,
             client_flow,
+            registered: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
         })
     }
 
@@ -97,6 +104,14 @@ impl Engine {
         &self,
         req: InstanceRegisterRequest,
     ) -> Result<InstanceRegisterResponse, PolarisError> {
+        if self.is_closed() {
+            return Err(PolarisError::new(
+                ErrorCode::InvalidState,
+                "sdk context is shutdown".to_string(),
+            ));
+        }
+        let register_key = req.to_heartbeat_request().beat_key();
+        let deregister_req = req.to_deregister_request();
         let mut instance = req.convert_instance();
 
         if instance.location.is_empty() {
@@ -118,6 +133,12 @@ impl Engine {
             })
             .await;
 
+        if rsp.is_ok() {
+            self.registered
+                .lock()
+                .unwrap()
+                .insert(register_key, deregister_req);
+        }
         match rsp {
             Ok(ins_rsp) => Ok(InstanceRegisterResponse {
                 instance_id: ins_rsp.instance.id.clone(),
@@ -132,6 +153,7 @@ impl Engine {
         &self,
         req: InstanceDeregisterRequest,
     ) -> Result<(), PolarisError> {
+        let register_key = req.to_heartbeat_request().beat_key();
         let connector = self.server_connector.clone();
         let rsp = connector
             .deregister_instance(InstanceRequest {
@@ -148,11 +170,94 @@ impl Engine {
             .await;
 
         match rsp {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                // 反注册成功之后才移除记录，失败时保留记录以便 shutdown 时再次反注册
+                self.registered.lock().unwrap().remove(&register_key);
+                Ok(())
+            }
             Err(err) => Err(err),
         }
     }
 
+    /// is_closed 是否已经执行过 shutdown
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// shutdown 反注册当前进程注册的所有实例，停止后台任务并销毁插件，timeout 为整个关闭流程的最长等待时间，
+    /// 包括反注册实例、等待后台任务退出以及将缓存数据写入容灾文件
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        crate::info!(
+            "[polaris][engine] start to shutdown, timeout: {:?}",
+            timeout
+        );
+        let deadline = tokio::time::Instant::now() + timeout;
+        let ret = tokio::time::timeout_at(deadline, self.deregister_all()).await;
+
+        self.client_flow.stop_flow(deadline).await;
+        if tokio::time::timeout_at(deadline, self.local_cache.flush())
+            .await
+            .is_err()
+        {
+            crate::warn!(
+                "[polaris][engine] flush resource cache not finished before shutdown timeout"
+            );
+        }
+        // 连接器的数据同步任务不会自行结束，这里直接取消
+        self.extensions.destroy();
+        crate::info!("[polaris][engine] shutdown finished");
+
+        match ret {
+            Ok(ret) => ret,
+            Err(_) => Err(PolarisError::new(
+                ErrorCode::ApiTimeout,
+                format!("deregister instances not finished in {:?}", timeout),
+            )),
+        }
+    }
+
+    /// deregister_all 反注册通过当前 Engine 注册且尚未反注册的所有实例，返回第一个反注册失败的错误
+    pub async fn deregister_all(&self) -> Result<(), PolarisError> {
+        // 反注册成功之后才移除记录，失败或者超时的实例仍然保留
+        let registered: Vec<(String, InstanceDeregisterRequest)> = self
+            .registered
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, req)| (key.clone(), req.clone()))
+            .collect();
+        let rets = futures::future::join_all(registered.into_iter().map(|(key, req)| {
+            let connector = self.server_connector.clone();
+            async move {
+                let ret = connector
+                    .deregister_instance(InstanceRequest {
+                        flow_id: uuid::Uuid::new_v4().to_string(),
+                        ttl: 0,
+                        instance: req.convert_instance(),
+                    })
+                    .await;
+                match &ret {
+                    Ok(_) => {
+                        self.registered.lock().unwrap().remove(&key);
+                        crate::info!("[polaris][engine] deregister instance {}", key)
+                    }
+                    Err(e) => {
+                        crate::error!("[polaris][engine] deregister instance {} fail: {}", key, e)
+                    }
+                }
+                ret
+            }
+        }))
+        .await;
+        match rets.into_iter().find(|ret| ret.is_err()) {
+            Some(Err(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     /// instance_heartbeat 同步实例心跳
     pub async fn instance_heartbeat(
         &self,
@@ -179,41 +284,88 @@ impl Engine {
         }
     }
 
-    /// get_service_instances 获取服务实例
+    /// get_service_instances 获取服务实例，如果被调服务是一个别名服务，则返回其真实服务的实例
     pub async fn get_service_instances(
         &self,
         req: GetAllInstanceRequest,
         only_available: bool,
     ) -> Result<InstancesResponse, PolarisError> {
-        let mut filter = HashMap::<String, String>::new();
-        filter.insert("service".to_string(), req.service.clone());
+        let mut svc_ins = self
+            .load_service_instances(&req.namespace, &req.service, req.timeout)
+            .await?;
+
+        // 北极星不允许为别名服务再创建别名，因此这里只需要解析一次
+        if let Some(alias_for) = svc_ins.get_alias_for() {
+            crate::debug!(
+                "[polaris][engine] service alias resolved: {}/{} -> {}/{}",
+                req.namespace,
+                req.service,
+                alias_for.namespace,
+                alias_for.name
+            );
+            svc_ins = self
+                .load_service_instances(&alias_for.namespace, &alias_for.name, req.timeout)
+                .await?;
+        }
+
+        Ok(InstancesResponse {
+            instances: ServiceInstances::new(
+                svc_ins.get_service_info(),
+                svc_ins.list_instances(only_available).await,
+            ),
+        })
+    }
 
-        let ret = self
+    /// get_services 获取命名空间下的服务列表，并按照服务元数据进行过滤
+    pub async fn get_services(
+        &self,
+        req: GetServicesRequest,
+    ) -> Result<ServicesResponse, PolarisError> {
+        let services = self
             .local_cache
-            .load_service_instances(Filter {
+            .load_services(Filter {
                 resource_key: ResourceEventKey {
                     namespace: req.namespace.clone(),
-                    event_type: EventType::Instance,
-                    filter,
+                    event_type: EventType::Service,
+                    filter: HashMap::new(),
                 },
                 internal_request: false,
                 include_cache: true,
                 timeout: req.timeout,
             })
-            .await;
-
-        if ret.is_err() {
-            return Err(ret.err().unwrap());
-        }
+            .await?;
+
+        Ok(ServicesResponse {
+            revision: services.revision,
+            services: services
+                .service_list
+                .into_iter()
+                .filter(|svc| svc.match_metadata(&req.metadata))
+                .collect(),
+        })
+    }
 
-        let svc_ins = ret.unwrap();
+    async fn load_service_instances(
+        &self,
+        namespace: &str,
+        service: &str,
+        timeout: Duration,
+    ) -> Result<ServiceInstancesCacheItem, PolarisError> {
+        let mut filter = HashMap::<String, String>::new();
+        filter.insert("service".to_string(), service.to_string());
 
-        Ok(InstancesResponse {
-            instances: ServiceInstances::new(
-                svc_ins.get_service_info(),
-                svc_ins.list_instances(only_available).await,
-            ),
-        })
+        self.local_cache
+            .load_service_instances(Filter {
+                resource_key: ResourceEventKey {
+                    namespace: namespace.to_string(),
+                    event_type: EventType::Instance,
+                    filter,
+                },
+                internal_request: false,
+                include_cache: true,
+                timeout,
+            })
+            .await
     }
 
     /// report_service_contract 上报服务契约数据
@@ -261,8 +413,10 @@ impl Engine {
             return Err(ret.err().unwrap());
         }
 
+        let rule = ret.unwrap();
         Ok(ServiceRuleResponse {
-            rules: ret.unwrap().rules,
+            rules: rule.rules,
+            revision: rule.revision,
         })
     }
 
@@ -399,3 +553,411 @@ impl Engine {
         self.extensions.clone()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::core::model::cache::RemoteData;
+    use crate::core::model::config::{
+        ConfigFileRequest, ConfigPublishRequest, ConfigReleaseRequest,
+    };
+    use crate::core::model::naming::{InstanceResponse, ServiceContract};
+    use crate::core::model::ReportClientRequest;
+    use crate::core::plugin::cache::InitResourceCacheOption;
+    use crate::core::plugin::connector::ResourceHandler;
+    use crate::core::plugin::plugins::Plugin;
+    use crate::plugins::cache::memory::memory::MemoryCache;
+
+    // 单元测试使用的最小配置，服务端地址不可达，实例相关的请求由 MockConnector 处理
+    pub(crate) const TEST_CONFIG: &str = r#"
+global:
+  api:
+    timeout: 1s
+    reportInterval: 10m
+    maxRetryTimes: 1
+    retryInterval: 500ms
+  serverConnectors:
+    addresses:
+      - 127.0.0.1:18091
+    protocol: grpc
+    connectTimeout: 500ms
+    messageTimeout: 1s
+    connectionIdleTimeout: 60s
+    serverSwitchInterval: 10m
+    reconnectInterval: 500ms
+  statReporter:
+    enable: false
+  location:
+    providers:
+      - name: local
+        options: {}
+  client:
+    id: ""
+    labels: {}
+consumer:
+  serviceRouter:
+    beforeChain: []
+    coreChain: []
+    afterChain: []
+  loadBalancer:
+    defaultPolicy: weightedRandom
+  circuitBreaker:
+    enable: false
+    enableRemotePull: false
+  localCache:
+    name: memory
+    serviceExpireEnable: true
+    serviceExpireTime: 24h
+    serviceRefreshInterval: 2s
+    serviceListRefreshInterval: 60s
+    persistEnable: false
+    persistDir: ./polaris/backup
+provider:
+  rateLimit:
+    enable: false
+    namespace: Polaris
+    service: polaris.limiter
+    maxWindowCount: 10000000
+    fallbackOnExceedWindowCount: pass
+    remoteSyncTimeout: 200ms
+    maxQueuingTime: 1s
+    reportMetrics: false
+  lossless:
+    enable: false
+    host: 0.0.0.0
+    port: 28080
+    delayRegisterInterval: 30s
+    healthCheckInterval: 5s
+config:
+  propertiesValueCacheSize: 100
+  propertiesValueExpireTime: 60000
+  configFilter:
+    enable: false
+    chain: []
+    plugin: {}
+"#;
+
+    /// MockConnector 记录实例的注册、反注册以及心跳请求，其余请求直接返回成功
+    #[derive(Default)]
+    pub(crate) struct MockConnector {
+        // calls 按照请求顺序记录，格式为 register|deregister|heartbeat:ip:port
+        pub(crate) calls: Arc<Mutex<Vec<String>>>,
+        // deregister_delay 反注册请求的耗时，用于模拟 shutdown 超时
+        pub(crate) deregister_delay: Duration,
+        pub(crate) heartbeats: Arc<AtomicUsize>,
+        // handlers 资源缓存订阅的资源，通过 push_remote_data 模拟服务端推送
+        pub(crate) handlers: Arc<Mutex<Vec<Box<dyn ResourceHandler>>>>,
+        // contracts 上报服务契约的次数
+        pub(crate) contracts: Arc<AtomicUsize>,
+        // fail_ports 这些端口的实例注册、反注册以及心跳请求返回失败
+        pub(crate) fail_ports: Vec<u32>,
+        // slow_ports 这些端口的实例请求额外耗时 300ms，用于打乱请求完成的顺序
+        pub(crate) slow_ports: Vec<u32>,
+        // missing_ports 这些端口的实例心跳返回实例不存在
+        pub(crate) missing_ports: Vec<u32>,
+    }
+
+    impl MockConnector {
+        async fn record(&self, action: &str, req: &InstanceRequest) -> Result<(), PolarisError> {
+            let port = req.instance.port;
+            if self.slow_ports.contains(&port) {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}:{}", action, req.instance.ip, port));
+            if self.fail_ports.contains(&port) {
+                return Err(PolarisError::new(
+                    ErrorCode::ServerError,
+                    format!("mock {} fail: {}", action, port),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    impl Plugin for MockConnector {
+        fn init(&mut self) {}
+
+        fn destroy(&self) {}
+
+        fn name(&self) -> String {
+            "mock".to_string()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Connector for MockConnector {
+        async fn register_resource_handler(
+            &self,
+            handler: Box<dyn ResourceHandler>,
+        ) -> Result<bool, PolarisError> {
+            self.handlers.lock().unwrap().push(handler);
+            Ok(true)
+        }
+
+        async fn register_instance(
+            &self,
+            req: InstanceRequest,
+        ) -> Result<InstanceResponse, PolarisError> {
+            self.record("register", &req).await?;
+            Ok(InstanceResponse::success(format!(
+                "{}:{}",
+                req.instance.ip, req.instance.port
+            )))
+        }
+
+        async fn deregister_instance(&self, req: InstanceRequest) -> Result<bool, PolarisError> {
+            tokio::time::sleep(self.deregister_delay).await;
+            self.record("deregister", &req).await?;
+            Ok(true)
+        }
+
+        async fn heartbeat_instance(&self, req: InstanceRequest) -> Result<bool, PolarisError> {
+            self.heartbeats.fetch_add(1, Ordering::SeqCst);
+            self.record("heartbeat", &req).await?;
+            if self.missing_ports.contains(&req.instance.port) {
+                return Err(PolarisError::new(
+                    ErrorCode::InstanceNotFound,
+                    format!("mock instance not found: {}", req.instance.port),
+                ));
+            }
+            Ok(true)
+        }
+
+        async fn report_client(&self, _req: ReportClientRequest) -> Result<bool, PolarisError> {
+            Ok(true)
+        }
+
+        async fn report_service_contract(
+            &self,
+            _req: ServiceContractRequest,
+        ) -> Result<bool, PolarisError> {
+            self.contracts.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+
+        async fn get_service_contract(
+            &self,
+            _req: ServiceContractRequest,
+        ) -> Result<ServiceContract, PolarisError> {
+            Err(PolarisError::new(
+                ErrorCode::NotSupport,
+                "mock connector not support get_service_contract".to_string(),
+            ))
+        }
+
+        async fn create_config_file(&self, _req: ConfigFileRequest) -> Result<bool, PolarisError> {
+            Ok(true)
+        }
+
+        async fn update_config_file(&self, _req: ConfigFileRequest) -> Result<bool, PolarisError> {
+            Ok(true)
+        }
+
+        async fn release_config_file(
+            &self,
+            _req: ConfigReleaseRequest,
+        ) -> Result<bool, PolarisError> {
+            Ok(true)
+        }
+
+        async fn upsert_publish_config_file(
+            &self,
+            _req: ConfigPublishRequest,
+        ) -> Result<bool, PolarisError> {
+            Ok(true)
+        }
+    }
+
+    /// new_engine 创建使用 MockConnector 的 Engine，Engine 内部的 grpc 插件需要在 tokio 运行时中创建，
+    /// 因此同时返回创建时使用的运行时，测试结束时需要在运行时之外释放 Engine
+    pub(crate) fn new_engine(connector: MockConnector) -> (Runtime, Arc<Engine>) {
+        let rt = Runtime::new().unwrap();
+        let conf: Configuration = serde_yaml::from_str(TEST_CONFIG).unwrap();
+        let engine = {
+            let _guard = rt.enter();
+            Engine::new(Arc::new(conf)).unwrap()
+        };
+        let server_connector: Arc<Box<dyn Connector>> = Arc::new(Box::new(connector));
+        // 资源缓存同样通过 MockConnector 订阅资源
+        let local_cache = (MemoryCache::builder().0)(InitResourceCacheOption {
+            conf: engine.extensions.conf.consumer.local_cache.clone(),
+            runtime: engine.runtime.clone(),
+            server_connector: server_connector.clone(),
+        });
+        let engine = Engine {
+            server_connector,
+            local_cache: Arc::new(local_cache),
+            ..engine
+        };
+        (rt, Arc::new(engine))
+    }
+
+    /// push_remote_data 等待资源缓存订阅 data 对应的资源后，模拟一次服务端推送
+    pub(crate) fn push_remote_data(
+        handlers: &Mutex<Vec<Box<dyn ResourceHandler>>>,
+        data: RemoteData,
+    ) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            {
+                let handlers = handlers.lock().unwrap();
+                let handler = handlers.iter().find(|handler| {
+                    let key = handler.interest_resource();
+                    key.event_type == data.event_key.event_type
+                        && key.namespace == data.event_key.namespace
+                        && key.filter == data.event_key.filter
+                });
+                if let Some(handler) = handler {
+                    handler.handle_event(data);
+                    return;
+                }
+            }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "resource {:?} not watched",
+                data.event_key
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    pub(crate) fn new_register_request(port: u32) -> InstanceRegisterRequest {
+        InstanceRegisterRequest {
+            flow_id: String::new(),
+            timeout: Duration::from_secs(1),
+            id: None,
+            namespace: "default".to_string(),
+            service: "polaris-rust".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port,
+            vpc_id: String::new(),
+            version: String::new(),
+            protocol: String::new(),
+            health: true,
+            isolated: false,
+            weight: 100,
+            priority: 0,
+            metadata: HashMap::new(),
+            location: Default::default(),
+            ttl: 5,
+            auto_heartbeat: false,
+            health_check: None,
+        }
+    }
+
+    /// wait_beats 等待心跳次数超过 count，超时返回 false
+    pub(crate) fn wait_beats(heartbeats: &AtomicUsize, count: usize) -> bool {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::time::Instant::now() < deadline {
+            if heartbeats.load(Ordering::SeqCst) > count {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+
+    #[test]
+    fn test_shutdown_deregister() {
+        let connector = MockConnector::default();
+        let calls = connector.calls.clone();
+        let (rt, engine) = new_engine(connector);
+
+        rt.block_on(async {
+            engine
+                .register_instance(new_register_request(8080))
+                .await
+                .unwrap();
+            engine
+                .register_instance(new_register_request(8081))
+                .await
+                .unwrap();
+            engine
+                .deregister_instance(new_register_request(8081).to_deregister_request())
+                .await
+                .unwrap();
+
+            engine.shutdown(Duration::from_secs(1)).await.unwrap();
+            // 重复调用 shutdown 直接返回，已经反注册的实例不会再次反注册
+            engine.shutdown(Duration::from_secs(1)).await.unwrap();
+            let ret = engine.register_instance(new_register_request(8082)).await;
+            assert_eq!(ErrorCode::InvalidState, ret.err().unwrap().get_code());
+        });
+
+        assert!(engine.is_closed());
+        assert_eq!(
+            vec![
+                "register:127.0.0.1:8080",
+                "register:127.0.0.1:8081",
+                "deregister:127.0.0.1:8081",
+                "deregister:127.0.0.1:8080",
+            ],
+            *calls.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deregister_fail_keep_registered() {
+        let connector = MockConnector {
+            fail_ports: vec![8081],
+            ..Default::default()
+        };
+        let (rt, engine) = new_engine(connector);
+        let registered_keys = || {
+            let mut keys: Vec<String> = engine.registered.lock().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+
+        rt.block_on(async {
+            engine
+                .register_instance(new_register_request(8080))
+                .await
+                .unwrap();
+            // 8081 的注册请求同样会失败，直接写入注册记录模拟反注册失败的实例
+            let deregister_req = new_register_request(8081).to_deregister_request();
+            engine.registered.lock().unwrap().insert(
+                deregister_req.to_heartbeat_request().beat_key(),
+                deregister_req.clone(),
+            );
+
+            assert!(engine.deregister_instance(deregister_req).await.is_err());
+            assert_eq!(2, registered_keys().len());
+
+            assert!(engine.deregister_all().await.is_err());
+            assert_eq!(
+                vec![new_register_request(8081)
+                    .to_deregister_request()
+                    .to_heartbeat_request()
+                    .beat_key()],
+                registered_keys()
+            );
+        });
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let connector = MockConnector {
+            deregister_delay: Duration::from_secs(3),
+            ..Default::default()
+        };
+        let (rt, engine) = new_engine(connector);
+
+        let start = std::time::Instant::now();
+        let ret = rt.block_on(async {
+            engine
+                .register_instance(new_register_request(8080))
+                .await
+                .unwrap();
+            engine.shutdown(Duration::from_millis(200)).await
+        });
+        assert_eq!(ErrorCode::ApiTimeout, ret.err().unwrap().get_code());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(engine.is_closed());
+    }
+}
@@ -17,21 +17,26 @@ use std::{
     collections::HashMap,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     time::Duration,
 };
 
-use tokio::{sync::RwLock, task::JoinHandle, time::sleep};
+use tokio::{
+    sync::{Notify, RwLock},
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
 
-use crate::core::plugin::router::ServiceRouter;
+use crate::discovery::req::ServiceCallResult;
 
 use super::{
     model::{
         circuitbreaker::{CheckResult, CircuitBreakerStatus, Resource, ResourceStat, Status},
         error::PolarisError,
-        naming::ServiceInstances,
-        router::RouteInfo,
+        loadbalance::{Criteria, InstanceWeight},
+        naming::{Instance, ServiceInstances},
+        router::{RouteInfo, RouterChain, RouterTraceItem},
         ClientContext, ReportClientRequest,
     },
     plugin::{
@@ -47,9 +52,11 @@ where
     client: Arc<ClientContext>,
     extensions: Arc<Extensions>,
 
-    futures: Vec<JoinHandle<()>>,
+    futures: Mutex<Vec<JoinHandle<()>>>,
 
     closed: Arc<AtomicBool>,
+    // close_notify 唤醒等待中的后台任务，使其尽快退出
+    close_notify: Arc<Notify>,
 }
 
 impl ClientFlow {
@@ -57,8 +64,9 @@ impl ClientFlow {
         ClientFlow {
             client,
             extensions: extensions,
-            futures: vec![],
+            futures: Mutex::new(vec![]),
             closed: Arc::new(AtomicBool::new(false)),
+            close_notify: Arc::new(Notify::new()),
         }
     }
 
@@ -66,18 +74,22 @@ impl ClientFlow {
         let client = self.client.clone();
         let extensions = self.extensions.clone();
         let is_closed = self.closed.clone();
+        let close_notify = self.close_notify.clone();
 
         let f: JoinHandle<()> = self.extensions.runtime.spawn(async move {
             loop {
                 ClientFlow::report_client(client.clone(), extensions.clone()).await;
-                sleep(Duration::from_secs(60)).await;
+                tokio::select! {
+                    _ = sleep(Duration::from_secs(60)) => {}
+                    _ = close_notify.notified() => {}
+                }
                 if is_closed.load(Ordering::Relaxed) {
                     return;
                 }
             }
         });
 
-        self.futures.push(f);
+        self.futures.lock().unwrap().push(f);
     }
 
     /// report_client 上报客户端信息数据
@@ -97,8 +109,16 @@ impl ClientFlow {
         }
     }
 
-    pub fn stop_flow(&mut self) {
+    /// stop_flow 停止后台任务并等待其退出，超过 deadline 仍未退出的任务直接取消
+    pub async fn stop_flow(&self, deadline: Instant) {
         self.closed.store(true, Ordering::SeqCst);
+        self.close_notify.notify_one();
+        let futures: Vec<JoinHandle<()>> = self.futures.lock().unwrap().drain(..).collect();
+        for mut f in futures {
+            if tokio::time::timeout_at(deadline, &mut f).await.is_err() {
+                f.abort();
+            }
+        }
     }
 }
 
@@ -164,70 +184,87 @@ impl RouterFlow {
         lb.get(name).cloned()
     }
 
-    pub async fn choose_instances(
+    /// choose_instance 使用负载均衡器选择实例，负载均衡器支持动态权重时按照实例的有效权重选择
+    pub fn choose_instance(
         &self,
-        route_info: RouteInfo,
+        lb: &Arc<Box<dyn LoadBalancer>>,
+        criteria: Criteria,
         instances: ServiceInstances,
-    ) -> Result<ServiceInstances, PolarisError> {
-        let router_container = self.extensions.get_router_container();
-
-        let route_ctx = RouteContext {
-            route_info,
-            extensions: Some(self.extensions.clone()),
-        };
+    ) -> Result<Instance, PolarisError> {
+        let dynamic_weight = self.extensions.get_dynamic_weight();
+        if !lb.use_dynamic_weight() || !dynamic_weight.enable() {
+            return lb.choose_instance(criteria, instances);
+        }
 
-        let mut routers = Vec::<Arc<Box<dyn ServiceRouter>>>::new();
-        let chain = &route_ctx.route_info.chain;
+        let mut instance = lb.choose_instance(criteria, dynamic_weight.apply(&instances))?;
+        restore_static_weight(&instances, &mut instance);
+        Ok(instance)
+    }
 
-        // 处理前置路由
-        chain.before.iter().for_each(|name| {
-            if let Some(router) = router_container.before_routers.get(name) {
-                routers.push(router.clone());
-            }
-        });
+    /// choose_candidates 使用负载均衡器按照顺序选择至多 count 个不同的实例，第一个为首选实例
+    pub fn choose_candidates(
+        &self,
+        lb: &Arc<Box<dyn LoadBalancer>>,
+        criteria: Criteria,
+        instances: ServiceInstances,
+        count: usize,
+    ) -> Result<Vec<Instance>, PolarisError> {
+        let dynamic_weight = self.extensions.get_dynamic_weight();
+        if !lb.use_dynamic_weight() || !dynamic_weight.enable() {
+            return lb.choose_candidates(criteria, instances, count);
+        }
 
-        let mut tmp_instance = instances;
-        for (_, ele) in routers.iter().enumerate() {
-            let ret = ele.choose_instances(route_ctx.clone(), tmp_instance).await;
-            if let Err(e) = ret {
-                return Err(e);
-            }
-            tmp_instance = ret.unwrap().instances;
+        let mut candidates =
+            lb.choose_candidates(criteria, dynamic_weight.apply(&instances), count)?;
+        for instance in candidates.iter_mut() {
+            restore_static_weight(&instances, instance);
         }
-        routers.clear();
+        Ok(candidates)
+    }
 
-        // 处理核心路由
-        chain.core.iter().for_each(|name| {
-            if let Some(router) = router_container.core_routers.get(name) {
-                routers.push(router.clone());
-            }
-        });
+    /// get_instance_weights 获取实例的动态权重计算结果
+    pub fn get_instance_weights(&self, instances: &ServiceInstances) -> Vec<InstanceWeight> {
+        self.extensions.get_dynamic_weight().compute_weights(instances)
+    }
 
-        for (_, ele) in routers.iter().enumerate() {
-            let ret = ele.choose_instances(route_ctx.clone(), tmp_instance).await;
-            if let Err(e) = ret {
-                return Err(e);
-            }
-            tmp_instance = ret.unwrap().instances;
+    /// report_call_result 将实例调用结果上报给动态权重以及所有的负载均衡器
+    pub async fn report_call_result(&self, result: &ServiceCallResult) {
+        self.extensions.get_dynamic_weight().report_call_result(result);
+        let lbs = self.load_balancer.read().await;
+        for lb in lbs.values() {
+            lb.report_call_result(result);
         }
-        routers.clear();
+    }
 
-        // 处理后置路由
-        chain.after.iter().for_each(|name| {
-            if let Some(router) = router_container.before_routers.get(name) {
-                routers.push(router.clone());
-            }
-        });
+    /// choose_instances 按照 before -> core -> after 的顺序执行路由链，trace 为 true 时记录每个路由插件的执行情况
+    ///
+    /// 路由链的选择规则见 RouterContainer::resolve_chain
+    pub async fn choose_instances(
+        &self,
+        mut route_info: RouteInfo,
+        instances: ServiceInstances,
+        chain_override: Option<RouterChain>,
+        trace: bool,
+    ) -> Result<(ServiceInstances, Vec<RouterTraceItem>), PolarisError> {
+        let router_container = self.extensions.get_router_container();
 
-        for (_, ele) in routers.iter().enumerate() {
-            let ret = ele.choose_instances(route_ctx.clone(), tmp_instance).await;
-            if let Err(e) = ret {
-                return Err(e);
-            }
-            tmp_instance = ret.unwrap().instances;
-        }
+        route_info.chain = router_container.resolve_chain(&route_info, chain_override)?;
+
+        let route_ctx = RouteContext {
+            route_info,
+            extensions: Some(self.extensions.clone()),
+        };
 
-        Ok(tmp_instance)
+        router_container
+            .execute_chain(&route_ctx, instances, trace)
+            .await
+    }
+}
+
+// restore_static_weight 返回给调用方的实例保持注册时的静态权重
+fn restore_static_weight(instances: &ServiceInstances, instance: &mut Instance) {
+    if let Some(origin) = instances.instances.iter().find(|ins| ins.id == instance.id) {
+        instance.weight = origin.weight;
     }
 }
 
@@ -242,4 +279,4 @@ impl RatelimitFlow {
             extensions,
         }
     }
-}
\ No newline at end of file
+}
@@ -15,7 +15,7 @@
 
 pub mod config;
 pub mod context;
-mod engine;
+pub(crate) mod engine;
 pub mod flow;
 pub mod model;
 pub mod plugin;
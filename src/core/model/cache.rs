@@ -14,6 +14,7 @@
 // specific language governing permissions and limitations under the License.
 
 use std::{
+    any::Any,
     collections::HashMap,
     fmt::Display,
     sync::{atomic::AtomicBool, Arc},
@@ -33,7 +34,7 @@ use polaris_specification::v1::{
 
 use super::{
     config::{ConfigFile, ConfigGroup},
-    naming::{Instance, ServiceInfo},
+    naming::{Instance, ServiceInfo, ServiceKey, ServiceRule},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -160,6 +161,13 @@ impl CacheItemType {
         }
     }
 
+    pub fn to_services(&self) -> Option<ServicesCacheItem> {
+        match self {
+            CacheItemType::Service(item) => Some(item.clone()),
+            _ => None,
+        }
+    }
+
     pub fn to_config_file(&self) -> Option<ConfigFileCacheItem> {
         match self {
             CacheItemType::ConfigFile(item) => Some(item.clone()),
@@ -173,6 +181,40 @@ impl CacheItemType {
             _ => None,
         }
     }
+
+    /// to_service_rule 将规则类的缓存数据转换为 ServiceRule，非规则类数据返回 None
+    pub async fn to_service_rule(&self) -> Option<ServiceRule> {
+        let (rules, revision, initialized) = match self {
+            CacheItemType::RouterRule(item) => {
+                let mut rules = vec![];
+                for val in item.value.read().await.iter() {
+                    rules.push(Box::new(val.clone()) as Box<dyn Any + Send>);
+                }
+                (rules, item.revision(), item.is_initialized())
+            }
+            CacheItemType::CircuitBreakerRule(item) => (
+                vec![Box::new(item.value.clone()) as Box<dyn Any + Send>],
+                item.revision(),
+                item.is_initialized(),
+            ),
+            CacheItemType::RateLimitRule(item) => (
+                vec![Box::new(item.value.clone()) as Box<dyn Any + Send>],
+                item.revision(),
+                item.is_initialized(),
+            ),
+            CacheItemType::FaultDetectRule(item) => (
+                vec![Box::new(item.value.clone()) as Box<dyn Any + Send>],
+                item.revision(),
+                item.is_initialized(),
+            ),
+            _ => return None,
+        };
+        Some(ServiceRule {
+            rules,
+            revision,
+            initialized,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -262,7 +304,8 @@ impl ResourceEventKey {
     }
 
     pub fn to_spec_service(&self, revision: String) -> Service {
-        let svc = self.filter.get("service").unwrap().to_string();
+        // 服务列表的订阅只有命名空间维度
+        let svc = self.filter.get("service").cloned().unwrap_or_default();
         Service {
             namespace: Some(self.namespace.clone()),
             name: Some(svc),
@@ -311,6 +354,7 @@ impl ToString for ResourceEventKey {
                 let service = self.filter.get("group");
                 key.push_str(service.unwrap().as_str());
             }
+            EventType::Service => {}
             _ => {
                 let service = self.filter.get("service");
                 key.push_str(service.unwrap().as_str());
@@ -368,6 +412,15 @@ impl ServicesCacheItem {
             revision: String::new(),
         }
     }
+
+    pub fn finish_initialize(&self) {
+        let _ = self.initialized.compare_exchange(
+            false,
+            true,
+            std::sync::atomic::Ordering::SeqCst,
+            std::sync::atomic::Ordering::SeqCst,
+        );
+    }
 }
 
 impl Clone for ServicesCacheItem {
@@ -400,7 +453,7 @@ impl RegistryCacheValue for ServicesCacheItem {
     }
 
     fn revision(&self) -> String {
-        todo!()
+        self.revision.clone()
     }
 }
 
@@ -408,6 +461,8 @@ impl RegistryCacheValue for ServicesCacheItem {
 pub struct ServiceInstancesCacheItem {
     initialized: Arc<AtomicBool>,
     pub svc_info: Service,
+    // alias_for: 如果当前服务是一个别名服务，则指向其真实的服务
+    pub alias_for: Option<ServiceKey>,
     pub value: Arc<RwLock<Vec<Instance>>>,
     pub available_instances: Arc<RwLock<Vec<Instance>>>,
     pub total_weight: u64,
@@ -434,6 +489,7 @@ impl ServiceInstancesCacheItem {
         Self {
             initialized: Arc::new(AtomicBool::new(false)),
             svc_info: Service::default(),
+            alias_for: None,
             value: Arc::new(RwLock::new(Vec::new())),
             available_instances: Arc::new(RwLock::new(Vec::new())),
             total_weight: 0,
@@ -463,6 +519,21 @@ impl ServiceInstancesCacheItem {
             revision: revision.unwrap_or_default().clone(),
         }
     }
+
+    /// get_alias_for 获取别名服务指向的真实服务，非别名服务返回 None
+    pub fn get_alias_for(&self) -> Option<ServiceKey> {
+        let alias_for = self.alias_for.as_ref()?;
+        if alias_for.namespace.is_empty() || alias_for.name.is_empty() {
+            return None;
+        }
+        let svc_info = &self.svc_info;
+        if svc_info.namespace.as_deref() == Some(alias_for.namespace.as_str())
+            && svc_info.name.as_deref() == Some(alias_for.name.as_str())
+        {
+            return None;
+        }
+        Some(alias_for.clone())
+    }
 }
 
 impl Clone for ServiceInstancesCacheItem {
@@ -470,6 +541,7 @@ impl Clone for ServiceInstancesCacheItem {
         Self {
             initialized: self.initialized.clone(),
             svc_info: self.svc_info.clone(),
+            alias_for: self.alias_for.clone(),
             value: self.value.clone(),
             available_instances: self.available_instances.clone(),
             total_weight: self.total_weight,
@@ -969,3 +1041,40 @@ impl RegistryCacheValue for ConfigFileCacheItem {
         self.revision.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_service(namespace: &str, name: &str) -> Service {
+        Service {
+            namespace: Some(namespace.to_string()),
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_alias_for() {
+        let mut item = ServiceInstancesCacheItem::new();
+        item.svc_info = new_service("default", "echo-alias");
+        assert!(item.get_alias_for().is_none());
+
+        item.alias_for = Some(ServiceKey {
+            namespace: "default".to_string(),
+            name: "echo".to_string(),
+        });
+        let alias_for = item.get_alias_for().unwrap();
+        assert_eq!("default", alias_for.namespace);
+        assert_eq!("echo", alias_for.name);
+
+        // 服务端对非别名服务可能返回指向自身或者空的 alias_for
+        item.svc_info = new_service("default", "echo");
+        assert!(item.get_alias_for().is_none());
+        item.alias_for = Some(ServiceKey {
+            namespace: String::new(),
+            name: String::new(),
+        });
+        assert!(item.get_alias_for().is_none());
+    }
+}
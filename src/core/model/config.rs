@@ -173,6 +173,34 @@ pub struct ConfigGroupChangeEvent {
     pub config_group: ConfigGroup,
 }
 
+/// ConfigKeyChangeType 配置项变更类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigKeyChangeType {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// ConfigKeyChange 单个配置项的变更，key 为 a.b.c 形式的完整路径
+#[derive(Clone, Debug)]
+pub struct ConfigKeyChange {
+    pub key: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub change_type: ConfigKeyChangeType,
+}
+
+/// ConfigKeyChangeEvent 配置文件重新发布后发生变更的配置项
+#[derive(Clone, Debug)]
+pub struct ConfigKeyChangeEvent {
+    pub namespace: String,
+    pub group: String,
+    pub file: String,
+    // version 变更后的配置文件版本
+    pub version: u64,
+    pub changes: Vec<ConfigKeyChange>,
+}
+
 pub fn get_encrypt_data_key(file: &polaris_specification::v1::ClientConfigFileInfo) -> String {
     for (_k, v) in file.tags.iter().enumerate() {
         let label_key = v.key.clone().unwrap();
@@ -16,7 +16,7 @@
 use std::fmt;
 use std::fmt::Display;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ErrorCode {
     Success = 0,
     ApiInvalidArgument = 1001,
@@ -89,4 +89,12 @@ impl PolarisError {
             err_code: code,
         }
     }
+
+    pub fn get_code(&self) -> ErrorCode {
+        self.err_code.clone()
+    }
+
+    pub fn get_err_msg(&self) -> &str {
+        &self.err_msg
+    }
 }
@@ -0,0 +1,296 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::ArgumentType;
+
+/// TrafficLabelProvider 流量标签提供者，治理规则匹配请求标签时调用，key 为规则中配置的标签名
+pub trait TrafficLabelProvider: Send + Sync {
+    fn get_label(&self, arg_type: ArgumentType, key: &str) -> Option<String>;
+}
+
+impl<F> TrafficLabelProvider for F
+where
+    F: Fn(ArgumentType, &str) -> Option<String> + Send + Sync,
+{
+    fn get_label(&self, arg_type: ArgumentType, key: &str) -> Option<String> {
+        self(arg_type, key)
+    }
+}
+
+/// ExternalParameterSupplier 外部参数提供者，规则中的参数来源为外部数据源时调用
+pub trait ExternalParameterSupplier: Send + Sync {
+    fn get_parameter(&self, key: &str) -> Option<String>;
+}
+
+impl<F> ExternalParameterSupplier for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn get_parameter(&self, key: &str) -> Option<String> {
+        self(key)
+    }
+}
+
+pub(crate) fn default_traffic_label_provider() -> Arc<dyn TrafficLabelProvider> {
+    Arc::new(|_: ArgumentType, _: &str| -> Option<String> { None })
+}
+
+pub(crate) fn default_external_parameter_supplier() -> Arc<dyn ExternalParameterSupplier> {
+    Arc::new(|key: &str| -> Option<String> { std::env::var(key).ok() })
+}
+
+/// RequestLabels 从一次请求中提取出的流量标签快照，可以直接作为 TrafficLabelProvider 使用
+#[derive(Clone, Debug, Default)]
+pub struct RequestLabels {
+    pub method: String,
+    pub path: String,
+    pub caller_ip: String,
+    // headers key 统一为小写
+    pub headers: HashMap<String, String>,
+    pub queries: HashMap<String, String>,
+    pub cookies: HashMap<String, String>,
+    pub custom: HashMap<String, String>,
+}
+
+impl RequestLabels {
+    /// from_parts 从请求的 method、path、query、headers 中提取流量标签，caller_ip 由调用方传入，可以是连接的远端地址或者 X-Forwarded-For 中的地址
+    pub fn from_parts<'a, I>(
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: I,
+        caller_ip: &str,
+    ) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a [u8])>,
+    {
+        let mut labels = Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            caller_ip: caller_ip.to_string(),
+            ..Default::default()
+        };
+        labels.parse_query(query);
+        for (name, value) in headers {
+            if let Ok(value) = std::str::from_utf8(value) {
+                labels.add_header(name, value);
+            }
+        }
+        labels
+    }
+
+    /// from_http_request 从 http::Request 中提取流量标签
+    pub fn from_http_request<B>(req: &http::Request<B>, caller_ip: &str) -> Self {
+        Self::from_parts(
+            req.method().as_str(),
+            req.uri().path(),
+            req.uri().query(),
+            req.headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+            caller_ip,
+        )
+    }
+
+    /// from_hyper_request 从 hyper::Request 中提取流量标签
+    pub fn from_hyper_request<B>(req: &hyper::Request<B>, caller_ip: &str) -> Self {
+        Self::from_parts(
+            req.method().as_str(),
+            req.uri().path(),
+            req.uri().query(),
+            req.headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+            caller_ip,
+        )
+    }
+
+    /// from_tonic_request 从 tonic::Request 的 metadata 中提取流量标签，method 为 gRPC 的方法全路径
+    pub fn from_tonic_request<T>(req: &tonic::Request<T>, method: &str) -> Self {
+        let mut labels = Self {
+            method: method.to_string(),
+            path: method.to_string(),
+            ..Default::default()
+        };
+        for entry in req.metadata().iter() {
+            if let tonic::metadata::KeyAndValueRef::Ascii(name, value) = entry {
+                if let Ok(value) = value.to_str() {
+                    labels.add_header(name.as_str(), value);
+                }
+            }
+        }
+        if let Some(addr) = req.remote_addr() {
+            labels.set_caller_addr(addr);
+        }
+        labels
+    }
+
+    /// with_caller_ip 设置主调方 IP
+    pub fn with_caller_ip(mut self, caller_ip: String) -> Self {
+        self.caller_ip = caller_ip;
+        self
+    }
+
+    /// with_custom 设置自定义标签
+    pub fn with_custom(mut self, key: String, value: String) -> Self {
+        self.custom.insert(key, value);
+        self
+    }
+
+    /// into_provider 转换为路由、限流请求使用的 TrafficLabelProvider
+    pub fn into_provider(self) -> Arc<dyn TrafficLabelProvider> {
+        Arc::new(self)
+    }
+
+    fn set_caller_addr(&mut self, addr: SocketAddr) {
+        self.caller_ip = addr.ip().to_string();
+    }
+
+    fn add_header(&mut self, name: &str, value: &str) {
+        let name = name.to_lowercase();
+        if name == "cookie" {
+            for pair in value.split(';') {
+                if let Some((k, v)) = pair.trim().split_once('=') {
+                    self.cookies.insert(k.to_string(), v.to_string());
+                }
+            }
+        }
+        // 同名 header 出现多次时，按照 HTTP 规范使用逗号拼接
+        self.headers
+            .entry(name)
+            .and_modify(|v| {
+                v.push(',');
+                v.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    fn parse_query(&mut self, query: Option<&str>) {
+        if let Some(query) = query {
+            for (k, v) in form_urlencoded::parse(query.as_bytes()) {
+                self.queries.entry(k.into_owned()).or_insert(v.into_owned());
+            }
+        }
+    }
+}
+
+impl TrafficLabelProvider for RequestLabels {
+    fn get_label(&self, arg_type: ArgumentType, key: &str) -> Option<String> {
+        match arg_type {
+            ArgumentType::Method => Some(self.method.clone()),
+            ArgumentType::Path => Some(self.path.clone()),
+            ArgumentType::CallerIP => Some(self.caller_ip.clone()),
+            ArgumentType::Header => self.headers.get(&key.to_lowercase()).cloned(),
+            ArgumentType::Query => self.queries.get(key).cloned(),
+            ArgumentType::Cookie => self.cookies.get(key).cloned(),
+            ArgumentType::Custom => self.custom.get(key).cloned(),
+            ArgumentType::CallerService => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_http_request() {
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("http://127.0.0.1/echo?user=polaris&env=test%20a")
+            .header("X-Lane", "gray")
+            .header("Cookie", "uid=123; region=gz")
+            .body(())
+            .unwrap();
+
+        let labels = RequestLabels::from_http_request(&req, "10.0.0.1");
+        assert_eq!(
+            Some("POST".to_string()),
+            labels.get_label(ArgumentType::Method, "")
+        );
+        assert_eq!(
+            Some("/echo".to_string()),
+            labels.get_label(ArgumentType::Path, "")
+        );
+        assert_eq!(
+            Some("gray".to_string()),
+            labels.get_label(ArgumentType::Header, "x-lane")
+        );
+        assert_eq!(
+            Some("test a".to_string()),
+            labels.get_label(ArgumentType::Query, "env")
+        );
+        assert_eq!(
+            Some("gz".to_string()),
+            labels.get_label(ArgumentType::Cookie, "region")
+        );
+        assert_eq!(
+            Some("10.0.0.1".to_string()),
+            labels.get_label(ArgumentType::CallerIP, "")
+        );
+        assert_eq!(None, labels.get_label(ArgumentType::Custom, "none"));
+    }
+
+    #[test]
+    fn test_from_hyper_request() {
+        let req = hyper::Request::builder()
+            .method("GET")
+            .uri("/echo?user=polaris")
+            .header("X-Lane", "gray")
+            .header("X-Lane", "base")
+            .body(())
+            .unwrap();
+
+        let labels = RequestLabels::from_hyper_request(&req, "10.0.0.2");
+        assert_eq!(
+            Some("/echo".to_string()),
+            labels.get_label(ArgumentType::Path, "")
+        );
+        assert_eq!(
+            Some("polaris".to_string()),
+            labels.get_label(ArgumentType::Query, "user")
+        );
+        // 同名 header 使用逗号拼接
+        assert_eq!(
+            Some("gray,base".to_string()),
+            labels.get_label(ArgumentType::Header, "x-lane")
+        );
+        assert_eq!(
+            Some("10.0.0.2".to_string()),
+            labels.get_label(ArgumentType::CallerIP, "")
+        );
+    }
+
+    #[test]
+    fn test_from_tonic_request() {
+        let mut req = tonic::Request::new(());
+        req.metadata_mut()
+            .insert("x-user", "polaris".parse().unwrap());
+
+        let labels = RequestLabels::from_tonic_request(&req, "/echo.Echo/Say");
+        assert_eq!(
+            Some("polaris".to_string()),
+            labels.get_label(ArgumentType::Header, "X-User")
+        );
+        assert_eq!(
+            Some("/echo.Echo/Say".to_string()),
+            labels.get_label(ArgumentType::Method, "")
+        );
+    }
+}
@@ -13,8 +13,162 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-#[derive(Debug, Clone)]
+use std::{collections::HashSet, time::Duration};
+
+use super::label::TrafficLabelProvider;
+use super::naming::{ServiceInstances, ServiceKey};
+use super::ArgumentType;
+
+/// SERVICE_METADATA_HASH_KEY 被调服务 metadata 中配置一致性哈希 key 来源的 key，优先级高于本地配置
+pub static SERVICE_METADATA_HASH_KEY: &str = "internal-lb-hash-key";
+
+#[derive(Debug, Clone, Default)]
 pub struct Criteria {
     pub policy: String,
     pub hash_key: String,
+    // replicate_index 一致性哈希时选择哈希环上第几个不同的节点，0 表示首选节点，重试时可以依次递增
+    pub replicate_index: usize,
+    // exclude_instances 不参与本次负载均衡的实例 ID，例如重试时已经调用失败的实例
+    pub exclude_instances: HashSet<String>,
+}
+
+impl Criteria {
+    /// is_excluded 实例是否被排除
+    pub fn is_excluded(&self, ins_id: &str) -> bool {
+        self.exclude_instances.contains(ins_id)
+    }
+
+    /// filter_excluded 移除被排除的实例，并重新计算总权重
+    pub fn filter_excluded(&self, instances: ServiceInstances) -> ServiceInstances {
+        if self.exclude_instances.is_empty() {
+            return instances;
+        }
+        let all_ins = instances
+            .instances
+            .into_iter()
+            .filter(|ins| !self.is_excluded(&ins.id))
+            .collect();
+        ServiceInstances::new(instances.service, all_ins)
+    }
+}
+
+/// HashKeySource 一致性哈希 key 的来源，格式为 {type}.{key}，例如 header.x-user-id、cookie.sid、query.uid、caller_ip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashKeySource {
+    pub arg_type: ArgumentType,
+    pub key: String,
+}
+
+impl HashKeySource {
+    /// parse 解析 hash key 来源，格式不合法时返回 None
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (type_name, key) = s.split_once('.').unwrap_or((s, ""));
+        let arg_type = match type_name {
+            "custom" | "method" | "path" | "header" | "cookie" | "query" | "caller_service"
+            | "caller_ip" => ArgumentType::parse_from_str(type_name),
+            _ => return None,
+        };
+        let need_key = matches!(
+            arg_type,
+            ArgumentType::Custom
+                | ArgumentType::Header
+                | ArgumentType::Cookie
+                | ArgumentType::Query
+        );
+        if need_key == key.is_empty() {
+            return None;
+        }
+        Some(Self {
+            arg_type,
+            key: key.to_string(),
+        })
+    }
+
+    /// resolve 从请求的流量标签中获取 hash key，主调服务直接使用路由信息中的主调服务
+    pub fn resolve(
+        &self,
+        caller: &ServiceKey,
+        provider: &dyn TrafficLabelProvider,
+    ) -> Option<String> {
+        let value = match self.arg_type {
+            ArgumentType::CallerService if !caller.name.is_empty() => {
+                Some(format!("{}/{}", caller.namespace, caller.name))
+            }
+            _ => provider.get_label(self.arg_type.clone(), &self.key),
+        };
+        value.filter(|v| !v.is_empty())
+    }
+}
+
+/// InstanceWeight 实例的动态权重计算结果
+#[derive(Debug, Clone, Default)]
+pub struct InstanceWeight {
+    pub id: String,
+    pub host: String,
+    pub port: u32,
+    // base_weight 实例注册时的静态权重
+    pub base_weight: u32,
+    // effective_weight 负载均衡实际使用的权重
+    pub effective_weight: u32,
+    // total_request 统计窗口内的调用次数
+    pub total_request: u64,
+    // success_rate 统计窗口内的调用成功率
+    pub success_rate: f64,
+    // avg_latency 统计窗口内的平均耗时
+    pub avg_latency: Duration,
+    // load 实例通过 metadata 上报的负载
+    pub load: Option<u32>,
+    // warmup_percent 实例预热进度，100 表示预热完成
+    pub warmup_percent: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::label::RequestLabels;
+
+    #[test]
+    fn test_hash_key_source() {
+        assert_eq!(
+            Some(HashKeySource {
+                arg_type: ArgumentType::Header,
+                key: "x-user-id".to_string(),
+            }),
+            HashKeySource::parse("header.x-user-id")
+        );
+        assert_eq!(
+            Some(ArgumentType::CallerIP),
+            HashKeySource::parse("caller_ip").map(|s| s.arg_type)
+        );
+        assert_eq!(None, HashKeySource::parse(""));
+        assert_eq!(None, HashKeySource::parse("header"));
+        assert_eq!(None, HashKeySource::parse("caller_ip.x"));
+        assert_eq!(None, HashKeySource::parse("body.uid"));
+
+        let labels = RequestLabels {
+            caller_ip: "127.0.0.1".to_string(),
+            ..Default::default()
+        }
+        .with_custom("uid".to_string(), "polaris".to_string());
+        let caller = ServiceKey::default();
+        assert_eq!(
+            Some("polaris".to_string()),
+            HashKeySource::parse("custom.uid")
+                .unwrap()
+                .resolve(&caller, &labels)
+        );
+        assert_eq!(
+            Some("127.0.0.1".to_string()),
+            HashKeySource::parse("caller_ip")
+                .unwrap()
+                .resolve(&caller, &labels)
+        );
+        assert_eq!(
+            None,
+            HashKeySource::parse("query.uid")
+                .unwrap()
+                .resolve(&caller, &labels)
+        );
+    }
 }
@@ -24,11 +24,13 @@ pub mod circuitbreaker;
 pub mod cluster;
 pub mod config;
 pub mod error;
+pub mod label;
 pub mod loadbalance;
 pub mod naming;
 pub mod ratelimit;
 pub mod router;
 pub mod stat;
+pub mod watch;
 
 use std::collections::HashMap;
 use std::hash::Hash;
@@ -271,6 +273,11 @@ impl ClientContext {
 }
 
 pub fn get_pod_name() -> String {
+    get_pod_name_by(|k| std::env::var(k).ok())
+}
+
+/// get_pod_name_by 从 env 提供的环境变量中读取容器名字
+pub(crate) fn get_pod_name_by(env: impl Fn(&str) -> Option<String>) -> String {
     // 各种容器平台的获取容器名字的环境变量.
     let container_name_envs = vec![
         // taf/sumeru容器环境变量
@@ -284,7 +291,7 @@ pub fn get_pod_name() -> String {
     ];
 
     for k in container_name_envs {
-        if let Ok(pod_name) = std::env::var(k) {
+        if let Some(pod_name) = env(k) {
             return pod_name;
         }
     }
@@ -16,7 +16,10 @@
 use polaris_specification::v1::HeartbeatHealthCheck;
 use prost::Message;
 use serde::{Deserialize, Serialize};
-use std::{any::Any, collections::HashMap};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+};
 
 #[derive(Default)]
 pub struct Services {
@@ -46,6 +49,33 @@ pub struct ServiceInfo {
     pub revision: String,
 }
 
+impl ServiceInfo {
+    pub fn convert_from_spec(data: polaris_specification::v1::Service) -> ServiceInfo {
+        ServiceInfo {
+            id: data.id.unwrap_or_default(),
+            namespace: data.namespace.unwrap_or_default(),
+            name: data.name.unwrap_or_default(),
+            metadata: data.metadata,
+            revision: data.revision.unwrap_or_default(),
+        }
+    }
+
+    /// match_metadata 服务元数据是否包含 filter 中的全部键值对
+    pub fn match_metadata(&self, filter: &HashMap<String, String>) -> bool {
+        filter
+            .iter()
+            .all(|(k, v)| self.metadata.get(k).is_some_and(|val| val == v))
+    }
+}
+
+/// ServicesChangeEvent 服务列表变更事件
+#[derive(Clone, Debug)]
+pub struct ServicesChangeEvent {
+    pub namespace: String,
+    pub revision: String,
+    pub services: Vec<ServiceInfo>,
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct ServiceInstances {
     pub service: ServiceInfo,
@@ -97,6 +127,9 @@ pub struct Instance {
     pub metadata: HashMap<String, String>,
     pub location: Location,
     pub revision: String,
+    // ctime 实例在服务端的创建时间，格式为 yyyy-MM-dd HH:mm:ss
+    #[serde(default)]
+    pub ctime: String,
 }
 
 impl Instance {
@@ -104,6 +137,24 @@ impl Instance {
         Default::default()
     }
 
+    /// is_changed 判断实例数据相比 other 是否发生了变化，双方都带有 revision 时直接比较 revision
+    pub fn is_changed(&self, other: &Instance) -> bool {
+        if !self.revision.is_empty() && !other.revision.is_empty() {
+            return self.revision != other.revision;
+        }
+        self.ip != other.ip
+            || self.port != other.port
+            || self.vpc_id != other.vpc_id
+            || self.version != other.version
+            || self.protocol != other.protocol
+            || self.health != other.health
+            || self.isolated != other.isolated
+            || self.weight != other.weight
+            || self.priority != other.priority
+            || self.metadata != other.metadata
+            || self.location != other.location
+    }
+
     pub fn is_available(&self) -> bool {
         if self.weight == 0 {
             return false;
@@ -144,11 +195,12 @@ impl Instance {
                 campus: location.campus.unwrap_or_default(),
             },
             revision: data.revision.unwrap_or_default(),
+            ctime: data.ctime.unwrap_or_default(),
         }
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Location {
     pub region: String,
     pub zone: String,
@@ -308,9 +360,75 @@ impl InstanceResponse {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct ServiceInstancesChangeEvent {
     pub service: ServiceInfo,
     pub instances: Vec<Instance>,
+    // old_revision 变更前的服务实例版本
+    pub old_revision: String,
+    // new_revision 变更后的服务实例版本
+    pub new_revision: String,
+    // added 新增的实例
+    pub added: Vec<Instance>,
+    // deleted 被删除的实例
+    pub deleted: Vec<Instance>,
+    // modified 发生变更的实例，比如权重、健康状态、元数据发生变化
+    pub modified: Vec<InstanceModification>,
+}
+
+impl ServiceInstancesChangeEvent {
+    /// new 根据变更前的实例快照计算实例变更差异
+    pub fn new(
+        service: ServiceInfo,
+        old_revision: String,
+        old_instances: &HashMap<String, Instance>,
+        instances: Vec<Instance>,
+    ) -> Self {
+        let mut added = vec![];
+        let mut modified = vec![];
+        let mut exists = HashSet::with_capacity(instances.len());
+        for ins in instances.iter() {
+            exists.insert(ins.id.as_str());
+            match old_instances.get(&ins.id) {
+                None => added.push(ins.clone()),
+                Some(old) => {
+                    if old.is_changed(ins) {
+                        modified.push(InstanceModification {
+                            old: old.clone(),
+                            new: ins.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        let deleted = old_instances
+            .values()
+            .filter(|ins| !exists.contains(ins.id.as_str()))
+            .cloned()
+            .collect();
+
+        Self {
+            new_revision: service.revision.clone(),
+            service,
+            instances,
+            old_revision,
+            added,
+            deleted,
+            modified,
+        }
+    }
+
+    /// is_empty 实例列表是否没有任何变化
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.deleted.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// InstanceModification 实例变更前后的数据
+#[derive(Clone, Debug)]
+pub struct InstanceModification {
+    pub old: Instance,
+    pub new: Instance,
 }
 
 #[derive(Clone, Debug)]
@@ -336,6 +454,8 @@ pub struct ServiceContract {
     pub interfaces: Vec<ServiceInterfaceDescripitor>,
     // 标签
     pub metadata: HashMap<String, String>,
+    // 契约内容摘要，内容不变时不会重复上报
+    pub revision: String,
 }
 
 impl ServiceContract {
@@ -362,6 +482,7 @@ impl ServiceContract {
             protocol: spec.protocol.clone(),
             interfaces,
             metadata: HashMap::new(),
+            revision: spec.revision.clone(),
         }
     }
 
@@ -376,7 +497,7 @@ impl ServiceContract {
             protocol: self.protocol.clone(),
             interfaces: Vec::new(),
             status: "".to_string(),
-            revision: "".to_string(),
+            revision: self.revision.clone(),
             r#type: self.name.clone(),
             ctime: "".to_string(),
             mtime: "".to_string(),
@@ -425,3 +546,63 @@ pub struct ServiceInterfaceDescripitor {
     // 接口描述信息
     pub content: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Instance, ServiceInfo, ServiceInstancesChangeEvent};
+
+    fn new_instance(id: &str, weight: u32) -> Instance {
+        Instance {
+            id: id.to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 8080,
+            weight,
+            health: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_instances_change_diff() {
+        let mut old_instances = HashMap::new();
+        for ins in [new_instance("a", 100), new_instance("b", 100)] {
+            old_instances.insert(ins.id.clone(), ins);
+        }
+        let service = ServiceInfo {
+            revision: "v2".to_string(),
+            ..Default::default()
+        };
+
+        let event = ServiceInstancesChangeEvent::new(
+            service,
+            "v1".to_string(),
+            &old_instances,
+            vec![new_instance("b", 50), new_instance("c", 100)],
+        );
+        assert_eq!("v1", event.old_revision);
+        assert_eq!("v2", event.new_revision);
+        assert_eq!(vec!["c"], ids(&event.added));
+        assert_eq!(vec!["a"], ids(&event.deleted));
+        assert_eq!(1, event.modified.len());
+        assert_eq!(100, event.modified[0].old.weight);
+        assert_eq!(50, event.modified[0].new.weight);
+        assert!(!event.is_empty());
+    }
+
+    fn ids(instances: &[Instance]) -> Vec<&str> {
+        instances.iter().map(|ins| ins.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_convert_from_spec_ctime() {
+        let ins = Instance::convert_from_spec(polaris_specification::v1::Instance {
+            id: Some("a".to_string()),
+            ctime: Some("2024-03-01 12:30:45".to_string()),
+            ..Default::default()
+        });
+        assert_eq!("a", ins.id);
+        assert_eq!("2024-03-01 12:30:45", ins.ctime);
+    }
+}
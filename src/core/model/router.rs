@@ -14,10 +14,15 @@
 // specific language governing permissions and limitations under the License.
 
 use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
 
 use super::{
+    label::{
+        default_external_parameter_supplier, default_traffic_label_provider,
+        ExternalParameterSupplier, TrafficLabelProvider,
+    },
     naming::{ServiceInstances, ServiceKey},
-    ArgumentType, TrafficArgument,
 };
 
 pub static DEFAULT_ROUTER_ISOLATED: &str = "isolatedRouter";
@@ -38,6 +43,8 @@ pub static DEFAULT_ROUTER_LANE: &str = "laneRouter";
 
 pub static DEFAULT_ROUTER_NAMESPACE: &str = "namespaceRouter";
 
+pub static DEFAULT_ROUTER_PRIORITY: &str = "priorityRouter";
+
 #[derive(Clone, Debug)]
 pub enum MetadataFailoverType {
     MetadataFailoverNone,
@@ -53,34 +60,68 @@ pub enum RouteState {
 pub struct RouteResult {
     pub state: RouteState,
     pub instances: ServiceInstances,
+    // matched_rule 命中的路由规则标识，没有规则参与时为 None
+    pub matched_rule: Option<String>,
+    // failover 路由过程中发生的降级行为描述，未降级时为 None
+    pub failover: Option<String>,
 }
 
-fn default_traffic_label_provider(_: ArgumentType, _: &str) -> Option<String> {
-    None
+impl RouteResult {
+    /// next 构建一个直接进入下一个路由插件的结果
+    pub fn next(instances: ServiceInstances) -> Self {
+        Self {
+            state: RouteState::Next,
+            instances,
+            matched_rule: None,
+            failover: None,
+        }
+    }
 }
 
-fn default_external_parameter_supplier(key: &str) -> Option<String> {
-    match std::env::var(key) {
-        Ok(v) => Some(v),
-        Err(_) => None,
-    }
+/// RouterTraceItem 路由链中单个路由插件的执行记录
+#[derive(Clone, Debug, Default)]
+pub struct RouterTraceItem {
+    // chain 所在的路由链阶段: before/core/after
+    pub chain: String,
+    // router 路由插件名称
+    pub router: String,
+    // matched_rule 命中的路由规则标识
+    pub matched_rule: Option<String>,
+    // input_count 路由前的实例数
+    pub input_count: usize,
+    // output_count 路由后的实例数
+    pub output_count: usize,
+    // failover 发生的降级行为
+    pub failover: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct RouteInfo {
     // 主调服务数据信息
     pub caller: ServiceKey,
     // 被调服务数据信息
     pub callee: ServiceKey,
-    // 路由链
+    // 路由链，为空时按照被调服务维度配置或者全局配置选择路由链
     pub chain: RouterChain,
     // 用于元数据路由
     pub metadata: HashMap<String, String>,
     pub metadata_failover: MetadataFailoverType,
     // traffic_label_provider 流量标签提供者
-    pub traffic_label_provider: fn(ArgumentType, &str) -> Option<String>,
+    pub traffic_label_provider: Arc<dyn TrafficLabelProvider>,
     // 北极星内部治理规则执行时，会识别规则中的参数来源类别，如果发现规则中的参数来源指定为外部数据源时，会调用本接口进行获取
-    pub external_parameter_supplier: fn(&str) -> Option<String>,
+    pub external_parameter_supplier: Arc<dyn ExternalParameterSupplier>,
+}
+
+impl Debug for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RouteInfo")
+            .field("caller", &self.caller)
+            .field("callee", &self.callee)
+            .field("chain", &self.chain)
+            .field("metadata", &self.metadata)
+            .field("metadata_failover", &self.metadata_failover)
+            .finish()
+    }
 }
 
 impl Default for RouteInfo {
@@ -91,8 +132,8 @@ impl Default for RouteInfo {
             chain: Default::default(),
             metadata: HashMap::<String, String>::new(),
             metadata_failover: MetadataFailoverType::MetadataFailoverNone,
-            external_parameter_supplier: default_external_parameter_supplier,
-            traffic_label_provider: default_traffic_label_provider,
+            external_parameter_supplier: default_external_parameter_supplier(),
+            traffic_label_provider: default_traffic_label_provider(),
         }
     }
 }
@@ -105,6 +146,10 @@ pub struct RouterChain {
 }
 
 impl RouterChain {
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.core.is_empty() && self.after.is_empty()
+    }
+
     pub fn exist_route(&self, n: &str) -> bool {
         self.before.iter().any(|x| x == n)
             || self.core.iter().any(|x| x == n)
@@ -0,0 +1,273 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use futures::Stream;
+
+/// LagPolicy 消费速度跟不上事件产生速度、缓冲区已满时的处理策略
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LagPolicy {
+    // DropOldest 丢弃缓冲区中最旧的事件
+    DropOldest,
+    // Latest 每次发送都替换缓冲区中未消费的事件，缓冲区中最多只有一个最新的事件，适用于每个事件都是全量数据的场景
+    #[default]
+    Latest,
+}
+
+/// WatchStreamOption 监听流的缓冲配置
+#[derive(Clone, Copy, Debug)]
+pub struct WatchStreamOption {
+    // buffer_size 缓冲区大小，LagPolicy::Latest 时缓冲区最多只保留一个事件
+    pub buffer_size: usize,
+    pub lag_policy: LagPolicy,
+}
+
+impl Default for WatchStreamOption {
+    fn default() -> Self {
+        Self {
+            buffer_size: 16,
+            lag_policy: LagPolicy::Latest,
+        }
+    }
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    // delivered 是否已经发送过事件
+    delivered: bool,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+struct Shared<T> {
+    option: WatchStreamOption,
+    state: Mutex<State<T>>,
+    // lagged 因为消费过慢被丢弃的事件数
+    lagged: AtomicU64,
+}
+
+/// channel 创建一个有界的监听事件通道
+pub fn channel<T>(option: WatchStreamOption) -> (WatchSender<T>, WatchStream<T>) {
+    let option = WatchStreamOption {
+        buffer_size: option.buffer_size.max(1),
+        lag_policy: option.lag_policy,
+    };
+    let shared = Arc::new(Shared {
+        option,
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(option.buffer_size),
+            waker: None,
+            delivered: false,
+            sender_closed: false,
+            receiver_closed: false,
+        }),
+        lagged: AtomicU64::new(0),
+    });
+    (
+        WatchSender {
+            shared: shared.clone(),
+        },
+        WatchStream {
+            shared,
+            on_cancel: None,
+            on_deliver: None,
+        },
+    )
+}
+
+/// WatchSender 监听事件的发送端，发送不会阻塞，缓冲区满时按照 LagPolicy 丢弃事件
+pub struct WatchSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> WatchSender<T> {
+    /// send 发送事件，接收端已经关闭时返回 false
+    pub fn send(&self, event: T) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        self.push(state, event)
+    }
+
+    /// send_initial 发送初始快照，如果已经有变更事件先一步发送则丢弃快照
+    pub(crate) fn send_initial(&self, event: T) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        if state.delivered {
+            return !state.receiver_closed;
+        }
+        self.push(state, event)
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, State<T>>, event: T) -> bool {
+        if state.receiver_closed {
+            return false;
+        }
+        state.delivered = true;
+        let dropped = match self.shared.option.lag_policy {
+            LagPolicy::DropOldest if state.queue.len() >= self.shared.option.buffer_size => {
+                state.queue.pop_front().map(|_| 1).unwrap_or(0)
+            }
+            LagPolicy::DropOldest => 0,
+            LagPolicy::Latest => {
+                let dropped = state.queue.len() as u64;
+                state.queue.clear();
+                dropped
+            }
+        };
+        if dropped > 0 {
+            self.shared.lagged.fetch_add(dropped, Ordering::Relaxed);
+        }
+        state.queue.push_back(event);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// is_closed 接收端是否已经关闭
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().receiver_closed
+    }
+}
+
+impl<T> Drop for WatchSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.sender_closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// WatchStream 监听事件流，drop 时自动取消监听
+pub struct WatchStream<T> {
+    shared: Arc<Shared<T>>,
+    on_cancel: Option<Box<dyn FnOnce() + Send>>,
+    // on_deliver 事件被消费时执行的转换，用于基于上一次消费的事件重新计算增量数据
+    on_deliver: Option<Box<dyn FnMut(T) -> T + Send>>,
+}
+
+impl<T> WatchStream<T> {
+    /// with_cancel 设置 drop 时执行的取消监听动作
+    pub(crate) fn with_cancel(mut self, on_cancel: Box<dyn FnOnce() + Send>) -> Self {
+        self.on_cancel = Some(on_cancel);
+        self
+    }
+
+    /// with_deliver 设置事件被消费时执行的转换
+    pub(crate) fn with_deliver(mut self, on_deliver: Box<dyn FnMut(T) -> T + Send>) -> Self {
+        self.on_deliver = Some(on_deliver);
+        self
+    }
+
+    /// lagged 因为消费过慢被丢弃的事件数
+    pub fn lagged(&self) -> u64 {
+        self.shared.lagged.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Stream for WatchStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        let mut state = this.shared.state.lock().unwrap();
+        if let Some(event) = state.queue.pop_front() {
+            drop(state);
+            let event = match this.on_deliver.as_mut() {
+                Some(on_deliver) => on_deliver(event),
+                None => event,
+            };
+            return Poll::Ready(Some(event));
+        }
+        if state.sender_closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for WatchStream<T> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.receiver_closed = true;
+            state.queue.clear();
+        }
+        if let Some(on_cancel) = self.on_cancel.take() {
+            on_cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lag_policy() {
+        let (tx, mut rx) = channel::<u32>(WatchStreamOption {
+            buffer_size: 2,
+            lag_policy: LagPolicy::DropOldest,
+        });
+        for i in 0..4 {
+            assert!(tx.send(i));
+        }
+        assert_eq!(Some(2), rx.next().await);
+        assert_eq!(Some(3), rx.next().await);
+        assert_eq!(2, rx.lagged());
+
+        // 缓冲区没有满时同样只保留最新的事件
+        let (tx, mut rx) = channel::<u32>(WatchStreamOption {
+            buffer_size: 16,
+            lag_policy: LagPolicy::Latest,
+        });
+        for i in 0..3 {
+            assert!(tx.send(i));
+        }
+        assert_eq!(2, rx.lagged());
+        assert_eq!(Some(2), rx.next().await);
+        assert!(tx.send_initial(9));
+        assert!(tx.send(3));
+        assert_eq!(Some(3), rx.next().await);
+        drop(tx);
+        assert_eq!(None, rx.next().await);
+    }
+
+    #[tokio::test]
+    async fn test_drop_cancel() {
+        let canceled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = channel::<u32>(WatchStreamOption::default());
+        let flag = canceled.clone();
+        let rx = rx.with_cancel(Box::new(move || flag.store(true, Ordering::SeqCst)));
+
+        let task = tokio::spawn(async move { rx.take(1).collect::<Vec<u32>>().await });
+        assert!(tx.send(7));
+        assert_eq!(vec![7], task.await.unwrap());
+        assert!(canceled.load(Ordering::SeqCst));
+        assert!(tx.is_closed());
+        assert!(!tx.send(8));
+    }
+}
@@ -80,6 +80,8 @@ pub trait ResourceCache: Plugin {
     async fn load_config_group_files(&self, filter: Filter) -> Result<ConfigGroup, PolarisError>;
     // 注册资源监听器
     async fn register_resource_listener(&self, listener: Arc<dyn ResourceListener>);
+    // flush 停止处理服务端推送，并将已经收到的数据写入缓存以及容灾文件，SDK 关闭时调用
+    async fn flush(&self);
 }
 
 #[async_trait::async_trait]
@@ -148,4 +150,6 @@ impl ResourceCache for NoopResourceCache {
     async fn register_resource_listener(&self, listener: Arc<dyn ResourceListener>) {
         todo!()
     }
+
+    async fn flush(&self) {}
 }
@@ -13,11 +13,23 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::core::config::consumer::{DynamicWeightConfig, WarmupConfig};
 use crate::core::model::{
+    circuitbreaker::RetStatus,
     error::PolarisError,
-    loadbalance::Criteria,
+    loadbalance::{Criteria, InstanceWeight},
     naming::{Instance, ServiceInstances},
 };
+use crate::discovery::req::ServiceCallResult;
 
 use super::plugins::Plugin;
 
@@ -32,4 +44,720 @@ where
         criteria: Criteria,
         instances: ServiceInstances,
     ) -> Result<Instance, PolarisError>;
+
+    /// choose_candidates 按照顺序返回至多 count 个不同的实例，第一个为首选实例，其余为重试时使用的备选实例
+    ///
+    /// 默认实现会多次调用 choose_instance，只适用于无状态的负载均衡器；
+    /// 选择实例时会修改内部状态的负载均衡器（例如加权轮询）需要覆盖该方法，避免备选实例的选择影响后续的负载分布
+    fn choose_candidates(
+        &self,
+        criteria: Criteria,
+        instances: ServiceInstances,
+        count: usize,
+    ) -> Result<Vec<Instance>, PolarisError> {
+        let mut criteria = criteria;
+        let mut candidates = Vec::<Instance>::with_capacity(count);
+        while candidates.len() < count {
+            match self.choose_instance(criteria.clone(), instances.clone()) {
+                Ok(ins) => {
+                    criteria.exclude_instances.insert(ins.id.clone());
+                    candidates.push(ins);
+                }
+                Err(e) => {
+                    if candidates.is_empty() {
+                        return Err(e);
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// report_call_result 上报实例调用结果，需要根据实时调用情况选择实例的负载均衡器可以实现该方法
+    fn report_call_result(&self, _result: &ServiceCallResult) {}
+
+    /// use_dynamic_weight 是否按照动态权重选择实例，开启动态权重后，传入的实例权重为计算后的有效权重
+    fn use_dynamic_weight(&self) -> bool {
+        false
+    }
+}
+
+// MIN_SEEN_EXPIRE 实例发现记录的最短保留时间
+const MIN_SEEN_EXPIRE: Duration = Duration::from_secs(60);
+
+/// DynamicWeightManager 动态权重管理，根据实例最近的调用成功率、耗时以及实例通过 metadata 上报的负载计算有效权重，
+/// 并对新上线的实例进行预热
+pub struct DynamicWeightManager {
+    conf: DynamicWeightConfig,
+    warmup: WarmupConfig,
+    // stats 实例 ID -> 实例的窗口调用统计
+    stats: RwLock<HashMap<String, Arc<Mutex<WindowStat>>>>,
+    // first_seen 服务 -> 实例 ID -> 实例的发现记录，只用于没有创建时间的实例
+    first_seen: RwLock<HashMap<String, HashMap<String, SeenRecord>>>,
+    // epoch 计算实例最近出现时间的基准
+    epoch: Instant,
+    // ctime_offset_ms 服务端时区相对 UTC 的毫秒数，未配置时不使用实例的创建时间
+    ctime_offset_ms: Option<i64>,
+}
+
+// SeenRecord 实例的发现记录
+struct SeenRecord {
+    // first_seen 实例被发现的时间，首次拉取服务时已经存在的实例为 None，视为已预热
+    first_seen: Option<Instant>,
+    // last_seen_ms 实例最近一次出现在实例列表中的时间，相对于 epoch 的毫秒数
+    last_seen_ms: AtomicU64,
+}
+
+impl DynamicWeightManager {
+    pub fn new(conf: DynamicWeightConfig, warmup: WarmupConfig) -> Self {
+        let ctime_offset_ms = warmup.server_utc_offset.as_deref().and_then(|offset| {
+            let ret = parse_utc_offset(offset);
+            if ret.is_none() {
+                crate::warn!(
+                    "[polaris][loadbalancer][dynamic_weight] invalid serverUtcOffset {}, ignore instance ctime",
+                    offset
+                );
+            }
+            ret
+        });
+        Self {
+            conf,
+            warmup,
+            stats: RwLock::new(HashMap::new()),
+            first_seen: RwLock::new(HashMap::new()),
+            epoch: Instant::now(),
+            ctime_offset_ms,
+        }
+    }
+
+    /// enable 是否开启动态权重或者实例预热
+    pub fn enable(&self) -> bool {
+        self.conf.enable || self.warmup.enable
+    }
+
+    /// report_call_result 记录实例调用结果
+    pub fn report_call_result(&self, result: &ServiceCallResult) {
+        if !self.conf.enable {
+            return;
+        }
+        let stat = {
+            let stats = self.stats.read().unwrap();
+            stats.get(&result.instance.id).cloned()
+        };
+        let stat = match stat {
+            Some(stat) => stat,
+            None => {
+                let mut stats = self.stats.write().unwrap();
+                // 清理长时间没有调用的实例统计数据
+                let expire = self.conf.window * 10;
+                stats.retain(|_, stat| stat.lock().unwrap().window_start.elapsed() < expire);
+                stats
+                    .entry(result.instance.id.clone())
+                    .or_insert_with(|| Arc::new(Mutex::new(WindowStat::new())))
+                    .clone()
+            }
+        };
+
+        let mut stat = stat.lock().unwrap();
+        stat.rotate(self.conf.window);
+        stat.current.total += 1;
+        if result.ret_status == RetStatus::RetSuccess {
+            stat.current.success += 1;
+        }
+        stat.current.latency_sum += result.delay;
+    }
+
+    /// compute_weights 计算实例的有效权重，未开启动态权重时有效权重等于静态权重
+    pub fn compute_weights(&self, instances: &ServiceInstances) -> Vec<InstanceWeight> {
+        let mut weights: Vec<InstanceWeight> = {
+            let stats = self.stats.read().unwrap();
+            instances
+                .instances
+                .iter()
+                .map(|ins| {
+                    let counter = match stats.get(&ins.id) {
+                        Some(stat) => {
+                            let mut stat = stat.lock().unwrap();
+                            stat.rotate(self.conf.window);
+                            stat.snapshot()
+                        }
+                        None => Counter::default(),
+                    };
+                    InstanceWeight {
+                        id: ins.id.clone(),
+                        host: ins.ip.clone(),
+                        port: ins.port,
+                        base_weight: ins.weight,
+                        effective_weight: ins.weight,
+                        total_request: counter.total,
+                        success_rate: counter.success_rate(),
+                        avg_latency: counter.avg_latency(),
+                        load: ins
+                            .metadata
+                            .get(&self.conf.load_metadata_key)
+                            .and_then(|v| v.trim().parse::<u32>().ok())
+                            .map(|v| v.min(100)),
+                        warmup_percent: 100,
+                    }
+                })
+                .collect()
+        };
+        if self.warmup.enable {
+            self.compute_warmup(instances, &mut weights);
+        }
+        if !self.enable() {
+            return weights;
+        }
+
+        // 耗时按照和服务内其他实例的平均耗时比较，比平均耗时慢的实例降低权重
+        let sampled: Vec<&InstanceWeight> = weights
+            .iter()
+            .filter(|w| w.total_request >= self.conf.min_request)
+            .collect();
+        let svc_avg_latency = if sampled.is_empty() {
+            0.0
+        } else {
+            sampled
+                .iter()
+                .map(|w| w.avg_latency.as_secs_f64())
+                .sum::<f64>()
+                / sampled.len() as f64
+        };
+
+        for weight in weights.iter_mut() {
+            if weight.base_weight == 0 {
+                continue;
+            }
+            let base = weight.base_weight as f64;
+            let warmup_factor = weight.warmup_percent as f64 / 100.0;
+            if !self.conf.enable {
+                weight.effective_weight = (base * warmup_factor).round().clamp(1.0, base) as u32;
+                continue;
+            }
+
+            let mut factor = 1.0;
+            if weight.total_request >= self.conf.min_request {
+                factor *= weight.success_rate;
+                let latency = weight.avg_latency.as_secs_f64();
+                if latency > 0.0 && svc_avg_latency > 0.0 {
+                    factor *= (svc_avg_latency / latency).min(1.0);
+                }
+            }
+            if let Some(load) = weight.load {
+                factor *= (100 - load) as f64 / 100.0;
+            }
+
+            let min_weight = (base * self.conf.min_weight_percent as f64 / 100.0)
+                .ceil()
+                .max(1.0);
+            let dynamic_weight = (base * factor).clamp(min_weight, base);
+            weight.effective_weight =
+                (dynamic_weight * warmup_factor).round().clamp(1.0, base) as u32;
+        }
+        weights
+    }
+
+    // compute_warmup 计算实例的预热进度，预热开始时间的优先级: 实例 metadata 中的时间 > 实例在服务端的创建时间 > 实例被发现的时间，
+    // 服务端的创建时间只在配置了服务端时区时使用
+    fn compute_warmup(&self, instances: &ServiceInstances, weights: &mut [InstanceWeight]) {
+        let first_seen = self.observe_instances(instances);
+        let window = self.warmup.window.as_secs_f64();
+        if window <= 0.0 {
+            return;
+        }
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        for (ins, weight) in instances.instances.iter().zip(weights.iter_mut()) {
+            let start_ms = ins
+                .metadata
+                .get(&self.warmup.start_metadata_key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .or_else(|| self.parse_ctime_ms(&ins.ctime))
+                .filter(|start_ms| *start_ms <= now_ms);
+            let elapsed = match start_ms {
+                Some(start_ms) => Duration::from_millis(now_ms.saturating_sub(start_ms)),
+                None => match first_seen.get(&ins.id).copied().flatten() {
+                    Some(seen) => seen.elapsed(),
+                    None => continue,
+                },
+            };
+            let time_factor = elapsed.as_secs_f64() / window;
+            if time_factor >= 1.0 {
+                continue;
+            }
+            let aggression = if self.warmup.aggression > 0.0 {
+                self.warmup.aggression
+            } else {
+                1.0
+            };
+            let min_factor = self.warmup.min_weight_percent.min(100) as f64 / 100.0;
+            let factor = time_factor.powf(1.0 / aggression).max(min_factor);
+            weight.warmup_percent = (factor * 100.0).floor() as u32;
+        }
+    }
+
+    // observe_instances 记录实例被发现的时间，返回本次实例列表中实例的发现时间
+    //
+    // 传入的实例列表可能是路由后的部分实例，因此不能按照单次的列表删除记录；实例超过过期时间没有出现在任何一次实例列表中时，
+    // 视为已经下线并删除记录，清理在有新实例加入时进行
+    fn observe_instances(&self, instances: &ServiceInstances) -> HashMap<String, Option<Instant>> {
+        let cache_key = instances.get_cache_key();
+        let now_ms = self.epoch.elapsed().as_millis() as u64;
+        {
+            let first_seen = self.first_seen.read().unwrap();
+            if let Some(svc_seen) = first_seen.get(&cache_key) {
+                let ret: Option<HashMap<String, Option<Instant>>> = instances
+                    .instances
+                    .iter()
+                    .map(|ins| {
+                        svc_seen.get(&ins.id).map(|record| {
+                            record.last_seen_ms.store(now_ms, Ordering::Relaxed);
+                            (ins.id.clone(), record.first_seen)
+                        })
+                    })
+                    .collect();
+                if let Some(ret) = ret {
+                    return ret;
+                }
+            }
+        }
+
+        let mut first_seen = self.first_seen.write().unwrap();
+        let expire_ms = self.seen_expire().as_millis() as u64;
+        for svc_seen in first_seen.values_mut() {
+            svc_seen.retain(|_, record| {
+                now_ms.saturating_sub(record.last_seen_ms.load(Ordering::Relaxed)) < expire_ms
+            });
+        }
+        first_seen.retain(|key, svc_seen| key == &cache_key || !svc_seen.is_empty());
+
+        let initial = !first_seen.contains_key(&cache_key);
+        let svc_seen = first_seen.entry(cache_key).or_default();
+        let now = Instant::now();
+        instances
+            .instances
+            .iter()
+            .map(|ins| {
+                let record = svc_seen
+                    .entry(ins.id.clone())
+                    .or_insert_with(|| SeenRecord {
+                        first_seen: if initial { None } else { Some(now) },
+                        last_seen_ms: AtomicU64::new(now_ms),
+                    });
+                record.last_seen_ms.store(now_ms, Ordering::Relaxed);
+                (ins.id.clone(), record.first_seen)
+            })
+            .collect()
+    }
+
+    // parse_ctime_ms 将服务端的实例创建时间按照配置的服务端时区转换为 unix 毫秒
+    fn parse_ctime_ms(&self, ctime: &str) -> Option<u64> {
+        let offset_ms = self.ctime_offset_ms?;
+        let local_ms = i64::try_from(parse_ctime(ctime)?).ok()?;
+        u64::try_from(local_ms - offset_ms).ok()
+    }
+
+    // seen_expire 实例发现记录的过期时间，至少保留一个预热窗口
+    fn seen_expire(&self) -> Duration {
+        self.warmup.window.max(MIN_SEEN_EXPIRE)
+    }
+
+    /// apply 返回使用有效权重替换静态权重后的实例列表
+    pub fn apply(&self, instances: &ServiceInstances) -> ServiceInstances {
+        let weights = self.compute_weights(instances);
+        let mut all_ins = instances.instances.clone();
+        for (ins, weight) in all_ins.iter_mut().zip(weights.iter()) {
+            ins.weight = weight.effective_weight;
+        }
+        crate::debug!(
+            "[polaris][loadbalancer][dynamic_weight] {}/{} weights: {:?}",
+            instances.service.namespace,
+            instances.service.name,
+            weights
+        );
+        ServiceInstances::new(instances.service.clone(), all_ins)
+    }
+}
+
+// parse_utc_offset 解析 +08:00、-05:30 或 Z 形式的时区，返回相对 UTC 的毫秒数
+fn parse_utc_offset(offset: &str) -> Option<i64> {
+    let offset = offset.trim();
+    if offset == "Z" {
+        return Some(0);
+    }
+    let (sign, rest) = match offset.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hour, minute) = rest.split_once(':')?;
+    let (hour, minute) = (hour.parse::<i64>().ok()?, minute.parse::<i64>().ok()?);
+    if !(0..=14).contains(&hour) || !(0..60).contains(&minute) {
+        return None;
+    }
+    Some(sign * (hour * 3600 + minute * 60) * 1000)
+}
+
+// parse_ctime 解析服务端下发的实例创建时间 (yyyy-MM-dd HH:mm:ss)，按照 UTC 时间返回 unix 毫秒，
+// 服务端未携带时区信息，由调用方按照配置的服务端时区修正
+fn parse_ctime(ctime: &str) -> Option<u64> {
+    let (date, time) = ctime.trim().split_once(' ')?;
+    let mut date_parts = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    let mut time_parts = time.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (
+        time_parts.next()??,
+        time_parts.next()??,
+        time_parts.next()??,
+    );
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+    // 公历日期转换为距离 1970-01-01 的天数
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(secs).ok().map(|secs| secs * 1000)
+}
+
+#[derive(Default, Clone, Copy)]
+struct Counter {
+    total: u64,
+    success: u64,
+    latency_sum: Duration,
+}
+
+impl Counter {
+    fn success_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.success as f64 / self.total as f64
+    }
+
+    fn avg_latency(&self) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+        self.latency_sum / self.total as u32
+    }
+}
+
+// WindowStat 保留当前窗口以及上一个窗口的调用统计，计算时合并两个窗口，避免窗口切换时数据突变
+struct WindowStat {
+    window_start: Instant,
+    current: Counter,
+    previous: Counter,
+}
+
+impl WindowStat {
+    fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            current: Counter::default(),
+            previous: Counter::default(),
+        }
+    }
+
+    fn rotate(&mut self, window: Duration) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < window {
+            return;
+        }
+        self.previous = if elapsed < window * 2 {
+            self.current
+        } else {
+            Counter::default()
+        };
+        self.current = Counter::default();
+        self.window_start = Instant::now();
+    }
+
+    fn snapshot(&self) -> Counter {
+        Counter {
+            total: self.current.total + self.previous.total,
+            success: self.current.success + self.previous.success,
+            latency_sum: self.current.latency_sum + self.previous.latency_sum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::ServiceInfo;
+
+    fn call_result(ins: &Instance, delay: Duration, ret_status: RetStatus) -> ServiceCallResult {
+        ServiceCallResult {
+            flow_id: String::new(),
+            namespace: "default".to_string(),
+            service: "weight".to_string(),
+            instance: ins.clone(),
+            method: String::new(),
+            ret_status,
+            ret_code: 0,
+            delay,
+        }
+    }
+
+    #[test]
+    fn test_compute_weights() {
+        let manager = DynamicWeightManager::new(
+            DynamicWeightConfig {
+                enable: true,
+                ..Default::default()
+            },
+            WarmupConfig::default(),
+        );
+        let mut instances: Vec<Instance> = (0..3)
+            .map(|i| Instance {
+                id: format!("ins-{}", i),
+                weight: 100,
+                ..Default::default()
+            })
+            .collect();
+        instances[2]
+            .metadata
+            .insert("polaris.load".to_string(), "80".to_string());
+        let svc_instances = ServiceInstances::new(ServiceInfo::default(), instances);
+
+        for _ in 0..20 {
+            let (healthy, broken) = (&svc_instances.instances[0], &svc_instances.instances[1]);
+            manager.report_call_result(&call_result(
+                healthy,
+                Duration::from_millis(10),
+                RetStatus::RetSuccess,
+            ));
+            manager.report_call_result(&call_result(
+                broken,
+                Duration::from_millis(10),
+                RetStatus::RetFail,
+            ));
+        }
+
+        let weights = manager.compute_weights(&svc_instances);
+        assert_eq!(100, weights[0].effective_weight);
+        // 全部失败的实例保留最低权重
+        assert_eq!(10, weights[1].effective_weight);
+        assert_eq!(20, weights[2].effective_weight);
+
+        let applied = manager.apply(&svc_instances);
+        assert_eq!(130, applied.get_total_weight());
+    }
+
+    #[test]
+    fn test_warmup() {
+        let manager = DynamicWeightManager::new(
+            DynamicWeightConfig::default(),
+            WarmupConfig {
+                enable: true,
+                window: Duration::from_secs(100),
+                ..Default::default()
+            },
+        );
+        let build = |id: &str| Instance {
+            id: id.to_string(),
+            weight: 100,
+            ..Default::default()
+        };
+
+        // 首次发现服务时已经存在的实例不需要预热
+        let svc_instances = ServiceInstances::new(ServiceInfo::default(), vec![build("a")]);
+        let weights = manager.compute_weights(&svc_instances);
+        assert_eq!(100, weights[0].effective_weight);
+
+        // 后续新增的实例从最低权重开始预热
+        let mut warm_start = build("c");
+        let start_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+            - 50_000;
+        warm_start
+            .metadata
+            .insert("polaris.warmup.start".to_string(), start_ms.to_string());
+        let svc_instances = ServiceInstances::new(
+            ServiceInfo::default(),
+            vec![build("a"), build("b"), warm_start],
+        );
+        let weights = manager.compute_weights(&svc_instances);
+        assert_eq!(100, weights[0].effective_weight);
+        assert_eq!(10, weights[1].effective_weight);
+        assert_eq!(10, weights[1].warmup_percent);
+        // 按照 metadata 中的预热开始时间计算
+        assert!(weights[2].effective_weight >= 49 && weights[2].effective_weight <= 51);
+    }
+
+    // format_ctime 将 unix 秒格式化为服务端实例创建时间的格式 (UTC)
+    fn format_ctime(secs: i64) -> String {
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            rem / 3600,
+            rem % 3600 / 60,
+            rem % 60
+        )
+    }
+
+    #[test]
+    fn test_parse_ctime() {
+        assert_eq!(Some(0), parse_ctime("1970-01-01 00:00:00"));
+        assert_eq!(Some(1709296245000), parse_ctime("2024-03-01 12:30:45"));
+        assert_eq!(Some(951782400000), parse_ctime("2000-02-29 00:00:00"));
+        assert_eq!("2024-03-01 12:30:45", format_ctime(1709296245));
+        assert_eq!(None, parse_ctime(""));
+        assert_eq!(None, parse_ctime("2024-13-01 00:00:00"));
+        assert_eq!(None, parse_ctime("2024-03-01T12:30:45"));
+
+        assert_eq!(Some(8 * 3600 * 1000), parse_utc_offset("+08:00"));
+        assert_eq!(
+            Some(-(5 * 3600 + 30 * 60) * 1000),
+            parse_utc_offset("-05:30")
+        );
+        assert_eq!(Some(0), parse_utc_offset("Z"));
+        assert_eq!(None, parse_utc_offset("08:00"));
+        assert_eq!(None, parse_utc_offset("+8"));
+        assert_eq!(None, parse_utc_offset("+25:00"));
+    }
+
+    #[test]
+    fn test_warmup_by_ctime() {
+        let manager = DynamicWeightManager::new(
+            DynamicWeightConfig::default(),
+            WarmupConfig {
+                enable: true,
+                window: Duration::from_secs(100),
+                server_utc_offset: Some("+08:00".to_string()),
+                ..Default::default()
+            },
+        );
+        // 服务端按照 +08:00 时区下发实例创建时间
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+            + 8 * 3600;
+        let build = |id: &str, ctime: String| Instance {
+            id: id.to_string(),
+            weight: 100,
+            ctime,
+            ..Default::default()
+        };
+
+        // 按照服务端的创建时间计算预热进度，首次拉取服务时刚创建的实例同样需要预热
+        let svc_instances = ServiceInstances::new(
+            ServiceInfo::default(),
+            vec![
+                build("a", format_ctime(now_secs - 50)),
+                build("b", format_ctime(now_secs - 1000)),
+                // 创建时间晚于当前时间时忽略，回退到实例被发现的时间
+                build("c", format_ctime(now_secs + 3600)),
+            ],
+        );
+        let weights = manager.compute_weights(&svc_instances);
+        assert!(weights[0].effective_weight >= 49 && weights[0].effective_weight <= 51);
+        assert_eq!(100, weights[1].effective_weight);
+        assert_eq!(100, weights[2].effective_weight);
+    }
+
+    #[test]
+    fn test_warmup_ignore_ctime_without_offset() {
+        let manager = DynamicWeightManager::new(
+            DynamicWeightConfig::default(),
+            WarmupConfig {
+                enable: true,
+                window: Duration::from_secs(100),
+                ..Default::default()
+            },
+        );
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        // 未配置服务端时区时不使用创建时间，首次拉取服务时发现的实例不需要预热
+        let svc_instances = ServiceInstances::new(
+            ServiceInfo::default(),
+            vec![Instance {
+                id: "a".to_string(),
+                weight: 100,
+                ctime: format_ctime(now_secs - 50),
+                ..Default::default()
+            }],
+        );
+        let weights = manager.compute_weights(&svc_instances);
+        assert_eq!(100, weights[0].effective_weight);
+    }
+
+    #[test]
+    fn test_drop_disappeared_instances() {
+        let mut manager = DynamicWeightManager::new(
+            DynamicWeightConfig::default(),
+            WarmupConfig {
+                enable: true,
+                ..Default::default()
+            },
+        );
+        manager.epoch = Instant::now() - MIN_SEEN_EXPIRE * 2;
+        let build = |id: &str| Instance {
+            id: id.to_string(),
+            weight: 100,
+            ..Default::default()
+        };
+        let svc_instances =
+            ServiceInstances::new(ServiceInfo::default(), vec![build("a"), build("b")]);
+        manager.observe_instances(&svc_instances);
+
+        // b 已经超过过期时间没有出现在实例列表中，新实例加入时清理
+        {
+            let first_seen = manager.first_seen.read().unwrap();
+            let svc_seen = first_seen.get(&svc_instances.get_cache_key()).unwrap();
+            svc_seen
+                .get("b")
+                .unwrap()
+                .last_seen_ms
+                .store(0, Ordering::Relaxed);
+        }
+        let svc_instances =
+            ServiceInstances::new(ServiceInfo::default(), vec![build("a"), build("c")]);
+        let seen = manager.observe_instances(&svc_instances);
+        // 服务已经被发现过，新加入的实例需要预热
+        assert!(seen.get("c").unwrap().is_some());
+
+        let first_seen = manager.first_seen.read().unwrap();
+        let svc_seen = first_seen.get(&svc_instances.get_cache_key()).unwrap();
+        let mut ids: Vec<&String> = svc_seen.keys().collect();
+        ids.sort();
+        assert_eq!(vec!["a", "c"], ids);
+    }
 }
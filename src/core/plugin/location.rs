@@ -13,6 +13,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+
 use crate::{
     core::{
         config::global::LocationConfig,
@@ -56,7 +60,10 @@ pub struct LocationProvider {
     pub chain: Vec<Box<dyn LocationSupplier>>,
 }
 
-pub fn new_location_provider(opt: &LocationConfig) -> Result<LocationProvider, PolarisError> {
+pub fn new_location_provider(
+    opt: &LocationConfig,
+    runtime: Arc<Runtime>,
+) -> Result<LocationProvider, PolarisError> {
     let mut chain = Vec::<Box<dyn LocationSupplier>>::new();
     let providers = opt.clone().providers;
     if providers.is_none() {
@@ -66,18 +73,19 @@ pub fn new_location_provider(opt: &LocationConfig) -> Result<LocationProvider, P
         ));
     }
 
-    providers.unwrap().iter().for_each(|provider| {
+    for provider in providers.unwrap().iter() {
         let name: String = provider.name.clone();
-        match LocationType::parse(name.as_str()) {
-            LocationType::Local => {
-                chain.push(Box::new(LocalLocationSupplier::new(provider.clone())));
-            }
-            LocationType::Http => {
-                chain.push(Box::new(RemoteHttpLocationSupplier::new(provider.clone())));
-            }
-            LocationType::Service => {}
-        }
-    });
+        let mut supplier: Box<dyn LocationSupplier> = match LocationType::parse(name.as_str()) {
+            LocationType::Local => Box::new(LocalLocationSupplier::new(provider.clone())),
+            LocationType::Http => Box::new(RemoteHttpLocationSupplier::new(
+                provider.clone(),
+                runtime.clone(),
+            )?),
+            LocationType::Service => continue,
+        };
+        supplier.init();
+        chain.push(supplier);
+    }
 
     Ok(LocationProvider { chain })
 }
@@ -85,7 +93,11 @@ pub fn new_location_provider(opt: &LocationConfig) -> Result<LocationProvider, P
 impl Plugin for LocationProvider {
     fn init(&mut self) {}
 
-    fn destroy(&self) {}
+    fn destroy(&self) {
+        for supplier in self.chain.iter() {
+            supplier.destroy();
+        }
+    }
 
     fn name(&self) -> String {
         "chain".to_string()
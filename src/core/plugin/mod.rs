@@ -19,7 +19,6 @@ pub mod connector;
 pub mod filter;
 pub mod loadbalance;
 pub mod location;
-pub mod lossless;
 pub mod plugins;
 pub mod ratelimit;
 pub mod router;
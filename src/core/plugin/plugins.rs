@@ -15,7 +15,9 @@
 
 use crate::core::config::config::Configuration;
 use crate::core::config::config_file::ConfigFilter;
-use crate::core::config::consumer::{ServiceRouterConfig, ServiceRouterPluginConfig};
+use crate::core::config::consumer::{
+    LoadBalancerConfig, LoadBalancerPluginConfig, ServiceRouterConfig, ServiceRouterPluginConfig,
+};
 use crate::core::config::global::{LocalCacheConfig, LocationConfig, ServerConnectorConfig};
 use crate::core::model::error::{ErrorCode, PolarisError};
 use crate::core::model::ClientContext;
@@ -26,22 +28,24 @@ use crate::plugins::cache::memory::memory::MemoryCache;
 use crate::plugins::circuitbreaker::composite::circuitbreaker::CompositeCircuitBreaker;
 use crate::plugins::connector::grpc::connector::GrpcConnector;
 use crate::plugins::filter::configcrypto::crypto::ConfigFileCryptoFilter;
+use crate::plugins::loadbalance::leastrequest::leastrequest::LeastRequestLoadBalancer;
+use crate::plugins::loadbalance::maglev::maglev::MaglevLoadBalancer;
 use crate::plugins::loadbalance::random::random::WeightRandomLoadbalancer;
 use crate::plugins::loadbalance::ringhash::ringhash::ConsistentHashLoadBalancer;
 use crate::plugins::loadbalance::roundrobin::roundrobin::WeightedRoundRobinBalancer;
-use crate::plugins::location::local::local::LocalLocationSupplier;
-use crate::plugins::location::remotehttp::remotehttp::RemoteHttpLocationSupplier;
 use crate::plugins::ratelimit::concurrency::concurrency::ConcurrencyLimiter;
 use crate::plugins::router::health::health::HealthRouter;
 use crate::plugins::router::lane::lane::LaneRouter;
 use crate::plugins::router::metadata::metadata::MetadataRouter;
+use crate::plugins::router::namespace::namespace::NamespaceRouter;
 use crate::plugins::router::nearby::nearby::NearbyRouter;
+use crate::plugins::router::priority::priority::PriorityRouter;
 use crate::plugins::router::rule::rule::RuleRouter;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::hash::Hash;
-use std::net::{IpAddr, ToSocketAddrs};
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
 use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, RwLock};
 use std::{env, fmt};
@@ -51,8 +55,8 @@ use super::cache::{InitResourceCacheOption, ResourceCacheFailover};
 use super::circuitbreaker::CircuitBreaker;
 use super::connector::InitConnectorOption;
 use super::filter::DiscoverFilter;
-use super::loadbalance::LoadBalancer;
-use super::location::{LocationProvider, LocationSupplier, LocationType};
+use super::loadbalance::{DynamicWeightManager, LoadBalancer};
+use super::location::{new_location_provider, LocationProvider};
 use super::ratelimit::ServiceRateLimiter;
 use super::router::RouterContainer;
 
@@ -111,6 +115,8 @@ where
     pub service_routers: Option<Arc<RouterContainer>>,
     // load_balancers 负载均衡器
     pub load_balancers: Arc<tokio::sync::RwLock<HashMap<String, Arc<Box<dyn LoadBalancer>>>>>,
+    // dynamic_weight 动态权重管理
+    pub dynamic_weight: Arc<DynamicWeightManager>,
 }
 
 impl Extensions {
@@ -130,6 +136,10 @@ impl Extensions {
             resource_cache: None,
             service_routers: None,
             load_balancers: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+            dynamic_weight: Arc::new(DynamicWeightManager::new(
+                conf.consumer.load_balancer.dynamic_weight.clone(),
+                conf.consumer.load_balancer.warmup.clone(),
+            )),
         };
 
         let ret = extension.load_all_plugins(conf.clone());
@@ -171,7 +181,7 @@ impl Extensions {
         }
 
         // 初始化 loadbalancers
-        let ret = self.load_loadbalancers();
+        let ret = self.load_loadbalancers(&conf.consumer.load_balancer);
         if ret.is_err() {
             return Err(ret.err().unwrap());
         }
@@ -193,10 +203,27 @@ impl Extensions {
         self.load_balancers.clone()
     }
 
+    pub fn get_dynamic_weight(&self) -> Arc<DynamicWeightManager> {
+        self.dynamic_weight.clone()
+    }
+
     pub fn get_location_provider(&self) -> Arc<LocationProvider> {
         self.locatin_provider.clone().unwrap()
     }
 
+    /// destroy 销毁插件，取消插件启动的后台任务
+    pub fn destroy(&self) {
+        if let Some(cache) = self.resource_cache.as_ref() {
+            cache.destroy();
+        }
+        if let Some(connector) = self.server_connector.as_ref() {
+            connector.destroy();
+        }
+        if let Some(provider) = self.locatin_provider.as_ref() {
+            provider.destroy();
+        }
+    }
+
     pub fn get_router_container(&self) -> Arc<RouterContainer> {
         self.service_routers.clone().unwrap()
     }
@@ -289,44 +316,100 @@ impl Extensions {
         route_conf: &ServiceRouterConfig,
     ) -> Result<(), PolarisError> {
         let mut container = RouterContainer::new();
-        for (name, supplier) in CLIENT_PLUGIN_CONTAINER
-            .read()
-            .unwrap()
-            .service_routers
-            .iter()
-        {
-            for (_i, router_conf) in route_conf.before_chain.iter().enumerate() {
-                if router_conf.name == name.to_string() {
-                    let router = supplier(router_conf);
-                    container
-                        .before_routers
-                        .insert(name.clone(), Arc::new(router));
-                }
-            }
-            for (_i, router_conf) in route_conf.core_chain.iter().enumerate() {
-                if router_conf.name == name.to_string() {
-                    let router = supplier(router_conf);
-                    container
-                        .core_routers
-                        .insert(name.clone(), Arc::new(router));
+        let plugin_container = CLIENT_PLUGIN_CONTAINER.read().unwrap();
+        let registered = &plugin_container.service_routers;
+
+        // 全局路由链，未注册的路由插件直接忽略
+        let stages = [
+            (
+                &route_conf.before_chain,
+                &mut container.before_routers,
+                &mut container.default_chain.before,
+            ),
+            (
+                &route_conf.core_chain,
+                &mut container.core_routers,
+                &mut container.default_chain.core,
+            ),
+            (
+                &route_conf.after_chain,
+                &mut container.after_routers,
+                &mut container.default_chain.after,
+            ),
+        ];
+        for (chain_conf, routers, chain) in stages {
+            for router_conf in chain_conf.iter() {
+                match registered.get(&router_conf.name) {
+                    Some(supplier) => {
+                        routers.insert(router_conf.name.clone(), Arc::new(supplier(router_conf)?));
+                        chain.push(router_conf.name.clone());
+                    }
+                    None => {
+                        crate::warn!(
+                            "[polaris][plugin] service router {} not registered, ignore it",
+                            router_conf.name
+                        );
+                    }
                 }
             }
+        }
 
-            for (_i, router_conf) in route_conf.after_chain.iter().enumerate() {
-                if router_conf.name == name.to_string() {
-                    let router = supplier(router_conf);
-                    container
-                        .after_routers
-                        .insert(name.clone(), Arc::new(router));
+        // 被调服务维度的路由链，引用的路由插件必须已经注册
+        for callee_conf in route_conf.callee_chains.iter() {
+            let mut chain = container.default_chain.clone();
+            let stages = [
+                (
+                    &callee_conf.before_chain,
+                    &mut container.before_routers,
+                    &mut chain.before,
+                ),
+                (
+                    &callee_conf.core_chain,
+                    &mut container.core_routers,
+                    &mut chain.core,
+                ),
+                (
+                    &callee_conf.after_chain,
+                    &mut container.after_routers,
+                    &mut chain.after,
+                ),
+            ];
+            for (names, routers, chain) in stages {
+                let names = match names {
+                    Some(names) => names,
+                    None => continue,
+                };
+                for name in names.iter() {
+                    let supplier = registered.get(name).ok_or_else(|| {
+                        PolarisError::new(
+                            ErrorCode::InvalidConfig,
+                            format!(
+                                "service router {} of callee {}/{} not registered",
+                                name, callee_conf.namespace, callee_conf.service
+                            ),
+                        )
+                    })?;
+                    if !routers.contains_key(name) {
+                        let router = supplier(&ServiceRouterPluginConfig {
+                            name: name.clone(),
+                            options: None,
+                        })?;
+                        routers.insert(name.clone(), Arc::new(router));
+                    }
                 }
+                chain.clone_from(names);
             }
+            container.callee_chains.insert(
+                format!("{}#{}", callee_conf.namespace, callee_conf.service),
+                chain,
+            );
         }
 
         self.service_routers = Some(Arc::new(container));
         Ok(())
     }
 
-    fn load_loadbalancers(&mut self) -> Result<(), PolarisError> {
+    fn load_loadbalancers(&mut self, conf: &LoadBalancerConfig) -> Result<(), PolarisError> {
         let mut loadbalancers = HashMap::<String, Arc<Box<dyn LoadBalancer>>>::new();
         for (name, supplier) in CLIENT_PLUGIN_CONTAINER
             .read()
@@ -334,7 +417,7 @@ impl Extensions {
             .load_balancers
             .iter()
         {
-            let lb = supplier();
+            let lb = supplier(&conf.get_plugin_config(name));
             loadbalancers.insert(name.clone(), Arc::new(lb));
         }
         self.load_balancers = Arc::new(tokio::sync::RwLock::new(loadbalancers));
@@ -342,29 +425,7 @@ impl Extensions {
     }
 
     fn load_location_providers(&mut self, opt: &LocationConfig) -> Result<(), PolarisError> {
-        let mut chain = Vec::<Box<dyn LocationSupplier>>::new();
-        let providers = opt.clone().providers;
-        if providers.is_none() {
-            return Err(PolarisError::new(
-                ErrorCode::ApiInvalidArgument,
-                "".to_string(),
-            ));
-        }
-
-        providers.unwrap().iter().for_each(|provider| {
-            let name: String = provider.name.clone();
-            match LocationType::parse(name.as_str()) {
-                LocationType::Local => {
-                    chain.push(Box::new(LocalLocationSupplier::new(provider.clone())));
-                }
-                LocationType::Http => {
-                    chain.push(Box::new(RemoteHttpLocationSupplier::new(provider.clone())));
-                }
-                LocationType::Service => {}
-            }
-        });
-
-        let ret = Arc::new(LocationProvider { chain: chain });
+        let ret = Arc::new(new_location_provider(opt, self.runtime.clone())?);
         self.locatin_provider = Some(ret.clone());
         return Ok(());
     }
@@ -388,9 +449,12 @@ pub struct PluginContainer {
         HashMap<String, fn(serde_yaml::Value) -> Result<Box<dyn DiscoverFilter>, PolarisError>>,
     /// ------- 治理规则相关插件 -------
     // service_routers: 路由器
-    service_routers: HashMap<String, fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>>,
+    service_routers: HashMap<
+        String,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
+    >,
     // load_balancers: 负载均衡器
-    load_balancers: HashMap<String, fn() -> Box<dyn LoadBalancer>>,
+    load_balancers: HashMap<String, fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>>,
     // circuit_breakers: 熔断器
     circuit_breakers: HashMap<String, fn() -> Box<dyn CircuitBreaker>>,
     // ratelimiter: 限流器
@@ -431,7 +495,9 @@ impl PluginContainer {
             HealthRouter::builder,
             LaneRouter::builder,
             MetadataRouter::builder,
+            NamespaceRouter::builder,
             NearbyRouter::builder,
+            PriorityRouter::builder,
             RuleRouter::builder,
         ];
         for c in vec {
@@ -452,6 +518,8 @@ impl PluginContainer {
         let vec = vec![
             WeightRandomLoadbalancer::builder,
             ConsistentHashLoadBalancer::builder,
+            MaglevLoadBalancer::builder,
+            LeastRequestLoadBalancer::builder,
             WeightedRoundRobinBalancer::builder,
         ];
         for c in vec {
@@ -505,7 +573,7 @@ impl PluginContainer {
     pub fn register_custom_service_router(
         &mut self,
         name: String,
-        supplier: fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        supplier: fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
     ) {
         self.service_routers.insert(name, supplier);
     }
@@ -514,7 +582,7 @@ impl PluginContainer {
     pub fn register_custom_load_balancer(
         &mut self,
         name: String,
-        supplier: fn() -> Box<dyn LoadBalancer>,
+        supplier: fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
     ) {
         self.load_balancers.insert(name, supplier);
     }
@@ -525,6 +593,17 @@ impl PluginContainer {
     }
 }
 
+/// register_service_router 注册自定义的服务路由插件，需要在创建 SDKContext 之前调用
+pub fn register_service_router(
+    name: String,
+    supplier: fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
+) {
+    CLIENT_PLUGIN_CONTAINER
+        .write()
+        .unwrap()
+        .register_custom_service_router(name, supplier);
+}
+
 pub fn acquire_client_context(conf: Arc<Configuration>) -> ClientContext {
     let mut client_id = conf.global.client.id.clone();
     let self_ip = acquire_client_self_ip(conf.clone());
@@ -554,24 +633,37 @@ pub fn acquire_client_context(conf: Arc<Configuration>) -> ClientContext {
 }
 
 pub fn acquire_client_self_ip(conf: Arc<Configuration>) -> String {
-    // 和北极星服务端做一个 TCP connect 连接获取 本地 IP 地址
+    // 和北极星服务端做一个 UDP connect 获取本机访问北极星服务端所使用的 IP 地址，UDP connect 不会发送任何报文
     let host = conf.global.server_connectors.addresses.first();
 
     let mut origin_endpoint = host.unwrap().as_str().trim_start_matches("discover://");
     origin_endpoint = origin_endpoint.trim_start_matches("config://");
-    let addrs = (origin_endpoint).to_socket_addrs();
-    match addrs {
-        Ok(mut addr_iter) => {
-            if let Some(addr) = addr_iter.next() {
-                if let IpAddr::V4(ipv4) = addr.ip() {
-                    return format!("{}", ipv4);
-                } else if let IpAddr::V6(ipv6) = addr.ip() {
-                    return format!("{}", ipv6);
-                }
+    let addr = match origin_endpoint.to_socket_addrs() {
+        Ok(mut addr_iter) => match addr_iter.next() {
+            Some(addr) => addr,
+            None => {
+                crate::error!("acquire_client_self_ip not ipv4 or ipv6, impossible run here");
+                return "127.0.0.1".to_string();
             }
-            crate::error!("acquire_client_self_ip not ipv4 or ipv6, impossible run here");
-            "127.0.0.1".to_string()
+        },
+        Err(_err) => {
+            crate::error!("acquire_client_self_ip error: {:?}", _err);
+            return "127.0.0.1".to_string();
         }
+    };
+    let bind_addr = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let local_addr = UdpSocket::bind(bind_addr)
+        .and_then(|socket| socket.connect(addr).map(|_| socket))
+        .and_then(|socket| socket.local_addr());
+    match local_addr {
+        Ok(local_addr) => match local_addr.ip() {
+            IpAddr::V4(ipv4) => format!("{}", ipv4),
+            IpAddr::V6(ipv6) => format!("{}", ipv6),
+        },
         Err(_err) => {
             crate::error!("acquire_client_self_ip error: {:?}", _err);
             "127.0.0.1".to_string()
@@ -15,11 +15,13 @@
 
 use std::{collections::HashMap, sync::Arc};
 
+use tracing::Instrument;
+
 use crate::core::{
     model::{
-        error::PolarisError,
-        naming::ServiceInstances,
-        router::{RouteInfo, RouteResult},
+        error::{ErrorCode, PolarisError},
+        naming::{ServiceInstances, ServiceKey},
+        router::{RouteInfo, RouteResult, RouterChain, RouterTraceItem},
     },
     plugin::plugins::Plugin,
 };
@@ -31,6 +33,10 @@ pub struct RouterContainer {
     pub before_routers: HashMap<String, Arc<Box<dyn ServiceRouter>>>,
     pub core_routers: HashMap<String, Arc<Box<dyn ServiceRouter>>>,
     pub after_routers: HashMap<String, Arc<Box<dyn ServiceRouter>>>,
+    // default_chain 全局配置的路由链
+    pub default_chain: RouterChain,
+    // callee_chains 被调服务维度的路由链 key: namespace#service
+    pub callee_chains: HashMap<String, RouterChain>,
 }
 
 impl RouterContainer {
@@ -39,7 +45,133 @@ impl RouterContainer {
             before_routers: HashMap::new(),
             core_routers: HashMap::new(),
             after_routers: HashMap::new(),
+            default_chain: RouterChain::default(),
+            callee_chains: HashMap::new(),
+        }
+    }
+
+    /// lookup_chain 查找被调服务的路由链，优先精确匹配，其次匹配命名空间下的 *，最后使用全局路由链
+    pub fn lookup_chain(&self, callee: &ServiceKey) -> RouterChain {
+        let exact_key = format!("{}#{}", callee.namespace, callee.name);
+        if let Some(chain) = self.callee_chains.get(&exact_key) {
+            return chain.clone();
+        }
+        let wildcard_key = format!("{}#*", callee.namespace);
+        if let Some(chain) = self.callee_chains.get(&wildcard_key) {
+            return chain.clone();
+        }
+        self.default_chain.clone()
+    }
+
+    /// check_chain 检查路由链中的路由插件是否都已经加载
+    pub fn check_chain(&self, chain: &RouterChain) -> Result<(), PolarisError> {
+        let stages = [
+            ("before", &chain.before, &self.before_routers),
+            ("core", &chain.core, &self.core_routers),
+            ("after", &chain.after, &self.after_routers),
+        ];
+        for (stage, names, routers) in stages {
+            for name in names.iter() {
+                if !routers.contains_key(name) {
+                    return Err(PolarisError::new(
+                        ErrorCode::ApiInvalidArgument,
+                        format!("router {} not found in {} chain", name, stage),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// resolve_chain 确定本次路由使用的路由链，优先级: chain_override > route_info.chain > 被调服务维度配置 > 全局配置
+    ///
+    /// route_info.chain 为空时视为未指定，会回退到被调服务维度或者全局的路由链；
+    /// 需要跳过全部路由插件时，应通过 chain_override 传入一个空的路由链
+    pub fn resolve_chain(
+        &self,
+        route_info: &RouteInfo,
+        chain_override: Option<RouterChain>,
+    ) -> Result<RouterChain, PolarisError> {
+        if let Some(chain) = chain_override {
+            self.check_chain(&chain)?;
+            return Ok(chain);
         }
+        if route_info.chain.is_empty() {
+            return Ok(self.lookup_chain(&route_info.callee));
+        }
+        Ok(route_info.chain.clone())
+    }
+
+    /// execute_chain 按照 before -> core -> after 的顺序执行 route_ctx 中的路由链，trace 为 true 时记录每个路由插件的执行情况
+    pub async fn execute_chain(
+        &self,
+        route_ctx: &RouteContext,
+        instances: ServiceInstances,
+        trace: bool,
+    ) -> Result<(ServiceInstances, Vec<RouterTraceItem>), PolarisError> {
+        let chain = &route_ctx.route_info.chain;
+        let stages = [
+            ("before", &chain.before, &self.before_routers),
+            ("core", &chain.core, &self.core_routers),
+            ("after", &chain.after, &self.after_routers),
+        ];
+
+        let mut traces = Vec::<RouterTraceItem>::new();
+        let mut tmp_instance = instances;
+        for (stage, names, routers) in stages {
+            for name in names.iter() {
+                let router = match routers.get(name) {
+                    Some(router) => router.clone(),
+                    None => continue,
+                };
+                if !trace {
+                    tmp_instance = router
+                        .choose_instances(route_ctx.clone(), tmp_instance)
+                        .await?
+                        .instances;
+                    continue;
+                }
+
+                let input_count = tmp_instance.instances.len();
+                let span = tracing::info_span!(
+                    "polaris.router",
+                    chain = stage,
+                    router = name.as_str(),
+                    callee = %format!(
+                        "{}/{}",
+                        route_ctx.route_info.callee.namespace, route_ctx.route_info.callee.name
+                    ),
+                    input_count,
+                    output_count = tracing::field::Empty,
+                    matched_rule = tracing::field::Empty,
+                    failover = tracing::field::Empty,
+                );
+                let ret = router
+                    .choose_instances(route_ctx.clone(), tmp_instance)
+                    .instrument(span.clone())
+                    .await?;
+
+                let item = RouterTraceItem {
+                    chain: stage.to_string(),
+                    router: name.clone(),
+                    matched_rule: ret.matched_rule,
+                    input_count,
+                    output_count: ret.instances.instances.len(),
+                    failover: ret.failover,
+                };
+                span.record("output_count", item.output_count);
+                if let Some(rule) = &item.matched_rule {
+                    span.record("matched_rule", rule.as_str());
+                }
+                if let Some(failover) = &item.failover {
+                    span.record("failover", failover.as_str());
+                }
+                traces.push(item);
+                tmp_instance = ret.instances;
+            }
+        }
+
+        Ok((tmp_instance, traces))
     }
 }
 
@@ -61,3 +193,267 @@ pub trait ServiceRouter: Plugin {
     /// enable 是否启用
     async fn enable(&self, route_info: RouteContext, instances: ServiceInstances) -> bool;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::{Instance, ServiceInfo};
+    use crate::core::model::router::RouteState;
+
+    // KeepRouter 只保留前 keep 个实例的测试路由插件
+    struct KeepRouter {
+        keep: usize,
+        matched_rule: Option<String>,
+        failover: Option<String>,
+    }
+
+    impl Plugin for KeepRouter {
+        fn init(&mut self) {}
+
+        fn destroy(&self) {}
+
+        fn name(&self) -> String {
+            "keep".to_string()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ServiceRouter for KeepRouter {
+        async fn choose_instances(
+            &self,
+            _route_info: RouteContext,
+            instances: ServiceInstances,
+        ) -> Result<RouteResult, PolarisError> {
+            let ins = instances.instances.into_iter().take(self.keep).collect();
+            Ok(RouteResult {
+                state: RouteState::Next,
+                instances: ServiceInstances::new(instances.service, ins),
+                matched_rule: self.matched_rule.clone(),
+                failover: self.failover.clone(),
+            })
+        }
+
+        async fn enable(&self, _route_info: RouteContext, _instances: ServiceInstances) -> bool {
+            true
+        }
+    }
+
+    fn keep_router(
+        keep: usize,
+        matched_rule: Option<&str>,
+        failover: Option<&str>,
+    ) -> Arc<Box<dyn ServiceRouter>> {
+        Arc::new(Box::new(KeepRouter {
+            keep,
+            matched_rule: matched_rule.map(|v| v.to_string()),
+            failover: failover.map(|v| v.to_string()),
+        }))
+    }
+
+    fn new_instances(count: usize) -> ServiceInstances {
+        let ins = (0..count)
+            .map(|i| Instance {
+                id: i.to_string(),
+                weight: 100,
+                ..Default::default()
+            })
+            .collect();
+        ServiceInstances::new(ServiceInfo::default(), ins)
+    }
+
+    fn new_route_ctx(chain: RouterChain) -> RouteContext {
+        RouteContext {
+            route_info: RouteInfo {
+                chain,
+                ..Default::default()
+            },
+            extensions: None,
+        }
+    }
+
+    fn new_container() -> RouterContainer {
+        let mut container = RouterContainer::new();
+        container
+            .before_routers
+            .insert("b".to_string(), keep_router(4, None, None));
+        container
+            .core_routers
+            .insert("c1".to_string(), keep_router(2, Some("rule-1"), None));
+        container.core_routers.insert(
+            "c2".to_string(),
+            keep_router(1, None, Some("degrade from zone to region")),
+        );
+        container
+    }
+
+    #[tokio::test]
+    async fn test_execute_chain_trace() {
+        let container = new_container();
+        let ctx = new_route_ctx(RouterChain {
+            before: vec!["b".to_string()],
+            // 未注册的路由插件直接跳过，不记录执行情况
+            core: vec!["c1".to_string(), "unknown".to_string(), "c2".to_string()],
+            after: vec![],
+        });
+
+        let (ins, traces) = container
+            .execute_chain(&ctx, new_instances(5), true)
+            .await
+            .unwrap();
+        assert_eq!(1, ins.instances.len());
+
+        let summary: Vec<(&str, &str, usize, usize)> = traces
+            .iter()
+            .map(|t| {
+                (
+                    t.chain.as_str(),
+                    t.router.as_str(),
+                    t.input_count,
+                    t.output_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("before", "b", 5, 4),
+                ("core", "c1", 4, 2),
+                ("core", "c2", 2, 1)
+            ],
+            summary
+        );
+        assert_eq!(None, traces[0].matched_rule);
+        assert_eq!(Some("rule-1".to_string()), traces[1].matched_rule);
+        assert_eq!(
+            Some("degrade from zone to region".to_string()),
+            traces[2].failover
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_chain_without_trace() {
+        let container = new_container();
+        let ctx = new_route_ctx(RouterChain {
+            before: vec!["b".to_string()],
+            core: vec!["c1".to_string()],
+            after: vec![],
+        });
+
+        let (ins, traces) = container
+            .execute_chain(&ctx, new_instances(5), false)
+            .await
+            .unwrap();
+        assert_eq!(2, ins.instances.len());
+        assert!(traces.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execute_chain_after_routers() {
+        let mut container = new_container();
+        container
+            .after_routers
+            .insert("a".to_string(), keep_router(3, None, None));
+        // 后置路由只从 after_routers 中查找，before_routers 中的同名插件不参与 after 阶段
+        let ctx = new_route_ctx(RouterChain {
+            before: vec![],
+            core: vec![],
+            after: vec!["a".to_string(), "b".to_string()],
+        });
+
+        let (ins, traces) = container
+            .execute_chain(&ctx, new_instances(5), true)
+            .await
+            .unwrap();
+        assert_eq!(3, ins.instances.len());
+        assert_eq!(1, traces.len());
+        assert_eq!("after", traces[0].chain);
+        assert_eq!("a", traces[0].router);
+    }
+
+    fn core_chain(names: &[&str]) -> RouterChain {
+        RouterChain {
+            core: names.iter().map(|n| n.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn new_chain_container() -> RouterContainer {
+        let mut container = new_container();
+        container.default_chain = core_chain(&["c1", "c2"]);
+        container
+            .callee_chains
+            .insert("default#echo".to_string(), core_chain(&["c1"]));
+        container
+            .callee_chains
+            .insert("default#*".to_string(), core_chain(&["c2"]));
+        container
+    }
+
+    #[test]
+    fn test_lookup_chain() {
+        let container = new_chain_container();
+        // 精确匹配优先
+        let chain = container.lookup_chain(&ServiceKey::new("default".into(), "echo".into()));
+        assert_eq!(vec!["c1"], chain.core);
+        // 其次匹配命名空间下的 *
+        let chain = container.lookup_chain(&ServiceKey::new("default".into(), "other".into()));
+        assert_eq!(vec!["c2"], chain.core);
+        // 最后使用全局路由链
+        let chain = container.lookup_chain(&ServiceKey::new("test".into(), "echo".into()));
+        assert_eq!(vec!["c1", "c2"], chain.core);
+    }
+
+    #[test]
+    fn test_check_chain() {
+        let container = new_chain_container();
+        assert!(container.check_chain(&core_chain(&["c1", "c2"])).is_ok());
+        assert!(container.check_chain(&RouterChain::default()).is_ok());
+
+        let err = container.check_chain(&core_chain(&["c1", "unknown"]));
+        assert!(matches!(err, Err(ref e) if e.get_code() == ErrorCode::ApiInvalidArgument));
+        // 路由插件需要注册在对应的阶段
+        let chain = RouterChain {
+            after: vec!["b".to_string()],
+            ..Default::default()
+        };
+        assert!(container.check_chain(&chain).is_err());
+    }
+
+    #[test]
+    fn test_resolve_chain() {
+        let container = new_chain_container();
+        let mut route_info = RouteInfo {
+            callee: ServiceKey::new("default".into(), "echo".into()),
+            ..Default::default()
+        };
+
+        // route_info.chain 为空时回退到被调服务维度的路由链
+        let chain = container.resolve_chain(&route_info, None).unwrap();
+        assert_eq!(vec!["c1"], chain.core);
+        // 没有被调服务维度的配置时回退到全局路由链
+        let other = RouteInfo {
+            callee: ServiceKey::new("test".into(), "echo".into()),
+            ..Default::default()
+        };
+        let chain = container.resolve_chain(&other, None).unwrap();
+        assert_eq!(vec!["c1", "c2"], chain.core);
+
+        // 指定了 route_info.chain 时直接使用
+        route_info.chain = core_chain(&["c2"]);
+        let chain = container.resolve_chain(&route_info, None).unwrap();
+        assert_eq!(vec!["c2"], chain.core);
+
+        // chain_override 优先级最高，空的 chain_override 表示跳过全部路由插件
+        let chain = container
+            .resolve_chain(&route_info, Some(RouterChain::default()))
+            .unwrap();
+        assert!(chain.is_empty());
+        let chain = container
+            .resolve_chain(&route_info, Some(core_chain(&["c1"])))
+            .unwrap();
+        assert_eq!(vec!["c1"], chain.core);
+        // chain_override 中存在未注册的路由插件时返回错误
+        assert!(container
+            .resolve_chain(&route_info, Some(core_chain(&["unknown"])))
+            .is_err());
+    }
+}
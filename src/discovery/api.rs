@@ -14,9 +14,12 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::context::SDKContext;
 use crate::core::model::error::PolarisError;
+use crate::core::model::naming::{ServiceInstancesChangeEvent, ServicesChangeEvent};
+use crate::core::model::watch::WatchStream;
 use crate::discovery::default::{DefaultConsumerAPI, DefaultLosslessAPI, DefaultProviderAPI};
 use crate::discovery::req::*;
 
@@ -56,7 +59,26 @@ where
     /// heartbeat 实例心跳上报
     async fn heartbeat(&self, req: InstanceHeartbeatRequest) -> Result<(), PolarisError>;
 
-    /// report_service_contract 上报服务接口定义信息
+    /// batch_register 批量注册实例，北极星客户端协议没有批量注册接口，这里会并发发送单实例请求，
+    /// 返回的结果与请求中实例的顺序一一对应
+    async fn batch_register(
+        &self,
+        req: BatchInstanceRegisterRequest,
+    ) -> BatchInstanceResponse<InstanceRegisterResponse>;
+
+    /// batch_deregister 批量反注册实例，返回的结果与请求中实例的顺序一一对应
+    async fn batch_deregister(
+        &self,
+        req: BatchInstanceDeregisterRequest,
+    ) -> BatchInstanceResponse<()>;
+
+    /// batch_heartbeat 批量上报实例心跳，返回的结果与请求中实例的顺序一一对应
+    async fn batch_heartbeat(
+        &self,
+        req: BatchInstanceHeartbeatRequest,
+    ) -> BatchInstanceResponse<()>;
+
+    /// report_service_contract 上报服务接口定义信息，契约内容摘要未变化时不会重复上报
     async fn report_service_contract(
         &self,
         req: ReportServiceContractRequest,
@@ -64,6 +86,10 @@ where
 
     /// close 关闭 ProviderAPI 实例
     async fn close(&mut self);
+
+    /// shutdown 停止自动心跳并反注册通过当前 SDKContext 注册的实例，管理 SDKContext 生命周期时同时关闭 SDKContext，
+    /// timeout 为等待反注册完成的最长时间
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
 
 /// new_consumer_api
@@ -94,6 +120,12 @@ where
         req: GetOneInstanceRequest,
     ) -> Result<InstanceResponse, PolarisError>;
 
+    /// get_instance_candidates 拉取多个按照优先级排序的不同实例，首选实例之后的实例用于调用失败后的重试
+    async fn get_instance_candidates(
+        &self,
+        req: GetInstanceCandidatesRequest,
+    ) -> Result<InstanceCandidatesResponse, PolarisError>;
+
     /// get_health_instance 拉取健康实例
     async fn get_health_instance(
         &self,
@@ -112,44 +144,90 @@ where
         req: WatchInstanceRequest,
     ) -> Result<WatchInstanceResponse, PolarisError>;
 
+    /// watch_instance_stream 以事件流的方式监听实例变化，首个事件为当前的实例列表，drop 事件流时自动取消监听，
+    /// 事件的变更差异基于该事件流上一次消费的实例计算，消费过慢丢弃事件时不会遗漏变更
+    async fn watch_instance_stream(
+        &self,
+        req: WatchInstanceStreamRequest,
+    ) -> Result<WatchStream<ServiceInstancesChangeEvent>, PolarisError>;
+
+    /// get_services 获取命名空间下的服务列表，支持按照服务元数据过滤
+    async fn get_services(&self, req: GetServicesRequest)
+        -> Result<ServicesResponse, PolarisError>;
+
+    /// watch_services 监听命名空间下的服务列表变化
+    async fn watch_services(
+        &self,
+        req: WatchServicesRequest,
+    ) -> Result<WatchServicesResponse, PolarisError>;
+
+    /// watch_services_stream 以事件流的方式监听服务列表变化，首个事件为当前的服务列表，drop 事件流时自动取消监听
+    async fn watch_services_stream(
+        &self,
+        req: WatchServicesStreamRequest,
+    ) -> Result<WatchStream<ServicesChangeEvent>, PolarisError>;
+
     /// get_service_rule 获取服务规则
     async fn get_service_rule(
         &self,
         req: GetServiceRuleRequest,
     ) -> Result<ServiceRuleResponse, PolarisError>;
 
+    /// watch_service_rule_stream 以事件流的方式监听服务规则变化，首个事件为当前的规则，drop 事件流时自动取消监听
+    async fn watch_service_rule_stream(
+        &self,
+        req: WatchServiceRuleStreamRequest,
+    ) -> Result<WatchStream<ServiceRuleChangeEvent>, PolarisError>;
+
     /// report_service_call 上报服务调用结果
     async fn report_service_call(&self, req: ServiceCallResult);
+
+    /// shutdown 关闭 ConsumerAPI 实例，取消所有监听，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
 
 /// new_lossless_api 创建优雅上下线客户端实例
-pub(crate) fn new_lossless_api() -> Result<impl LosslessAPI, PolarisError> {
+pub fn new_lossless_api() -> Result<impl LosslessAPI, PolarisError> {
     let context_ret = SDKContext::default();
     if context_ret.is_err() {
         return Err(context_ret.err().unwrap());
     }
 
-    Ok(DefaultLosslessAPI::new(context_ret.unwrap()))
+    Ok(DefaultLosslessAPI::new_raw(context_ret.unwrap()))
 }
 
 /// new_lossless_api_by_context 创建优雅上下线客户端实例
-pub(crate) fn new_lossless_api_by_context(
-    context: SDKContext,
-) -> Result<Arc<dyn LosslessAPI>, PolarisError> {
-    Ok(Arc::new(DefaultLosslessAPI::new(context)))
+pub fn new_lossless_api_by_context(
+    context: Arc<SDKContext>,
+) -> Result<impl LosslessAPI, PolarisError> {
+    Ok(DefaultLosslessAPI::new(context))
 }
 
 /// LosslessAPI 负责优雅上下线客户端的生命周期管理
-pub(crate) trait LosslessAPI {
-    fn set_action_provider(
+#[async_trait::async_trait]
+pub trait LosslessAPI
+where
+    Self: Send + Sync,
+{
+    /// set_action_provider 设置实例上下线时执行的动作
+    async fn set_action_provider(
         &self,
         ins: Arc<dyn BaseInstance>,
         action: Arc<dyn LosslessActionProvider>,
     );
 
-    fn lossless_register(&self, ins: Arc<dyn BaseInstance>);
+    /// lossless_register 优雅上线，就绪检查通过或者延迟注册时间到达后才注册实例
+    async fn lossless_register(&self, ins: Arc<dyn BaseInstance>) -> Result<(), PolarisError>;
+
+    /// lossless_deregister 优雅下线，先反注册实例，再等待存量请求处理完成
+    async fn lossless_deregister(&self, ins: Arc<dyn BaseInstance>) -> Result<(), PolarisError>;
+
+    /// is_ready 所有实例是否都已经上线，与 admin 接口 /readiness 的结果一致
+    async fn is_ready(&self) -> bool;
 
-    fn lossless_deregister(&self, ins: Arc<dyn BaseInstance>);
+    /// shutdown 进程退出前调用，下线所有实例、等待存量请求处理完成并关闭 admin 接口，
+    /// 管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
 
 mod tests {
@@ -0,0 +1,322 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use sha2::{Digest, Sha256};
+
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::core::model::naming::{ServiceContract, ServiceInterfaceDescripitor};
+
+pub static CONTRACT_PROTOCOL_GRPC: &str = "grpc";
+pub static CONTRACT_PROTOCOL_HTTP: &str = "http";
+
+// OpenAPI 中 path item 下表示 http method 的字段
+const HTTP_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// build_grpc_contract 根据 protobuf 的 FileDescriptorSet 生成 gRPC 服务契约，每个 rpc 方法对应一个接口，
+/// descriptor_set 一般来自 tonic-build 的 file_descriptor_set_path 或者 tonic-reflection 使用的描述文件
+pub fn build_grpc_contract(
+    namespace: &str,
+    service: &str,
+    descriptor_set: &[u8],
+) -> Result<ServiceContract, PolarisError> {
+    let set = FileDescriptorSet::decode(descriptor_set).map_err(|e| {
+        PolarisError::new(
+            ErrorCode::ApiInvalidArgument,
+            format!("decode file descriptor set fail: {}", e),
+        )
+    })?;
+    let mut interfaces = Vec::new();
+    for file in set.file.iter() {
+        for svc in file.service.iter() {
+            let path = match file.package() {
+                "" => svc.name().to_string(),
+                package => format!("{}.{}", package, svc.name()),
+            };
+            for method in svc.method.iter() {
+                let content = serde_json::json!({
+                    "input_type": method.input_type(),
+                    "output_type": method.output_type(),
+                    "client_streaming": method.client_streaming(),
+                    "server_streaming": method.server_streaming(),
+                });
+                interfaces.push(ServiceInterfaceDescripitor {
+                    name: CONTRACT_PROTOCOL_GRPC.to_string(),
+                    namespace: namespace.to_string(),
+                    service: service.to_string(),
+                    version: String::new(),
+                    protocol: CONTRACT_PROTOCOL_GRPC.to_string(),
+                    path: path.clone(),
+                    method: method.name().to_string(),
+                    content: content.to_string(),
+                });
+            }
+        }
+    }
+    Ok(new_contract(
+        namespace,
+        service,
+        CONTRACT_PROTOCOL_GRPC,
+        String::new(),
+        interfaces,
+    ))
+}
+
+/// build_openapi_contract 根据 OpenAPI 3 或 Swagger 2 文档（json/yaml）生成 http 服务契约，
+/// 每个 path + method 对应一个接口，契约版本使用文档中的 info.version
+pub fn build_openapi_contract(
+    namespace: &str,
+    service: &str,
+    document: &str,
+) -> Result<ServiceContract, PolarisError> {
+    // yaml 兼容 json，统一使用 yaml 解析
+    let doc: serde_yaml::Value = serde_yaml::from_str(document).map_err(|e| {
+        PolarisError::new(
+            ErrorCode::ApiInvalidArgument,
+            format!("parse openapi document fail: {}", e),
+        )
+    })?;
+    let paths = doc
+        .get("paths")
+        .and_then(|paths| paths.as_mapping())
+        .ok_or_else(|| {
+            PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "openapi document has no paths".to_string(),
+            )
+        })?;
+    let version = doc
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(|version| version.as_str())
+        .unwrap_or_default()
+        .to_string();
+    // Swagger 2 中的 basePath 需要拼接到每个 path 前
+    let base_path = doc
+        .get("basePath")
+        .and_then(|base| base.as_str())
+        .unwrap_or_default()
+        .trim_end_matches('/');
+
+    let mut interfaces = Vec::new();
+    for (path, item) in paths.iter() {
+        let path = match path.as_str() {
+            Some(path) => format!("{}{}", base_path, path),
+            None => continue,
+        };
+        for method in HTTP_METHODS {
+            let operation = match item.get(method) {
+                Some(operation) => operation,
+                None => continue,
+            };
+            interfaces.push(ServiceInterfaceDescripitor {
+                name: CONTRACT_PROTOCOL_HTTP.to_string(),
+                namespace: namespace.to_string(),
+                service: service.to_string(),
+                version: version.clone(),
+                protocol: CONTRACT_PROTOCOL_HTTP.to_string(),
+                path: path.clone(),
+                method: method.to_uppercase(),
+                content: serde_json::to_string(operation).unwrap_or_default(),
+            });
+        }
+    }
+    Ok(new_contract(
+        namespace,
+        service,
+        CONTRACT_PROTOCOL_HTTP,
+        version,
+        interfaces,
+    ))
+}
+
+/// compute_revision 计算契约内容摘要，与接口的先后顺序无关
+pub fn compute_revision(contract: &ServiceContract) -> String {
+    let mut interfaces: Vec<&ServiceInterfaceDescripitor> = contract.interfaces.iter().collect();
+    interfaces.sort_by(|a, b| interface_sort_key(a).cmp(&interface_sort_key(b)));
+    let mut metadata: Vec<(&String, &String)> = contract.metadata.iter().collect();
+    metadata.sort();
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut write = |value: &str| {
+        // 每个字段后追加分隔符，避免相邻字段拼接后产生相同的摘要
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(0);
+    };
+    write(&contract.name);
+    write(&contract.namespace);
+    write(&contract.service);
+    write(&contract.version);
+    write(&contract.protocol);
+    write(&contract.content);
+    for (key, value) in metadata {
+        write(key);
+        write(value);
+    }
+    for ele in interfaces {
+        write(&ele.name);
+        write(&ele.version);
+        write(&ele.protocol);
+        write(&ele.path);
+        write(&ele.method);
+        write(&ele.content);
+    }
+    // 使用与 Rust 版本无关的 SHA-256 计算摘要，保证不同进程计算的摘要一致
+    hex::encode(Sha256::digest(&buf))
+}
+
+// interface_sort_key path 与 method 相同的接口继续按照其余字段排序，保证摘要与接口的先后顺序无关
+fn interface_sort_key(ele: &ServiceInterfaceDescripitor) -> [&str; 6] {
+    [
+        &ele.path,
+        &ele.method,
+        &ele.name,
+        &ele.version,
+        &ele.protocol,
+        &ele.content,
+    ]
+}
+
+fn new_contract(
+    namespace: &str,
+    service: &str,
+    protocol: &str,
+    version: String,
+    mut interfaces: Vec<ServiceInterfaceDescripitor>,
+) -> ServiceContract {
+    interfaces.sort_by(|a, b| (&a.path, &a.method).cmp(&(&b.path, &b.method)));
+    let mut contract = ServiceContract {
+        name: protocol.to_string(),
+        namespace: namespace.to_string(),
+        service: service.to_string(),
+        version,
+        protocol: protocol.to_string(),
+        content: String::new(),
+        interfaces,
+        metadata: HashMap::new(),
+        revision: String::new(),
+    };
+    contract.revision = compute_revision(&contract);
+    contract
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{FileDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto};
+
+    use super::*;
+
+    #[test]
+    fn test_build_grpc_contract() {
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                package: Some("echo.v1".to_string()),
+                service: vec![ServiceDescriptorProto {
+                    name: Some("Echo".to_string()),
+                    method: vec![
+                        MethodDescriptorProto {
+                            name: Some("Say".to_string()),
+                            input_type: Some(".echo.v1.SayRequest".to_string()),
+                            output_type: Some(".echo.v1.SayResponse".to_string()),
+                            ..Default::default()
+                        },
+                        MethodDescriptorProto {
+                            name: Some("Ping".to_string()),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let contract = build_grpc_contract("default", "echo", &set.encode_to_vec()).unwrap();
+        assert_eq!("grpc", contract.protocol);
+        assert_eq!(2, contract.interfaces.len());
+        assert_eq!("echo.v1.Echo", contract.interfaces[0].path);
+        assert_eq!("Ping", contract.interfaces[0].method);
+        assert_eq!("Say", contract.interfaces[1].method);
+        assert!(!contract.revision.is_empty());
+
+        assert!(build_grpc_contract("default", "echo", &[0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_build_openapi_contract() {
+        let document = r#"
+swagger: "2.0"
+info:
+  title: echo
+  version: 1.0.0
+basePath: /api/
+paths:
+  /echo:
+    get:
+      operationId: echo
+    post:
+      operationId: create
+"#;
+        let contract = build_openapi_contract("default", "echo", document).unwrap();
+        assert_eq!("http", contract.protocol);
+        assert_eq!("1.0.0", contract.version);
+        let apis: Vec<(&str, &str)> = contract
+            .interfaces
+            .iter()
+            .map(|ele| (ele.path.as_str(), ele.method.as_str()))
+            .collect();
+        assert_eq!(vec![("/api/echo", "GET"), ("/api/echo", "POST")], apis);
+
+        // 接口顺序不影响摘要，接口内容变化时摘要变化
+        let mut reversed = contract.clone();
+        reversed.interfaces.reverse();
+        assert_eq!(contract.revision, compute_revision(&reversed));
+        let changed =
+            build_openapi_contract("default", "echo", &document.replace("create", "update"))
+                .unwrap();
+        assert_ne!(contract.revision, changed.revision);
+    }
+
+    #[test]
+    fn test_compute_revision_same_path() {
+        let new_interface = |content: &str| ServiceInterfaceDescripitor {
+            name: "http".to_string(),
+            namespace: "default".to_string(),
+            service: "echo".to_string(),
+            version: "1.0.0".to_string(),
+            protocol: "http".to_string(),
+            path: "/echo".to_string(),
+            method: "GET".to_string(),
+            content: content.to_string(),
+        };
+        let mut contract = new_contract(
+            "default",
+            "echo",
+            CONTRACT_PROTOCOL_HTTP,
+            "1.0.0".to_string(),
+            vec![new_interface("a"), new_interface("b")],
+        );
+        assert_eq!(64, contract.revision.len());
+
+        // path 与 method 相同的接口顺序不影响摘要
+        let revision = contract.revision.clone();
+        contract.interfaces.reverse();
+        assert_eq!(revision, compute_revision(&contract));
+    }
+}
@@ -16,46 +16,115 @@
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use futures::StreamExt;
 use tokio::sync::RwLock;
-use tokio::task::JoinHandle;
 
 use crate::core::context::SDKContext;
-use crate::core::model::error::PolarisError;
-use crate::core::model::naming::{ServiceInstancesChangeEvent, ServiceKey};
+use crate::core::model::cache::EventType;
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::core::model::loadbalance::{Criteria, HashKeySource, SERVICE_METADATA_HASH_KEY};
+use crate::core::model::naming::{
+    Instance, ServiceInstances, ServiceInstancesChangeEvent, ServiceKey, ServicesChangeEvent,
+};
+use crate::core::model::router::{RouteInfo, RouterChain};
+use crate::core::model::watch::{channel, WatchSender, WatchStream};
 use crate::core::plugin::cache::ResourceListener;
 use crate::discovery::api::{ConsumerAPI, LosslessAPI, ProviderAPI};
+use crate::discovery::contract::compute_revision;
+use crate::discovery::enrich::InstanceEnricher;
+use crate::discovery::heartbeat::HeartbeatScheduler;
+use crate::discovery::lossless::{instance_key, LosslessState};
 use crate::discovery::req::{
-    BaseInstance, GetAllInstanceRequest, GetHealthInstanceRequest, GetOneInstanceRequest,
-    GetServiceRuleRequest, InstanceDeregisterRequest, InstanceHeartbeatRequest,
+    BaseInstance, BatchInstanceDeregisterRequest, BatchInstanceHeartbeatRequest,
+    BatchInstanceRegisterRequest, BatchInstanceResponse, GetAllInstanceRequest, GetHealthInstanceRequest, GetInstanceCandidatesRequest,
+    GetOneInstanceRequest, GetServiceRuleRequest, GetServicesRequest, InstanceCandidatesResponse, InstanceDeregisterRequest, InstanceHeartbeatRequest,
     InstanceRegisterRequest, InstanceRegisterResponse, InstancesResponse, LosslessActionProvider,
-    ReportServiceContractRequest, ServiceCallResult, ServiceRuleResponse, WatchInstanceRequest,
+    ReportServiceContractRequest, ServiceCallResult, ServiceRuleChangeEvent, ServiceRuleResponse,
+    ServiceRuleType, ServicesResponse, WatchInstanceRequest, WatchInstanceStreamRequest,
+    WatchServiceRuleStreamRequest, WatchServicesRequest, WatchServicesResponse,
+    WatchServicesStreamRequest,
 };
 use crate::router::api::RouterAPI;
 use crate::router::default::DefaultRouterAPI;
-use crate::router::req::{ProcessLoadBalanceRequest, ProcessRouteRequest};
+use crate::router::req::{
+    ProcessLoadBalanceCandidatesRequest, ProcessLoadBalanceRequest, ProcessRouteRequest,
+};
 
 use super::req::{InstanceResponse, WatchInstanceResponse};
 
+// 批量操作时同时发送的最大请求数
+const BATCH_CONCURRENCY: usize = 16;
+// 服务契约上报记录的有效期，过期后即使摘要没有变化也会重新上报，避免服务端数据丢失后无法恢复
+const CONTRACT_REPORT_EXPIRE: Duration = Duration::from_secs(10 * 60);
+
 struct InstanceWatcher {
     req: WatchInstanceRequest,
 }
 
+// InstanceSnapshot 上一次通知给监听者的实例数据，用于计算实例变更差异
+struct InstanceSnapshot {
+    revision: String,
+    // instances: instance_id -> Instance
+    instances: HashMap<String, Instance>,
+}
+
+impl InstanceSnapshot {
+    fn new(revision: String, instances: &[Instance]) -> Self {
+        Self {
+            revision,
+            instances: instances
+                .iter()
+                .map(|ins| (ins.id.clone(), ins.clone()))
+                .collect(),
+        }
+    }
+}
+
 pub struct InstanceResourceListener {
     // watcher_id: 监听 listener key 的唯一标识
     watcher_id: Arc<AtomicU64>,
     // watchers: namespace#service -> InstanceWatcher
     watchers: Arc<RwLock<HashMap<String, HashMap<u64, InstanceWatcher>>>>,
+    // snapshots: namespace#service -> InstanceSnapshot，仅保存存在监听者的服务
+    snapshots: Arc<RwLock<HashMap<String, InstanceSnapshot>>>,
 }
 
 impl InstanceResourceListener {
+    fn new() -> Self {
+        Self {
+            watcher_id: Arc::new(AtomicU64::new(0)),
+            watchers: Arc::new(RwLock::new(HashMap::new())),
+            snapshots: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
     pub async fn cancel_watch(&self, watch_key: &str, watch_id: u64) {
         let mut watchers = self.watchers.write().await;
         let items = watchers.get_mut(watch_key);
         if let Some(vals) = items {
             vals.remove(&watch_id);
+            if vals.is_empty() {
+                watchers.remove(watch_key);
+                self.snapshots.write().await.remove(watch_key);
+            }
         }
     }
+
+    // seed_snapshot 如果还没有实例快照，则使用当前的实例数据作为基准
+    async fn seed_snapshot(&self, watch_key: String, instances: &ServiceInstances) {
+        self.snapshots
+            .write()
+            .await
+            .entry(watch_key)
+            .or_insert_with(|| {
+                InstanceSnapshot::new(
+                    instances.service.revision.clone(),
+                    &instances.instances,
+                )
+            });
+    }
 }
 
 #[async_trait::async_trait]
@@ -76,11 +145,31 @@ impl ResourceListener for InstanceResourceListener {
             let ins_cache_opt = val.value.to_service_instances();
             match ins_cache_opt {
                 Some(ins_cache_val) => {
+                    let service = ins_cache_val.get_service_info();
+                    let instances = ins_cache_val.list_instances(false).await;
+                    // 基于上一次的快照计算一次变更差异，所有监听者共享
+                    let event = {
+                        let mut snapshots = self.snapshots.write().await;
+                        let new_snapshot =
+                            InstanceSnapshot::new(service.revision.clone(), &instances);
+                        let old_snapshot = snapshots.insert(watch_key.clone(), new_snapshot);
+                        match old_snapshot {
+                            Some(old) => ServiceInstancesChangeEvent::new(
+                                service,
+                                old.revision,
+                                &old.instances,
+                                instances,
+                            ),
+                            None => ServiceInstancesChangeEvent::new(
+                                service,
+                                String::new(),
+                                &HashMap::new(),
+                                instances,
+                            ),
+                        }
+                    };
                     for watcher in watchers {
-                        (watcher.1.req.call_back)(ServiceInstancesChangeEvent {
-                            service: ins_cache_val.get_service_info(),
-                            instances: ins_cache_val.list_instances(false).await,
-                        })
+                        (watcher.1.req.call_back)(event.clone())
                     }
                 }
                 None => {
@@ -95,6 +184,142 @@ impl ResourceListener for InstanceResourceListener {
     }
 }
 
+struct ServiceRuleWatcher {
+    sender: Arc<WatchSender<ServiceRuleChangeEvent>>,
+}
+
+pub struct ServiceRuleResourceListener {
+    rule_type: ServiceRuleType,
+    // watcher_id: 监听 listener key 的唯一标识
+    watcher_id: AtomicU64,
+    // watchers: namespace#service -> ServiceRuleWatcher
+    watchers: RwLock<HashMap<String, HashMap<u64, ServiceRuleWatcher>>>,
+    // registered: 是否已经注册到资源缓存
+    registered: AtomicBool,
+}
+
+impl ServiceRuleResourceListener {
+    fn new(rule_type: ServiceRuleType) -> Self {
+        Self {
+            rule_type,
+            watcher_id: AtomicU64::new(0),
+            watchers: RwLock::new(HashMap::new()),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn cancel_watch(&self, watch_key: &str, watch_id: u64) {
+        let mut watchers = self.watchers.write().await;
+        if let Some(vals) = watchers.get_mut(watch_key) {
+            vals.remove(&watch_id);
+            if vals.is_empty() {
+                watchers.remove(watch_key);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ResourceListener for ServiceRuleResourceListener {
+    async fn on_event(
+        &self,
+        _action: crate::core::plugin::cache::Action,
+        val: crate::core::model::cache::ServerEvent,
+    ) {
+        let event_key = val.event_key;
+        let service = match event_key.filter.get("service") {
+            Some(service) => service.clone(),
+            None => return,
+        };
+        let watch_key = format!("{}#{}", event_key.namespace, service);
+
+        let watchers = self.watchers.read().await;
+        if let Some(watchers) = watchers.get(&watch_key) {
+            for watcher in watchers.values() {
+                // 规则数据以 Box<dyn Any> 形式下发，每个监听者需要独立的一份
+                if let Some(rule) = val.value.to_service_rule().await {
+                    watcher.sender.send(ServiceRuleChangeEvent {
+                        namespace: event_key.namespace.clone(),
+                        service: service.clone(),
+                        rule_type: self.rule_type,
+                        revision: rule.revision,
+                        rules: rule.rules,
+                    });
+                }
+            }
+        }
+    }
+
+    fn watch_key(&self) -> EventType {
+        self.rule_type.to_event_type()
+    }
+}
+
+struct ServicesWatcher {
+    req: WatchServicesRequest,
+}
+
+pub struct ServiceResourceListener {
+    // watcher_id: 监听 listener key 的唯一标识
+    watcher_id: AtomicU64,
+    // watchers: namespace -> ServicesWatcher
+    watchers: RwLock<HashMap<String, HashMap<u64, ServicesWatcher>>>,
+    // registered: 是否已经注册到资源缓存
+    registered: AtomicBool,
+}
+
+impl ServiceResourceListener {
+    fn new() -> Self {
+        Self {
+            watcher_id: AtomicU64::new(0),
+            watchers: RwLock::new(HashMap::new()),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn cancel_watch(&self, watch_key: &str, watch_id: u64) {
+        let mut watchers = self.watchers.write().await;
+        if let Some(vals) = watchers.get_mut(watch_key) {
+            vals.remove(&watch_id);
+            if vals.is_empty() {
+                watchers.remove(watch_key);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ResourceListener for ServiceResourceListener {
+    async fn on_event(
+        &self,
+        _action: crate::core::plugin::cache::Action,
+        val: crate::core::model::cache::ServerEvent,
+    ) {
+        let namespace = val.event_key.namespace;
+        let watchers = self.watchers.read().await;
+        if let Some(watchers) = watchers.get(&namespace) {
+            if let Some(svc_cache_val) = val.value.to_services() {
+                let services = svc_cache_val.value.read().await.clone();
+                for watcher in watchers.values() {
+                    (watcher.req.call_back)(ServicesChangeEvent {
+                        namespace: namespace.clone(),
+                        revision: svc_cache_val.revision.clone(),
+                        services: services
+                            .iter()
+                            .filter(|svc| svc.match_metadata(&watcher.req.metadata))
+                            .cloned()
+                            .collect(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn watch_key(&self) -> EventType {
+        EventType::Service
+    }
+}
+
 /// DefaultConsumerAPI
 pub struct DefaultConsumerAPI {
     manage_sdk: bool,
@@ -104,6 +329,12 @@ pub struct DefaultConsumerAPI {
     watchers: Arc<InstanceResourceListener>,
     // register_resource_watcher: 是否已经注册资源监听器
     register_resource_watcher: AtomicBool,
+    // hash_key_source: 本地配置的一致性哈希 key 来源
+    hash_key_source: Option<HashKeySource>,
+    // rule_watchers: 规则类型 -> 服务规则监听器
+    rule_watchers: HashMap<EventType, Arc<ServiceRuleResourceListener>>,
+    // service_watchers: namespace -> ServicesWatcher
+    service_watchers: Arc<ServiceResourceListener>,
 }
 
 impl DefaultConsumerAPI {
@@ -111,30 +342,149 @@ impl DefaultConsumerAPI {
         let ctx = Arc::new(context);
         Self {
             manage_sdk: true,
+            hash_key_source: parse_hash_key_source(&ctx),
             context: ctx.clone(),
             router_api: Box::new(DefaultRouterAPI::new(ctx)),
-            watchers: Arc::new(InstanceResourceListener {
-                watcher_id: Arc::new(AtomicU64::new(0)),
-                watchers: Arc::new(RwLock::new(HashMap::new())),
-            }),
+            watchers: Arc::new(InstanceResourceListener::new()),
             register_resource_watcher: AtomicBool::new(false),
+            rule_watchers: new_rule_watchers(),
+            service_watchers: Arc::new(ServiceResourceListener::new()),
         }
     }
 
     pub fn new(context: Arc<SDKContext>) -> Self {
         Self {
-            manage_sdk: true,
+            hash_key_source: parse_hash_key_source(&context),
+            manage_sdk: false,
             context: context.clone(),
             router_api: Box::new(DefaultRouterAPI::new(context.clone())),
-            watchers: Arc::new(InstanceResourceListener {
-                watcher_id: Arc::new(AtomicU64::new(0)),
-                watchers: Arc::new(RwLock::new(HashMap::new())),
-            }),
+            watchers: Arc::new(InstanceResourceListener::new()),
             register_resource_watcher: AtomicBool::new(false),
+            rule_watchers: new_rule_watchers(),
+            service_watchers: Arc::new(ServiceResourceListener::new()),
+        }
+    }
+
+    // route_instances 拉取被调服务的实例并执行路由
+    async fn route_instances(
+        &self,
+        flow_id: String,
+        timeout: Duration,
+        namespace: String,
+        service: String,
+        mut route_info: RouteInfo,
+        chain_override: Option<RouterChain>,
+    ) -> Result<ServiceInstances, PolarisError> {
+        let engine = self.context.get_engine();
+        let rsp = engine
+            .get_service_instances(
+                GetAllInstanceRequest {
+                    flow_id,
+                    timeout,
+                    service: service.clone(),
+                    namespace: namespace.clone(),
+                },
+                true,
+            )
+            .await?;
+
+        // 重新设置被调服务数据信息
+        route_info.callee = ServiceKey {
+            namespace,
+            name: service,
+        };
+        let instances = rsp.instances;
+
+        // 被调服务为别名服务时，使用真实服务的规则进行路由
+        if !instances.service.name.is_empty() {
+            route_info.callee = ServiceKey {
+                namespace: instances.service.namespace.clone(),
+                name: instances.service.name.clone(),
+            };
+        }
+
+        // 执行路由逻辑
+        let route_ret = self
+            .router_api
+            .router(ProcessRouteRequest {
+                service_instances: instances,
+                route_info,
+                chain_override,
+                trace: false,
+            })
+            .await?;
+        Ok(route_ret.service_instances)
+    }
+
+    // fill_hash_key 请求未指定 hash_key 时，根据被调服务 metadata 或者本地配置的来源从流量标签中获取
+    fn fill_hash_key(
+        &self,
+        criteria: &mut Criteria,
+        route_info: Option<RouteInfo>,
+        instances: &ServiceInstances,
+    ) {
+        let route_info = match route_info {
+            Some(route_info) => route_info,
+            None => return,
+        };
+        let source = instances
+            .service
+            .metadata
+            .get(SERVICE_METADATA_HASH_KEY)
+            .and_then(|v| {
+                let source = HashKeySource::parse(v);
+                if source.is_none() {
+                    crate::warn!(
+                        "[polaris][discovery][consumer] invalid hash key source {} in service {}/{}",
+                        v,
+                        instances.service.namespace,
+                        instances.service.name
+                    );
+                }
+                source
+            })
+            .or_else(|| self.hash_key_source.clone());
+        if let Some(source) = source {
+            if let Some(hash_key) =
+                source.resolve(&route_info.caller, route_info.traffic_label_provider.as_ref())
+            {
+                criteria.hash_key = hash_key;
+            }
         }
     }
 }
 
+fn new_rule_watchers() -> HashMap<EventType, Arc<ServiceRuleResourceListener>> {
+    let mut watchers = HashMap::new();
+    for rule_type in [
+        ServiceRuleType::Router,
+        ServiceRuleType::CircuitBreaker,
+        ServiceRuleType::RateLimit,
+        ServiceRuleType::FaultDetector,
+    ] {
+        watchers.insert(
+            rule_type.to_event_type(),
+            Arc::new(ServiceRuleResourceListener::new(rule_type)),
+        );
+    }
+    watchers
+}
+
+fn parse_hash_key_source(ctx: &SDKContext) -> Option<HashKeySource> {
+    let conf = &ctx.conf.consumer.load_balancer.hash_key;
+    if conf.is_empty() {
+        return None;
+    }
+    let source = HashKeySource::parse(conf);
+    if source.is_none() {
+        crate::warn!(
+            "[polaris][discovery][consumer] invalid loadBalancer.hashKey {}, ignore it",
+            conf
+        );
+    }
+    source
+}
+
 impl Drop for DefaultConsumerAPI {
     fn drop(&mut self) {
         if !self.manage_sdk {
@@ -163,65 +513,66 @@ impl ConsumerAPI for DefaultConsumerAPI {
         let check_ret = req.check_valid();
         check_ret?;
 
-        let engine = self.context.get_engine();
-        let rsp = engine
-            .get_service_instances(
-                GetAllInstanceRequest {
-                    flow_id: req.flow_id.clone(),
-                    timeout: req.timeout,
-                    service: req.service.clone(),
-                    namespace: req.namespace.clone(),
-                },
-                true,
+        let mut criteria = req.criteria;
+        let hash_route_info = criteria.hash_key.is_empty().then(|| req.route_info.clone());
+        let instances = self
+            .route_instances(
+                req.flow_id,
+                req.timeout,
+                req.namespace,
+                req.service,
+                req.route_info,
+                req.chain_override,
             )
-            .await;
+            .await?;
+        self.fill_hash_key(&mut criteria, hash_route_info, &instances);
 
-        // 重新设置被调服务数据信息
-        let mut route_info = req.route_info;
-        route_info.callee  = ServiceKey{
-            namespace: req.namespace.clone(),
-            name: req.service.clone(),
-        };
+        // 执行负载均衡逻辑
+        let balance_ret = self
+            .router_api
+            .load_balance(ProcessLoadBalanceRequest {
+                service_instances: instances,
+                criteria,
+            })
+            .await?;
 
-        match rsp {
-            Ok(rsp) => {
-                let instances = rsp.instances;
-                let criteria = req.criteria;
-
-                // 执行路由逻辑
-                let route_ret = self
-                    .router_api
-                    .router(ProcessRouteRequest {
-                        service_instances: instances,
-                        route_info: route_info,
-                    })
-                    .await;
+        Ok(InstanceResponse {
+            instance: balance_ret.instance,
+        })
+    }
 
-                if route_ret.is_err() {
-                    return Err(route_ret.err().unwrap());
-                }
+    async fn get_instance_candidates(
+        &self,
+        req: GetInstanceCandidatesRequest,
+    ) -> Result<InstanceCandidatesResponse, PolarisError> {
+        req.check_valid()?;
 
-                // 执行负载均衡逻辑
-                let balance_ret = self
-                    .router_api
-                    .load_balance(ProcessLoadBalanceRequest {
-                        service_instances: route_ret.unwrap().service_instances,
-                        criteria,
-                    })
-                    .await;
+        let mut criteria = req.criteria;
+        let hash_route_info = criteria.hash_key.is_empty().then(|| req.route_info.clone());
+        let instances = self
+            .route_instances(
+                req.flow_id,
+                req.timeout,
+                req.namespace,
+                req.service,
+                req.route_info,
+                req.chain_override,
+            )
+            .await?;
+        self.fill_hash_key(&mut criteria, hash_route_info, &instances);
 
-                if balance_ret.is_err() {
-                    return Err(balance_ret.err().unwrap());
-                }
+        let balance_ret = self
+            .router_api
+            .load_balance_candidates(ProcessLoadBalanceCandidatesRequest {
+                service_instances: instances,
+                criteria,
+                count: req.count,
+            })
+            .await?;
 
-                Ok(InstanceResponse {
-                    instance: balance_ret.unwrap().instance,
-                })
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
+        Ok(InstanceCandidatesResponse {
+            instances: balance_ret.instances,
+        })
     }
 
     async fn get_health_instance(
@@ -275,13 +626,29 @@ impl ConsumerAPI for DefaultConsumerAPI {
                 .await;
         }
 
-        let mut watchers = self.watchers.watchers.write().await;
-
         let watch_key = req.get_key();
-        let items = watchers.entry(watch_key.clone()).or_insert(HashMap::new());
+        let cache_req = GetAllInstanceRequest {
+            flow_id: String::new(),
+            timeout: Duration::ZERO,
+            service: req.service.clone(),
+            namespace: req.namespace.clone(),
+        };
         let watch_id = self.watchers.watcher_id.fetch_add(1, Ordering::Relaxed);
+        self.watchers
+            .watchers
+            .write()
+            .await
+            .entry(watch_key.clone())
+            .or_insert(HashMap::new())
+            .insert(watch_id, InstanceWatcher { req });
 
-        items.insert(watch_id, InstanceWatcher { req });
+        // 使用缓存中当前的实例作为基准，保证第一次变更通知中能够带上被删除的实例，服务还没有缓存时不等待加载
+        let engine = self.context.get_engine();
+        if let Ok(rsp) = engine.get_service_instances(cache_req, false).await {
+            self.watchers
+                .seed_snapshot(watch_key.clone(), &rsp.instances)
+                .await;
+        }
         Ok(WatchInstanceResponse::new(
             watch_id,
             watch_key,
@@ -289,6 +656,160 @@ impl ConsumerAPI for DefaultConsumerAPI {
         ))
     }
 
+    async fn watch_instance_stream(
+        &self,
+        req: WatchInstanceStreamRequest,
+    ) -> Result<WatchStream<ServiceInstancesChangeEvent>, PolarisError> {
+        req.check_valid()?;
+
+        let (sender, stream) = channel(req.option);
+        let sender = Arc::new(sender);
+        let watch_sender = sender.clone();
+        let watch_rsp = self
+            .watch_instance(WatchInstanceRequest {
+                namespace: req.namespace.clone(),
+                service: req.service.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_sender.send(event);
+                }),
+            })
+            .await?;
+        let engine = self.context.get_engine();
+        let watch_key = format!("{}#{}", req.namespace, req.service);
+        // 消费过慢时事件会被丢弃，变更差异需要基于该监听流上一次消费的实例重新计算
+        let mut delivered = InstanceSnapshot::new(String::new(), &[]);
+        let stream = stream
+            .with_cancel(watch_rsp.into_canceler(engine.get_executor()))
+            .with_deliver(Box::new(move |event: ServiceInstancesChangeEvent| {
+                let old = std::mem::replace(
+                    &mut delivered,
+                    InstanceSnapshot::new(event.new_revision.clone(), &event.instances),
+                );
+                ServiceInstancesChangeEvent::new(
+                    event.service,
+                    old.revision,
+                    &old.instances,
+                    event.instances,
+                )
+            }));
+
+        // 拉取一次实例，确保资源已经被订阅，同时作为首个事件下发
+        let rsp = engine
+            .get_service_instances(
+                GetAllInstanceRequest {
+                    flow_id: req.flow_id,
+                    timeout: req.timeout,
+                    service: req.service,
+                    namespace: req.namespace,
+                },
+                false,
+            )
+            .await?;
+        self.watchers
+            .seed_snapshot(watch_key, &rsp.instances)
+            .await;
+        let instances = rsp.instances;
+        sender.send_initial(ServiceInstancesChangeEvent::new(
+            instances.service,
+            String::new(),
+            &HashMap::new(),
+            instances.instances,
+        ));
+        Ok(stream)
+    }
+
+    async fn get_services(
+        &self,
+        req: GetServicesRequest,
+    ) -> Result<ServicesResponse, PolarisError> {
+        req.check_valid()?;
+        self.context.get_engine().get_services(req).await
+    }
+
+    async fn watch_services(
+        &self,
+        req: WatchServicesRequest,
+    ) -> Result<WatchServicesResponse, PolarisError> {
+        req.check_valid()?;
+        let engine = self.context.get_engine();
+        if self
+            .service_watchers
+            .registered
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::SeqCst)
+            .is_ok()
+        {
+            // 延迟注册资源监听器
+            engine
+                .register_resource_listener(self.service_watchers.clone())
+                .await;
+        }
+
+        let get_req = GetServicesRequest {
+            flow_id: req.flow_id.clone(),
+            timeout: req.timeout,
+            namespace: req.namespace.clone(),
+            metadata: HashMap::new(),
+        };
+        let watch_key = req.namespace.clone();
+        let watch_id = self
+            .service_watchers
+            .watcher_id
+            .fetch_add(1, Ordering::Relaxed);
+        self.service_watchers
+            .watchers
+            .write()
+            .await
+            .entry(watch_key.clone())
+            .or_default()
+            .insert(watch_id, ServicesWatcher { req });
+        let watch_rsp =
+            WatchServicesResponse::new(watch_id, watch_key, self.service_watchers.clone());
+
+        // 拉取一次服务列表，确保服务列表已经被订阅
+        if let Err(err) = engine.get_services(get_req).await {
+            watch_rsp.cancel_watch().await;
+            return Err(err);
+        }
+        Ok(watch_rsp)
+    }
+
+    async fn watch_services_stream(
+        &self,
+        req: WatchServicesStreamRequest,
+    ) -> Result<WatchStream<ServicesChangeEvent>, PolarisError> {
+        let (sender, stream) = channel(req.option);
+        let sender = Arc::new(sender);
+        let watch_sender = sender.clone();
+        let watch_rsp = self
+            .watch_services(WatchServicesRequest {
+                flow_id: req.flow_id.clone(),
+                timeout: req.timeout,
+                namespace: req.namespace.clone(),
+                metadata: req.metadata.clone(),
+                call_back: Arc::new(move |event| {
+                    watch_sender.send(event);
+                }),
+            })
+            .await?;
+        let engine = self.context.get_engine();
+        let stream = stream.with_cancel(watch_rsp.into_canceler(engine.get_executor()));
+
+        let rsp = engine
+            .get_services(GetServicesRequest {
+                flow_id: req.flow_id,
+                timeout: req.timeout,
+                namespace: req.namespace.clone(),
+                metadata: req.metadata,
+            })
+            .await?;
+        sender.send_initial(ServicesChangeEvent {
+            namespace: req.namespace,
+            revision: rsp.revision,
+            services: rsp.services,
+        });
+        Ok(stream)
+    }
+
     async fn get_service_rule(
         &self,
         req: GetServiceRuleRequest,
@@ -297,8 +818,87 @@ impl ConsumerAPI for DefaultConsumerAPI {
         engine.get_service_rule(req).await
     }
 
-    async fn report_service_call(&self, _req: ServiceCallResult) {
-        todo!()
+    async fn watch_service_rule_stream(
+        &self,
+        req: WatchServiceRuleStreamRequest,
+    ) -> Result<WatchStream<ServiceRuleChangeEvent>, PolarisError> {
+        let listener = match self.rule_watchers.get(&req.rule_type.to_event_type()) {
+            Some(listener) => listener.clone(),
+            None => {
+                return Err(PolarisError::new(
+                    ErrorCode::ApiInvalidArgument,
+                    format!("rule type {:?} not support watch", req.rule_type),
+                ));
+            }
+        };
+        let engine = self.context.get_engine();
+        if listener
+            .registered
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::SeqCst)
+            .is_ok()
+        {
+            // 延迟注册资源监听器
+            engine.register_resource_listener(listener.clone()).await;
+        }
+
+        let (sender, stream) = channel(req.option);
+        let sender = Arc::new(sender);
+        let watch_key = req.get_key();
+        let watch_id = listener.watcher_id.fetch_add(1, Ordering::Relaxed);
+        listener
+            .watchers
+            .write()
+            .await
+            .entry(watch_key.clone())
+            .or_default()
+            .insert(
+                watch_id,
+                ServiceRuleWatcher {
+                    sender: sender.clone(),
+                },
+            );
+
+        let executor = engine.get_executor();
+        let owner = listener.clone();
+        let stream = stream.with_cancel(Box::new(move || {
+            executor.spawn(async move {
+                owner.cancel_watch(&watch_key, watch_id).await;
+            });
+        }));
+
+        // 拉取一次规则，确保资源已经被订阅，同时作为首个事件下发
+        let rsp = engine
+            .get_service_rule(GetServiceRuleRequest {
+                namespace: req.namespace.clone(),
+                service: req.service.clone(),
+                rule_type: req.rule_type,
+                timeout: req.timeout,
+            })
+            .await?;
+        sender.send_initial(ServiceRuleChangeEvent {
+            namespace: req.namespace,
+            service: req.service,
+            rule_type: req.rule_type,
+            revision: rsp.revision,
+            rules: rsp.rules,
+        });
+        Ok(stream)
+    }
+
+    async fn report_service_call(&self, req: ServiceCallResult) {
+        self.router_api.report_call_result(&req).await;
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        self.watchers.watchers.write().await.clear();
+        for listener in self.rule_watchers.values() {
+            listener.watchers.write().await.clear();
+        }
+        self.service_watchers.watchers.write().await.clear();
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context.shutdown(timeout).await
     }
 }
 
@@ -309,29 +909,37 @@ where
 {
     manage_sdk: bool,
     context: Arc<SDKContext>,
-    beat_tasks: Arc<RwLock<HashMap<String, JoinHandle<()>>>>,
+    // heartbeat 自动心跳调度器
+    heartbeat: HeartbeatScheduler,
+    // enricher 注册实例时自动补全实例信息
+    enricher: InstanceEnricher,
+    // reported_contracts 已经上报成功的服务契约摘要以及上报时间，key 为 namespace#service#name#version#protocol
+    reported_contracts: std::sync::Mutex<HashMap<String, (String, Instant)>>,
 }
 
 impl DefaultProviderAPI {
     pub fn new_raw(context: SDKContext) -> Self {
-        Self {
-            context: Arc::new(context),
-            manage_sdk: true,
-            beat_tasks: Arc::new(RwLock::new(HashMap::new())),
-        }
+        let mut api = Self::new(Arc::new(context));
+        api.manage_sdk = true;
+        api
     }
 
     pub fn new(context: Arc<SDKContext>) -> Self {
+        let heartbeat = HeartbeatScheduler::new(context.get_engine(), &context.conf.provider);
+        let enricher = InstanceEnricher::new(context.conf.clone());
         Self {
             context,
             manage_sdk: false,
-            beat_tasks: Arc::new(RwLock::new(HashMap::new())),
+            heartbeat,
+            enricher,
+            reported_contracts: std::sync::Mutex::new(HashMap::new()),
         }
     }
 }
 
 impl Drop for DefaultProviderAPI {
     fn drop(&mut self) {
+        self.heartbeat.stop();
         if !self.manage_sdk {
             return;
         }
@@ -353,97 +961,506 @@ impl Drop for DefaultProviderAPI {
 impl ProviderAPI for DefaultProviderAPI {
     async fn register(
         &self,
-        req: InstanceRegisterRequest,
+        mut req: InstanceRegisterRequest,
     ) -> Result<InstanceRegisterResponse, PolarisError> {
+        self.enricher.enrich_register(&mut req);
         let auto_heartbeat = req.auto_heartbeat;
-        let ttl = req.ttl;
-        let beat_req = req.to_heartbeat_request();
         crate::info!("[polaris][discovery][provider] register instance request: {req:?}");
-        let rsp = self.context.get_engine().register_instance(req).await;
-        let engine = self.context.get_engine();
+        let rsp = self.context.get_engine().register_instance(req.clone()).await;
         if rsp.is_ok() && auto_heartbeat {
-            let task_key = beat_req.beat_key();
-            crate::info!(
-                "[polaris][discovery][heartbeat] add one auto_beat task={} duration={}s",
-                task_key,
-                ttl,
-            );
-            let beat_engine = engine.clone();
-            // 开启了心跳自动上报功能，这里需要维护一个自动心跳上报的任务
-            let handler = engine.get_executor().spawn(async move {
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(u64::from(ttl))).await;
-                    crate::debug!(
-                        "[polaris][discovery][heartbeat] start to auto_beat instance: {beat_req:?}"
-                    );
-                    let beat_ret = beat_engine.instance_heartbeat(beat_req.clone()).await;
-                    if let Err(e) = beat_ret {
-                        crate::error!(
-                        "[polaris][discovery][heartbeat] auto_beat instance to server fail: {e}"
-                    );
-                    }
-                }
-            });
-            self.beat_tasks.write().await.insert(task_key, handler);
+            // 开启了心跳自动上报功能，交给心跳调度器统一上报
+            self.heartbeat.add(req);
         }
         return rsp;
     }
 
-    async fn deregister(&self, req: InstanceDeregisterRequest) -> Result<(), PolarisError> {
+    async fn deregister(&self, mut req: InstanceDeregisterRequest) -> Result<(), PolarisError> {
+        self.enricher.enrich_deregister(&mut req);
         let beat_req = req.to_heartbeat_request();
-        let task_key = beat_req.beat_key();
-        let wait_remove_task = self.beat_tasks.write().await.remove(&task_key);
-        if let Some(task) = wait_remove_task {
-            crate::info!(
-                "[polaris][discovery][heartbeat] remove one auto_beat task={}",
-                task_key,
-            );
-            task.abort();
-        }
+        self.heartbeat.remove(&beat_req.beat_key());
 
         let engine = self.context.get_engine();
         engine.deregister_instance(req).await
     }
 
-    async fn heartbeat(&self, req: InstanceHeartbeatRequest) -> Result<(), PolarisError> {
+    async fn heartbeat(&self, mut req: InstanceHeartbeatRequest) -> Result<(), PolarisError> {
+        self.enricher.enrich_heartbeat(&mut req);
         let engine = self.context.get_engine();
         engine.instance_heartbeat(req).await
     }
 
+    async fn batch_register(
+        &self,
+        req: BatchInstanceRegisterRequest,
+    ) -> BatchInstanceResponse<InstanceRegisterResponse> {
+        let results = futures::stream::iter(req.requests)
+            .map(|req| self.register(req))
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+        BatchInstanceResponse { results }
+    }
+
+    async fn batch_deregister(
+        &self,
+        req: BatchInstanceDeregisterRequest,
+    ) -> BatchInstanceResponse<()> {
+        let results = futures::stream::iter(req.requests)
+            .map(|req| self.deregister(req))
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+        BatchInstanceResponse { results }
+    }
+
+    async fn batch_heartbeat(
+        &self,
+        req: BatchInstanceHeartbeatRequest,
+    ) -> BatchInstanceResponse<()> {
+        let results = futures::stream::iter(req.requests)
+            .map(|req| self.heartbeat(req))
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+        BatchInstanceResponse { results }
+    }
+
     async fn report_service_contract(
         &self,
-        req: ReportServiceContractRequest,
+        mut req: ReportServiceContractRequest,
     ) -> Result<(), PolarisError> {
-        todo!()
+        if req.contract.revision.is_empty() {
+            req.contract.revision = compute_revision(&req.contract);
+        }
+        let contract = &req.contract;
+        let key = format!(
+            "{}#{}#{}#{}#{}",
+            contract.namespace, contract.service, contract.name, contract.version, contract.protocol
+        );
+        let revision = contract.revision.clone();
+        {
+            let reported = self.reported_contracts.lock().unwrap();
+            let skip = matches!(
+                reported.get(&key),
+                Some((reported_revision, reported_at))
+                    if *reported_revision == revision
+                        && reported_at.elapsed() < CONTRACT_REPORT_EXPIRE
+            );
+            if skip {
+                crate::debug!(
+                    "[polaris][discovery][provider] service contract {} not changed, skip report",
+                    key
+                );
+                return Ok(());
+            }
+        }
+        self.context
+            .get_engine()
+            .report_service_contract(req)
+            .await?;
+        let mut reported = self.reported_contracts.lock().unwrap();
+        reported.retain(|_, (_, reported_at)| reported_at.elapsed() < CONTRACT_REPORT_EXPIRE);
+        reported.insert(key, (revision, Instant::now()));
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.heartbeat.stop();
     }
 
-    async fn close(&mut self) {}
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        self.heartbeat.stop();
+        if self.manage_sdk {
+            return self.context.shutdown(timeout).await;
+        }
+        // 共享 SDKContext 时不关闭 SDKContext，只反注册通过该 SDKContext 注册的实例，已注册实例统一由 Engine 记录
+        let engine = self.context.get_engine();
+        match tokio::time::timeout(timeout, engine.deregister_all()).await {
+            Ok(ret) => ret,
+            Err(_) => Err(PolarisError::new(
+                ErrorCode::ApiTimeout,
+                format!("deregister instances not finished in {:?}", timeout),
+            )),
+        }
+    }
 }
 
 pub struct DefaultLosslessAPI {
-    context: SDKContext,
+    manage_sdk: bool,
+    context: Arc<SDKContext>,
+    state: Arc<LosslessState>,
 }
 
 impl DefaultLosslessAPI {
-    pub fn new(context: SDKContext) -> Self {
-        Self { context }
+    pub fn new_raw(context: SDKContext) -> Self {
+        let mut api = Self::new(Arc::new(context));
+        api.manage_sdk = true;
+        api
+    }
+
+    pub fn new(context: Arc<SDKContext>) -> Self {
+        let conf = context.conf.provider.lossless.clone();
+        Self {
+            manage_sdk: false,
+            context,
+            state: Arc::new(LosslessState::new(conf)),
+        }
+    }
+}
+
+impl Drop for DefaultLosslessAPI {
+    fn drop(&mut self) {
+        // admin 接口的任务持有状态的引用，需要主动停止
+        self.state.stop_admin();
+        if !self.manage_sdk {
+            return;
+        }
+        let ctx = self.context.to_owned();
+        let ret = Arc::try_unwrap(ctx);
+
+        match ret {
+            Ok(ctx) => {
+                drop(ctx);
+            }
+            Err(_) => {
+                // do nothing
+            }
+        }
     }
 }
 
+#[async_trait::async_trait]
 impl LosslessAPI for DefaultLosslessAPI {
-    fn set_action_provider(
+    async fn set_action_provider(
         &self,
-        _ins: Arc<dyn BaseInstance>,
-        _action: Arc<dyn LosslessActionProvider>,
+        ins: Arc<dyn BaseInstance>,
+        action: Arc<dyn LosslessActionProvider>,
     ) {
-        todo!()
+        self.state
+            .set_action(instance_key(ins.as_ref()), action)
+            .await;
+    }
+
+    async fn lossless_register(&self, ins: Arc<dyn BaseInstance>) -> Result<(), PolarisError> {
+        self.state
+            .start_admin(self.context.get_engine().get_executor())
+            .await?;
+        self.state.register(&instance_key(ins.as_ref())).await
+    }
+
+    async fn lossless_deregister(&self, ins: Arc<dyn BaseInstance>) -> Result<(), PolarisError> {
+        self.state.deregister(&instance_key(ins.as_ref())).await
+    }
+
+    async fn is_ready(&self) -> bool {
+        self.state.is_ready().await
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        let start = std::time::Instant::now();
+        if tokio::time::timeout(timeout, self.state.shutdown())
+            .await
+            .is_err()
+        {
+            self.state.stop_admin();
+            crate::warn!("[polaris][lossless] shutdown not finished in {:?}", timeout);
+        }
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context
+            .shutdown(timeout.saturating_sub(start.elapsed()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polaris_specification::v1::{DiscoverResponse, Service};
+
+    use super::*;
+    use crate::core::context::tests::new_context;
+    use crate::core::engine::tests::{new_register_request, push_remote_data, MockConnector};
+    use crate::core::model::cache::{RemoteData, ResourceEventKey};
+    use crate::core::model::naming::ServiceContract;
+    use crate::core::model::watch::{LagPolicy, WatchStreamOption};
+
+    fn new_instance_data(revision: &str, ids: &[&str]) -> RemoteData {
+        let instances = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| polaris_specification::v1::Instance {
+                id: Some(id.to_string()),
+                host: Some("127.0.0.1".to_string()),
+                port: Some(8080 + i as u32),
+                weight: Some(100),
+                healthy: Some(true),
+                isolate: Some(false),
+                ..Default::default()
+            })
+            .collect();
+        RemoteData {
+            event_key: ResourceEventKey {
+                namespace: "default".to_string(),
+                event_type: EventType::Instance,
+                filter: HashMap::from([("service".to_string(), "echo".to_string())]),
+            },
+            discover_value: Some(DiscoverResponse {
+                service: Some(Service {
+                    namespace: Some("default".to_string()),
+                    name: Some("echo".to_string()),
+                    revision: Some(revision.to_string()),
+                    ..Default::default()
+                }),
+                instances,
+                ..Default::default()
+            }),
+            config_value: None,
+        }
+    }
+
+    #[test]
+    fn test_watch_instance_deleted() {
+        let connector = MockConnector::default();
+        let handlers = connector.handlers.clone();
+        let (rt, ctx) = new_context(connector);
+        let consumer = Arc::new(DefaultConsumerAPI::new(ctx));
+
+        // 服务实例已经在缓存中
+        let load_consumer = consumer.clone();
+        let load = rt.spawn(async move {
+            load_consumer
+                .get_all_instance(GetAllInstanceRequest {
+                    flow_id: String::new(),
+                    timeout: Duration::from_secs(5),
+                    service: "echo".to_string(),
+                    namespace: "default".to_string(),
+                })
+                .await
+        });
+        push_remote_data(&handlers, new_instance_data("rev-1", &["ins-1", "ins-2"]));
+        assert_eq!(2, rt.block_on(load).unwrap().unwrap().instances.instances.len());
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let _watch = rt
+            .block_on(consumer.watch_instance(WatchInstanceRequest {
+                namespace: "default".to_string(),
+                service: "echo".to_string(),
+                call_back: Arc::new(move |event| {
+                    let _ = tx.send(event);
+                }),
+            }))
+            .unwrap();
+
+        // 监听之后的第一次变更需要基于监听时的缓存数据计算差异
+        push_remote_data(&handlers, new_instance_data("rev-2", &["ins-1"]));
+        let event = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!("rev-1", event.old_revision);
+        assert_eq!("rev-2", event.new_revision);
+        assert!(event.added.is_empty());
+        assert_eq!(
+            vec!["ins-2"],
+            event
+                .deleted
+                .iter()
+                .map(|ins| ins.id.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_watch_instance_stream_lagged() {
+        let connector = MockConnector::default();
+        let handlers = connector.handlers.clone();
+        let (rt, ctx) = new_context(connector);
+        let consumer = Arc::new(DefaultConsumerAPI::new(ctx));
+
+        let watch_consumer = consumer.clone();
+        let watch = rt.spawn(async move {
+            watch_consumer
+                .watch_instance_stream(WatchInstanceStreamRequest {
+                    flow_id: String::new(),
+                    timeout: Duration::from_secs(5),
+                    namespace: "default".to_string(),
+                    service: "echo".to_string(),
+                    option: WatchStreamOption {
+                        buffer_size: 1,
+                        lag_policy: LagPolicy::Latest,
+                    },
+                })
+                .await
+        });
+        push_remote_data(&handlers, new_instance_data("rev-1", &["ins-1", "ins-2"]));
+        let mut stream = rt.block_on(watch).unwrap().unwrap();
+        let event = rt.block_on(stream.next()).unwrap();
+        assert_eq!("rev-1", event.new_revision);
+        assert_eq!(2, event.added.len());
+
+        // rev-2 的事件还没有被消费，被 rev-3 覆盖
+        push_remote_data(&handlers, new_instance_data("rev-2", &["ins-1"]));
+        push_remote_data(&handlers, new_instance_data("rev-3", &["ins-1", "ins-3"]));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stream.lagged() < 1 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, stream.lagged());
+
+        // 变更差异基于监听流上一次消费的 rev-1 计算，不会遗漏 rev-2 删除的实例
+        let event = rt.block_on(stream.next()).unwrap();
+        assert_eq!("rev-1", event.old_revision);
+        assert_eq!("rev-3", event.new_revision);
+        let ids = |instances: &[Instance]| {
+            instances.iter().map(|ins| ins.id.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["ins-3"], ids(&event.added));
+        assert_eq!(vec!["ins-2"], ids(&event.deleted));
+    }
+
+    #[test]
+    fn test_provider_shutdown_shared_context() {
+        let connector = MockConnector::default();
+        let calls = connector.calls.clone();
+        let (rt, ctx) = new_context(connector);
+        let provider = DefaultProviderAPI::new(ctx.clone());
+
+        rt.block_on(async {
+            provider.register(new_register_request(8080)).await.unwrap();
+            // 直接通过 Engine 注册的实例同样会在 shutdown 时反注册
+            ctx.get_engine()
+                .register_instance(new_register_request(8081))
+                .await
+                .unwrap();
+            provider.shutdown(Duration::from_secs(1)).await.unwrap();
+        });
+
+        let mut deregistered: Vec<String> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.starts_with("deregister"))
+            .cloned()
+            .collect();
+        deregistered.sort();
+        assert_eq!(
+            vec!["deregister:127.0.0.1:8080", "deregister:127.0.0.1:8081"],
+            deregistered
+        );
+        // 共享的 SDKContext 不会被关闭
+        assert!(!ctx.is_shutdown());
     }
 
-    fn lossless_register(&self, _ins: Arc<dyn BaseInstance>) {
-        todo!()
+    #[test]
+    fn test_batch_operations() {
+        // 8080 的请求最晚完成，8081 的请求失败
+        let connector = MockConnector {
+            fail_ports: vec![8081],
+            slow_ports: vec![8080],
+            ..Default::default()
+        };
+        let (rt, ctx) = new_context(connector);
+        let provider = DefaultProviderAPI::new(ctx);
+        let requests: Vec<InstanceRegisterRequest> =
+            (8080..8083).map(new_register_request).collect();
+
+        rt.block_on(async {
+            let ret = provider
+                .batch_register(BatchInstanceRegisterRequest {
+                    requests: requests.clone(),
+                })
+                .await;
+            assert!(!ret.is_all_success());
+            assert_eq!(
+                vec![1],
+                ret.errors().iter().map(|(i, _)| *i).collect::<Vec<_>>()
+            );
+            let ids: Vec<Option<&str>> = ret
+                .results
+                .iter()
+                .map(|r| r.as_ref().ok().map(|resp| resp.instance_id.as_str()))
+                .collect();
+            assert_eq!(
+                vec![Some("127.0.0.1:8080"), None, Some("127.0.0.1:8082")],
+                ids
+            );
+
+            let ret = provider
+                .batch_heartbeat(BatchInstanceHeartbeatRequest {
+                    requests: requests
+                        .iter()
+                        .map(|req| req.to_heartbeat_request())
+                        .collect(),
+                })
+                .await;
+            assert_eq!(
+                vec![true, false, true],
+                ret.results.iter().map(|r| r.is_ok()).collect::<Vec<_>>()
+            );
+
+            let ret = provider
+                .batch_deregister(BatchInstanceDeregisterRequest {
+                    requests: requests
+                        .iter()
+                        .map(|req| req.to_deregister_request())
+                        .collect(),
+                })
+                .await;
+            assert_eq!(
+                vec![true, false, true],
+                ret.results.iter().map(|r| r.is_ok()).collect::<Vec<_>>()
+            );
+            assert_eq!(1, ret.errors()[0].0);
+        });
+    }
+
+    #[test]
+    fn test_report_service_contract() {
+        let connector = MockConnector::default();
+        let contracts = connector.contracts.clone();
+        let (rt, ctx) = new_context(connector);
+        let provider = DefaultProviderAPI::new(ctx);
+        let new_request = || ReportServiceContractRequest {
+            flow_id: String::new(),
+            timeout: Duration::from_secs(1),
+            contract: ServiceContract {
+                name: "http".to_string(),
+                namespace: "default".to_string(),
+                service: "echo".to_string(),
+                version: "1.0.0".to_string(),
+                protocol: "http".to_string(),
+                content: String::new(),
+                interfaces: Vec::new(),
+                metadata: HashMap::new(),
+                revision: String::new(),
+            },
+        };
+
+        rt.block_on(async {
+            provider.report_service_contract(new_request()).await.unwrap();
+            // 摘要没有变化时不会重复上报
+            provider.report_service_contract(new_request()).await.unwrap();
+            assert_eq!(1, contracts.load(Ordering::SeqCst));
+
+            // 上报记录过期后重新上报
+            for (_, reported_at) in provider
+                .reported_contracts
+                .lock()
+                .unwrap()
+                .values_mut()
+            {
+                *reported_at = Instant::now() - CONTRACT_REPORT_EXPIRE;
+            }
+            provider.report_service_contract(new_request()).await.unwrap();
+            assert_eq!(2, contracts.load(Ordering::SeqCst));
+        });
     }
 
-    fn lossless_deregister(&self, _ins: Arc<dyn BaseInstance>) {
-        todo!()
+    #[test]
+    fn test_consumer_shutdown_shared_context() {
+        let (rt, ctx) = new_context(MockConnector::default());
+        let consumer = DefaultConsumerAPI::new(ctx.clone());
+        rt.block_on(consumer.shutdown(Duration::from_secs(1)))
+            .unwrap();
+        // 共享 SDKContext 的 ConsumerAPI 不会关闭 SDKContext
+        assert!(!ctx.is_shutdown());
     }
 }
@@ -0,0 +1,337 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::core::config::config::Configuration;
+use crate::core::model::get_pod_name_by;
+use crate::core::plugin::plugins::acquire_client_self_ip;
+use crate::discovery::req::{
+    InstanceDeregisterRequest, InstanceHeartbeatRequest, InstanceRegisterRequest,
+};
+
+pub static METADATA_POD_NAME: &str = "pod_name";
+pub static METADATA_POD_NAMESPACE: &str = "pod_namespace";
+pub static METADATA_NODE_NAME: &str = "node_name";
+pub static METADATA_HOST_IP: &str = "host_ip";
+
+// pod 的版本标签，写入实例版本而不是 metadata
+const POD_LABEL_VERSION: &str = "version";
+
+// Pod 内通过 ServiceAccount 挂载的命名空间文件
+const SERVICE_ACCOUNT_NAMESPACE_FILE: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+// KubernetesInfo 从环境变量以及 downward API 中读取的 pod 信息
+#[derive(Default, Debug)]
+struct KubernetesInfo {
+    pod_ip: String,
+    host_ip: String,
+    pod_name: String,
+    pod_namespace: String,
+    node_name: String,
+    // pod_labels downward API 中的 pod 标签
+    pod_labels: HashMap<String, String>,
+}
+
+impl KubernetesInfo {
+    fn load(
+        env: impl Fn(&str) -> Option<String>,
+        read_file: impl Fn(&Path) -> Option<String>,
+        downward_api_path: &str,
+    ) -> Self {
+        let first_env = |keys: &[&str]| {
+            keys.iter()
+                .filter_map(|k| env(k))
+                .find(|v| !v.is_empty())
+                .unwrap_or_default()
+        };
+        let mut pod_namespace = first_env(&["POD_NAMESPACE", "MY_POD_NAMESPACE"]);
+        if pod_namespace.is_empty() {
+            pod_namespace = read_file(Path::new(SERVICE_ACCOUNT_NAMESPACE_FILE))
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default();
+        }
+        let pod_labels = read_file(&Path::new(downward_api_path).join("labels"))
+            .map(|v| parse_downward_labels(&v))
+            .unwrap_or_default();
+
+        Self {
+            pod_ip: first_env(&["POD_IP", "MY_POD_IP"]),
+            host_ip: first_env(&["HOST_IP", "MY_HOST_IP", "NODE_IP"]),
+            pod_name: get_pod_name_by(&env),
+            pod_namespace,
+            node_name: first_env(&["NODE_NAME", "MY_NODE_NAME"]),
+            pod_labels,
+        }
+    }
+}
+
+// parse_downward_labels 解析 downward API 的 labels 文件，每行格式为 key="value"
+fn parse_downward_labels(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().trim_matches('"').to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// InstanceEnricher 注册实例时补全调用方没有设置的实例信息，已经设置的字段以及 metadata 不会被覆盖，
+/// 实例的地域信息在 Engine 注册实例时统一通过 LocationProvider 补全。
+/// 实例 IP 为空时北极星服务端会拒绝请求，因此没有开启补全时同样会补全实例 IP
+pub(crate) struct InstanceEnricher {
+    enable: bool,
+    kube: KubernetesInfo,
+    labels: HashMap<String, String>,
+    // local_ip 实例 IP，优先使用 pod IP，没有时使用本机访问北极星服务端的 IP
+    local_ip: String,
+    version: String,
+    protocol: String,
+    vpc_id: String,
+}
+
+impl InstanceEnricher {
+    pub(crate) fn new(conf: Arc<Configuration>) -> Self {
+        let enrich_conf = &conf.provider.enrich;
+        if !enrich_conf.enable {
+            return Self {
+                enable: false,
+                kube: KubernetesInfo::default(),
+                labels: HashMap::new(),
+                local_ip: acquire_client_self_ip(conf.clone()),
+                version: String::new(),
+                protocol: String::new(),
+                vpc_id: String::new(),
+            };
+        }
+        let kube = if enrich_conf.kubernetes {
+            KubernetesInfo::load(
+                |k| std::env::var(k).ok(),
+                |p| std::fs::read_to_string(p).ok(),
+                &enrich_conf.downward_api_path,
+            )
+        } else {
+            KubernetesInfo::default()
+        };
+        let labels = if enrich_conf.client_labels {
+            conf.global.client.labels.clone()
+        } else {
+            HashMap::new()
+        };
+        let local_ip = if kube.pod_ip.is_empty() {
+            acquire_client_self_ip(conf.clone())
+        } else {
+            kube.pod_ip.clone()
+        };
+        let version = kube
+            .pod_labels
+            .get(POD_LABEL_VERSION)
+            .filter(|v| !v.is_empty())
+            .unwrap_or(&enrich_conf.version)
+            .clone();
+        Self {
+            enable: true,
+            kube,
+            labels,
+            local_ip,
+            version,
+            protocol: enrich_conf.protocol.clone(),
+            vpc_id: enrich_conf.vpc_id.clone(),
+        }
+    }
+
+    /// enrich_register 补全注册请求的实例 IP、版本、协议、VPC 以及 metadata
+    pub(crate) fn enrich_register(&self, req: &mut InstanceRegisterRequest) {
+        fill_empty(&mut req.ip, &self.local_ip);
+        if !self.enable {
+            return;
+        }
+        fill_empty(&mut req.vpc_id, &self.vpc_id);
+        fill_empty(&mut req.version, &self.version);
+        fill_empty(&mut req.protocol, &self.protocol);
+        let kube = &self.kube;
+        for (k, v) in [
+            (METADATA_POD_NAME, &kube.pod_name),
+            (METADATA_POD_NAMESPACE, &kube.pod_namespace),
+            (METADATA_NODE_NAME, &kube.node_name),
+            (METADATA_HOST_IP, &kube.host_ip),
+        ] {
+            if !v.is_empty() {
+                req.metadata
+                    .entry(k.to_string())
+                    .or_insert_with(|| v.clone());
+            }
+        }
+        // pod 的 version 标签已经写入实例版本
+        let pod_labels = kube
+            .pod_labels
+            .iter()
+            .filter(|(k, _)| k.as_str() != POD_LABEL_VERSION);
+        for (k, v) in pod_labels.chain(self.labels.iter()) {
+            req.metadata.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+    }
+
+    /// enrich_deregister 补全反注册请求的实例 IP 以及 VPC，保证和注册时的实例一致
+    pub(crate) fn enrich_deregister(&self, req: &mut InstanceDeregisterRequest) {
+        fill_empty(&mut req.ip, &self.local_ip);
+        if self.enable {
+            fill_empty(&mut req.vpc_id, &self.vpc_id);
+        }
+    }
+
+    /// enrich_heartbeat 补全心跳请求的实例 IP 以及 VPC，保证和注册时的实例一致
+    pub(crate) fn enrich_heartbeat(&self, req: &mut InstanceHeartbeatRequest) {
+        fill_empty(&mut req.ip, &self.local_ip);
+        if self.enable {
+            fill_empty(&mut req.vpc_id, &self.vpc_id);
+        }
+    }
+}
+
+fn fill_empty(field: &mut String, value: &str) {
+    if field.is_empty() && !value.is_empty() {
+        *field = value.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::engine::tests::{new_register_request, TEST_CONFIG};
+
+    fn new_kube_enricher() -> InstanceEnricher {
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("POD_IP", "10.0.0.8"),
+            ("POD_NAME", "demo-7d9f"),
+            ("NODE_NAME", "node-1"),
+            ("HOST_IP", "192.168.1.2"),
+        ]);
+        let kube = KubernetesInfo::load(
+            |k| env.get(k).map(|v| v.to_string()),
+            |p| {
+                if p.ends_with("labels") {
+                    Some("app=\"demo\"\nversion=\"v1\"\n".to_string())
+                } else {
+                    Some("prod\n".to_string())
+                }
+            },
+            "/etc/podinfo",
+        );
+        InstanceEnricher {
+            enable: true,
+            local_ip: kube.pod_ip.clone(),
+            version: kube.pod_labels[POD_LABEL_VERSION].clone(),
+            kube,
+            labels: HashMap::from([("env".to_string(), "test".to_string())]),
+            protocol: "grpc".to_string(),
+            vpc_id: "vpc-1".to_string(),
+        }
+    }
+
+    fn new_empty_request() -> InstanceRegisterRequest {
+        let mut req = new_register_request(8080);
+        req.ip = String::new();
+        req.metadata = HashMap::from([("app".to_string(), "custom".to_string())]);
+        req
+    }
+
+    #[test]
+    fn test_enrich_register() {
+        let enricher = new_kube_enricher();
+        let mut req = new_empty_request();
+        enricher.enrich_register(&mut req);
+
+        assert_eq!("10.0.0.8", req.ip);
+        assert_eq!("v1", req.version);
+        assert_eq!("grpc", req.protocol);
+        assert_eq!("vpc-1", req.vpc_id);
+        assert_eq!("demo-7d9f", req.metadata[METADATA_POD_NAME]);
+        assert_eq!("prod", req.metadata[METADATA_POD_NAMESPACE]);
+        assert_eq!("node-1", req.metadata[METADATA_NODE_NAME]);
+        assert_eq!("192.168.1.2", req.metadata[METADATA_HOST_IP]);
+        assert_eq!("test", req.metadata["env"]);
+        // pod 的 version 标签只写入实例版本
+        assert!(!req.metadata.contains_key(POD_LABEL_VERSION));
+        // 调用方设置的 metadata 不会被覆盖
+        assert_eq!("custom", req.metadata["app"]);
+
+        let mut heartbeat = new_empty_request().to_heartbeat_request();
+        enricher.enrich_heartbeat(&mut heartbeat);
+        assert_eq!("10.0.0.8", heartbeat.ip);
+        assert_eq!("vpc-1", heartbeat.vpc_id);
+        let mut deregister = new_empty_request().to_deregister_request();
+        enricher.enrich_deregister(&mut deregister);
+        assert_eq!("10.0.0.8", deregister.ip);
+        assert_eq!("vpc-1", deregister.vpc_id);
+    }
+
+    #[test]
+    fn test_enrich_keep_request_fields() {
+        let enricher = new_kube_enricher();
+        let mut req = new_register_request(8080);
+        req.version = "v2".to_string();
+        req.protocol = "http".to_string();
+        req.vpc_id = "vpc-2".to_string();
+        enricher.enrich_register(&mut req);
+
+        assert_eq!("127.0.0.1", req.ip);
+        assert_eq!("v2", req.version);
+        assert_eq!("http", req.protocol);
+        assert_eq!("vpc-2", req.vpc_id);
+    }
+
+    #[test]
+    fn test_enrich_from_config() {
+        let mut conf: Configuration = serde_yaml::from_str(TEST_CONFIG).unwrap();
+        let enrich_conf = &mut conf.provider.enrich;
+        enrich_conf.enable = true;
+        enrich_conf.kubernetes = false;
+        enrich_conf.version = "v3".to_string();
+        enrich_conf.protocol = "grpc".to_string();
+        enrich_conf.vpc_id = "vpc-3".to_string();
+        conf.global
+            .client
+            .labels
+            .insert("env".to_string(), "test".to_string());
+        let enricher = InstanceEnricher::new(Arc::new(conf));
+
+        let mut req = new_empty_request();
+        enricher.enrich_register(&mut req);
+        // 没有 pod IP 时使用本机访问北极星服务端的 IP
+        assert_eq!("127.0.0.1", req.ip);
+        assert_eq!("v3", req.version);
+        assert_eq!("grpc", req.protocol);
+        assert_eq!("vpc-3", req.vpc_id);
+        assert_eq!("test", req.metadata["env"]);
+    }
+
+    #[test]
+    fn test_enrich_disabled() {
+        let conf: Configuration = serde_yaml::from_str(TEST_CONFIG).unwrap();
+        let enricher = InstanceEnricher::new(Arc::new(conf));
+
+        let mut req = new_empty_request();
+        enricher.enrich_register(&mut req);
+        // 没有开启补全时只补全实例 IP
+        assert_eq!("127.0.0.1", req.ip);
+        assert!(req.version.is_empty());
+        assert_eq!(1, req.metadata.len());
+    }
+}
@@ -0,0 +1,381 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::core::config::provider::ProviderConfig;
+use crate::core::engine::Engine;
+use crate::core::model::error::ErrorCode;
+use crate::discovery::req::{InstanceHeartbeatRequest, InstanceRegisterRequest};
+
+// 调度器扫描到期心跳任务的周期
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+// 每个批次最多包含的心跳数，批次只是对到期任务按数量切分，并不按照服务端节点分组，
+// 同一批次内的心跳由一个 worker 并发上报，每个实例仍然单独发送一次心跳请求
+const BATCH_SIZE: usize = 32;
+const DEFAULT_WORKER_SIZE: usize = 4;
+// 实例未设置 ttl 时使用北极星服务端默认的 5s
+const DEFAULT_TTL: u32 = 5;
+// 心跳周期的抖动比例
+const JITTER_PERCENT: f64 = 0.1;
+
+struct BeatTask {
+    register: InstanceRegisterRequest,
+    beat: InstanceHeartbeatRequest,
+    interval: Duration,
+    next_beat: Instant,
+    last_register: Instant,
+    // running 心跳正在上报中，避免上一次心跳未结束时重复上报
+    running: bool,
+}
+
+struct BeatJob {
+    key: String,
+    register: InstanceRegisterRequest,
+    beat: InstanceHeartbeatRequest,
+    last_register: Instant,
+}
+
+/// HeartbeatScheduler 统一调度自动心跳任务，由固定数量的 worker 按批次上报心跳，
+/// 用于限制同时上报的心跳数量，每个实例的心跳仍然是一次独立的请求
+pub(crate) struct HeartbeatScheduler {
+    engine: Arc<Engine>,
+    tasks: Arc<Mutex<HashMap<String, BeatTask>>>,
+    worker_size: usize,
+    min_register_interval: Duration,
+    started: AtomicBool,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl HeartbeatScheduler {
+    pub(crate) fn new(engine: Arc<Engine>, conf: &ProviderConfig) -> Self {
+        let worker_size = conf
+            .heartbeat_worker_size
+            .map(|v| v as usize)
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_WORKER_SIZE);
+        Self {
+            engine,
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            worker_size,
+            min_register_interval: conf.min_register_interval,
+            started: AtomicBool::new(false),
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// add 添加实例的自动心跳任务，req 用于服务端丢失实例时重新注册，
+    /// req 设置了 health_check 时只在健康检查通过时上报心跳
+    pub(crate) fn add(&self, req: InstanceRegisterRequest) {
+        self.start();
+        let beat = req.to_heartbeat_request();
+        let key = beat.beat_key();
+        let ttl = if req.ttl == 0 { DEFAULT_TTL } else { req.ttl };
+        let interval = Duration::from_secs(u64::from(ttl));
+        let now = Instant::now();
+        crate::info!(
+            "[polaris][discovery][heartbeat] add one auto_beat task={} duration={}s",
+            key,
+            ttl,
+        );
+        // 首次心跳时间在一个周期内随机分布，避免大量实例同时注册后在同一时刻上报心跳
+        let next_beat = now + interval.mul_f64(rand::thread_rng().gen::<f64>());
+        self.tasks.lock().unwrap().insert(
+            key,
+            BeatTask {
+                register: req,
+                beat,
+                interval,
+                next_beat,
+                last_register: now,
+                running: false,
+            },
+        );
+    }
+
+    /// remove 移除实例的自动心跳任务
+    pub(crate) fn remove(&self, key: &str) {
+        if self.tasks.lock().unwrap().remove(key).is_some() {
+            crate::info!(
+                "[polaris][discovery][heartbeat] remove one auto_beat task={}",
+                key,
+            );
+        }
+    }
+
+    /// stop 停止调度器以及所有 worker，之后再次 add 会重新启动调度器
+    pub(crate) fn stop(&self) {
+        self.tasks.lock().unwrap().clear();
+        let mut handles = self.handles.lock().unwrap();
+        for handle in handles.drain(..) {
+            handle.abort();
+        }
+        self.started.store(false, Ordering::SeqCst);
+    }
+
+    fn start(&self) {
+        // 与 stop 使用同一把锁，保证启动与停止不会交错
+        let mut handles = self.handles.lock().unwrap();
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let executor = self.engine.get_executor();
+        let (tx, rx) = mpsc::channel::<Vec<BeatJob>>(self.worker_size);
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+
+        for _ in 0..self.worker_size {
+            let rx = rx.clone();
+            let engine = self.engine.clone();
+            let tasks = self.tasks.clone();
+            let min_register_interval = self.min_register_interval;
+            handles.push(executor.spawn(async move {
+                loop {
+                    let batch = rx.lock().await.recv().await;
+                    let batch = match batch {
+                        Some(batch) => batch,
+                        None => return,
+                    };
+                    futures::future::join_all(batch.into_iter().map(|job| {
+                        do_beat(engine.clone(), tasks.clone(), job, min_register_interval)
+                    }))
+                    .await;
+                }
+            }));
+        }
+
+        let tasks = self.tasks.clone();
+        let engine = self.engine.clone();
+        handles.push(executor.spawn(async move {
+            loop {
+                tokio::time::sleep(TICK_INTERVAL).await;
+                // SDK 已经关闭，实例已经被反注册，不再上报心跳
+                if engine.is_closed() {
+                    return;
+                }
+                let batches = collect_due(&mut tasks.lock().unwrap(), Instant::now());
+                for batch in batches {
+                    // worker 全部繁忙时在这里等待，保证同时上报的心跳数量有上限
+                    if tx.send(batch).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }));
+    }
+}
+
+// collect_due 取出到期的心跳任务并按照 BATCH_SIZE 切分为多个批次，同时计算下一次心跳的时间
+fn collect_due(tasks: &mut HashMap<String, BeatTask>, now: Instant) -> Vec<Vec<BeatJob>> {
+    let mut batches: Vec<Vec<BeatJob>> = Vec::new();
+    let mut batch = Vec::new();
+    for (key, task) in tasks.iter_mut() {
+        if task.running || task.next_beat > now {
+            continue;
+        }
+        task.running = true;
+        // 每个任务单独计算抖动，避免实例的心跳时间逐渐收敛到同一时刻
+        task.next_beat = now + jitter(task.interval);
+        batch.push(BeatJob {
+            key: key.clone(),
+            register: task.register.clone(),
+            beat: task.beat.clone(),
+            last_register: task.last_register,
+        });
+        if batch.len() >= BATCH_SIZE {
+            batches.push(std::mem::take(&mut batch));
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+// jitter 在心跳周期的基础上增加 ±10% 的随机抖动
+fn jitter(interval: Duration) -> Duration {
+    let factor = rand::thread_rng().gen_range(1.0 - JITTER_PERCENT..=1.0 + JITTER_PERCENT);
+    interval.mul_f64(factor)
+}
+
+async fn do_beat(
+    engine: Arc<Engine>,
+    tasks: Arc<Mutex<HashMap<String, BeatTask>>>,
+    job: BeatJob,
+    min_register_interval: Duration,
+) {
+    crate::debug!(
+        "[polaris][discovery][heartbeat] start to auto_beat instance: {:?}",
+        job.beat
+    );
+    let mut registered = false;
+    let healthy = match job.register.health_check.as_ref() {
+        Some(check) => check.check(),
+        None => true,
+    };
+    if !healthy {
+        // 健康检查未通过时不上报心跳，实例在 ttl 之后会被服务端标记为不健康
+        crate::warn!(
+            "[polaris][discovery][heartbeat] instance {} health check fail, skip auto_beat",
+            job.key
+        );
+    } else if let Err(e) = engine.instance_heartbeat(job.beat).await {
+        // 心跳上报期间实例可能已经被反注册或者 SDK 已经关闭，此时不能再重新注册，
+        // 否则会留下一个没有心跳任务的实例
+        if e.get_code() == ErrorCode::InstanceNotFound
+            && job.last_register.elapsed() >= min_register_interval
+            && !engine.is_closed()
+            && tasks.lock().unwrap().contains_key(&job.key)
+        {
+            // 服务端已经不存在该实例，例如北极星服务端数据丢失，使用原始的注册请求重新注册
+            crate::warn!(
+                "[polaris][discovery][heartbeat] instance {} not found in server, re-register it",
+                job.key
+            );
+            match engine.register_instance(job.register).await {
+                Ok(_) => registered = true,
+                Err(e) => crate::error!(
+                    "[polaris][discovery][heartbeat] re-register instance {} fail: {}",
+                    job.key,
+                    e
+                ),
+            }
+        } else {
+            crate::error!("[polaris][discovery][heartbeat] auto_beat instance to server fail: {e}");
+        }
+    }
+    if let Some(task) = tasks.lock().unwrap().get_mut(&job.key) {
+        task.running = false;
+        if registered {
+            task.last_register = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::engine::tests::{new_engine, new_register_request, wait_beats, MockConnector};
+
+    fn new_task(port: u32, next_beat: Instant) -> BeatTask {
+        let mut register = new_register_request(port);
+        register.auto_heartbeat = true;
+        BeatTask {
+            beat: register.to_heartbeat_request(),
+            register,
+            interval: Duration::from_secs(5),
+            next_beat,
+            last_register: next_beat,
+            running: false,
+        }
+    }
+
+    #[test]
+    fn test_collect_due() {
+        let now = Instant::now();
+        let mut tasks = HashMap::new();
+        for port in 0..(BATCH_SIZE as u32 + 1) {
+            tasks.insert(format!("due-{}", port), new_task(port, now));
+        }
+        tasks.insert(
+            "later".to_string(),
+            new_task(10000, now + Duration::from_secs(5)),
+        );
+
+        let batches = collect_due(&mut tasks, now);
+        assert_eq!(2, batches.len());
+        assert_eq!(BATCH_SIZE + 1, batches.iter().map(Vec::len).sum::<usize>());
+        for (key, task) in tasks.iter() {
+            if key == "later" {
+                assert!(!task.running);
+                continue;
+            }
+            assert!(task.running);
+            assert!(task.next_beat >= now + Duration::from_millis(4500));
+            assert!(task.next_beat <= now + Duration::from_millis(5500));
+        }
+        // 每个任务的下一次心跳时间单独抖动，不会对齐到同一个时刻
+        let next_beat = tasks["due-0"].next_beat;
+        assert!(tasks
+            .iter()
+            .filter(|(key, _)| key.as_str() != "later")
+            .any(|(_, task)| task.next_beat != next_beat));
+        // 上报中的任务不会被重复调度
+        assert!(collect_due(&mut tasks, now + Duration::from_secs(6))
+            .iter()
+            .all(|batch| batch.iter().all(|job| job.key == "later")));
+    }
+
+    #[test]
+    fn test_add_after_stop() {
+        let connector = MockConnector::default();
+        let heartbeats = connector.heartbeats.clone();
+        let (_rt, engine) = new_engine(connector);
+        let scheduler =
+            HeartbeatScheduler::new(engine.clone(), &engine.get_extensions().conf.provider);
+        let mut req = new_task(8080, Instant::now()).register;
+        req.ttl = 1;
+
+        scheduler.add(req.clone());
+        assert!(wait_beats(&heartbeats, 0));
+
+        scheduler.stop();
+        std::thread::sleep(Duration::from_millis(600));
+        let stopped = heartbeats.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(1500));
+        assert_eq!(stopped, heartbeats.load(Ordering::SeqCst));
+
+        // 停止之后重新添加的任务仍然会上报心跳
+        scheduler.add(req);
+        assert!(wait_beats(&heartbeats, stopped));
+    }
+
+    #[test]
+    fn test_deregister_during_beat() {
+        // 心跳耗时 300ms，并且返回实例不存在
+        let connector = MockConnector {
+            slow_ports: vec![8080],
+            missing_ports: vec![8080],
+            ..Default::default()
+        };
+        let heartbeats = connector.heartbeats.clone();
+        let calls = connector.calls.clone();
+        let (_rt, engine) = new_engine(connector);
+        let mut scheduler =
+            HeartbeatScheduler::new(engine.clone(), &engine.get_extensions().conf.provider);
+        scheduler.min_register_interval = Duration::ZERO;
+        let mut req = new_task(8080, Instant::now()).register;
+        req.ttl = 1;
+        let key = req.to_heartbeat_request().beat_key();
+
+        scheduler.add(req);
+        assert!(wait_beats(&heartbeats, 0));
+        // 心跳上报期间反注册实例
+        scheduler.remove(&key);
+        // 等待心跳以及可能发生的重新注册完成
+        std::thread::sleep(Duration::from_millis(1000));
+        assert!(calls
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|call| !call.starts_with("register")));
+    }
+}
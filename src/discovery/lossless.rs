@@ -0,0 +1,593 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::{Notify, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::core::config::provider::LosslessConfig;
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::discovery::api::ProviderAPI;
+use crate::discovery::req::{
+    BaseInstance, InstanceProperties, InstanceRegisterRequest, LosslessActionProvider,
+};
+
+/// PATH_ONLINE admin 接口，立即上线所有实例
+pub static PATH_ONLINE: &str = "/online";
+/// PATH_OFFLINE admin 接口，下线所有实例，可作为 Kubernetes 的 preStop 钩子
+pub static PATH_OFFLINE: &str = "/offline";
+/// PATH_READINESS admin 接口，所有实例上线后返回 200，可作为 Kubernetes 的 readinessProbe
+pub static PATH_READINESS: &str = "/readiness";
+
+/// ProviderLosslessAction 通过 ProviderAPI 执行注册、反注册的优雅上下线动作
+pub struct ProviderLosslessAction {
+    provider: Arc<dyn ProviderAPI>,
+    req: InstanceRegisterRequest,
+    // readiness 用户提供的就绪检查，未设置时按照 delayRegisterInterval 延迟注册
+    readiness: Option<Arc<dyn Fn() -> bool + Send + Sync>>,
+}
+
+impl ProviderLosslessAction {
+    pub fn new(provider: Arc<dyn ProviderAPI>, req: InstanceRegisterRequest) -> Self {
+        Self {
+            provider,
+            req,
+            readiness: None,
+        }
+    }
+
+    /// with_readiness 设置就绪检查，检查通过后才会注册实例
+    pub fn with_readiness<F>(mut self, readiness: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.readiness = Some(Arc::new(readiness));
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl LosslessActionProvider for ProviderLosslessAction {
+    fn get_name(&self) -> String {
+        "provider".to_string()
+    }
+
+    async fn do_register(&self, prop: InstanceProperties) -> Result<(), PolarisError> {
+        let mut req = self.req.clone();
+        req.metadata.extend(prop.metadata);
+        self.provider.register(req).await.map(|_| ())
+    }
+
+    async fn do_deregister(&self) -> Result<(), PolarisError> {
+        self.provider
+            .deregister(self.req.to_deregister_request())
+            .await
+    }
+
+    fn is_enable_healthcheck(&self) -> bool {
+        self.readiness.is_some()
+    }
+
+    async fn do_healthcheck(&self) -> bool {
+        self.readiness.as_ref().map(|f| f()).unwrap_or(true)
+    }
+}
+
+pub(crate) fn instance_key(ins: &dyn BaseInstance) -> String {
+    format!(
+        "{}#{}#{}#{}",
+        ins.get_namespace(),
+        ins.get_service(),
+        ins.get_ip(),
+        ins.get_port()
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LosslessStatus {
+    // Offline 未注册或者已经下线
+    Offline,
+    // Pending 等待就绪检查通过或者延迟注册时间到达
+    Pending,
+    Online,
+}
+
+struct LosslessEntry {
+    action: Arc<dyn LosslessActionProvider>,
+    status: LosslessStatus,
+}
+
+/// LosslessState 优雅上下线的实例状态，LosslessAPI 与 admin 接口共享
+pub(crate) struct LosslessState {
+    conf: LosslessConfig,
+    entries: RwLock<HashMap<String, LosslessEntry>>,
+    // online_notify 通过 /online 接口上线时唤醒等待中的延迟注册
+    online_notify: Notify,
+    shutting_down: AtomicBool,
+    admin_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl LosslessState {
+    pub(crate) fn new(conf: LosslessConfig) -> Self {
+        Self {
+            conf,
+            entries: RwLock::new(HashMap::new()),
+            online_notify: Notify::new(),
+            shutting_down: AtomicBool::new(false),
+            admin_task: Mutex::new(None),
+        }
+    }
+
+    pub(crate) async fn set_action(&self, key: String, action: Arc<dyn LosslessActionProvider>) {
+        let mut entries = self.entries.write().await;
+        match entries.get_mut(&key) {
+            Some(entry) => entry.action = action,
+            None => {
+                entries.insert(
+                    key,
+                    LosslessEntry {
+                        action,
+                        status: LosslessStatus::Offline,
+                    },
+                );
+            }
+        }
+    }
+
+    /// register 优雅上线，开启优雅上下线时等待就绪检查通过或者延迟注册时间到达后再注册，就绪检查最多等待延迟注册时间
+    pub(crate) async fn register(&self, key: &str) -> Result<(), PolarisError> {
+        let action = {
+            let mut entries = self.entries.write().await;
+            let entry = entries.get_mut(key).ok_or_else(|| {
+                PolarisError::new(
+                    ErrorCode::ApiInvalidArgument,
+                    format!("lossless action provider of {} not set", key),
+                )
+            })?;
+            if entry.status == LosslessStatus::Online {
+                return Ok(());
+            }
+            entry.status = LosslessStatus::Pending;
+            entry.action.clone()
+        };
+        if self.conf.enable {
+            self.wait_ready(key, action.as_ref()).await;
+        }
+        self.online(key, true).await
+    }
+
+    async fn wait_ready(&self, key: &str, action: &dyn LosslessActionProvider) {
+        let start = std::time::Instant::now();
+        loop {
+            if !self.is_pending(key).await {
+                return;
+            }
+            if action.is_enable_healthcheck() && action.do_healthcheck().await {
+                crate::info!(
+                    "[polaris][lossless] instance {} health check pass, cost {:?}",
+                    key,
+                    start.elapsed()
+                );
+                return;
+            }
+            // 配置了就绪检查时，最多等待 delayRegisterInterval，超时后仍然注册实例
+            let remain = match self
+                .conf
+                .delay_register_interval
+                .checked_sub(start.elapsed())
+            {
+                Some(remain) if !remain.is_zero() => remain,
+                _ => {
+                    if action.is_enable_healthcheck() {
+                        crate::warn!(
+                            "[polaris][lossless] instance {} health check not pass in {:?}, register anyway",
+                            key,
+                            self.conf.delay_register_interval
+                        );
+                    }
+                    return;
+                }
+            };
+            let wait = if action.is_enable_healthcheck() {
+                self.conf.health_check_interval.min(remain)
+            } else {
+                remain
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.online_notify.notified() => {}
+            }
+        }
+    }
+
+    async fn is_pending(&self, key: &str) -> bool {
+        self.entries
+            .read()
+            .await
+            .get(key)
+            .map(|entry| entry.status == LosslessStatus::Pending)
+            .unwrap_or(false)
+    }
+
+    // online 注册实例，pending_only 为 true 时只注册仍在等待上线的实例
+    async fn online(&self, key: &str, pending_only: bool) -> Result<(), PolarisError> {
+        let action = {
+            let entries = self.entries.read().await;
+            match entries.get(key) {
+                Some(entry) if entry.status == LosslessStatus::Online => return Ok(()),
+                Some(entry) if pending_only && entry.status != LosslessStatus::Pending => {
+                    return Ok(())
+                }
+                Some(entry) => entry.action.clone(),
+                None => return Ok(()),
+            }
+        };
+        action.do_register(InstanceProperties::default()).await?;
+        if let Some(entry) = self.entries.write().await.get_mut(key) {
+            entry.status = LosslessStatus::Online;
+        }
+        crate::info!("[polaris][lossless] instance {} online", key);
+        Ok(())
+    }
+
+    /// deregister 优雅下线，先反注册实例，再等待存量请求处理完成
+    pub(crate) async fn deregister(&self, key: &str) -> Result<(), PolarisError> {
+        let action = {
+            let mut entries = self.entries.write().await;
+            match entries.get(key) {
+                Some(entry) if entry.status == LosslessStatus::Online => entry.action.clone(),
+                // 还没有上线的实例直接移除，等待中的延迟注册不再执行
+                Some(_) => {
+                    entries.remove(key);
+                    return Ok(());
+                }
+                None => return Ok(()),
+            }
+        };
+        // 反注册失败时保留实例，可以再次下线或者在 shutdown 时下线
+        action.do_deregister().await?;
+        self.entries.write().await.remove(key);
+        crate::info!("[polaris][lossless] instance {} offline", key);
+        self.drain().await;
+        Ok(())
+    }
+
+    /// online_all 立即上线所有实例，包括等待中的延迟注册
+    pub(crate) async fn online_all(&self) -> Result<(), PolarisError> {
+        let keys: Vec<String> = self.entries.read().await.keys().cloned().collect();
+        let mut ret = Ok(());
+        for key in keys {
+            if let Err(e) = self.online(&key, false).await {
+                crate::error!("[polaris][lossless] instance {} online fail: {}", key, e);
+                ret = Err(e);
+            }
+        }
+        self.online_notify.notify_waiters();
+        ret
+    }
+
+    /// offline_all 下线所有实例，等待中的延迟注册不再执行
+    pub(crate) async fn offline_all(&self) -> Result<(), PolarisError> {
+        let mut actions = Vec::new();
+        for (key, entry) in self.entries.write().await.iter_mut() {
+            if entry.status == LosslessStatus::Online {
+                actions.push((key.clone(), entry.action.clone()));
+            }
+            entry.status = LosslessStatus::Offline;
+        }
+        self.online_notify.notify_waiters();
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let mut ret = Ok(());
+        for (key, action) in actions {
+            match action.do_deregister().await {
+                Ok(_) => crate::info!("[polaris][lossless] instance {} offline", key),
+                Err(e) => {
+                    crate::error!("[polaris][lossless] instance {} offline fail: {}", key, e);
+                    ret = Err(e);
+                }
+            }
+        }
+        self.drain().await;
+        ret
+    }
+
+    async fn drain(&self) {
+        if self.conf.enable && !self.conf.drain_interval.is_zero() {
+            crate::info!(
+                "[polaris][lossless] wait {:?} for in-flight requests to drain",
+                self.conf.drain_interval
+            );
+            tokio::time::sleep(self.conf.drain_interval).await;
+        }
+    }
+
+    /// is_ready 所有实例都已经上线并且进程没有在退出中
+    pub(crate) async fn is_ready(&self) -> bool {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return false;
+        }
+        let entries = self.entries.read().await;
+        !entries.is_empty()
+            && entries
+                .values()
+                .all(|entry| entry.status == LosslessStatus::Online)
+    }
+
+    /// shutdown 进程退出前调用，下线所有实例并关闭 admin 接口
+    pub(crate) async fn shutdown(&self) {
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.offline_all().await;
+        self.stop_admin();
+    }
+
+    /// start_admin 启动优雅上下线的 admin 接口，重复调用时只会启动一次
+    pub(crate) async fn start_admin(
+        self: &Arc<Self>,
+        executor: Arc<Runtime>,
+    ) -> Result<(), PolarisError> {
+        if !self.conf.enable || self.admin_task.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        let addr = format!("{}:{}", self.conf.host, self.conf.port);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let state = self.clone();
+        let bind_addr = addr.clone();
+        let task = executor.spawn(async move {
+            let listener = match TcpListener::bind(&bind_addr).await {
+                Ok(listener) => {
+                    let _ = tx.send(Ok(()));
+                    listener
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        crate::warn!("[polaris][lossless] admin accept fail: {}", e);
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<Incoming>| {
+                        let state = state.clone();
+                        async move {
+                            let (code, body) = handle_admin(&state, req.uri().path()).await;
+                            let mut rsp = Response::new(Full::new(Bytes::from(body)));
+                            *rsp.status_mut() = code;
+                            Ok::<_, Infallible>(rsp)
+                        }
+                    });
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        crate::debug!("[polaris][lossless] admin connection closed: {}", e);
+                    }
+                });
+            }
+        });
+
+        match rx.await {
+            Ok(Ok(_)) => {
+                crate::info!("[polaris][lossless] admin server listen on {}", addr);
+                let mut admin_task = self.admin_task.lock().unwrap();
+                match admin_task.as_ref() {
+                    // 并发启动时只保留一个
+                    Some(_) => task.abort(),
+                    None => *admin_task = Some(task),
+                }
+                Ok(())
+            }
+            Ok(Err(e)) => Err(PolarisError::new(
+                ErrorCode::NetworkError,
+                format!("lossless admin server listen on {} fail: {}", addr, e),
+            )),
+            Err(e) => Err(PolarisError::new(ErrorCode::InternalError, e.to_string())),
+        }
+    }
+
+    pub(crate) fn stop_admin(&self) {
+        if let Some(task) = self.admin_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+async fn handle_admin(state: &LosslessState, path: &str) -> (StatusCode, String) {
+    if path == PATH_ONLINE {
+        return match state.online_all().await {
+            Ok(_) => (StatusCode::OK, "online".to_string()),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+    }
+    if path == PATH_OFFLINE {
+        return match state.offline_all().await {
+            Ok(_) => (StatusCode::OK, "offline".to_string()),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+    }
+    if path == PATH_READINESS {
+        return match state.is_ready().await {
+            true => (StatusCode::OK, "ready".to_string()),
+            false => (StatusCode::SERVICE_UNAVAILABLE, "not ready".to_string()),
+        };
+    }
+    (StatusCode::NOT_FOUND, "not found".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockAction {
+        ready: AtomicBool,
+        register_count: AtomicU32,
+        deregister_count: AtomicU32,
+        deregister_fail: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl LosslessActionProvider for MockAction {
+        fn get_name(&self) -> String {
+            "mock".to_string()
+        }
+
+        async fn do_register(&self, _prop: InstanceProperties) -> Result<(), PolarisError> {
+            self.register_count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn do_deregister(&self) -> Result<(), PolarisError> {
+            self.deregister_count.fetch_add(1, Ordering::SeqCst);
+            if self.deregister_fail.load(Ordering::SeqCst) {
+                return Err(PolarisError::new(
+                    ErrorCode::ServerError,
+                    "mock deregister fail".to_string(),
+                ));
+            }
+            Ok(())
+        }
+
+        fn is_enable_healthcheck(&self) -> bool {
+            true
+        }
+
+        async fn do_healthcheck(&self) -> bool {
+            self.ready.load(Ordering::SeqCst)
+        }
+    }
+
+    fn new_state() -> Arc<LosslessState> {
+        Arc::new(LosslessState::new(LosslessConfig {
+            enable: true,
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            delay_register_interval: Duration::from_secs(30),
+            health_check_interval: Duration::from_millis(10),
+            drain_interval: Duration::from_millis(10),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_register_after_health_check() {
+        let state = new_state();
+        let action = Arc::new(MockAction::default());
+        state.set_action("ins".to_string(), action.clone()).await;
+
+        let register_state = state.clone();
+        let task = tokio::spawn(async move { register_state.register("ins").await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(0, action.register_count.load(Ordering::SeqCst));
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            handle_admin(&state, PATH_READINESS).await.0
+        );
+
+        action.ready.store(true, Ordering::SeqCst);
+        task.await.unwrap().unwrap();
+        assert_eq!(1, action.register_count.load(Ordering::SeqCst));
+        assert_eq!(StatusCode::OK, handle_admin(&state, PATH_READINESS).await.0);
+
+        assert_eq!(StatusCode::OK, handle_admin(&state, PATH_OFFLINE).await.0);
+        assert_eq!(1, action.deregister_count.load(Ordering::SeqCst));
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            handle_admin(&state, PATH_READINESS).await.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_online_wakes_pending_register() {
+        let state = new_state();
+        let action = Arc::new(MockAction::default());
+        state.set_action("ins".to_string(), action.clone()).await;
+
+        let register_state = state.clone();
+        let task = tokio::spawn(async move { register_state.register("ins").await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(StatusCode::OK, handle_admin(&state, PATH_ONLINE).await.0);
+        task.await.unwrap().unwrap();
+        assert_eq!(1, action.register_count.load(Ordering::SeqCst));
+
+        state.shutdown().await;
+        assert_eq!(1, action.deregister_count.load(Ordering::SeqCst));
+        assert!(!state.is_ready().await);
+    }
+
+    #[tokio::test]
+    async fn test_deregister_fail_keep_instance() {
+        let state = new_state();
+        let action = Arc::new(MockAction::default());
+        action.ready.store(true, Ordering::SeqCst);
+        state.set_action("ins".to_string(), action.clone()).await;
+        state.register("ins").await.unwrap();
+
+        action.deregister_fail.store(true, Ordering::SeqCst);
+        assert!(state.deregister("ins").await.is_err());
+        assert!(state.entries.read().await.contains_key("ins"));
+        assert!(state.is_ready().await);
+
+        // 反注册失败的实例可以再次下线
+        action.deregister_fail.store(false, Ordering::SeqCst);
+        state.deregister("ins").await.unwrap();
+        assert!(!state.entries.read().await.contains_key("ins"));
+        assert_eq!(2, action.deregister_count.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_health_check_wait_capped_by_delay() {
+        let state = Arc::new(LosslessState::new(LosslessConfig {
+            delay_register_interval: Duration::from_millis(50),
+            ..new_state().conf.clone()
+        }));
+        let action = Arc::new(MockAction::default());
+        state.set_action("ins".to_string(), action.clone()).await;
+
+        // 就绪检查一直不通过时，等待 delayRegisterInterval 后仍然注册
+        let start = std::time::Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), state.register("ins"))
+            .await
+            .expect("register should not wait forever")
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(1, action.register_count.load(Ordering::SeqCst));
+    }
+}
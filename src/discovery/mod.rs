@@ -16,4 +16,8 @@
 pub mod req;
 
 pub mod api;
+pub mod lossless;
+pub mod enrich;
+pub mod contract;
 mod default;
+mod heartbeat;
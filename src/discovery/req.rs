@@ -16,18 +16,23 @@
 use prost::Message;
 
 use crate::core::model::cache::EventType;
+use crate::core::model::circuitbreaker::RetStatus;
 use crate::core::model::error::{ErrorCode, PolarisError};
 use crate::core::model::loadbalance::Criteria;
 use crate::core::model::naming::{
-    Instance, Location, ServiceContract, ServiceInstances, ServiceInstancesChangeEvent,
+    Instance, Location, ServiceContract, ServiceInfo, ServiceInstances,
+    ServiceInstancesChangeEvent, ServicesChangeEvent,
 };
-use crate::core::model::router::RouteInfo;
+use crate::core::model::router::{RouteInfo, RouterChain};
+use crate::core::model::watch::WatchStreamOption;
 use std::any::Any;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use super::default::InstanceResourceListener;
+use tokio::runtime::Runtime;
+
+use super::default::{InstanceResourceListener, ServiceResourceListener};
 
 #[derive(Clone, Debug)]
 pub struct InstanceRegisterRequest {
@@ -59,6 +64,29 @@ pub struct InstanceRegisterRequest {
     // do heartbeat action by the SDK.
     // If it is false, the instance will not be automatically do heartbeat action by the SDK.
     pub auto_heartbeat: bool,
+    // health_check is called before each automatic heartbeat. If it returns false,
+    // the heartbeat is skipped and the server marks the instance unhealthy after ttl.
+    pub health_check: Option<HealthCheck>,
+}
+
+/// HealthCheck 自动心跳上报前执行的健康检查，返回 false 时跳过本次心跳
+#[derive(Clone)]
+pub struct HealthCheck(pub Arc<dyn Fn() -> bool + Send + Sync>);
+
+impl HealthCheck {
+    pub fn new(check: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(check))
+    }
+
+    pub fn check(&self) -> bool {
+        (self.0)()
+    }
+}
+
+impl std::fmt::Debug for HealthCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HealthCheck")
+    }
 }
 
 impl InstanceRegisterRequest {
@@ -79,6 +107,7 @@ impl InstanceRegisterRequest {
             metadata: self.metadata.clone(),
             location: self.location.clone(),
             revision: "".to_string(),
+            ctime: "".to_string(),
         }
     }
 
@@ -94,6 +123,18 @@ impl InstanceRegisterRequest {
             vpc_id: self.vpc_id.clone(),
         }
     }
+
+    pub fn to_deregister_request(&self) -> InstanceDeregisterRequest {
+        InstanceDeregisterRequest {
+            flow_id: self.flow_id.clone(),
+            timeout: self.timeout,
+            namespace: self.namespace.clone(),
+            service: self.service.clone(),
+            ip: self.ip.clone(),
+            port: self.port,
+            vpc_id: self.vpc_id.clone(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -102,6 +143,7 @@ pub struct InstanceRegisterResponse {
     pub exist: bool,
 }
 
+#[derive(Clone, Debug)]
 pub struct InstanceDeregisterRequest {
     pub flow_id: String,
     pub timeout: Duration,
@@ -130,6 +172,7 @@ impl InstanceDeregisterRequest {
             metadata: Default::default(),
             location: Default::default(),
             revision: "".to_string(),
+            ctime: "".to_string(),
         }
     }
 
@@ -193,10 +236,48 @@ impl InstanceHeartbeatRequest {
             metadata: Default::default(),
             location: Default::default(),
             revision: "".to_string(),
+            ctime: "".to_string(),
         }
     }
 }
 
+/// BatchInstanceRegisterRequest 批量注册实例，比如同一个进程对外暴露多个端口
+pub struct BatchInstanceRegisterRequest {
+    pub requests: Vec<InstanceRegisterRequest>,
+}
+
+/// BatchInstanceDeregisterRequest 批量反注册实例
+pub struct BatchInstanceDeregisterRequest {
+    pub requests: Vec<InstanceDeregisterRequest>,
+}
+
+/// BatchInstanceHeartbeatRequest 批量上报实例心跳
+pub struct BatchInstanceHeartbeatRequest {
+    pub requests: Vec<InstanceHeartbeatRequest>,
+}
+
+/// BatchInstanceResponse 批量操作的结果，results 与请求中实例的顺序一一对应
+#[derive(Debug)]
+pub struct BatchInstanceResponse<T> {
+    pub results: Vec<Result<T, PolarisError>>,
+}
+
+impl<T> BatchInstanceResponse<T> {
+    /// is_all_success 是否所有实例都操作成功
+    pub fn is_all_success(&self) -> bool {
+        self.results.iter().all(|ret| ret.is_ok())
+    }
+
+    /// errors 操作失败的实例下标以及对应的错误
+    pub fn errors(&self) -> Vec<(usize, &PolarisError)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, ret)| ret.as_ref().err().map(|err| (index, err)))
+            .collect()
+    }
+}
+
 pub struct ReportServiceContractRequest {
     pub flow_id: String,
     pub timeout: Duration,
@@ -214,6 +295,8 @@ pub struct GetOneInstanceRequest {
     pub criteria: Criteria,
     // 用于路由
     pub route_info: RouteInfo,
+    // 覆盖本次请求使用的路由链
+    pub chain_override: Option<RouterChain>,
 }
 
 impl GetOneInstanceRequest {
@@ -235,6 +318,47 @@ impl GetOneInstanceRequest {
     }
 }
 
+pub struct GetInstanceCandidatesRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    pub service: String,
+    pub namespace: String,
+    // 用于负载均衡，exclude_instances 可以排除已经调用失败的实例
+    pub criteria: Criteria,
+    // 用于路由
+    pub route_info: RouteInfo,
+    // 覆盖本次请求使用的路由链
+    pub chain_override: Option<RouterChain>,
+    // 最多返回的候选实例个数
+    pub count: usize,
+}
+
+impl GetInstanceCandidatesRequest {
+    pub fn check_valid(&self) -> Result<(), PolarisError> {
+        if self.service.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "service is empty".to_string(),
+            ));
+        }
+
+        if self.namespace.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "namespace is empty".to_string(),
+            ));
+        }
+
+        if self.count == 0 {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "count must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
 pub struct GetHealthInstanceRequest {
     pub flow_id: String,
     pub timeout: Duration,
@@ -293,6 +417,11 @@ pub struct InstanceResponse {
     pub instance: Instance,
 }
 
+pub struct InstanceCandidatesResponse {
+    // instances 按照优先级排序的不同实例，第一个为首选实例，其余用于重试
+    pub instances: Vec<Instance>,
+}
+
 #[derive(Clone, Debug)]
 pub struct InstancesResponse {
     pub instances: ServiceInstances,
@@ -349,10 +478,160 @@ impl WatchInstanceResponse {
             .cancel_watch(&self.watch_key, self.watch_id)
             .await;
     }
+
+    /// into_canceler 转换为可以在同步上下文中执行的取消监听动作
+    pub(crate) fn into_canceler(self, executor: Arc<Runtime>) -> Box<dyn FnOnce() + Send> {
+        Box::new(move || {
+            executor.spawn(async move {
+                self.cancel_watch().await;
+            });
+        })
+    }
+}
+
+/// WatchInstanceStreamRequest 以事件流的方式监听实例变化
+#[derive(Clone, Debug)]
+pub struct WatchInstanceStreamRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    pub namespace: String,
+    pub service: String,
+    // option 事件流的缓冲区配置
+    pub option: WatchStreamOption,
 }
 
-pub struct ServiceCallResult {}
+impl WatchInstanceStreamRequest {
+    pub fn check_valid(&self) -> Result<(), PolarisError> {
+        if self.service.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "service is empty".to_string(),
+            ));
+        }
+
+        if self.namespace.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "namespace is empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
 
+/// GetServicesRequest 获取命名空间下的服务列表
+#[derive(Clone, Debug)]
+pub struct GetServicesRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    pub namespace: String,
+    // metadata 服务元数据过滤条件，只返回包含全部键值对的服务
+    pub metadata: HashMap<String, String>,
+}
+
+impl GetServicesRequest {
+    pub fn check_valid(&self) -> Result<(), PolarisError> {
+        if self.namespace.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "namespace is empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServicesResponse {
+    pub revision: String,
+    pub services: Vec<ServiceInfo>,
+}
+
+pub struct WatchServicesRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    pub namespace: String,
+    // metadata 服务元数据过滤条件，只通知包含全部键值对的服务
+    pub metadata: HashMap<String, String>,
+    pub call_back: Arc<dyn Fn(ServicesChangeEvent) + Send + Sync>,
+}
+
+impl WatchServicesRequest {
+    pub fn check_valid(&self) -> Result<(), PolarisError> {
+        if self.namespace.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::ApiInvalidArgument,
+                "namespace is empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// WatchServicesStreamRequest 以事件流的方式监听服务列表变化
+#[derive(Clone, Debug)]
+pub struct WatchServicesStreamRequest {
+    pub flow_id: String,
+    pub timeout: Duration,
+    pub namespace: String,
+    // metadata 服务元数据过滤条件，只通知包含全部键值对的服务
+    pub metadata: HashMap<String, String>,
+    // option 事件流的缓冲区配置
+    pub option: WatchStreamOption,
+}
+
+pub struct WatchServicesResponse {
+    pub watch_id: u64,
+    watch_key: String,
+    owner: Arc<ServiceResourceListener>,
+}
+
+impl WatchServicesResponse {
+    pub fn new(watch_id: u64, watch_key: String, owner: Arc<ServiceResourceListener>) -> Self {
+        WatchServicesResponse {
+            watch_id,
+            watch_key,
+            owner,
+        }
+    }
+
+    pub async fn cancel_watch(&self) {
+        self.owner
+            .cancel_watch(&self.watch_key, self.watch_id)
+            .await;
+    }
+
+    /// into_canceler 转换为可以在同步上下文中执行的取消监听动作
+    pub(crate) fn into_canceler(self, executor: Arc<Runtime>) -> Box<dyn FnOnce() + Send> {
+        Box::new(move || {
+            executor.spawn(async move {
+                self.cancel_watch().await;
+            });
+        })
+    }
+}
+
+/// ServiceCallResult 服务调用结果，负载均衡器会根据调用结果统计实例的实时负载
+#[derive(Clone, Debug)]
+pub struct ServiceCallResult {
+    pub flow_id: String,
+    // 被调服务所在命名空间
+    pub namespace: String,
+    // 被调服务名称
+    pub service: String,
+    // 被调实例
+    pub instance: Instance,
+    // 调用的接口
+    pub method: String,
+    // 调用结果
+    pub ret_status: RetStatus,
+    // 调用返回码
+    pub ret_code: i32,
+    // 调用耗时
+    pub delay: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ServiceRuleType {
     Router,
     CircuitBreaker,
@@ -382,13 +661,44 @@ pub struct GetServiceRuleRequest {
 
 pub struct ServiceRuleResponse {
     pub rules: Vec<Box<dyn Any + Send>>,
+    pub revision: String,
+}
+
+/// WatchServiceRuleStreamRequest 以事件流的方式监听服务规则变化
+#[derive(Clone, Debug)]
+pub struct WatchServiceRuleStreamRequest {
+    pub namespace: String,
+    pub service: String,
+    pub rule_type: ServiceRuleType,
+    pub timeout: Duration,
+    // option 事件流的缓冲区配置
+    pub option: WatchStreamOption,
+}
+
+impl WatchServiceRuleStreamRequest {
+    pub fn get_key(&self) -> String {
+        format!("{}#{}", self.namespace, self.service)
+    }
+}
+
+/// ServiceRuleChangeEvent 服务规则变更事件
+pub struct ServiceRuleChangeEvent {
+    pub namespace: String,
+    pub service: String,
+    pub rule_type: ServiceRuleType,
+    pub revision: String,
+    pub rules: Vec<Box<dyn Any + Send>>,
 }
 
 // LossLessAPI request and response definition
 
-pub struct InstanceProperties {}
+/// InstanceProperties 优雅上线注册实例时附加的实例属性
+#[derive(Clone, Debug, Default)]
+pub struct InstanceProperties {
+    pub metadata: HashMap<String, String>,
+}
 
-pub trait BaseInstance {
+pub trait BaseInstance: Send + Sync {
     fn get_namespace(&self) -> String;
 
     fn get_service(&self) -> String;
@@ -398,14 +708,34 @@ pub trait BaseInstance {
     fn get_port(&self) -> u32;
 }
 
-pub trait LosslessActionProvider {
+impl BaseInstance for InstanceRegisterRequest {
+    fn get_namespace(&self) -> String {
+        self.namespace.clone()
+    }
+
+    fn get_service(&self) -> String {
+        self.service.clone()
+    }
+
+    fn get_ip(&self) -> String {
+        self.ip.clone()
+    }
+
+    fn get_port(&self) -> u32 {
+        self.port
+    }
+}
+
+/// LosslessActionProvider 优雅上下线时实际执行注册、反注册以及就绪检查的动作
+#[async_trait::async_trait]
+pub trait LosslessActionProvider: Send + Sync {
     fn get_name(&self) -> String;
 
-    fn do_register(&self, prop: InstanceProperties);
+    async fn do_register(&self, prop: InstanceProperties) -> Result<(), PolarisError>;
 
-    fn do_deregister(&self);
+    async fn do_deregister(&self) -> Result<(), PolarisError>;
 
     fn is_enable_healthcheck(&self) -> bool;
 
-    fn do_healthcheck(&self) -> bool;
+    async fn do_healthcheck(&self) -> bool;
 }
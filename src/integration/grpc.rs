@@ -0,0 +1,94 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tonic::codegen::{http, Body, Bytes};
+use tonic::transport::server::{Router, Routes};
+use tower::{Layer, Service};
+
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::discovery::api::ProviderAPI;
+use crate::integration::registrar::{ServerRegisterOption, ServerRegistration};
+
+/// serve_tonic 在 addr 上启动 tonic server，监听成功后将实例注册到北极星并结合健康检查上报心跳；
+/// signal 完成时先反注册实例，再等待存量请求处理完成后退出
+pub async fn serve_tonic<L, ResBody>(
+    router: Router<L>,
+    addr: SocketAddr,
+    provider: Arc<dyn ProviderAPI>,
+    mut opt: ServerRegisterOption,
+    signal: impl Future<Output = ()>,
+) -> Result<(), PolarisError>
+where
+    L: Layer<Routes>,
+    L::Service: Service<http::Request<tonic::transport::Body>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    <<L as Layer<Routes>>::Service as Service<http::Request<tonic::transport::Body>>>::Future:
+        Send + 'static,
+    <<L as Layer<Routes>>::Service as Service<http::Request<tonic::transport::Body>>>::Error:
+        Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        PolarisError::new(
+            ErrorCode::NetworkError,
+            format!("grpc server listen on {} fail: {}", addr, e),
+        )
+    })?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| PolarisError::new(ErrorCode::NetworkError, e.to_string()))?;
+    if opt.protocol.is_empty() {
+        opt.protocol = "grpc".to_string();
+    }
+
+    let registration = ServerRegistration::register(provider, &opt, local_addr).await?;
+    let registration = Arc::new(Mutex::new(Some(registration)));
+
+    let incoming = futures::stream::unfold(listener, |listener| async move {
+        let ret = listener.accept().await.map(|(stream, _)| stream);
+        Some((ret, listener))
+    });
+    let shutdown_registration = registration.clone();
+    let shutdown = async move {
+        signal.await;
+        // 先反注册，避免主调方继续路由到正在关闭的实例
+        deregister(&shutdown_registration).await;
+    };
+
+    let ret = router
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+        .map_err(|e| PolarisError::new(ErrorCode::InternalError, e.to_string()));
+    // server 异常退出时同样需要反注册
+    deregister(&registration).await;
+    ret
+}
+
+pub(crate) async fn deregister(registration: &Mutex<Option<ServerRegistration>>) {
+    if let Some(registration) = registration.lock().await.take() {
+        if let Err(e) = registration.deregister().await {
+            crate::error!("[polaris][integration] deregister server fail: {}", e);
+        }
+    }
+}
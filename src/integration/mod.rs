@@ -13,18 +13,6 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use crate::core::plugin::plugins::Plugin;
-use crate::discovery::req::{BaseInstance, InstanceProperties};
-
-#[async_trait::async_trait]
-pub trait LosslessPolicy: Plugin {
-    fn build_instance_properties(&self, instance_properties: InstanceProperties);
-
-    async fn lossless_register(
-        &self,
-        instance: dyn BaseInstance,
-        instance_properties: InstanceProperties,
-    );
-
-    async fn lossless_deregister(&self, instance: dyn BaseInstance);
-}
+pub mod grpc;
+pub mod registrar;
+pub mod web;
@@ -0,0 +1,308 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::model::error::PolarisError;
+use crate::core::model::naming::ServiceContract;
+use crate::discovery::api::ProviderAPI;
+use crate::discovery::req::{
+    HealthCheck, InstanceDeregisterRequest, InstanceRegisterRequest, ReportServiceContractRequest,
+};
+
+/// METADATA_GRPC_SERVICES 实例 metadata 中记录对外提供的 gRPC 服务名，多个服务名使用逗号分隔
+pub static METADATA_GRPC_SERVICES: &str = "grpc.services";
+
+/// ServerRegisterOption 服务端自注册的配置
+#[derive(Clone)]
+pub struct ServerRegisterOption {
+    pub namespace: String,
+    pub service: String,
+    // advertise_ip 注册到北极星的实例 IP，为空时使用监听地址，监听地址为 0.0.0.0 时使用本机访问北极星服务端的 IP
+    pub advertise_ip: Option<String>,
+    // protocol 实例协议，为空时由具体的 server 集成填充，例如 grpc、http
+    pub protocol: String,
+    pub version: String,
+    pub weight: u32,
+    // ttl 心跳周期，单位秒
+    pub ttl: u32,
+    pub metadata: HashMap<String, String>,
+    // timeout 注册、心跳、反注册请求的超时时间
+    pub timeout: Duration,
+    // health_check 健康检查，返回 false 时暂停上报心跳，实例在 ttl 之后会被服务端标记为不健康
+    pub health_check: Option<HealthCheck>,
+    // grpc_services 对外提供的 gRPC 服务名
+    pub grpc_services: Vec<String>,
+    // contract 服务契约，注册成功后上报
+    pub contract: Option<ServiceContract>,
+}
+
+impl ServerRegisterOption {
+    pub fn new(namespace: impl Into<String>, service: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            service: service.into(),
+            advertise_ip: None,
+            protocol: String::new(),
+            version: String::new(),
+            weight: 100,
+            ttl: 5,
+            metadata: HashMap::new(),
+            timeout: Duration::from_secs(1),
+            health_check: None,
+            grpc_services: Vec::new(),
+            contract: None,
+        }
+    }
+
+    /// with_health_check 设置健康检查，心跳只在健康检查通过时上报
+    pub fn with_health_check(mut self, check: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        self.health_check = Some(HealthCheck::new(check));
+        self
+    }
+
+    /// with_metadata 添加实例 metadata
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// with_grpc_service 记录对外提供的 gRPC 服务，服务名会写入实例 metadata
+    pub fn with_grpc_service<S: tonic::server::NamedService>(mut self) -> Self {
+        self.grpc_services.push(S::NAME.to_string());
+        self
+    }
+
+    /// with_contract 设置服务契约，可以通过 discovery::contract 中的方法从 protobuf 描述文件或 OpenAPI 文档生成
+    pub fn with_contract(mut self, contract: ServiceContract) -> Self {
+        self.contract = Some(contract);
+        self
+    }
+
+    fn to_register_request(&self, addr: SocketAddr) -> InstanceRegisterRequest {
+        let mut metadata = self.metadata.clone();
+        if !self.grpc_services.is_empty() {
+            metadata.insert(
+                METADATA_GRPC_SERVICES.to_string(),
+                self.grpc_services.join(","),
+            );
+        }
+        InstanceRegisterRequest {
+            flow_id: String::new(),
+            timeout: self.timeout,
+            id: None,
+            namespace: self.namespace.clone(),
+            service: self.service.clone(),
+            ip: resolve_advertise_ip(self.advertise_ip.as_deref(), addr),
+            port: u32::from(addr.port()),
+            vpc_id: String::new(),
+            version: self.version.clone(),
+            protocol: self.protocol.clone(),
+            health: true,
+            isolated: false,
+            weight: self.weight,
+            priority: 0,
+            metadata,
+            location: Default::default(),
+            ttl: self.ttl,
+            // 心跳由 ProviderAPI 的心跳调度器结合健康检查上报
+            auto_heartbeat: true,
+            health_check: self.health_check.clone(),
+        }
+    }
+}
+
+// resolve_advertise_ip 计算注册到北极星的实例 IP，监听地址为 0.0.0.0 时返回空，
+// 由 ProviderAPI 使用本机访问北极星服务端的 IP 补全
+fn resolve_advertise_ip(advertise_ip: Option<&str>, addr: SocketAddr) -> String {
+    if let Some(ip) = advertise_ip.filter(|ip| !ip.is_empty()) {
+        return ip.to_string();
+    }
+    if addr.ip().is_unspecified() {
+        return String::new();
+    }
+    addr.ip().to_string()
+}
+
+/// ServerRegistration 已经注册到北极星的服务实例，心跳由 ProviderAPI 的心跳调度器结合健康检查上报，
+/// 没有主动反注册时在 drop 时反注册实例
+pub struct ServerRegistration {
+    provider: Arc<dyn ProviderAPI>,
+    register: InstanceRegisterRequest,
+    deregistered: bool,
+}
+
+impl ServerRegistration {
+    /// register 使用监听地址 addr 注册实例，并开始上报心跳
+    pub async fn register(
+        provider: Arc<dyn ProviderAPI>,
+        opt: &ServerRegisterOption,
+        addr: SocketAddr,
+    ) -> Result<Self, PolarisError> {
+        let register = opt.to_register_request(addr);
+        provider.register(register.clone()).await?;
+        crate::info!(
+            "[polaris][integration] register server {}/{} {}:{}",
+            register.namespace,
+            register.service,
+            register.ip,
+            register.port
+        );
+        if let Some(contract) = opt.contract.clone() {
+            let ret = provider
+                .report_service_contract(ReportServiceContractRequest {
+                    flow_id: String::new(),
+                    timeout: opt.timeout,
+                    contract,
+                })
+                .await;
+            // 契约上报失败不影响实例提供服务
+            if let Err(e) = ret {
+                crate::warn!("[polaris][integration] report service contract fail: {}", e);
+            }
+        }
+        Ok(Self {
+            provider,
+            register,
+            deregistered: false,
+        })
+    }
+
+    /// instance 注册的实例信息，IP 为空时实例使用 ProviderAPI 补全的本机 IP
+    pub fn instance(&self) -> &InstanceRegisterRequest {
+        &self.register
+    }
+
+    /// deregister 停止心跳并反注册实例
+    pub async fn deregister(mut self) -> Result<(), PolarisError> {
+        self.deregistered = true;
+        let req: InstanceDeregisterRequest = self.register.to_deregister_request();
+        crate::info!(
+            "[polaris][integration] deregister server {}/{} {}:{}",
+            req.namespace,
+            req.service,
+            req.ip,
+            req.port
+        );
+        self.provider.deregister(req).await
+    }
+}
+
+impl Drop for ServerRegistration {
+    fn drop(&mut self) {
+        if self.deregistered {
+            return;
+        }
+        // 反注册会同时移除心跳任务，避免丢弃之后仍然上报心跳
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let provider = self.provider.clone();
+            let req = self.register.to_deregister_request();
+            handle.spawn(async move {
+                if let Err(e) = provider.deregister(req).await {
+                    crate::error!("[polaris][integration] deregister server fail: {}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::core::context::tests::new_context;
+    use crate::core::engine::tests::{wait_beats, MockConnector};
+    use crate::discovery::api::new_provider_api_by_context;
+
+    #[test]
+    fn test_register_with_health_check() {
+        let connector = MockConnector::default();
+        let calls = connector.calls.clone();
+        let heartbeats = connector.heartbeats.clone();
+        let (rt, ctx) = new_context(connector);
+        let provider: Arc<dyn ProviderAPI> = Arc::new(new_provider_api_by_context(ctx).unwrap());
+        let healthy = Arc::new(AtomicBool::new(false));
+        let check = healthy.clone();
+        let mut opt = ServerRegisterOption::new("default", "polaris-rust")
+            .with_health_check(move || check.load(Ordering::SeqCst));
+        opt.ttl = 1;
+
+        let registration = rt
+            .block_on(ServerRegistration::register(
+                provider,
+                &opt,
+                "0.0.0.0:8080".parse().unwrap(),
+            ))
+            .unwrap();
+        // 监听 0.0.0.0 时由 ProviderAPI 使用本机访问北极星服务端的 IP 注册
+        assert!(registration.instance().ip.is_empty());
+        assert!(calls
+            .lock()
+            .unwrap()
+            .contains(&"register:127.0.0.1:8080".to_string()));
+
+        // 健康检查未通过时不上报心跳
+        std::thread::sleep(Duration::from_millis(1500));
+        assert_eq!(0, heartbeats.load(Ordering::SeqCst));
+        healthy.store(true, Ordering::SeqCst);
+        assert!(wait_beats(&heartbeats, 0));
+
+        // 反注册之后心跳调度器不再上报心跳
+        rt.block_on(registration.deregister()).unwrap();
+        std::thread::sleep(Duration::from_millis(600));
+        let stopped = heartbeats.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(1500));
+        assert_eq!(stopped, heartbeats.load(Ordering::SeqCst));
+        assert!(calls
+            .lock()
+            .unwrap()
+            .contains(&"deregister:127.0.0.1:8080".to_string()));
+    }
+
+    #[test]
+    fn test_to_register_request() {
+        let opt = ServerRegisterOption::new("default", "polaris-rust")
+            .with_metadata("env", "test")
+            .with_grpc_service::<tonic_health_stub::HealthServer>();
+        let req = opt.to_register_request("127.0.0.1:8080".parse().unwrap());
+        assert_eq!("127.0.0.1", req.ip);
+        assert_eq!(8080, req.port);
+        assert!(req.auto_heartbeat);
+        assert_eq!("test", req.metadata["env"]);
+        assert_eq!(
+            "grpc.health.v1.Health",
+            req.metadata[METADATA_GRPC_SERVICES]
+        );
+
+        let opt = ServerRegisterOption {
+            advertise_ip: Some("10.0.0.1".to_string()),
+            ..opt
+        };
+        let req = opt.to_register_request("0.0.0.0:8080".parse().unwrap());
+        assert_eq!("10.0.0.1", req.ip);
+    }
+
+    mod tonic_health_stub {
+        pub struct HealthServer;
+
+        impl tonic::server::NamedService for HealthServer {
+            const NAME: &'static str = "grpc.health.v1.Health";
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::body::{Body, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::Service;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinSet;
+
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::discovery::api::ProviderAPI;
+use crate::integration::grpc::deregister;
+use crate::integration::registrar::{ServerRegisterOption, ServerRegistration};
+
+/// serve_hyper 在 addr 上启动 hyper http1 server，监听成功后将实例注册到北极星并结合健康检查上报心跳；
+/// signal 完成时先反注册实例，再停止接收新连接并等待存量连接优雅关闭。
+/// axum 的 Router 可以通过 hyper_util::service::TowerToHyperService 包装后传入
+pub async fn serve_hyper<S, B>(
+    service: S,
+    addr: SocketAddr,
+    provider: Arc<dyn ProviderAPI>,
+    mut opt: ServerRegisterOption,
+    signal: impl Future<Output = ()>,
+) -> Result<(), PolarisError>
+where
+    S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        PolarisError::new(
+            ErrorCode::NetworkError,
+            format!("http server listen on {} fail: {}", addr, e),
+        )
+    })?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| PolarisError::new(ErrorCode::NetworkError, e.to_string()))?;
+    if opt.protocol.is_empty() {
+        opt.protocol = "http".to_string();
+    }
+
+    let registration = ServerRegistration::register(provider, &opt, local_addr).await?;
+    let registration = Mutex::new(Some(registration));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(signal);
+    loop {
+        tokio::select! {
+            _ = &mut signal => break,
+            ret = listener.accept() => {
+                let stream = match ret {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        crate::warn!("[polaris][integration] http server accept fail: {}", e);
+                        continue;
+                    }
+                };
+                let service = service.clone();
+                let mut shutdown_rx = shutdown_rx.clone();
+                connections.spawn(async move {
+                    let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+                    tokio::pin!(conn);
+                    let ret = tokio::select! {
+                        ret = conn.as_mut() => ret,
+                        _ = shutdown_rx.changed() => {
+                            conn.as_mut().graceful_shutdown();
+                            conn.await
+                        }
+                    };
+                    if let Err(e) = ret {
+                        crate::debug!("[polaris][integration] http connection closed: {}", e);
+                    }
+                });
+            }
+            // 回收已经结束的连接任务，避免 JoinSet 无限增长
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+
+    // 先反注册，避免主调方继续路由到正在关闭的实例
+    deregister(&registration).await;
+    drop(listener);
+    let _ = shutdown_tx.send(true);
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
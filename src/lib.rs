@@ -17,6 +17,7 @@ pub mod circuitbreaker;
 pub mod config;
 pub mod core;
 pub mod discovery;
+pub mod integration;
 pub mod plugins;
 pub mod ratelimit;
 pub mod router;
@@ -91,6 +92,7 @@ mod tests {
                     ttl: 5,
                     // 这里开启心跳的自动上报能力
                     auto_heartbeat: true,
+                    health_check: None,
                 };
                 let _ret = provier.register(req).await;
                 match _ret {
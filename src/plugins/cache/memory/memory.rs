@@ -15,7 +15,8 @@
 
 use prost::Message;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
+use tokio::task::JoinHandle;
 
 use crate::core::model::cache::{
     CacheItemType, CircuitBreakerRulesCacheItem, ConfigFileCacheItem, ConfigGroupCacheItem,
@@ -25,7 +26,7 @@ use crate::core::model::cache::{
 };
 use crate::core::model::config::{ConfigFile, ConfigGroup};
 use crate::core::model::error::{ErrorCode, PolarisError};
-use crate::core::model::naming::{Instance, ServiceRule, Services};
+use crate::core::model::naming::{Instance, ServiceInfo, ServiceKey, ServiceRule, Services};
 use crate::core::plugin::cache::{
     Action, Filter, InitResourceCacheOption, ResourceCache, ResourceCacheFailover, ResourceListener,
 };
@@ -69,6 +70,10 @@ pub struct MemoryCache {
     remote_sender: UnboundedSender<RemoteData>,
     //
     failover: Option<Arc<dyn ResourceCacheFailover>>,
+    // recive_task 处理服务端推送数据的任务，destroy 时取消
+    recive_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    // close_notify 通知 recive_task 处理完已经收到的数据后退出
+    close_notify: Arc<Notify>,
 }
 
 impl MemoryCache {
@@ -82,13 +87,24 @@ impl MemoryCache {
     async fn run_remote_data_recive(
         handler: Arc<MemoryResourceHandler>,
         remote_reciver: &mut UnboundedReceiver<RemoteData>,
+        close_notify: Arc<Notify>,
     ) {
         loop {
             tokio::select! {
                 remote_data = remote_reciver.recv() => {
-                    if let Some(remote_data) = remote_data {
+                    match remote_data {
+                        Some(remote_data) => {
+                            MemoryCache::on_spec_event(handler.clone(), remote_data).await
+                        }
+                        None => return,
+                    }
+                }
+                _ = close_notify.notified() => {
+                    // 处理完已经收到的推送数据，保证容灾文件中保存的是最新的数据
+                    while let Ok(remote_data) = remote_reciver.try_recv() {
                         MemoryCache::on_spec_event(handler.clone(), remote_data).await;
                     }
+                    return;
                 }
             }
         }
@@ -141,7 +157,33 @@ impl MemoryCache {
         let copy_event = event.clone();
 
         match event_type {
-            EventType::Service => {}
+            EventType::Service => {
+                let remote_val = event.discover_value.unwrap();
+                let mut safe_map = handler.services.write().await;
+                let cache_val_opt = safe_map.get_mut(event_key.namespace.as_str());
+                if cache_val_opt.is_none() {
+                    error!(
+                        "[polaris][resource_cache][memory] services cache not found: namespace={}",
+                        event_key.namespace.clone()
+                    );
+                    return;
+                }
+                let cache_val = cache_val_opt.unwrap();
+                let mut services = cache_val.value.write().await;
+                services.clear();
+                for svc in remote_val.services {
+                    services.push(ServiceInfo::convert_from_spec(svc));
+                }
+                drop(services);
+
+                cache_val.namespace = event_key.namespace.clone();
+                cache_val.revision = remote_val
+                    .service
+                    .and_then(|svc| svc.revision)
+                    .unwrap_or_default();
+                cache_val.finish_initialize();
+                notify_event.value = CacheItemType::Service(cache_val.clone());
+            }
             EventType::Instance => {
                 let remote_val = event.discover_value.unwrap();
                 let svc = remote_val.service.unwrap();
@@ -164,14 +206,30 @@ impl MemoryCache {
                 }
                 let cache_val = cache_val_opt.unwrap();
                 let mut instances = cache_val.value.write().await;
+                let mut available_instances = cache_val.available_instances.write().await;
 
                 instances.clear();
+                available_instances.clear();
+                let mut total_weight: u64 = 0;
                 let remote_instances = remote_val.instances;
                 for (_, val) in remote_instances.iter().enumerate() {
-                    instances.push(Instance::convert_from_spec(val.clone()));
+                    let ins = Instance::convert_from_spec(val.clone());
+                    total_weight += ins.weight as u64;
+                    if ins.is_available() {
+                        available_instances.push(ins.clone());
+                    }
+                    instances.push(ins);
                 }
-
-                cache_val.revision = svc.revision.unwrap();
+                drop(instances);
+                drop(available_instances);
+
+                cache_val.total_weight = total_weight;
+                cache_val.alias_for = remote_val.alias_for.map(|alias_for| ServiceKey {
+                    namespace: alias_for.namespace.unwrap_or_default(),
+                    name: alias_for.name.unwrap_or_default(),
+                });
+                cache_val.revision = svc.revision.clone().unwrap_or_default();
+                cache_val.svc_info = svc;
                 cache_val.finish_initialize();
                 notify_event.value = CacheItemType::Instance(cache_val.clone());
             }
@@ -312,11 +370,10 @@ impl MemoryCache {
                     return;
                 }
                 let cache_val = cache_val_opt.unwrap();
-                let rules = &cache_val.value;
                 let remote_rules = event.config_value.unwrap().config_file.unwrap_or_default();
-                rules.to_owned().clone_from(&remote_rules);
-
                 cache_val.revision = remote_rules.version.unwrap().to_string();
+                // 使用推送的配置文件覆盖缓存，否则读取以及监听到的都是初始化时的空配置
+                cache_val.value = remote_rules;
                 cache_val.finish_initialize();
                 notify_event.value = CacheItemType::ConfigFile(cache_val.clone());
             }
@@ -407,12 +464,16 @@ fn new_resource_cache(opt: InitResourceCacheOption) -> Box<dyn ResourceCache> {
         remote_sender: sx,
         // 默认使用磁盘容灾
         failover: Some(failover),
+        recive_task: std::sync::Mutex::new(None),
+        close_notify: Arc::new(Notify::new()),
     };
 
     let handler = mc.handler.clone();
-    mc.opt.runtime.spawn(async move {
-        MemoryCache::run_remote_data_recive(handler, &mut rx).await;
+    let close_notify = mc.close_notify.clone();
+    let task = mc.opt.runtime.spawn(async move {
+        MemoryCache::run_remote_data_recive(handler, &mut rx, close_notify).await;
     });
+    *mc.recive_task.lock().unwrap() = Some(task);
 
     Box::new(mc) as Box<dyn ResourceCache + 'static>
 }
@@ -420,7 +481,11 @@ fn new_resource_cache(opt: InitResourceCacheOption) -> Box<dyn ResourceCache> {
 impl Plugin for MemoryCache {
     fn init(&mut self) {}
 
-    fn destroy(&self) {}
+    fn destroy(&self) {
+        if let Some(task) = self.recive_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
 
     fn name(&self) -> String {
         MEMORY_CACHE_NAME.to_string()
@@ -627,8 +692,7 @@ impl ResourceCache for MemoryCache {
             ));
         }
 
-        let mut services = vec![];
-        services.clone_from_slice(cache_val.value.read().await.as_slice());
+        let services = cache_val.value.read().await.clone();
         Ok(Services {
             service_list: services,
             initialized: cache_val.is_initialized(),
@@ -779,6 +843,14 @@ impl ResourceCache for MemoryCache {
 
         listeners.push(listener);
     }
+
+    async fn flush(&self) {
+        let task = self.recive_task.lock().unwrap().take();
+        if let Some(task) = task {
+            self.close_notify.notify_one();
+            let _ = task.await;
+        }
+    }
 }
 
 /// MemoryResourceWatcher 用于监听远程资源变化
@@ -813,3 +885,256 @@ impl ResourceHandler for MemoryResourceWatcher {
         self.event_key.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use polaris_specification::v1::{DiscoverResponse, Service};
+
+    use super::*;
+
+    struct NoopFailover {}
+
+    #[async_trait::async_trait]
+    impl ResourceCacheFailover for NoopFailover {
+        async fn failover_naming_load(
+            &self,
+            _filter: Filter,
+        ) -> Result<DiscoverResponse, PolarisError> {
+            Err(PolarisError::new(ErrorCode::NotSupport, String::new()))
+        }
+
+        async fn save_naming_failover(
+            &self,
+            _value: DiscoverResponse,
+        ) -> Result<(), PolarisError> {
+            Ok(())
+        }
+
+        async fn failover_config_load(
+            &self,
+            _filter: Filter,
+        ) -> Result<polaris_specification::v1::ConfigDiscoverResponse, PolarisError> {
+            Err(PolarisError::new(ErrorCode::NotSupport, String::new()))
+        }
+
+        async fn save_config_failover(
+            &self,
+            _value: polaris_specification::v1::ConfigDiscoverResponse,
+        ) -> Result<(), PolarisError> {
+            Ok(())
+        }
+    }
+
+    fn new_handler() -> Arc<MemoryResourceHandler> {
+        Arc::new(MemoryResourceHandler {
+            failover: Some(Arc::new(NoopFailover {})),
+            listeners: Arc::new(RwLock::new(HashMap::new())),
+            services: Arc::new(RwLock::new(HashMap::new())),
+            instances: Arc::new(RwLock::new(HashMap::new())),
+            router_rules: Arc::new(RwLock::new(HashMap::new())),
+            ratelimit_rules: Arc::new(RwLock::new(HashMap::new())),
+            circuitbreaker_rules: Arc::new(RwLock::new(HashMap::new())),
+            faultdetect_rules: Arc::new(RwLock::new(HashMap::new())),
+            config_groups: Arc::new(RwLock::new(HashMap::new())),
+            config_files: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    fn new_service(name: &str) -> Service {
+        Service {
+            namespace: Some("default".to_string()),
+            name: Some(name.to_string()),
+            revision: Some("rev-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn new_instance(
+        id: &str,
+        weight: u32,
+        healthy: bool,
+    ) -> polaris_specification::v1::Instance {
+        polaris_specification::v1::Instance {
+            id: Some(id.to_string()),
+            host: Some("127.0.0.1".to_string()),
+            port: Some(8080),
+            weight: Some(weight),
+            healthy: Some(healthy),
+            isolate: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn new_instance_event(
+        service: Service,
+        instances: Vec<polaris_specification::v1::Instance>,
+        alias_for: Option<Service>,
+    ) -> RemoteData {
+        let mut filter = HashMap::new();
+        filter.insert("service".to_string(), service.name.clone().unwrap());
+        RemoteData {
+            event_key: ResourceEventKey {
+                namespace: "default".to_string(),
+                event_type: EventType::Instance,
+                filter,
+            },
+            discover_value: Some(DiscoverResponse {
+                service: Some(service),
+                instances,
+                alias_for,
+                ..Default::default()
+            }),
+            config_value: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_instance_event() {
+        let handler = new_handler();
+        handler
+            .instances
+            .write()
+            .await
+            .insert("default#echo".to_string(), ServiceInstancesCacheItem::new());
+
+        let event = new_instance_event(
+            new_service("echo"),
+            vec![
+                new_instance("ins-1", 100, true),
+                new_instance("ins-2", 50, false),
+            ],
+            None,
+        );
+        MemoryCache::on_spec_event(handler.clone(), event).await;
+
+        let item = handler.instances.read().await["default#echo"].clone();
+        assert!(item.is_initialized());
+        assert_eq!("rev-1", item.revision());
+        assert_eq!(150, item.total_weight);
+        assert_eq!("echo", item.get_service_info().name);
+        assert_eq!(2, item.list_instances(false).await.len());
+        let available = item.list_instances(true).await;
+        assert_eq!(1, available.len());
+        assert_eq!("ins-1", available[0].id);
+        assert!(item.get_alias_for().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_on_alias_instance_event() {
+        let handler = new_handler();
+        handler
+            .instances
+            .write()
+            .await
+            .insert(
+                "default#echo-alias".to_string(),
+                ServiceInstancesCacheItem::new(),
+            );
+
+        let event = new_instance_event(
+            new_service("echo-alias"),
+            vec![],
+            Some(new_service("echo")),
+        );
+        MemoryCache::on_spec_event(handler.clone(), event).await;
+
+        let item = handler.instances.read().await["default#echo-alias"].clone();
+        let alias_for = item.get_alias_for().unwrap();
+        assert_eq!("default", alias_for.namespace);
+        assert_eq!("echo", alias_for.name);
+    }
+
+    // ServiceListener 记录收到的服务列表变更
+    struct ServiceListener {
+        revisions: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ResourceListener for ServiceListener {
+        async fn on_event(&self, _action: Action, val: ServerEvent) {
+            if let Some(item) = val.value.to_services() {
+                self.revisions.lock().unwrap().push(item.revision);
+            }
+        }
+
+        fn watch_key(&self) -> EventType {
+            EventType::Service
+        }
+    }
+
+    fn new_services_event(revision: &str, services: Vec<Service>) -> RemoteData {
+        RemoteData {
+            event_key: ResourceEventKey {
+                namespace: "default".to_string(),
+                event_type: EventType::Service,
+                filter: HashMap::new(),
+            },
+            discover_value: Some(DiscoverResponse {
+                service: Some(Service {
+                    namespace: Some("default".to_string()),
+                    revision: Some(revision.to_string()),
+                    ..Default::default()
+                }),
+                services,
+                ..Default::default()
+            }),
+            config_value: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_service_event() {
+        let handler = new_handler();
+        let listener = Arc::new(ServiceListener {
+            revisions: std::sync::Mutex::new(Vec::new()),
+        });
+        handler
+            .listeners
+            .write()
+            .await
+            .insert(EventType::Service, vec![listener.clone()]);
+
+        // 没有订阅的命名空间直接忽略
+        MemoryCache::on_spec_event(
+            handler.clone(),
+            new_services_event("rev-1", vec![new_service("echo")]),
+        )
+        .await;
+        assert!(handler.services.read().await.is_empty());
+        assert!(listener.revisions.lock().unwrap().is_empty());
+
+        handler
+            .services
+            .write()
+            .await
+            .insert("default".to_string(), ServicesCacheItem::new());
+        let mut echo = new_service("echo");
+        echo.metadata.insert("env".to_string(), "prod".to_string());
+        MemoryCache::on_spec_event(
+            handler.clone(),
+            new_services_event("rev-1", vec![echo, new_service("hello")]),
+        )
+        .await;
+
+        let item = handler.services.read().await["default"].clone();
+        assert!(item.is_initialized());
+        assert_eq!("rev-1", item.revision());
+        let services = item.value.read().await.clone();
+        assert_eq!(2, services.len());
+        assert_eq!("echo", services[0].name);
+        assert_eq!("prod", services[0].metadata["env"]);
+
+        // 新的服务列表整体替换旧的数据
+        MemoryCache::on_spec_event(
+            handler.clone(),
+            new_services_event("rev-2", vec![new_service("hello")]),
+        )
+        .await;
+        let item = handler.services.read().await["default"].clone();
+        assert_eq!("rev-2", item.revision());
+        let services = item.value.read().await.clone();
+        assert_eq!(1, services.len());
+        assert_eq!("hello", services[0].name);
+        assert_eq!(vec!["rev-1", "rev-2"], *listener.revisions.lock().unwrap());
+    }
+}
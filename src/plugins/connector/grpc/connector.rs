@@ -16,7 +16,7 @@
 use crate::core::config::global::ServerConnectorConfig;
 use crate::core::model::cache::{EventType, RemoteData};
 use crate::core::model::config::{ConfigFileRequest, ConfigPublishRequest, ConfigReleaseRequest};
-use crate::core::model::error::ErrorCode::{ServerError, ServerUserError};
+use crate::core::model::error::ErrorCode::{InstanceNotFound, ServerError, ServerUserError};
 use crate::core::model::error::PolarisError;
 use crate::core::model::naming::{
     InstanceRequest, InstanceResponse, ServiceContract, ServiceContractRequest,
@@ -35,10 +35,11 @@ use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::sync::RwLock;
+use tokio::task::AbortHandle;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
 use tonic::metadata::{AsciiMetadataKey, MetadataValue};
@@ -52,6 +53,27 @@ use crate::{debug, error, info};
 struct ResourceHandlerWrapper {
     handler: Box<dyn ResourceHandler>,
     revision: String,
+    // last_send_time 上一次发送订阅请求的时间
+    last_send_time: Instant,
+}
+
+impl ResourceHandlerWrapper {
+    // should_send 本轮是否需要发送订阅请求，服务列表按照 refresh_interval 定期刷新，其余资源每轮都发送
+    fn should_send(
+        &mut self,
+        event_type: EventType,
+        refresh_interval: Duration,
+        now: Instant,
+    ) -> bool {
+        if event_type != EventType::Service {
+            return true;
+        }
+        if now.duration_since(self.last_send_time) < refresh_interval {
+            return false;
+        }
+        self.last_send_time = now;
+        true
+    }
 }
 
 static PLUGIN_NAME: &str = "grpc";
@@ -66,16 +88,18 @@ pub struct GrpcConnector {
     config_spec_sender: Arc<UnboundedSender<ConfigDiscoverRequest>>,
 
     watch_resources: Arc<RwLock<HashMap<String, ResourceHandlerWrapper>>>,
+    // tasks 后台任务，destroy 时全部取消
+    tasks: Arc<std::sync::Mutex<Vec<AbortHandle>>>,
 }
 
 fn new_connector(opt: InitConnectorOption) -> Box<dyn Connector> {
     let conf = &opt.conf.global.server_connectors.clone();
     let (discover_channel, config_channel) = create_channel(conf);
 
-    let (discover_sender, mut discover_reciver) =
+    let (discover_sender, mut discover_reciver, discover_task) =
         run_discover_spec_stream(discover_channel.clone(), opt.runtime.clone()).unwrap();
 
-    let (config_sender, mut config_reciver) =
+    let (config_sender, mut config_reciver, config_task) =
         run_config_spec_stream(config_channel.clone(), opt.runtime.clone()).unwrap();
 
     let c = GrpcConnector {
@@ -87,11 +111,12 @@ fn new_connector(opt: InitConnectorOption) -> Box<dyn Connector> {
         config_spec_sender: Arc::new(config_sender),
 
         watch_resources: Arc::new(RwLock::new(HashMap::new())),
+        tasks: Arc::new(std::sync::Mutex::new(vec![discover_task, config_task])),
     };
 
     let receive_c = c.clone();
     // 创建一个新的线程，用于处理grpc的消息
-    c.opt.runtime.spawn(async move {
+    let receive_task = c.opt.runtime.spawn(async move {
         loop {
             tokio::select! {
                 discover_ret = discover_reciver.recv() => {
@@ -109,14 +134,24 @@ fn new_connector(opt: InitConnectorOption) -> Box<dyn Connector> {
     });
 
     let send_c = c.clone();
+    let service_list_refresh_interval = c
+        .opt
+        .conf
+        .consumer
+        .local_cache
+        .service_list_refresh_interval;
     // 开启一个异步任务，定期发送请求到服务端
-    c.opt.runtime.spawn(async move {
+    let send_task = c.opt.runtime.spawn(async move {
         loop {
             {
                 // 额外一个方法块，减少 lock 的占用时间
-                let watch_resources = send_c.watch_resources.read().await;
-                watch_resources.iter().for_each(|(_key, handler)| {
+                let mut watch_resources = send_c.watch_resources.write().await;
+                watch_resources.iter_mut().for_each(|(_key, handler)| {
                     let key = handler.handler.interest_resource();
+                    let now = Instant::now();
+                    if !handler.should_send(key.event_type, service_list_refresh_interval, now) {
+                        return;
+                    }
                     let filter = key.clone().filter;
                     debug!(
                         "[polaris][discovery][connector] send discover request: {:?} filter: {:?}",
@@ -137,6 +172,10 @@ fn new_connector(opt: InitConnectorOption) -> Box<dyn Connector> {
             tokio::time::sleep(Duration::from_secs(2)).await;
         }
     });
+    c.tasks
+        .lock()
+        .unwrap()
+        .extend([receive_task.abort_handle(), send_task.abort_handle()]);
 
     Box::new(c) as Box<dyn Connector + 'static>
 }
@@ -182,7 +221,11 @@ fn create_channel(conf: &ServerConnectorConfig) -> (Channel, Channel) {
 impl Plugin for GrpcConnector {
     fn init(&mut self) {}
 
-    fn destroy(&self) {}
+    fn destroy(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
 
     fn name(&self) -> String {
         PLUGIN_NAME.to_string()
@@ -243,7 +286,8 @@ impl GrpcConnector {
         let mut watch_key = "".to_string();
         match resp.r#type() {
             polaris_specification::v1::discover_response::DiscoverResponseType::Services => {
-                watch_key = resp.service.unwrap().namespace.clone().unwrap();
+                let svc = resp.service.unwrap().clone();
+                watch_key = format!("{:?}#{}#", EventType::Service, svc.namespace.unwrap());
             }
             polaris_specification::v1::discover_response::DiscoverResponseType::Instance => {
                 let svc = resp.service.unwrap().clone();
@@ -435,6 +479,7 @@ impl Connector for GrpcConnector {
             ResourceHandlerWrapper {
                 handler,
                 revision: String::new(),
+                last_send_time: Instant::now(),
             },
         );
 
@@ -557,6 +602,10 @@ impl Connector for GrpcConnector {
                     rsp.code.unwrap().clone(),
                     rsp.info.clone().unwrap(),
                 );
+                // 服务端不存在该实例时返回单独的错误码，由心跳调度器重新注册实例
+                if Code::NotFoundInstance.eq(&recv_code) || Code::NotFoundResource.eq(&recv_code) {
+                    return Err(PolarisError::new(InstanceNotFound, rsp.info.unwrap()));
+                }
                 Err(PolarisError::new(ServerError, rsp.info.unwrap()))
             }
             Err(err) => {
@@ -769,12 +818,13 @@ fn run_discover_spec_stream(
     (
         UnboundedSender<DiscoverRequest>,
         UnboundedReceiver<DiscoverResponse>,
+        AbortHandle,
     ),
     PolarisError,
 > {
     let (discover_sender, rx) = mpsc::unbounded_channel::<DiscoverRequest>();
     let (rsp_sender, rsp_recv) = mpsc::unbounded_channel::<DiscoverResponse>();
-    _ = executor.spawn(async move {
+    let task = executor.spawn(async move {
         info!("[polaris][discovery][connector] start naming_discover grpc stream");
         let reciver = UnboundedReceiverStream::new(rx);
 
@@ -820,7 +870,7 @@ fn run_discover_spec_stream(
         Ok(())
     });
 
-    Ok((discover_sender, rsp_recv))
+    Ok((discover_sender, rsp_recv, task.abort_handle()))
 }
 
 fn run_config_spec_stream(
@@ -830,13 +880,14 @@ fn run_config_spec_stream(
     (
         UnboundedSender<ConfigDiscoverRequest>,
         UnboundedReceiver<ConfigDiscoverResponse>,
+        AbortHandle,
     ),
     PolarisError,
 > {
     info!("[polaris][config][connector] start config_discover grpc stream");
     let (config_sender, config_reciver) = mpsc::unbounded_channel::<ConfigDiscoverRequest>();
     let (rsp_sender, rsp_recv) = mpsc::unbounded_channel::<ConfigDiscoverResponse>();
-    _ = executor.spawn(async move {
+    let task = executor.spawn(async move {
         let reciver = UnboundedReceiverStream::new(config_reciver);
         let mut client = PolarisConfigGrpcClient::new(channel);
 
@@ -878,7 +929,7 @@ fn run_config_spec_stream(
         Ok(())
     });
 
-    Ok((config_sender, rsp_recv))
+    Ok((config_sender, rsp_recv, task.abort_handle()))
 }
 
 struct GrpcConnectorInterceptor {
@@ -907,3 +958,75 @@ impl Interceptor for GrpcConnectorInterceptor {
         Ok(request)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::cache::ResourceEventKey;
+
+    struct NoopHandler {}
+
+    impl ResourceHandler for NoopHandler {
+        fn handle_event(&self, _event: RemoteData) {}
+
+        fn interest_resource(&self) -> ResourceEventKey {
+            ResourceEventKey::default()
+        }
+    }
+
+    fn new_wrapper(last_send_time: Instant) -> ResourceHandlerWrapper {
+        ResourceHandlerWrapper {
+            handler: Box::new(NoopHandler {}),
+            revision: String::new(),
+            last_send_time,
+        }
+    }
+
+    #[test]
+    fn test_service_list_refresh_interval() {
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut wrapper = new_wrapper(start);
+
+        // 服务列表在刷新间隔内不会重复发送订阅请求
+        assert!(!wrapper.should_send(
+            EventType::Service,
+            interval,
+            start + Duration::from_secs(2)
+        ));
+        assert!(!wrapper.should_send(
+            EventType::Service,
+            interval,
+            start + Duration::from_secs(59)
+        ));
+        assert!(wrapper.should_send(
+            EventType::Service,
+            interval,
+            start + Duration::from_secs(60)
+        ));
+        assert_eq!(start + Duration::from_secs(60), wrapper.last_send_time);
+        assert!(!wrapper.should_send(
+            EventType::Service,
+            interval,
+            start + Duration::from_secs(100)
+        ));
+        assert!(wrapper.should_send(
+            EventType::Service,
+            interval,
+            start + Duration::from_secs(121)
+        ));
+
+        // 其余资源每轮都发送
+        let mut wrapper = new_wrapper(start);
+        assert!(wrapper.should_send(
+            EventType::Instance,
+            interval,
+            start + Duration::from_secs(2)
+        ));
+        assert!(wrapper.should_send(
+            EventType::Instance,
+            interval,
+            start + Duration::from_secs(4)
+        ));
+    }
+}
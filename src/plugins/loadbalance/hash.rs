@@ -0,0 +1,230 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::core::model::loadbalance::Criteria;
+use crate::core::model::naming::{Instance, ServiceInstances};
+
+const C1: u32 = 0xcc9e_2d51;
+const C2: u32 = 0x1b87_3593;
+
+/// murmur3_32 MurmurHash3 x86_32 实现
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    let mut h1 = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in chunks.by_ref() {
+        let mut k1 = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k1 = k1.wrapping_mul(C1);
+        k1 = k1.rotate_left(15);
+        k1 = k1.wrapping_mul(C2);
+
+        h1 ^= k1;
+        h1 = h1.rotate_left(13);
+        h1 = h1.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k1: u32 = 0;
+        for (i, b) in tail.iter().enumerate() {
+            k1 |= (*b as u32) << (8 * i);
+        }
+        k1 = k1.wrapping_mul(C1);
+        k1 = k1.rotate_left(15);
+        k1 = k1.wrapping_mul(C2);
+        h1 ^= k1;
+    }
+
+    h1 ^= data.len() as u32;
+    fmix32(h1)
+}
+
+/// HashTable 一致性哈希负载均衡构建的哈希表，槽位按照哈希值排列，每个槽位对应一个实例
+pub(crate) trait HashTable {
+    // slots 哈希表的槽位数，为 0 时表示没有权重大于 0 的实例
+    fn slots(&self) -> usize;
+    // position hash 命中的槽位
+    fn position(&self, hash: u32) -> usize;
+    // node 槽位对应的实例在 instances 中的下标
+    fn node(&self, position: usize) -> usize;
+    // instances 构建哈希表使用的实例
+    fn instances(&self) -> &[Instance];
+}
+
+/// HashTableCache 按照服务缓存哈希表，服务版本或者参与负载均衡的实例发生变化时重新构建
+pub(crate) struct HashTableCache<T> {
+    tables: RwLock<HashMap<String, CachedTable<T>>>,
+}
+
+struct CachedTable<T> {
+    revision: String,
+    fingerprint: u32,
+    table: Arc<T>,
+}
+
+impl<T> HashTableCache<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            tables: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// get_or_build 获取服务的哈希表，没有缓存或者缓存已经过期时使用 build 重新构建
+    pub(crate) fn get_or_build(
+        &self,
+        instances: &ServiceInstances,
+        build: impl FnOnce(&ServiceInstances) -> T,
+    ) -> Arc<T> {
+        let cache_key = instances.get_cache_key();
+        let fingerprint = instances_fingerprint(instances);
+        {
+            let tables = self.tables.read().unwrap();
+            if let Some(cached) = tables.get(&cache_key) {
+                if cached.revision == instances.service.revision
+                    && cached.fingerprint == fingerprint
+                {
+                    return cached.table.clone();
+                }
+            }
+        }
+
+        let table = Arc::new(build(instances));
+        self.tables.write().unwrap().insert(
+            cache_key,
+            CachedTable {
+                revision: instances.service.revision.clone(),
+                fingerprint,
+                table: table.clone(),
+            },
+        );
+        table
+    }
+}
+
+/// choose_instance 使用请求的 hash_key 在哈希表中选择实例，hash_key 为空时随机选择，
+/// 从命中的槽位开始向后查找并跳过被排除的实例，replicate_index 大于 0 时返回第 replicate_index 个不同的实例，
+/// 不同的实例数不足时按照实例数取模
+pub(crate) fn choose_instance<T: HashTable>(
+    lb_name: &str,
+    table: &T,
+    criteria: &Criteria,
+    instances: &ServiceInstances,
+) -> Result<Instance, PolarisError> {
+    let total = table.slots();
+    if total == 0 {
+        return Err(PolarisError::new(
+            ErrorCode::InstanceNotFound,
+            format!(
+                "no instance with positive weight for {}, namespace={} service={}",
+                lb_name, instances.service.namespace, instances.service.name
+            ),
+        ));
+    }
+
+    let hash = if criteria.hash_key.is_empty() {
+        rand::random::<u32>()
+    } else {
+        murmur3_32(criteria.hash_key.as_bytes(), 0)
+    };
+    let start = table.position(hash);
+    let table_instances = table.instances();
+    let mut distinct = Vec::<usize>::new();
+    let mut seen = HashSet::<usize>::new();
+    for offset in 0..total {
+        let node = table.node((start + offset) % total);
+        if !seen.insert(node) || criteria.is_excluded(&table_instances[node].id) {
+            continue;
+        }
+        if distinct.len() == criteria.replicate_index {
+            return Ok(table_instances[node].clone());
+        }
+        distinct.push(node);
+    }
+    if distinct.is_empty() {
+        return Err(PolarisError::new(
+            ErrorCode::InstanceNotFound,
+            format!(
+                "all instances are excluded, namespace={} service={}",
+                instances.service.namespace, instances.service.name
+            ),
+        ));
+    }
+    Ok(table_instances[distinct[criteria.replicate_index % distinct.len()]].clone())
+}
+
+// instances_fingerprint 参与负载均衡的实例以及权重的摘要，路由结果不同的实例集合需要使用不同的哈希表
+fn instances_fingerprint(instances: &ServiceInstances) -> u32 {
+    let mut buf = String::with_capacity(instances.instances.len() * 48);
+    for ins in instances.instances.iter() {
+        buf.push_str(&ins.id);
+        buf.push(':');
+        buf.push_str(&ins.weight.to_string());
+        buf.push(';');
+    }
+    murmur3_32(buf.as_bytes(), 0)
+}
+
+fn fmix32(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::core::model::naming::ServiceInfo;
+
+    /// build_instances 按照权重构建一致性哈希负载均衡测试使用的实例，实例 ID 为 ins-{下标}
+    pub(crate) fn build_instances(weights: &[u32]) -> ServiceInstances {
+        let instances = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| Instance {
+                id: format!("ins-{}", i),
+                ip: format!("127.0.0.{}", i + 1),
+                port: 8080,
+                weight: *weight,
+                ..Default::default()
+            })
+            .collect();
+        ServiceInstances::new(
+            ServiceInfo {
+                namespace: "default".to_string(),
+                name: "hash".to_string(),
+                revision: "v1".to_string(),
+                ..Default::default()
+            },
+            instances,
+        )
+    }
+
+    #[test]
+    fn test_murmur3_32() {
+        assert_eq!(0, murmur3_32(b"", 0));
+        assert_eq!(0x514e_28b7, murmur3_32(b"", 1));
+        assert_eq!(0x248b_fa47, murmur3_32(b"hello", 0));
+        assert_eq!(
+            0x2e4f_f723,
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0)
+        );
+    }
+}
@@ -0,0 +1,404 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::core::{
+    config::consumer::LoadBalancerPluginConfig,
+    model::{
+        circuitbreaker::RetStatus,
+        error::{ErrorCode, PolarisError},
+        loadbalance::Criteria,
+        naming::{Instance, ServiceInstances},
+    },
+    plugin::{loadbalance::LoadBalancer, plugins::Plugin},
+};
+use crate::discovery::req::ServiceCallResult;
+
+static PLUGIN_NAME: &str = "leastRequest";
+
+static KEY_DECAY_TIME: &str = "decayTime";
+static KEY_FAILURE_PENALTY: &str = "failurePenalty";
+
+// DEFAULT_DECAY_TIME EWMA 的衰减时间，越小越偏向最近的调用耗时
+const DEFAULT_DECAY_TIME: Duration = Duration::from_secs(10);
+// DEFAULT_FAILURE_PENALTY 调用失败时按照该耗时计入 EWMA，避免快速失败的实例吸引更多流量
+const DEFAULT_FAILURE_PENALTY: Duration = Duration::from_secs(1);
+// INFLIGHT_EXPIRE 实例超过该时间没有任何调用结果上报时，认为未上报的在途请求已经结束
+const INFLIGHT_EXPIRE: Duration = Duration::from_secs(60);
+// STAT_EXPIRE 实例统计数据超过该时间未被访问则清理
+const STAT_EXPIRE: Duration = Duration::from_secs(300);
+
+/// LeastRequestLoadBalancer 最少请求负载均衡，随机选择两个实例 (P2C)，
+/// 按照 (在途请求数 + 1) * EWMA 耗时 / 权重 计算负载，选择负载更低的实例
+///
+/// 在途请求数在选中实例时增加，在通过 ConsumerAPI::report_service_call 或者 InvokeHandler 上报调用结果时减少
+pub struct LeastRequestLoadBalancer {
+    decay_time: Duration,
+    failure_penalty: Duration,
+    // stats 实例 ID -> 实例的实时调用统计
+    stats: Arc<RwLock<HashMap<String, Arc<Mutex<InstanceStat>>>>>,
+}
+
+impl LeastRequestLoadBalancer {
+    pub fn builder() -> (
+        fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
+        String,
+    ) {
+        (new_instance, PLUGIN_NAME.to_string())
+    }
+
+    fn get_or_create_stat(&self, ins_id: &str) -> Arc<Mutex<InstanceStat>> {
+        {
+            let stats = self.stats.read().unwrap();
+            if let Some(stat) = stats.get(ins_id) {
+                return stat.clone();
+            }
+        }
+        let mut stats = self.stats.write().unwrap();
+        stats.retain(|_, stat| stat.lock().unwrap().last_active.elapsed() < STAT_EXPIRE);
+        stats
+            .entry(ins_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(InstanceStat::new())))
+            .clone()
+    }
+
+    // score 实例当前的负载，越小越好
+    fn score(&self, ins: &Instance) -> f64 {
+        let stat = self.get_or_create_stat(&ins.id);
+        let mut stat = stat.lock().unwrap();
+        stat.expire_inflight();
+        // 还没有耗时数据的实例，只按照在途请求数比较
+        let latency = if stat.ewma_latency > 0.0 {
+            stat.ewma_latency
+        } else {
+            1.0
+        };
+        (stat.inflight as f64 + 1.0) * latency / ins.weight as f64
+    }
+}
+
+fn new_instance(conf: &LoadBalancerPluginConfig) -> Box<dyn LoadBalancer> {
+    // #描述: EWMA 耗时的衰减时间
+    // decayTime: 10s
+    // #描述: 调用失败时计入的耗时
+    // failurePenalty: 1s
+    let parse_duration = |key: &str, default_val: Duration| {
+        conf.options
+            .as_ref()
+            .and_then(|options| options.get(key))
+            .and_then(|v| match v.parse::<serde_duration_ext::DurationUnit>() {
+                Ok(unit) => Some(Duration::from(unit)),
+                Err(_) => {
+                    crate::error!(
+                        "[polaris][loadbalancer][least_request] invalid {}: {}, use default",
+                        key,
+                        v
+                    );
+                    None
+                }
+            })
+            .unwrap_or(default_val)
+    };
+
+    Box::new(LeastRequestLoadBalancer {
+        decay_time: parse_duration(KEY_DECAY_TIME, DEFAULT_DECAY_TIME),
+        failure_penalty: parse_duration(KEY_FAILURE_PENALTY, DEFAULT_FAILURE_PENALTY),
+        stats: Arc::new(RwLock::new(HashMap::new())),
+    })
+}
+
+impl Plugin for LeastRequestLoadBalancer {
+    fn name(&self) -> String {
+        PLUGIN_NAME.to_string()
+    }
+
+    fn init(&mut self) {}
+
+    fn destroy(&self) {}
+}
+
+impl LoadBalancer for LeastRequestLoadBalancer {
+    fn choose_instance(
+        &self,
+        criteria: Criteria,
+        instances: ServiceInstances,
+    ) -> Result<Instance, PolarisError> {
+        let candidates: Vec<&Instance> = instances
+            .instances
+            .iter()
+            .filter(|ins| ins.weight > 0 && !criteria.is_excluded(&ins.id))
+            .collect();
+        if candidates.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::InstanceNotFound,
+                format!(
+                    "no instance with positive weight for least request, namespace={} service={}",
+                    instances.service.namespace, instances.service.name
+                ),
+            ));
+        }
+
+        let selected = if candidates.len() == 1 {
+            candidates[0]
+        } else {
+            // 随机选择两个不同的实例，选择负载更低的一个
+            let first = rand::random::<usize>() % candidates.len();
+            let mut second = rand::random::<usize>() % (candidates.len() - 1);
+            if second >= first {
+                second += 1;
+            }
+            let (a, b) = (candidates[first], candidates[second]);
+            if self.score(b) < self.score(a) {
+                b
+            } else {
+                a
+            }
+        };
+
+        self.get_or_create_stat(&selected.id)
+            .lock()
+            .unwrap()
+            .on_choose();
+        Ok(selected.clone())
+    }
+
+    fn choose_candidates(
+        &self,
+        criteria: Criteria,
+        instances: ServiceInstances,
+        count: usize,
+    ) -> Result<Vec<Instance>, PolarisError> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        // 首选实例按照 P2C 选择，备选实例只是用于重试，按照负载从低到高排序且不计入在途请求数
+        let primary = self.choose_instance(criteria.clone(), instances.clone())?;
+        let mut backups: Vec<(f64, &Instance)> = instances
+            .instances
+            .iter()
+            .filter(|ins| ins.weight > 0 && ins.id != primary.id && !criteria.is_excluded(&ins.id))
+            .map(|ins| (self.score(ins), ins))
+            .collect();
+        backups.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut candidates = vec![primary];
+        candidates.extend(
+            backups
+                .into_iter()
+                .take(count - 1)
+                .map(|(_, ins)| ins.clone()),
+        );
+        Ok(candidates)
+    }
+
+    fn report_call_result(&self, result: &ServiceCallResult) {
+        let stat = {
+            let stats = self.stats.read().unwrap();
+            match stats.get(&result.instance.id) {
+                Some(stat) => stat.clone(),
+                // 没有经过该负载均衡器选择的实例，不需要统计
+                None => return,
+            }
+        };
+
+        let latency = if result.ret_status == RetStatus::RetSuccess {
+            result.delay
+        } else {
+            result.delay.max(self.failure_penalty)
+        };
+        stat.lock().unwrap().on_result(latency, self.decay_time);
+    }
+}
+
+// InstanceStat 实例的实时调用统计
+struct InstanceStat {
+    // inflight 在途请求数
+    inflight: u64,
+    // ewma_latency 调用耗时的指数加权移动平均，单位为毫秒
+    ewma_latency: f64,
+    // last_report 最近一次上报调用结果的时间
+    last_report: Instant,
+    // last_active 最近一次被选择或者上报调用结果的时间
+    last_active: Instant,
+}
+
+impl InstanceStat {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            inflight: 0,
+            ewma_latency: 0.0,
+            last_report: now,
+            last_active: now,
+        }
+    }
+
+    fn on_choose(&mut self) {
+        self.expire_inflight();
+        self.inflight += 1;
+        self.last_active = Instant::now();
+    }
+
+    fn on_result(&mut self, latency: Duration, decay_time: Duration) {
+        let now = Instant::now();
+        self.inflight = self.inflight.saturating_sub(1);
+
+        let sample = latency.as_secs_f64() * 1000.0;
+        if self.ewma_latency <= 0.0 {
+            self.ewma_latency = sample;
+        } else {
+            // 按照距离上次上报的时间计算衰减系数，长时间没有调用时更快地收敛到最新的耗时
+            let elapsed = now.duration_since(self.last_report).as_secs_f64();
+            let decay = decay_time.as_secs_f64().max(f64::EPSILON);
+            let weight = (-elapsed / decay).exp();
+            self.ewma_latency = self.ewma_latency * weight + sample * (1.0 - weight);
+        }
+        self.last_report = now;
+        self.last_active = now;
+    }
+
+    // expire_inflight 调用方没有上报调用结果时，避免在途请求数一直累积
+    fn expire_inflight(&mut self) {
+        if self.inflight > 0 && self.last_report.elapsed() > INFLIGHT_EXPIRE {
+            self.inflight = 0;
+            self.last_report = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::ServiceInfo;
+
+    fn build_instances() -> ServiceInstances {
+        let instances = (0..2)
+            .map(|i| Instance {
+                id: format!("ins-{}", i),
+                ip: format!("127.0.0.{}", i + 1),
+                port: 8080,
+                weight: 100,
+                ..Default::default()
+            })
+            .collect();
+        ServiceInstances::new(
+            ServiceInfo {
+                namespace: "default".to_string(),
+                name: "least".to_string(),
+                ..Default::default()
+            },
+            instances,
+        )
+    }
+
+    fn call_result(ins: &Instance, delay: Duration, ret_status: RetStatus) -> ServiceCallResult {
+        ServiceCallResult {
+            flow_id: String::new(),
+            namespace: "default".to_string(),
+            service: "least".to_string(),
+            instance: ins.clone(),
+            method: String::new(),
+            ret_status,
+            ret_code: 0,
+            delay,
+        }
+    }
+
+    #[test]
+    fn test_choose_lower_latency() {
+        let instances = build_instances();
+        let (fast, slow) = (&instances.instances[0], &instances.instances[1]);
+
+        // 失败的调用按照 failurePenalty 计入耗时
+        let lr = LeastRequestLoadBalancer {
+            decay_time: DEFAULT_DECAY_TIME,
+            failure_penalty: DEFAULT_FAILURE_PENALTY,
+            stats: Arc::new(RwLock::new(HashMap::new())),
+        };
+        lr.get_or_create_stat(&fast.id);
+        lr.get_or_create_stat(&slow.id);
+        lr.report_call_result(&call_result(
+            fast,
+            Duration::from_millis(5),
+            RetStatus::RetSuccess,
+        ));
+        lr.report_call_result(&call_result(
+            slow,
+            Duration::from_millis(5),
+            RetStatus::RetFail,
+        ));
+
+        for _ in 0..20 {
+            let ins = lr
+                .choose_instance(Criteria::default(), instances.clone())
+                .unwrap();
+            assert_eq!(fast.id, ins.id);
+            lr.report_call_result(&call_result(
+                &ins,
+                Duration::from_millis(5),
+                RetStatus::RetSuccess,
+            ));
+        }
+    }
+
+    #[test]
+    fn test_inflight() {
+        let mut stat = InstanceStat::new();
+        stat.on_choose();
+        stat.on_choose();
+        assert_eq!(2, stat.inflight);
+        stat.on_result(Duration::from_millis(10), DEFAULT_DECAY_TIME);
+        assert_eq!(1, stat.inflight);
+        assert!((stat.ewma_latency - 10.0).abs() < f64::EPSILON);
+        stat.on_result(Duration::from_millis(20), DEFAULT_DECAY_TIME);
+        stat.on_result(Duration::from_millis(20), DEFAULT_DECAY_TIME);
+        assert_eq!(0, stat.inflight);
+        assert!(stat.ewma_latency >= 10.0 && stat.ewma_latency <= 20.0);
+    }
+
+    #[test]
+    fn test_exclude_and_candidates() {
+        let instances = build_instances();
+        let lr = LeastRequestLoadBalancer {
+            decay_time: DEFAULT_DECAY_TIME,
+            failure_penalty: DEFAULT_FAILURE_PENALTY,
+            stats: Arc::new(RwLock::new(HashMap::new())),
+        };
+        let mut criteria = Criteria::default();
+        criteria.exclude_instances.insert("ins-0".to_string());
+        for _ in 0..5 {
+            let ins = lr
+                .choose_instance(criteria.clone(), instances.clone())
+                .unwrap();
+            assert_eq!("ins-1", ins.id);
+        }
+
+        let ids: Vec<String> = lr
+            .choose_candidates(criteria.clone(), instances.clone(), 2)
+            .unwrap()
+            .into_iter()
+            .map(|ins| ins.id)
+            .collect();
+        assert_eq!(vec!["ins-1"], ids);
+
+        criteria.exclude_instances.insert("ins-1".to_string());
+        assert!(lr.choose_instance(criteria, instances).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use crate::core::{
+    config::consumer::LoadBalancerPluginConfig,
+    model::{
+        error::PolarisError,
+        loadbalance::Criteria,
+        naming::{Instance, ServiceInstances},
+    },
+    plugin::{loadbalance::LoadBalancer, plugins::Plugin},
+};
+use crate::plugins::loadbalance::hash::{self, murmur3_32, HashTable, HashTableCache};
+
+static PLUGIN_NAME: &str = "maglev";
+
+static KEY_TABLE_SIZE: &str = "tableSize";
+
+// DEFAULT_TABLE_SIZE 查找表的默认大小，必须为质数
+const DEFAULT_TABLE_SIZE: u64 = 65537;
+
+/// MaglevLoadBalancer Maglev 一致性哈希负载均衡，相比 ring hash 负载更均匀，构建查找表的开销更低
+pub struct MaglevLoadBalancer {
+    table_size: u64,
+    // 按照服务缓存查找表，服务版本或者实例发生变化时重新构建
+    table_cache: HashTableCache<MaglevTable>,
+}
+
+impl MaglevLoadBalancer {
+    pub fn builder() -> (
+        fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
+        String,
+    ) {
+        (new_instance, PLUGIN_NAME.to_string())
+    }
+}
+
+fn new_instance(conf: &LoadBalancerPluginConfig) -> Box<dyn LoadBalancer> {
+    // #描述: 查找表大小，需要为质数且远大于实例数，非质数时使用大于该值的最小质数
+    // tableSize: 65537
+    let mut table_size = conf
+        .options
+        .as_ref()
+        .and_then(|options| options.get(KEY_TABLE_SIZE))
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_TABLE_SIZE);
+    if !is_prime(table_size) {
+        let size = next_prime(table_size);
+        crate::warn!(
+            "[polaris][loadbalancer][maglev] table size {} is not prime, use {}",
+            table_size,
+            size
+        );
+        table_size = size;
+    }
+
+    Box::new(MaglevLoadBalancer {
+        table_size,
+        table_cache: HashTableCache::new(),
+    })
+}
+
+impl Plugin for MaglevLoadBalancer {
+    fn name(&self) -> String {
+        PLUGIN_NAME.to_string()
+    }
+
+    fn init(&mut self) {}
+
+    fn destroy(&self) {}
+}
+
+impl LoadBalancer for MaglevLoadBalancer {
+    fn choose_instance(
+        &self,
+        criteria: Criteria,
+        instances: ServiceInstances,
+    ) -> Result<Instance, PolarisError> {
+        let table = self.table_cache.get_or_build(&instances, |instances| {
+            MaglevTable::new(instances, self.table_size)
+        });
+        hash::choose_instance(PLUGIN_NAME, table.as_ref(), &criteria, &instances)
+    }
+}
+
+// MaglevTable Maglev 查找表
+struct MaglevTable {
+    // entries 查找表，value 为 instances 的下标
+    entries: Vec<u32>,
+    instances: Vec<Instance>,
+}
+
+impl MaglevTable {
+    // 按照论文中的排列方式填充查找表，权重越大的实例每一轮获得的槽位越多
+    fn new(svc_instances: &ServiceInstances, table_size: u64) -> Self {
+        let instances = svc_instances.instances.clone();
+        let max_weight = instances.iter().map(|ins| ins.weight).max().unwrap_or(0);
+
+        let mut entries = Vec::<u32>::new();
+        if max_weight > 0 {
+            let mut permutations = Vec::new();
+            for (index, ins) in instances.iter().enumerate() {
+                if ins.weight == 0 {
+                    continue;
+                }
+                let key = format!("{}:{}", ins.ip, ins.port);
+                let offset = murmur3_32(key.as_bytes(), 0) as u64 % table_size;
+                let skip = murmur3_32(key.as_bytes(), 1) as u64 % (table_size - 1) + 1;
+                permutations.push(Permutation {
+                    index: index as u32,
+                    offset,
+                    skip,
+                    normalized_weight: ins.weight as f64 / max_weight as f64,
+                    target_weight: 0.0,
+                    next: 0,
+                });
+            }
+
+            let mut table = vec![u32::MAX; table_size as usize];
+            let mut filled: u64 = 0;
+            let mut iteration: u64 = 1;
+            while filled < table_size {
+                for permutation in permutations.iter_mut() {
+                    if (iteration as f64) * permutation.normalized_weight
+                        < permutation.target_weight
+                    {
+                        continue;
+                    }
+                    permutation.target_weight += 1.0;
+
+                    let mut slot = permutation.slot(table_size);
+                    while table[slot] != u32::MAX {
+                        permutation.next += 1;
+                        slot = permutation.slot(table_size);
+                    }
+                    table[slot] = permutation.index;
+                    permutation.next += 1;
+                    filled += 1;
+                    if filled == table_size {
+                        break;
+                    }
+                }
+                iteration += 1;
+            }
+            entries = table;
+        }
+
+        Self { entries, instances }
+    }
+}
+
+impl HashTable for MaglevTable {
+    fn slots(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn position(&self, hash: u32) -> usize {
+        hash as usize % self.entries.len()
+    }
+
+    fn node(&self, position: usize) -> usize {
+        self.entries[position] as usize
+    }
+
+    fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+}
+
+struct Permutation {
+    index: u32,
+    offset: u64,
+    skip: u64,
+    normalized_weight: f64,
+    target_weight: f64,
+    next: u64,
+}
+
+impl Permutation {
+    fn slot(&self, table_size: u64) -> usize {
+        ((self.offset + self.next * self.skip) % table_size) as usize
+    }
+}
+
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let mut i = 2;
+    while i * i <= n {
+        if n.is_multiple_of(i) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+fn next_prime(n: u64) -> u64 {
+    let mut n = n.max(2);
+    while !is_prime(n) {
+        n += 1;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::plugins::loadbalance::hash::tests::build_instances;
+
+    #[test]
+    fn test_maglev_table_weight() {
+        let table = MaglevTable::new(&build_instances(&[100, 300, 0]), 1009);
+        assert_eq!(1009, table.entries.len());
+
+        let mut counter = [0u32; 3];
+        for node in table.entries.iter() {
+            counter[*node as usize] += 1;
+        }
+        assert_eq!(0, counter[2]);
+        let ratio = counter[1] as f64 / counter[0] as f64;
+        assert!(ratio > 2.8 && ratio < 3.2);
+    }
+
+    #[test]
+    fn test_maglev_choose_instance() {
+        let mut options = HashMap::new();
+        options.insert(KEY_TABLE_SIZE.to_string(), "1000".to_string());
+        let lb = new_instance(&LoadBalancerPluginConfig {
+            name: PLUGIN_NAME.to_string(),
+            options: Some(options),
+        });
+        let instances = build_instances(&[100, 100, 100]);
+        let choose = |index: usize| {
+            lb.choose_instance(
+                Criteria {
+                    policy: PLUGIN_NAME.to_string(),
+                    hash_key: "user-1".to_string(),
+                    replicate_index: index,
+                    ..Default::default()
+                },
+                instances.clone(),
+            )
+            .unwrap()
+            .id
+        };
+
+        let first = choose(0);
+        assert_eq!(first, choose(0));
+        assert_ne!(first, choose(1));
+        assert_eq!(first, choose(3));
+
+        let ret = lb.choose_instance(Criteria::default(), build_instances(&[0, 0]));
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_next_prime() {
+        assert_eq!(1009, next_prime(1000));
+        assert_eq!(65537, next_prime(65537));
+    }
+
+    #[test]
+    fn test_maglev_exclude_and_candidates() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[100, 100, 100]);
+        let mut criteria = Criteria {
+            policy: PLUGIN_NAME.to_string(),
+            hash_key: "user-1".to_string(),
+            ..Default::default()
+        };
+        let first = lb
+            .choose_instance(criteria.clone(), instances.clone())
+            .unwrap()
+            .id;
+
+        // 排除首选实例后选择其他实例，且结果稳定
+        criteria.exclude_instances.insert(first.clone());
+        let second = lb
+            .choose_instance(criteria.clone(), instances.clone())
+            .unwrap()
+            .id;
+        assert_ne!(first, second);
+        assert_eq!(
+            second,
+            lb.choose_instance(criteria.clone(), instances.clone())
+                .unwrap()
+                .id
+        );
+
+        criteria.exclude_instances.clear();
+        let ids: Vec<String> = lb
+            .choose_candidates(criteria.clone(), instances.clone(), 5)
+            .unwrap()
+            .into_iter()
+            .map(|ins| ins.id)
+            .collect();
+        assert_eq!(vec![first, second], ids[..2].to_vec());
+        assert_eq!(3, ids.len());
+
+        for ins in instances.instances.iter() {
+            criteria.exclude_instances.insert(ins.id.clone());
+        }
+        assert!(lb.choose_instance(criteria, instances).is_err());
+    }
+}
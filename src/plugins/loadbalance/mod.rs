@@ -13,6 +13,9 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+pub mod hash;
+pub mod leastrequest;
+pub mod maglev;
 pub mod random;
 pub mod ringhash;
 pub mod roundrobin;
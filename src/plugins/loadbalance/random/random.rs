@@ -14,6 +14,7 @@
 // specific language governing permissions and limitations under the License.
 
 use crate::core::{
+    config::consumer::LoadBalancerPluginConfig,
     model::{
         error::{ErrorCode, PolarisError},
         naming::Instance,
@@ -28,12 +29,15 @@ static PLUGIN_NAME: &str = "weightedRandom";
 pub struct WeightRandomLoadbalancer {}
 
 impl WeightRandomLoadbalancer {
-    pub fn builder() -> (fn() -> Box<dyn LoadBalancer>, String) {
+    pub fn builder() -> (
+        fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
+        String,
+    ) {
         (new_instance, PLUGIN_NAME.to_string())
     }
 }
 
-fn new_instance() -> Box<dyn LoadBalancer> {
+fn new_instance(_conf: &LoadBalancerPluginConfig) -> Box<dyn LoadBalancer> {
     Box::new(WeightRandomLoadbalancer {})
 }
 
@@ -50,9 +54,10 @@ impl Plugin for WeightRandomLoadbalancer {
 impl LoadBalancer for WeightRandomLoadbalancer {
     fn choose_instance(
         &self,
-        _criteria: crate::core::model::loadbalance::Criteria,
+        criteria: crate::core::model::loadbalance::Criteria,
         instances: crate::core::model::naming::ServiceInstances,
     ) -> Result<Instance, PolarisError> {
+        let instances = criteria.filter_excluded(instances);
         let total_weight = instances.total_weight;
         if total_weight == 0 {
             return Err(PolarisError::new(
@@ -81,4 +86,56 @@ impl LoadBalancer for WeightRandomLoadbalancer {
         let index = total_weight % instances.instances.len() as u64;
         Ok(instances.instances[index as usize].clone())
     }
+
+    fn use_dynamic_weight(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::loadbalance::Criteria;
+    use crate::core::model::naming::{ServiceInfo, ServiceInstances};
+
+    fn build_instances(count: usize) -> ServiceInstances {
+        ServiceInstances::new(
+            ServiceInfo::default(),
+            (0..count)
+                .map(|i| Instance {
+                    id: format!("ins-{}", i),
+                    weight: 100,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_exclude_and_candidates() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(3);
+        let mut criteria = Criteria::default();
+        criteria.exclude_instances.insert("ins-0".to_string());
+        for _ in 0..20 {
+            let ins = lb
+                .choose_instance(criteria.clone(), instances.clone())
+                .unwrap();
+            assert_ne!("ins-0", ins.id);
+        }
+
+        // 备选实例互不相同，且不包含被排除的实例
+        let mut ids: Vec<String> = lb
+            .choose_candidates(criteria.clone(), instances.clone(), 5)
+            .unwrap()
+            .into_iter()
+            .map(|ins| ins.id)
+            .collect();
+        ids.sort();
+        assert_eq!(vec!["ins-1", "ins-2"], ids);
+
+        criteria.exclude_instances.insert("ins-1".to_string());
+        criteria.exclude_instances.insert("ins-2".to_string());
+        assert!(lb.choose_instance(criteria, instances).is_err());
+    }
 }
@@ -13,36 +13,43 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::{
-    collections::{BTreeMap, HashMap},
-    hash::{Hash, Hasher},
-    sync::{Arc, RwLock},
-};
-
 use crate::core::{
-    model::naming::Instance,
+    config::consumer::LoadBalancerPluginConfig,
+    model::{
+        error::PolarisError,
+        loadbalance::Criteria,
+        naming::{Instance, ServiceInstances},
+    },
     plugin::{loadbalance::LoadBalancer, plugins::Plugin},
 };
+use crate::plugins::loadbalance::hash::{self, murmur3_32, HashTable, HashTableCache};
 
 static PLUGIN_NAME: &str = "ringHash";
 
-const DEFAULT_REPLICAS: usize = 5;
+// DEFAULT_VNODE_COUNT 平均权重的实例在哈希环上的虚拟节点数
+const DEFAULT_VNODE_COUNT: u64 = 1024;
 
 /// ConsistentHashLoadBalancer 一致性哈希负载均衡
+///
+/// 虚拟节点的 key 为 `{ip}:{port}#{index}`，与请求的 hash_key 一样使用 murmur3_32(seed=0) 计算哈希值，
+/// 实例的虚拟节点数和其权重成正比，同一份实例列表对同一个 hash_key 总是选出相同的实例
 pub struct ConsistentHashLoadBalancer {
     // 需要把 ring hash 进行一次缓存，避免重复构建 ring hash
-    ring_hash_cache: Arc<RwLock<HashMap<String, RingHash>>>,
+    ring_hash_cache: HashTableCache<RingHash>,
 }
 
 impl ConsistentHashLoadBalancer {
-    pub fn builder() -> (fn() -> Box<dyn LoadBalancer>, String) {
+    pub fn builder() -> (
+        fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
+        String,
+    ) {
         (new_instance, PLUGIN_NAME.to_string())
     }
 }
 
-fn new_instance() -> Box<dyn LoadBalancer> {
+fn new_instance(_conf: &LoadBalancerPluginConfig) -> Box<dyn LoadBalancer> {
     Box::new(ConsistentHashLoadBalancer {
-        ring_hash_cache: Arc::new(RwLock::new(HashMap::new())),
+        ring_hash_cache: HashTableCache::new(),
     })
 }
 
@@ -59,79 +66,163 @@ impl Plugin for ConsistentHashLoadBalancer {
 impl LoadBalancer for ConsistentHashLoadBalancer {
     fn choose_instance(
         &self,
-        criteria: crate::core::model::loadbalance::Criteria,
-        instances: crate::core::model::naming::ServiceInstances,
-    ) -> Result<crate::core::model::naming::Instance, crate::core::model::error::PolarisError> {
-        let ring_cache_key = instances.get_cache_key();
-
-        // 先判断 ring hash 是否已经存在，如果不存在则创建并初始化一个
-        {
-            let mut ring_hash_cache = self.ring_hash_cache.write().unwrap();
-            ring_hash_cache
-                .entry(ring_cache_key.clone())
-                .and_modify(|ring_hash| {
-                    if ring_hash.revision != instances.service.revision {
-                        ring_hash.nodes.clear();
-                        for instance in instances.instances.iter() {
-                            ring_hash.add_node(instance.clone());
-                        }
-                        ring_hash.revision.clone_from(&instances.service.revision);
-                    }
-                })
-                .or_insert_with(|| {
-                    let mut ring_hash =
-                        RingHash::new(DEFAULT_REPLICAS, instances.service.revision.clone());
-                    for instance in instances.instances.iter() {
-                        ring_hash.add_node(instance.clone());
-                    }
-                    ring_hash
-                });
-        }
-
-        let ring_hash_repo = self.ring_hash_cache.read().unwrap();
-        let ring_hash = ring_hash_repo.get(&ring_cache_key).unwrap();
-
-        let hash = hash(&criteria.hash_key);
-        let node = ring_hash.get_node(hash).unwrap();
-
-        Ok(node.clone())
+        criteria: Criteria,
+        instances: ServiceInstances,
+    ) -> Result<Instance, PolarisError> {
+        let ring_hash = self.ring_hash_cache.get_or_build(&instances, |instances| {
+            RingHash::new(instances, DEFAULT_VNODE_COUNT)
+        });
+        hash::choose_instance("ring hash", ring_hash.as_ref(), &criteria, &instances)
     }
 }
 
-// 定义一个简单的哈希函数
-fn hash<T: Hash>(t: &T) -> u64 {
-    let mut s = std::collections::hash_map::DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
-}
-
 // 定义哈希环结构体
 struct RingHash {
-    nodes: BTreeMap<u64, Instance>,
-    replicas: usize,
-    revision: String,
+    // points 按照哈希值排序的虚拟节点，value 为 instances 的下标
+    points: Vec<(u32, usize)>,
+    instances: Vec<Instance>,
 }
 
 impl RingHash {
-    // 创建一个新的哈希环
-    fn new(replicas: usize, revision: String) -> Self {
-        Self {
-            nodes: BTreeMap::new(),
-            replicas,
-            revision,
+    // 创建一个新的哈希环，实例的虚拟节点数 = vnode_count * weight / 平均权重
+    fn new(svc_instances: &ServiceInstances, vnode_count: u64) -> Self {
+        let instances = svc_instances.instances.clone();
+        let total_weight: u64 = instances.iter().map(|ins| ins.weight as u64).sum();
+        let mut points = Vec::<(u32, usize)>::new();
+        if total_weight > 0 {
+            let ins_cnt = instances.len() as u64;
+            for (index, ins) in instances.iter().enumerate() {
+                if ins.weight == 0 {
+                    continue;
+                }
+                let replicas = (vnode_count * ins.weight as u64 * ins_cnt).div_ceil(total_weight);
+                for i in 0..replicas {
+                    let key = format!("{}:{}#{}", ins.ip, ins.port, i);
+                    points.push((murmur3_32(key.as_bytes(), 0), index));
+                }
+            }
         }
+        // 哈希值相同时按照实例下标排序，保证结果稳定
+        points.sort_unstable();
+
+        Self { points, instances }
+    }
+}
+
+impl HashTable for RingHash {
+    fn slots(&self) -> usize {
+        self.points.len()
+    }
+
+    // position 顺时针找到第一个哈希值不小于 hash 的虚拟节点，超过最大值时回到环的起点
+    fn position(&self, hash: u32) -> usize {
+        self.points.partition_point(|(point, _)| *point < hash)
+    }
+
+    fn node(&self, position: usize) -> usize {
+        self.points[position].1
     }
 
-    // 添加一个节点到哈希环
-    fn add_node(&mut self, node: Instance) {
-        for i in 0..self.replicas {
-            let key = hash(&(node.id.clone(), i));
-            self.nodes.insert(key, node.clone());
+    fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::plugins::loadbalance::hash::tests::build_instances;
+
+    fn choose(
+        lb: &dyn LoadBalancer,
+        instances: &ServiceInstances,
+        key: &str,
+        index: usize,
+    ) -> String {
+        lb.choose_instance(
+            Criteria {
+                policy: PLUGIN_NAME.to_string(),
+                hash_key: key.to_string(),
+                replicate_index: index,
+                ..Default::default()
+            },
+            instances.clone(),
+        )
+        .unwrap()
+        .id
+    }
+
+    #[test]
+    fn test_ring_hash_stable_and_replica() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[100, 100, 100]);
+
+        let first = choose(lb.as_ref(), &instances, "user-1", 0);
+        assert_eq!(first, choose(lb.as_ref(), &instances, "user-1", 0));
+
+        let second = choose(lb.as_ref(), &instances, "user-1", 1);
+        let third = choose(lb.as_ref(), &instances, "user-1", 2);
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        assert_ne!(first, third);
+        // 不同实例数不足时回绕
+        assert_eq!(first, choose(lb.as_ref(), &instances, "user-1", 3));
+    }
+
+    #[test]
+    fn test_ring_hash_weight() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[0, 100, 300]);
+
+        let mut counter = HashMap::<String, u32>::new();
+        for i in 0..4000 {
+            let id = choose(lb.as_ref(), &instances, &format!("key-{}", i), 0);
+            *counter.entry(id).or_default() += 1;
         }
+        assert!(!counter.contains_key("ins-0"));
+        let light = *counter.get("ins-1").unwrap() as f64;
+        let heavy = *counter.get("ins-2").unwrap() as f64;
+        assert!(heavy / light > 2.0 && heavy / light < 4.5);
+    }
+
+    #[test]
+    fn test_ring_hash_no_weight() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let ret = lb.choose_instance(
+            Criteria {
+                hash_key: "user-1".to_string(),
+                ..Default::default()
+            },
+            build_instances(&[0, 0]),
+        );
+        assert!(ret.is_err());
     }
 
-    // 根据对象的哈希值找到对应的节点
-    fn get_node(&self, hash: u64) -> Option<&Instance> {
-        self.nodes.range(..=hash).next_back().map(|(_, node)| node)
+    #[test]
+    fn test_ring_hash_exclude_and_candidates() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[100, 100, 100]);
+        let first = choose(lb.as_ref(), &instances, "user-1", 0);
+        let second = choose(lb.as_ref(), &instances, "user-1", 1);
+
+        // 排除首选实例后，选择环上的下一个实例
+        let mut criteria = Criteria {
+            hash_key: "user-1".to_string(),
+            ..Default::default()
+        };
+        criteria.exclude_instances.insert(first.clone());
+        let ins = lb
+            .choose_instance(criteria.clone(), instances.clone())
+            .unwrap();
+        assert_eq!(second, ins.id);
+
+        criteria.exclude_instances.clear();
+        let candidates = lb.choose_candidates(criteria, instances, 5).unwrap();
+        let ids: Vec<String> = candidates.into_iter().map(|ins| ins.id).collect();
+        assert_eq!(3, ids.len());
+        assert_eq!(first, ids[0]);
+        assert_eq!(second, ids[1]);
     }
 }
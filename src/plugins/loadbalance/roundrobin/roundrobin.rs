@@ -15,12 +15,17 @@
 
 use std::{
     collections::HashMap,
-    sync::{atomic::AtomicU32, Arc, RwLock},
+    sync::{atomic::AtomicI64, Arc, RwLock},
     time::Instant,
 };
 
 use crate::core::{
-    model::naming::Instance,
+    config::consumer::LoadBalancerPluginConfig,
+    model::{
+        error::{ErrorCode, PolarisError},
+        loadbalance::Criteria,
+        naming::{Instance, ServiceInstances},
+    },
     plugin::{loadbalance::LoadBalancer, plugins::Plugin},
 };
 
@@ -32,12 +37,15 @@ pub struct WeightedRoundRobinBalancer {
 }
 
 impl WeightedRoundRobinBalancer {
-    pub fn builder() -> (fn() -> Box<dyn LoadBalancer>, String) {
+    pub fn builder() -> (
+        fn(&LoadBalancerPluginConfig) -> Box<dyn LoadBalancer>,
+        String,
+    ) {
         (new_instance, PLUGIN_NAME.to_string())
     }
 }
 
-fn new_instance() -> Box<dyn LoadBalancer> {
+fn new_instance(_conf: &LoadBalancerPluginConfig) -> Box<dyn LoadBalancer> {
     Box::new(WeightedRoundRobinBalancer {
         round_robin_cache: Arc::new(RwLock::new(HashMap::new())),
     })
@@ -48,77 +56,119 @@ impl Plugin for WeightedRoundRobinBalancer {
         PLUGIN_NAME.to_string()
     }
 
-    fn init(&mut self) {
-        todo!()
-    }
+    fn init(&mut self) {}
 
-    fn destroy(&self) {
-        todo!()
-    }
+    fn destroy(&self) {}
 }
 
 impl LoadBalancer for WeightedRoundRobinBalancer {
     fn choose_instance(
         &self,
-        _criteria: crate::core::model::loadbalance::Criteria,
-        instances: crate::core::model::naming::ServiceInstances,
-    ) -> Result<crate::core::model::naming::Instance, crate::core::model::error::PolarisError> {
+        criteria: Criteria,
+        instances: ServiceInstances,
+    ) -> Result<Instance, PolarisError> {
+        let instances = criteria.filter_excluded(instances);
+        let total_weight = instances.get_total_weight();
+        if total_weight == 0 {
+            return Err(PolarisError::new(
+                ErrorCode::InstanceInfoError,
+                "total weight of instances is 0".to_string(),
+            ));
+        }
+
         let cache_key = instances.get_cache_key();
-        {
+        let round_robins = {
             let mut round_robin_cache = self.round_robin_cache.write().unwrap();
             round_robin_cache
-                .entry(cache_key.clone())
-                .and_modify(|round_robins| {
-                    for instance in instances.instances.iter() {
-                        let mut inss_round_robin = round_robins.round_robins.write().unwrap();
-                        let weight_robin = inss_round_robin
-                            .entry(instance.id.clone())
-                            .or_insert_with(|| WeightedRoundRobin::new(instance.weight));
-                        if weight_robin.is_expire() {
-                            round_robins
-                                .round_robins
-                                .write()
-                                .unwrap()
-                                .remove(&cache_key);
-                            return;
-                        }
-                        // 如果没过期，但是实例数据出现变化，则更新
-                        if weight_robin.get_ins_weight() != instance.weight {
-                            weight_robin.reset(instance.weight);
-                        }
-                    }
-                });
-        }
+                .entry(cache_key)
+                .or_insert_with(|| WeightedRoundRobins {
+                    round_robins: Arc::new(RwLock::new(HashMap::new())),
+                })
+                .round_robins
+                .clone()
+        };
 
-        let mut selected_wrr: Option<WeightedRoundRobin> = None;
-        let mut selected_ins: Option<&Instance> = None;
-        let mut max_weight: i32 = -1;
+        // 平滑加权轮询：每次选择时所有实例的当前权重加上自身权重，选择当前权重最大的实例，并减去总权重
+        let mut inss_round_robin = round_robins.write().unwrap();
+        inss_round_robin.retain(|_, weight_robin| !weight_robin.is_expire());
 
-        let svc_ins_cache_repo = self.round_robin_cache.read().unwrap();
-        let svc_ins_cache = svc_ins_cache_repo.get(&cache_key).unwrap();
-        for (_, ins) in instances.instances.iter().enumerate() {
-            let inss_round_robin = svc_ins_cache.round_robins.write().unwrap();
-            let weight_robin = inss_round_robin.get(&ins.id).unwrap();
+        let mut selected: Option<(&Instance, WeightedRoundRobin)> = None;
+        let mut max_weight = i64::MIN;
+        for ins in instances.instances.iter() {
+            if ins.weight == 0 {
+                continue;
+            }
+            let weight_robin = inss_round_robin
+                .entry(ins.id.clone())
+                .or_insert_with(|| WeightedRoundRobin::new(ins.weight));
+            // 实例权重出现变化，则重置
+            if weight_robin.get_ins_weight() != ins.weight {
+                weight_robin.reset(ins.weight);
+            }
             let cur_weight = weight_robin.increase_cur_weight();
-
             weight_robin.update_last_fetch();
 
-            if cur_weight as i32 > max_weight {
-                max_weight = cur_weight as i32;
-                selected_wrr = Some(weight_robin.clone());
-                selected_ins = Some(ins);
+            if cur_weight > max_weight {
+                max_weight = cur_weight;
+                selected = Some((ins, weight_robin.clone()));
             }
         }
 
-        if selected_ins.is_none() {
-            // 直接返回第一个
-            return Ok(instances.instances[0].clone());
-        }
+        let (ins, weight_robin) = selected.unwrap();
+        weight_robin.decrease_cur_weight(total_weight as i64);
+        Ok(ins.clone())
+    }
 
-        selected_wrr
+    fn choose_candidates(
+        &self,
+        criteria: Criteria,
+        instances: ServiceInstances,
+        count: usize,
+    ) -> Result<Vec<Instance>, PolarisError> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        // 首选实例按照平滑加权轮询选择，只推进一次轮询状态；备选实例只用于重试，
+        // 按照下一轮的当前权重从高到低排序，不修改轮询状态，避免打乱实例间的权重比例
+        let primary = self.choose_instance(criteria.clone(), instances.clone())?;
+        let round_robins = self
+            .round_robin_cache
+            .read()
             .unwrap()
-            .decrease_cur_weight(instances.get_total_weight() as u32);
-        Ok(selected_ins.unwrap().clone())
+            .get(&instances.get_cache_key())
+            .map(|cache| cache.round_robins.clone());
+        let mut backups: Vec<(i64, &Instance)> = {
+            let inss_round_robin = round_robins.as_ref().map(|r| r.read().unwrap());
+            instances
+                .instances
+                .iter()
+                .filter(|ins| {
+                    ins.weight > 0 && ins.id != primary.id && !criteria.is_excluded(&ins.id)
+                })
+                .map(|ins| {
+                    let cur_weight = inss_round_robin
+                        .as_ref()
+                        .and_then(|r| r.get(&ins.id))
+                        .map(|w| w.cur_weight.load(std::sync::atomic::Ordering::Relaxed))
+                        .unwrap_or(0);
+                    (cur_weight + ins.weight as i64, ins)
+                })
+                .collect()
+        };
+        backups.sort_by_key(|(cur_weight, _)| std::cmp::Reverse(*cur_weight));
+
+        let mut candidates = vec![primary];
+        candidates.extend(
+            backups
+                .into_iter()
+                .take(count - 1)
+                .map(|(_, ins)| ins.clone()),
+        );
+        Ok(candidates)
+    }
+
+    fn use_dynamic_weight(&self) -> bool {
+        true
     }
 }
 
@@ -128,16 +178,16 @@ struct WeightedRoundRobins {
 
 #[derive(Clone)]
 struct WeightedRoundRobin {
-    cur_weight: Arc<AtomicU32>,
-    weight: Arc<AtomicU32>,
+    cur_weight: Arc<AtomicI64>,
+    weight: Arc<AtomicI64>,
     last_fetch: Arc<RwLock<Instant>>,
 }
 
 impl WeightedRoundRobin {
     fn new(weight: u32) -> Self {
         Self {
-            cur_weight: Arc::new(AtomicU32::new(0)),
-            weight: Arc::new(AtomicU32::new(weight)),
+            cur_weight: Arc::new(AtomicI64::new(0)),
+            weight: Arc::new(AtomicI64::new(weight as i64)),
             last_fetch: Arc::new(RwLock::new(Instant::now())),
         }
     }
@@ -146,21 +196,22 @@ impl WeightedRoundRobin {
         self.cur_weight
             .store(0, std::sync::atomic::Ordering::Relaxed);
         self.weight
-            .store(weight, std::sync::atomic::Ordering::Relaxed);
+            .store(weight as i64, std::sync::atomic::Ordering::Relaxed);
     }
 
     fn get_ins_weight(&self) -> u32 {
-        self.weight.load(std::sync::atomic::Ordering::Relaxed)
+        self.weight.load(std::sync::atomic::Ordering::Relaxed) as u32
     }
 
-    fn increase_cur_weight(&self) -> u32 {
-        self.cur_weight.fetch_add(
-            self.weight.load(std::sync::atomic::Ordering::Relaxed),
-            std::sync::atomic::Ordering::Relaxed,
-        )
+    // increase_cur_weight 当前权重加上实例权重，返回增加后的当前权重
+    fn increase_cur_weight(&self) -> i64 {
+        let weight = self.weight.load(std::sync::atomic::Ordering::Relaxed);
+        self.cur_weight
+            .fetch_add(weight, std::sync::atomic::Ordering::Relaxed)
+            + weight
     }
 
-    fn decrease_cur_weight(&self, weight: u32) {
+    fn decrease_cur_weight(&self, weight: i64) {
         self.cur_weight
             .fetch_sub(weight, std::sync::atomic::Ordering::Relaxed);
     }
@@ -174,3 +225,91 @@ impl WeightedRoundRobin {
         self.last_fetch.read().unwrap().elapsed().as_secs() > 60
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::ServiceInfo;
+
+    #[test]
+    fn test_smooth_weighted_round_robin() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = ServiceInstances::new(
+            ServiceInfo::default(),
+            [("a", 5), ("b", 1), ("c", 1)]
+                .iter()
+                .map(|(id, weight)| Instance {
+                    id: id.to_string(),
+                    weight: *weight,
+                    ..Default::default()
+                })
+                .collect(),
+        );
+
+        let ids: Vec<String> = (0..7)
+            .map(|_| {
+                lb.choose_instance(Criteria::default(), instances.clone())
+                    .unwrap()
+                    .id
+            })
+            .collect();
+        assert_eq!(vec!["a", "a", "b", "a", "c", "a", "a"], ids);
+    }
+
+    fn build_instances(weights: &[(&str, u32)]) -> ServiceInstances {
+        ServiceInstances::new(
+            ServiceInfo::default(),
+            weights
+                .iter()
+                .map(|(id, weight)| Instance {
+                    id: id.to_string(),
+                    weight: *weight,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_choose_candidates_keep_round_robin() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[("a", 5), ("b", 1), ("c", 1)]);
+
+        // 选择备选实例不推进轮询状态，首选实例的序列与逐个调用 choose_instance 一致
+        let ids: Vec<String> = (0..7)
+            .map(|_| {
+                let candidates = lb
+                    .choose_candidates(Criteria::default(), instances.clone(), 3)
+                    .unwrap();
+                assert_eq!(3, candidates.len());
+                candidates[0].id.clone()
+            })
+            .collect();
+        assert_eq!(vec!["a", "a", "b", "a", "c", "a", "a"], ids);
+    }
+
+    #[test]
+    fn test_exclude_instances() {
+        let lb = new_instance(&LoadBalancerPluginConfig::default());
+        let instances = build_instances(&[("a", 5), ("b", 1), ("c", 1)]);
+        let mut criteria = Criteria::default();
+        criteria.exclude_instances.insert("a".to_string());
+
+        for _ in 0..4 {
+            let ins = lb
+                .choose_instance(criteria.clone(), instances.clone())
+                .unwrap();
+            assert_ne!("a", ins.id);
+        }
+        let candidates = lb
+            .choose_candidates(criteria.clone(), instances.clone(), 3)
+            .unwrap();
+        let ids: Vec<String> = candidates.into_iter().map(|ins| ins.id).collect();
+        assert_eq!(2, ids.len());
+        assert!(!ids.contains(&"a".to_string()));
+
+        criteria.exclude_instances.insert("b".to_string());
+        criteria.exclude_instances.insert("c".to_string());
+        assert!(lb.choose_instance(criteria, instances).is_err());
+    }
+}
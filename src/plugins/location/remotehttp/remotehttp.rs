@@ -13,24 +13,44 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
 use crate::core::{
     config::global::LocationProviderConfig,
     model::naming::Location,
     plugin::{location::LocationSupplier, plugins::Plugin},
 };
 
-use reqwest::blocking::Client;
-use crate::error;
+use crate::core::model::error::{ErrorCode, PolarisError};
+use crate::{error, info, warn};
+use reqwest::Client;
 
 static PLUGIN_NAME: &str = "remotehttp";
 
+static DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// 初始化时等待首次获取地域信息的最长时间，超时后继续在后台获取
+static DEFAULT_INIT_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct RemoteHttpLocationSupplier {
     _opt: LocationProviderConfig,
     access_url: Location,
+    // refresh_interval: 地域信息刷新间隔
+    refresh_interval: Duration,
+    // init_timeout: 初始化时等待首次获取地域信息的最长时间
+    init_timeout: Duration,
+    // loc_cache: 最近一次获取成功的地域信息
+    loc_cache: Arc<RwLock<Location>>,
+    runtime: Arc<Runtime>,
+    refresh_task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl RemoteHttpLocationSupplier {
-    pub fn new(opt: LocationProviderConfig) -> Self {
+    pub fn new(opt: LocationProviderConfig, runtime: Arc<Runtime>) -> Result<Self, PolarisError> {
         let copy_opt = opt.clone();
 
         let options = copy_opt.options;
@@ -50,37 +70,97 @@ impl RemoteHttpLocationSupplier {
             loc_ret.campus = campus.unwrap().to_string();
         }
 
-        Self {
+        let refresh_interval =
+            parse_duration(&options, "refreshInterval")?.unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        let init_timeout =
+            parse_duration(&options, "initTimeout")?.unwrap_or(DEFAULT_INIT_TIMEOUT);
+
+        Ok(Self {
             _opt: opt,
             access_url: loc_ret,
-        }
+            refresh_interval,
+            init_timeout,
+            loc_cache: Arc::new(RwLock::new(Location::default())),
+            runtime,
+            refresh_task: Mutex::new(None),
+        })
     }
 }
 
 impl Plugin for RemoteHttpLocationSupplier {
-    fn init(&mut self) {}
+    fn init(&mut self) {
+        let access_url = self.access_url.clone();
+        let loc_cache = self.loc_cache.clone();
+        let refresh_interval = self.refresh_interval;
+        let (first_tx, first_rx) = std::sync::mpsc::sync_channel::<()>(1);
+        let task = self.runtime.spawn(async move {
+            let client = Client::new();
+            let mut ticker = tokio::time::interval(refresh_interval);
+            loop {
+                ticker.tick().await;
+                let loc = RemoteHttpLocationSupplier::fetch_location(&client, &access_url).await;
+                // 获取失败时保留上一次的地域信息
+                if !loc.is_empty() {
+                    let mut cache = loc_cache.write().unwrap();
+                    if *cache != loc {
+                        info!(
+                            "[polaris][location][remotehttp] location changed: {:?} -> {:?}",
+                            *cache, loc
+                        );
+                        *cache = loc;
+                    }
+                }
+                // 首次获取完成并写入缓存后通知 init 返回，无论成功与否
+                let _ = first_tx.try_send(());
+            }
+        });
+        *self.refresh_task.lock().unwrap() = Some(task);
+        // 等待首次获取完成，避免初始化后立即路由时地域信息为空
+        if first_rx.recv_timeout(self.init_timeout).is_err() {
+            warn!(
+                "[polaris][location][remotehttp] fetch location not finished in {:?}, continue in background",
+                self.init_timeout
+            );
+        }
+    }
 
-    fn destroy(&self) {}
+    fn destroy(&self) {
+        if let Some(task) = self.refresh_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
 
     fn name(&self) -> String {
         PLUGIN_NAME.to_string()
     }
 }
 
+impl Drop for RemoteHttpLocationSupplier {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
 impl LocationSupplier for RemoteHttpLocationSupplier {
     fn get_location(&self) -> crate::core::model::naming::Location {
-        let access_url = &self.access_url;
-        let region = RemoteHttpLocationSupplier::get_http_response(
-            access_url.region.to_string(),
-            "region".to_string(),
-        );
-        let zone = RemoteHttpLocationSupplier::get_http_response(
-            access_url.zone.to_string(),
-            "zone".to_string(),
-        );
-        let campus = RemoteHttpLocationSupplier::get_http_response(
-            access_url.campus.to_string(),
-            "campus".to_string(),
+        self.loc_cache.read().unwrap().clone()
+    }
+}
+
+impl RemoteHttpLocationSupplier {
+    async fn fetch_location(client: &Client, access_url: &Location) -> Location {
+        let (region, zone, campus) = tokio::join!(
+            RemoteHttpLocationSupplier::get_http_response(
+                client,
+                access_url.region.as_str(),
+                "region"
+            ),
+            RemoteHttpLocationSupplier::get_http_response(client, access_url.zone.as_str(), "zone"),
+            RemoteHttpLocationSupplier::get_http_response(
+                client,
+                access_url.campus.as_str(),
+                "campus"
+            ),
         );
 
         if region.is_empty() && zone.is_empty() && campus.is_empty() {
@@ -93,17 +173,17 @@ impl LocationSupplier for RemoteHttpLocationSupplier {
             campus,
         }
     }
-}
 
-impl RemoteHttpLocationSupplier {
-    fn get_http_response(url: String, label: String) -> String {
-        let client = Client::new();
-        let response = client.get(url).send();
+    async fn get_http_response(client: &Client, url: &str, label: &str) -> String {
+        if url.is_empty() {
+            return "".to_string();
+        }
+        let response = client.get(url).send().await;
         match response {
             Ok(res) => {
-                let ret = res.text();
+                let ret = res.text().await;
                 match ret {
-                    Ok(body) => body,
+                    Ok(body) => body.trim().to_string(),
                     Err(e) => {
                         error!("get http response error: {}, label: {}", e, label);
                         "".to_string()
@@ -117,3 +197,81 @@ impl RemoteHttpLocationSupplier {
         }
     }
 }
+
+// parse_duration 解析时间类型的配置项，例如 10s、1m
+fn parse_duration(
+    options: &std::collections::HashMap<String, String>,
+    key: &str,
+) -> Result<Option<Duration>, PolarisError> {
+    match options.get(key) {
+        Some(val) => val
+            .parse::<serde_duration_ext::DurationUnit>()
+            .map(|unit| Some(unit.into()))
+            .map_err(|_| {
+                PolarisError::new(
+                    ErrorCode::InvalidConfig,
+                    format!("remotehttp location option {} is not a duration: {}", key, val),
+                )
+            }),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn build_conf(options: HashMap<String, String>) -> LocationProviderConfig {
+        LocationProviderConfig {
+            name: PLUGIN_NAME.to_string(),
+            options,
+        }
+    }
+
+    fn new_runtime() -> Arc<Runtime> {
+        Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_invalid_refresh_interval() {
+        let conf = build_conf(HashMap::from([(
+            "refreshInterval".to_string(),
+            "abc".to_string(),
+        )]));
+        let ret = RemoteHttpLocationSupplier::new(conf, new_runtime());
+        assert!(matches!(ret, Err(ref e) if e.get_code() == ErrorCode::InvalidConfig));
+    }
+
+    #[test]
+    fn test_location_ready_after_init() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\ngz",
+            );
+        });
+
+        let conf = build_conf(HashMap::from([
+            ("zone".to_string(), format!("http://{}/zone", addr)),
+            ("initTimeout".to_string(), "5s".to_string()),
+        ]));
+        let mut supplier = RemoteHttpLocationSupplier::new(conf, new_runtime()).unwrap();
+        supplier.init();
+        // init 返回时首次获取已经完成，地域信息不应为空
+        assert_eq!("gz", supplier.get_location().zone);
+    }
+}
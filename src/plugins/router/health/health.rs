@@ -28,15 +28,17 @@ use crate::core::{
     },
 };
 
-pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRouter> {
-    Box::new(HealthRouter {})
+pub fn new_service_router(
+    _conf: &ServiceRouterPluginConfig,
+) -> Result<Box<dyn ServiceRouter>, PolarisError> {
+    Ok(Box::new(HealthRouter {}))
 }
 
 pub struct HealthRouter {}
 
 impl HealthRouter {
     pub fn builder() -> (
-        fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
         String,
     ) {
         (new_service_router, DEFAULT_ROUTER_RECOVER.to_string())
@@ -86,6 +88,15 @@ impl ServiceRouter for HealthRouter {
             total_weight += ins.weight as u64;
         }
 
+        // 全部实例不可用时，走全死全活的兜底逻辑
+        let failover = if !instances.instances.is_empty()
+            && !instances.instances.iter().any(|ins| ins.is_available())
+        {
+            Some("all instances unavailable, recover all".to_string())
+        } else {
+            None
+        };
+
         // 重新算一次
         total_weight = 0 as u64;
         for instance in instances.instances {
@@ -104,6 +115,8 @@ impl ServiceRouter for HealthRouter {
                 total_weight: total_weight as u64,
             },
             state: RouteState::Next,
+            matched_rule: None,
+            failover,
         })
     }
 
@@ -18,7 +18,7 @@ use crate::core::{
     model::{
         error::PolarisError,
         naming::ServiceInstances,
-        router::{RouteResult, RouteState, DEFAULT_ROUTER_LANE},
+        router::{RouteResult, DEFAULT_ROUTER_LANE},
     },
     plugin::{
         plugins::Plugin,
@@ -26,15 +26,17 @@ use crate::core::{
     },
 };
 
-pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRouter> {
-    Box::new(LaneRouter {})
+pub fn new_service_router(
+    _conf: &ServiceRouterPluginConfig,
+) -> Result<Box<dyn ServiceRouter>, PolarisError> {
+    Ok(Box::new(LaneRouter {}))
 }
 
 pub struct LaneRouter {}
 
 impl LaneRouter {
     pub fn builder() -> (
-        fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
         String,
     ) {
         (new_service_router, DEFAULT_ROUTER_LANE.to_string())
@@ -59,10 +61,7 @@ impl ServiceRouter for LaneRouter {
         route_info: RouteContext,
         instances: ServiceInstances,
     ) -> Result<RouteResult, PolarisError> {
-        Ok(RouteResult {
-            instances,
-            state: RouteState::Next,
-        })
+        Ok(RouteResult::next(instances))
     }
 
     /// enable 是否启用
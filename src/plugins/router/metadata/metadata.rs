@@ -30,8 +30,8 @@ use crate::core::{
     },
 };
 
-pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRouter> {
-    Box::new(MetadataRouter {})
+pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError> {
+    Ok(Box::new(MetadataRouter {}))
 }
 
 static KEY_METADATA_FAILOVER: &str = "internal-metadata-failover-type";
@@ -49,7 +49,7 @@ pub struct MetadataRouter {}
 
 impl MetadataRouter {
     pub fn builder() -> (
-        fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
         String,
     ) {
         (new_service_router, DEFAULT_ROUTER_METADATA.to_string())
@@ -109,6 +109,8 @@ impl ServiceRouter for MetadataRouter {
                     total_weight: total_weight,
                 },
                 state: RouteState::Next,
+                matched_rule: None,
+                failover: None,
             });
         }
 
@@ -154,6 +156,8 @@ impl ServiceRouter for MetadataRouter {
                 total_weight: total_weight,
             },
             state: RouteState::Next,
+            matched_rule: None,
+            failover: Some(format!("metadata not match, failover {:?}", failover_type)),
         })
     }
 
@@ -16,5 +16,7 @@
 pub mod health;
 pub mod lane;
 pub mod metadata;
+pub mod namespace;
 pub mod nearby;
+pub mod priority;
 pub mod rule;
@@ -0,0 +1,244 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::HashMap;
+
+use crate::core::{
+    config::consumer::ServiceRouterPluginConfig,
+    model::{
+        cache::{EventType, ResourceEventKey},
+        error::{ErrorCode, PolarisError},
+        naming::{Instance, ServiceInstances},
+        router::{RouteResult, RouteState, DEFAULT_ROUTER_NAMESPACE},
+    },
+    plugin::{
+        cache::Filter,
+        plugins::Plugin,
+        router::{RouteContext, ServiceRouter},
+    },
+};
+
+static KEY_FAILOVER_NAMESPACE: &str = "failoverNamespace";
+
+pub fn new_service_router(
+    conf: &ServiceRouterPluginConfig,
+) -> Result<Box<dyn ServiceRouter>, PolarisError> {
+    // #描述: 被调服务所在命名空间下没有健康实例时，降级到该命名空间下的同名服务
+    // failoverNamespace: shared
+    let failover_namespace = conf
+        .options
+        .as_ref()
+        .and_then(|options| options.get(KEY_FAILOVER_NAMESPACE))
+        .cloned()
+        .unwrap_or_default();
+    Ok(Box::new(NamespaceRouter { failover_namespace }))
+}
+
+/// NamespaceRouter 命名空间路由，当被调服务在当前命名空间下没有健康实例时，降级到公共命名空间
+pub struct NamespaceRouter {
+    pub failover_namespace: String,
+}
+
+impl NamespaceRouter {
+    pub fn builder() -> (
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
+        String,
+    ) {
+        (new_service_router, DEFAULT_ROUTER_NAMESPACE.to_string())
+    }
+
+    async fn load_failover_instances(
+        &self,
+        route_ctx: &RouteContext,
+    ) -> Result<ServiceInstances, PolarisError> {
+        let extensions = route_ctx.extensions.clone().ok_or_else(|| {
+            PolarisError::new(
+                ErrorCode::InvalidState,
+                "namespace router requires extensions to load failover instances".to_string(),
+            )
+        })?;
+        let mut filter = HashMap::<String, String>::new();
+        filter.insert(
+            "service".to_string(),
+            route_ctx.route_info.callee.name.clone(),
+        );
+
+        let svc_ins = extensions
+            .get_resource_cache()
+            .load_service_instances(Filter {
+                resource_key: ResourceEventKey {
+                    namespace: self.failover_namespace.clone(),
+                    event_type: EventType::Instance,
+                    filter,
+                },
+                internal_request: false,
+                include_cache: true,
+                timeout: extensions.conf.global.api.timeout,
+            })
+            .await?;
+
+        Ok(ServiceInstances::new(
+            svc_ins.get_service_info(),
+            svc_ins.list_instances(true).await,
+        ))
+    }
+}
+
+impl Plugin for NamespaceRouter {
+    fn init(&mut self) {}
+
+    fn destroy(&self) {}
+
+    fn name(&self) -> String {
+        DEFAULT_ROUTER_NAMESPACE.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl ServiceRouter for NamespaceRouter {
+    /// choose_instances 实例路由
+    async fn choose_instances(
+        &self,
+        route_ctx: RouteContext,
+        instances: ServiceInstances,
+    ) -> Result<RouteResult, PolarisError> {
+        if self.failover_namespace.is_empty()
+            || route_ctx.route_info.callee.namespace == self.failover_namespace
+            || instances.instances.iter().any(Instance::is_available)
+        {
+            return Ok(RouteResult::next(instances));
+        }
+
+        match self.load_failover_instances(&route_ctx).await {
+            Ok(failover_instances) if !failover_instances.instances.is_empty() => {
+                crate::info!(
+                    "[polaris][router][namespace] no healthy instance in {}/{}, failover to namespace {}",
+                    route_ctx.route_info.callee.namespace,
+                    route_ctx.route_info.callee.name,
+                    self.failover_namespace
+                );
+                Ok(RouteResult {
+                    instances: failover_instances,
+                    state: RouteState::Next,
+                    matched_rule: None,
+                    failover: Some(format!("namespace {}", self.failover_namespace)),
+                })
+            }
+            Ok(_) => Ok(RouteResult::next(instances)),
+            Err(e) => {
+                crate::warn!(
+                    "[polaris][router][namespace] load instances from failover namespace {} fail: {}",
+                    self.failover_namespace,
+                    e
+                );
+                Ok(RouteResult::next(instances))
+            }
+        }
+    }
+
+    /// enable 是否启用
+    async fn enable(&self, _route_info: RouteContext, _instances: ServiceInstances) -> bool {
+        !self.failover_namespace.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::{ServiceInfo, ServiceKey};
+    use crate::core::model::router::RouteInfo;
+
+    fn new_instances(healthy: bool) -> ServiceInstances {
+        ServiceInstances {
+            service: ServiceInfo {
+                namespace: "default".to_string(),
+                name: "echo".to_string(),
+                ..Default::default()
+            },
+            instances: vec![Instance {
+                id: "ins-1".to_string(),
+                health: healthy,
+                weight: 100,
+                ..Default::default()
+            }],
+            total_weight: 100,
+        }
+    }
+
+    fn new_route_ctx(namespace: &str) -> RouteContext {
+        RouteContext {
+            route_info: RouteInfo {
+                callee: ServiceKey {
+                    namespace: namespace.to_string(),
+                    name: "echo".to_string(),
+                },
+                ..Default::default()
+            },
+            extensions: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_failover() {
+        let router = NamespaceRouter {
+            failover_namespace: "shared".to_string(),
+        };
+        // 存在健康实例时不降级
+        let ret = router
+            .choose_instances(new_route_ctx("default"), new_instances(true))
+            .await
+            .unwrap();
+        assert!(matches!(ret.state, RouteState::Next));
+        assert!(ret.failover.is_none());
+
+        // 被调服务本身就在降级命名空间下时不降级
+        let ret = router
+            .choose_instances(new_route_ctx("shared"), new_instances(false))
+            .await
+            .unwrap();
+        assert!(ret.failover.is_none());
+        assert_eq!(1, ret.instances.instances.len());
+
+        let router = NamespaceRouter {
+            failover_namespace: String::new(),
+        };
+        assert!(
+            !router
+                .enable(new_route_ctx("default"), new_instances(false))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failover_without_extensions() {
+        let router = NamespaceRouter {
+            failover_namespace: "shared".to_string(),
+        };
+        let err = router
+            .load_failover_instances(&new_route_ctx("default"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(ErrorCode::InvalidState, err.get_code());
+
+        // 加载降级实例失败时保留原有实例，不中断路由
+        let ret = router
+            .choose_instances(new_route_ctx("default"), new_instances(false))
+            .await
+            .unwrap();
+        assert!(ret.failover.is_none());
+        assert_eq!(1, ret.instances.instances.len());
+    }
+}
@@ -59,19 +59,21 @@ static ORDER_MATCH_LEVEL: Lazy<HashMap<u16, String>> = Lazy::new(|| {
     .collect()
 });
 
-pub fn new_service_router(conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRouter> {
+pub fn new_service_router(
+    conf: &ServiceRouterPluginConfig,
+) -> Result<Box<dyn ServiceRouter>, PolarisError> {
     if conf.options.is_none() {
         // 默认就近区域：默认城市 matchLevel: zone # 最大就近区域，默认为空（全匹配） maxMatchLevel: all #
         // 假如开启了严格就近，插件的初始化会等待地域信息获取成功才返回，假如获取失败（server获取失败或者IP地域信息缺失），则会初始化失败，而且必须按照 strictNearby: false #
         // 是否启用按服务不健康实例比例进行降级 enableDegradeByUnhealthyPercent: true，假如不启用，则不会降级#
         // 需要进行降级的实例比例，不健康实例达到百分之多少才进行降级。值(0, 100]。 # 默认100，即全部不健康才进行切换。
-        return Box::new(NearbyRouter {
+        return Ok(Box::new(NearbyRouter {
             strict_nearby: false,
             match_level: "zone".to_string(),
             max_match_level: "all".to_string(),
             enable_degrade_unhealthy_percent: false,
             unhealthy_percent_to_degrade: 100,
-        });
+        }));
     }
     // #描述: 就近路由的最小匹配级别。region(大区)、zone(区域)、campus(园区)
     // matchLevel: zone
@@ -86,25 +88,79 @@ pub fn new_service_router(conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRo
     // #描述: 是否通过上报方式获取地域信息
     // enableReportLocalAddress: false
     let options = conf.options.clone().unwrap();
-    Box::new(NearbyRouter {
-        strict_nearby: options
-            .get("strictNearby")
-            .unwrap()
-            .parse()
-            .expect("strictNearby must be a boolean"),
-        match_level: options.get("matchLevel").unwrap().to_string(),
-        max_match_level: options.get("maxMatchLevel").unwrap().to_string(),
-        enable_degrade_unhealthy_percent: options
-            .get("enableDegradeByUnhealthyPercent")
-            .unwrap()
-            .parse()
-            .expect("enableDegradeByUnhealthyPercent must be a boolean"),
-        unhealthy_percent_to_degrade: options
-            .get("unhealthyPercentToDegrade")
-            .unwrap()
-            .parse()
-            .expect("unhealthyPercentToDegrade must be a number, range [0, 100]"),
-    })
+    let unhealthy_percent_to_degrade = parse_option(
+        &options,
+        "unhealthyPercentToDegrade",
+        "a number, range [0, 100]",
+    )?
+    .unwrap_or(100);
+    if unhealthy_percent_to_degrade > 100 {
+        return Err(PolarisError::new(
+            ErrorCode::InvalidConfig,
+            format!(
+                "nearby router option unhealthyPercentToDegrade must be in range [0, 100], got {}",
+                unhealthy_percent_to_degrade
+            ),
+        ));
+    }
+    Ok(Box::new(NearbyRouter {
+        strict_nearby: parse_option(&options, "strictNearby", "a boolean")?.unwrap_or(false),
+        match_level: parse_match_level(&options, "matchLevel", DEFAULT_NEARBY_MATCH_LEVEL)?,
+        max_match_level: parse_match_level(
+            &options,
+            "maxMatchLevel",
+            DEFAULT_NEARBY_MAX_MATCH_LEVEL,
+        )?,
+        enable_degrade_unhealthy_percent: parse_option(
+            &options,
+            "enableDegradeByUnhealthyPercent",
+            "a boolean",
+        )?
+        .unwrap_or(false),
+        unhealthy_percent_to_degrade,
+    }))
+}
+
+// parse_match_level 解析就近级别配置项，只允许 MATCH_LEVEL 中的级别，否则路由时无法计算级别顺序
+fn parse_match_level(
+    options: &HashMap<String, String>,
+    key: &str,
+    default_level: &str,
+) -> Result<String, PolarisError> {
+    let level = match options.get(key) {
+        Some(val) if !val.trim().is_empty() => val.trim().to_string(),
+        _ => return Ok(default_level.to_string()),
+    };
+    if !MATCH_LEVEL.contains_key(&level) {
+        return Err(PolarisError::new(
+            ErrorCode::InvalidConfig,
+            format!(
+                "nearby router option {} must be one of unknown, campus, zone, region, all, got {}",
+                key, level
+            ),
+        ));
+    }
+    Ok(level)
+}
+
+// parse_option 解析插件配置项，配置值非法时返回 InvalidConfig 错误
+fn parse_option<T: std::str::FromStr>(
+    options: &HashMap<String, String>,
+    key: &str,
+    expect: &str,
+) -> Result<Option<T>, PolarisError> {
+    match options.get(key) {
+        Some(val) => val.trim().parse::<T>().map(Some).map_err(|_| {
+            PolarisError::new(
+                ErrorCode::InvalidConfig,
+                format!(
+                    "nearby router option {} must be {}, got {}",
+                    key, expect, val
+                ),
+            )
+        }),
+        None => Ok(None),
+    }
 }
 
 pub struct NearbyRouter {
@@ -117,7 +173,7 @@ pub struct NearbyRouter {
 
 impl NearbyRouter {
     pub fn builder() -> (
-        fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
         String,
     ) {
         (new_service_router, DEFAULT_ROUTER_NEARBY.to_string())
@@ -134,33 +190,20 @@ impl NearbyRouter {
         let mut total_weight: u64 = 0;
         let mut health_ins_cnt = 0 as u32;
         for (_, ins) in instances.instances.iter().enumerate() {
-            if ins.health {
-                health_ins_cnt += 1;
+            let matched = match match_level {
+                "campus" => local_loc.campus == "" || ins.location.campus == local_loc.campus,
+                "zone" => local_loc.zone == "" || ins.location.zone == local_loc.zone,
+                "region" => local_loc.region == "" || ins.location.region == local_loc.region,
+                _ => true,
+            };
+            if !matched {
+                continue;
             }
-            match match_level {
-                "campus" => {
-                    if local_loc.campus == "" || ins.location.campus == local_loc.campus {
-                        total_weight += ins.weight as u64;
-                        ret.push(ins.clone());
-                    }
-                }
-                "zone" => {
-                    if local_loc.zone == "" || ins.location.zone == local_loc.zone {
-                        total_weight += ins.weight as u64;
-                        ret.push(ins.clone());
-                    }
-                }
-                "region" => {
-                    if local_loc.region == "" || ins.location.region == local_loc.region {
-                        total_weight += ins.weight as u64;
-                        ret.push(ins.clone());
-                    }
-                }
-                _ => {
-                    total_weight += ins.weight as u64;
-                    ret.push(ins.clone());
-                }
+            if ins.is_available() {
+                health_ins_cnt += 1;
             }
+            total_weight += ins.weight as u64;
+            ret.push(ins.clone());
         }
 
         (
@@ -172,6 +215,15 @@ impl NearbyRouter {
             health_ins_cnt,
         )
     }
+
+    /// need_degrade 当前级别下不健康实例比例达到阈值时，需要降级到更大的范围
+    fn need_degrade(&self, total: u32, healthy: u32) -> bool {
+        if !self.enable_degrade_unhealthy_percent || total == 0 {
+            return false;
+        }
+        let unhealthy = total - healthy;
+        unhealthy * 100 >= self.unhealthy_percent_to_degrade as u32 * total
+    }
 }
 
 impl Plugin for NearbyRouter {
@@ -204,10 +256,22 @@ impl ServiceRouter for NearbyRouter {
         let locatin_provider = route_info
             .extensions
             .clone()
-            .unwrap()
+            .ok_or_else(|| {
+                PolarisError::new(
+                    ErrorCode::InvalidState,
+                    "nearby router requires extensions to get local location".to_string(),
+                )
+            })?
             .get_location_provider();
 
+        // 地域信息由 LocationProvider 异步刷新，每次路由时读取最新的值
         let location = locatin_provider.get_location();
+        if self.strict_nearby && location.is_empty() {
+            return Err(PolarisError::new(
+                ErrorCode::LocationMismatch,
+                "strict nearby is enabled, but local location is empty".to_string(),
+            ));
+        }
 
         if grater_match_level(min_available_level.as_str(), max_match_level.as_str()) {
             let (ret_ins, _health_cnt) =
@@ -215,30 +279,35 @@ impl ServiceRouter for NearbyRouter {
             if ret_ins.instances.is_empty() {
                 return Err(PolarisError::new(ErrorCode::LocationMismatch, format!("")));
             }
-            return Ok(RouteResult {
-                instances: ret_ins,
-                state: RouteState::Next,
-            });
+            return Ok(RouteResult::next(ret_ins));
         }
 
-        let min_level_ord = MATCH_LEVEL
-            .get(min_available_level.as_str())
-            .unwrap()
-            .clone();
-        let max_level_ord = MATCH_LEVEL.get(max_match_level.as_str()).unwrap().clone();
+        let min_level_ord = *MATCH_LEVEL.get(min_available_level.as_str()).unwrap();
+        let max_level_ord = *MATCH_LEVEL.get(max_match_level.as_str()).unwrap();
 
+        // 从最小匹配级别开始逐级扩大范围，直到找到满足健康比例要求的实例集合
         let mut cur_level = min_available_level.clone();
+        let mut matched_level = min_available_level.clone();
         let mut ret_ins: Option<ServiceInstances> = None;
         for i in min_level_ord..=max_level_ord {
             let cur_match_level = ORDER_MATCH_LEVEL.get(&i).unwrap();
             let (tmp_ins, health_cnt) =
                 self.select_instances(location.clone(), cur_match_level, &instances);
-            cur_level = cur_match_level.to_string().clone();
-            if !tmp_ins.instances.is_empty() {
-                ret_ins = Some(tmp_ins);
-            } else {
-                min_available_level = cur_match_level.to_string().clone();
+            cur_level = cur_match_level.to_string();
+            if tmp_ins.instances.is_empty() {
+                continue;
             }
+            let need_degrade = self.need_degrade(tmp_ins.instances.len() as u32, health_cnt);
+            ret_ins = Some(tmp_ins);
+            matched_level = cur_level.clone();
+            if !need_degrade {
+                break;
+            }
+            crate::debug!(
+                "[polaris][router][nearby] unhealthy percent of level {} reach {}%, degrade to next level",
+                cur_match_level,
+                self.unhealthy_percent_to_degrade
+            );
         }
 
         if ret_ins.is_none() {
@@ -248,20 +317,19 @@ impl ServiceRouter for NearbyRouter {
             ));
         }
 
-        // if !self.enable_degrade_unhealthy_percent
-        //     || cur_level == DEFAULT_NEARBY_MAX_MATCH_LEVEL.to_string()
-        // {
-        //     return Ok(RouteResult {
-        //         instances: ret_ins.unwrap(),
-        //         state: RouteState::Next,
-        //     });
-        // }
-
-        //TODO 需要做健康降级检查判断
-
+        let failover = if matched_level != min_available_level {
+            Some(format!(
+                "degrade from {} to {}",
+                min_available_level, matched_level
+            ))
+        } else {
+            None
+        };
         return Ok(RouteResult {
             instances: ret_ins.unwrap(),
             state: RouteState::Next,
+            matched_rule: None,
+            failover,
         });
     }
 
@@ -281,3 +349,117 @@ fn grater_match_level(a: &str, b: &str) -> bool {
     let b_level = MATCH_LEVEL.get(b).unwrap();
     a_level > b_level
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_instance(id: &str, zone: &str, health: bool) -> Instance {
+        Instance {
+            id: id.to_string(),
+            health,
+            weight: 100,
+            location: Location {
+                region: "south-china".to_string(),
+                zone: zone.to_string(),
+                campus: "".to_string(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn build_router(enable_degrade: bool, percent: u16) -> NearbyRouter {
+        NearbyRouter {
+            strict_nearby: false,
+            match_level: "zone".to_string(),
+            max_match_level: "all".to_string(),
+            enable_degrade_unhealthy_percent: enable_degrade,
+            unhealthy_percent_to_degrade: percent,
+        }
+    }
+
+    #[test]
+    fn test_select_instances_count_matched_health() {
+        let router = build_router(true, 50);
+        let instances = ServiceInstances::new(
+            Default::default(),
+            vec![
+                build_instance("1", "gz", true),
+                build_instance("2", "gz", false),
+                build_instance("3", "sz", true),
+            ],
+        );
+        let local = Location {
+            region: "south-china".to_string(),
+            zone: "gz".to_string(),
+            campus: "".to_string(),
+        };
+
+        let (ret, health_cnt) = router.select_instances(local.clone(), "zone", &instances);
+        assert_eq!(2, ret.instances.len());
+        assert_eq!(1, health_cnt);
+
+        let (ret, health_cnt) = router.select_instances(local, "region", &instances);
+        assert_eq!(3, ret.instances.len());
+        assert_eq!(2, health_cnt);
+    }
+
+    #[test]
+    fn test_need_degrade() {
+        // 未开启降级时，不论健康比例如何都不降级
+        assert!(!build_router(false, 50).need_degrade(4, 0));
+        // 不健康比例达到 50% 时降级
+        assert!(build_router(true, 50).need_degrade(4, 2));
+        assert!(!build_router(true, 50).need_degrade(4, 3));
+        // 默认 100%，只有全部不健康时才降级
+        assert!(build_router(true, 100).need_degrade(4, 0));
+        assert!(!build_router(true, 100).need_degrade(4, 1));
+    }
+
+    fn build_conf(key: &str, val: &str) -> ServiceRouterPluginConfig {
+        ServiceRouterPluginConfig {
+            name: "nearbyBasedRouter".to_string(),
+            options: Some(HashMap::from([(key.to_string(), val.to_string())])),
+        }
+    }
+
+    #[test]
+    fn test_new_service_router_invalid_option() {
+        for (key, val) in [
+            ("strictNearby", "abc"),
+            ("enableDegradeByUnhealthyPercent", "yes"),
+            ("unhealthyPercentToDegrade", "x"),
+            ("unhealthyPercentToDegrade", "101"),
+            ("matchLevel", "city"),
+            ("maxMatchLevel", "Zone"),
+        ] {
+            let ret = new_service_router(&build_conf(key, val));
+            assert!(
+                matches!(ret, Err(ref e) if e.get_code() == ErrorCode::InvalidConfig),
+                "{}: {} should be rejected",
+                key,
+                val
+            );
+        }
+        assert!(new_service_router(&build_conf("strictNearby", "true")).is_ok());
+        assert!(new_service_router(&build_conf("matchLevel", "region")).is_ok());
+        assert!(new_service_router(&build_conf("maxMatchLevel", "")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_choose_instances_without_extensions() {
+        let router = build_router(false, 100);
+        let route_ctx = RouteContext {
+            route_info: Default::default(),
+            extensions: None,
+        };
+        let instances =
+            ServiceInstances::new(Default::default(), vec![build_instance("1", "gz", true)]);
+        let err = router
+            .choose_instances(route_ctx, instances)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(ErrorCode::InvalidState, err.get_code());
+    }
+}
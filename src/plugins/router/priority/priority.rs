@@ -0,0 +1,212 @@
+// Tencent is pleased to support the open source community by making Polaris available.
+//
+// Copyright (C) 2019 THL A29 Limited, a Tencent company. All rights reserved.
+//
+// Licensed under the BSD 3-Clause License (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://opensource.org/licenses/BSD-3-Clause
+//
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::BTreeMap;
+
+use crate::core::{
+    config::consumer::ServiceRouterPluginConfig,
+    model::{
+        error::PolarisError,
+        naming::{Instance, ServiceInstances},
+        router::{RouteResult, RouteState, DEFAULT_ROUTER_PRIORITY},
+    },
+    plugin::{
+        plugins::Plugin,
+        router::{RouteContext, ServiceRouter},
+    },
+};
+
+static KEY_SPILL_THRESHOLD: &str = "spillThreshold";
+
+// DEFAULT_SPILL_THRESHOLD 默认的溢出阈值，与 Envoy 默认的 1.4 超额配置系数接近
+const DEFAULT_SPILL_THRESHOLD: u32 = 70;
+
+pub fn new_service_router(
+    conf: &ServiceRouterPluginConfig,
+) -> Result<Box<dyn ServiceRouter>, PolarisError> {
+    // #描述: 优先使用 priority 值最小的一组实例，该组健康实例的权重占比低于该百分比时，溢出到下一组
+    // spillThreshold: 70
+    let spill_threshold = conf
+        .options
+        .as_ref()
+        .and_then(|options| options.get(KEY_SPILL_THRESHOLD))
+        .and_then(|v| v.parse::<u32>().ok())
+        .map(|v| v.min(100))
+        .unwrap_or(DEFAULT_SPILL_THRESHOLD);
+    Ok(Box::new(PriorityRouter { spill_threshold }))
+}
+
+/// PriorityRouter 优先级路由，按照实例的 priority 从小到大分组，优先选择第一组的健康实例，
+/// 当已选择分组的健康容量累计不足 spill_threshold 时，继续加入下一组的健康实例
+pub struct PriorityRouter {
+    pub spill_threshold: u32,
+}
+
+impl PriorityRouter {
+    pub fn builder() -> (
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
+        String,
+    ) {
+        (new_service_router, DEFAULT_ROUTER_PRIORITY.to_string())
+    }
+
+    // select_tiers 返回选中的实例以及最后一个被选中分组的 priority
+    fn select_tiers(&self, instances: &[Instance]) -> (Vec<Instance>, Option<u32>) {
+        let mut tiers = BTreeMap::<u32, Vec<&Instance>>::new();
+        for ins in instances.iter() {
+            tiers.entry(ins.priority).or_default().push(ins);
+        }
+
+        let mut selected = Vec::<Instance>::new();
+        let mut last_priority = None;
+        let mut capacity: u64 = 0;
+        for (priority, tier) in tiers.iter() {
+            let total_weight: u64 = tier.iter().map(|ins| ins.weight as u64).sum();
+            let healthy: Vec<&&Instance> = tier.iter().filter(|ins| ins.is_available()).collect();
+            if healthy.is_empty() || total_weight == 0 {
+                continue;
+            }
+            let healthy_weight: u64 = healthy.iter().map(|ins| ins.weight as u64).sum();
+            selected.extend(healthy.into_iter().map(|ins| (*ins).clone()));
+            last_priority = Some(*priority);
+
+            capacity += healthy_weight * 100 / total_weight;
+            if capacity >= self.spill_threshold as u64 {
+                break;
+            }
+        }
+        (selected, last_priority)
+    }
+}
+
+impl Plugin for PriorityRouter {
+    fn init(&mut self) {}
+
+    fn destroy(&self) {}
+
+    fn name(&self) -> String {
+        DEFAULT_ROUTER_PRIORITY.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl ServiceRouter for PriorityRouter {
+    /// choose_instances 实例路由
+    async fn choose_instances(
+        &self,
+        _route_ctx: RouteContext,
+        instances: ServiceInstances,
+    ) -> Result<RouteResult, PolarisError> {
+        let first_priority = match instances.instances.iter().map(|ins| ins.priority).min() {
+            Some(priority) => priority,
+            None => return Ok(RouteResult::next(instances)),
+        };
+        if instances
+            .instances
+            .iter()
+            .all(|ins| ins.priority == first_priority)
+        {
+            return Ok(RouteResult::next(instances));
+        }
+
+        let (selected, last_priority) = self.select_tiers(&instances.instances);
+        // 没有任何健康实例时，交给后续的兜底路由处理
+        let last_priority = match last_priority {
+            Some(priority) => priority,
+            None => return Ok(RouteResult::next(instances)),
+        };
+
+        let failover = if last_priority != first_priority {
+            crate::debug!(
+                "[polaris][router][priority] {}/{} spill from priority {} to {}",
+                instances.service.namespace,
+                instances.service.name,
+                first_priority,
+                last_priority
+            );
+            Some(format!(
+                "spill from priority {} to {}",
+                first_priority, last_priority
+            ))
+        } else {
+            None
+        };
+
+        Ok(RouteResult {
+            instances: ServiceInstances::new(instances.service, selected),
+            state: RouteState::Next,
+            matched_rule: None,
+            failover,
+        })
+    }
+
+    /// enable 是否启用
+    async fn enable(&self, _route_info: RouteContext, _instances: ServiceInstances) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_instance(id: &str, priority: u32, healthy: bool) -> Instance {
+        Instance {
+            id: id.to_string(),
+            weight: 100,
+            priority,
+            health: healthy,
+            ..Default::default()
+        }
+    }
+
+    fn ids(instances: &[Instance]) -> Vec<&str> {
+        instances.iter().map(|ins| ins.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_select_tiers() {
+        let router = PriorityRouter {
+            spill_threshold: DEFAULT_SPILL_THRESHOLD,
+        };
+
+        // 第一组健康容量充足，只使用第一组
+        let instances = vec![
+            build_instance("a", 0, true),
+            build_instance("b", 0, true),
+            build_instance("c", 1, true),
+        ];
+        let (selected, last) = router.select_tiers(&instances);
+        assert_eq!(vec!["a", "b"], ids(&selected));
+        assert_eq!(Some(0), last);
+
+        // 第一组只剩一半健康实例，溢出到第二组
+        let instances = vec![
+            build_instance("a", 0, true),
+            build_instance("b", 0, false),
+            build_instance("c", 1, true),
+            build_instance("d", 2, true),
+        ];
+        let (selected, last) = router.select_tiers(&instances);
+        assert_eq!(vec!["a", "c"], ids(&selected));
+        assert_eq!(Some(1), last);
+
+        // 全部不健康
+        let instances = vec![build_instance("a", 0, false), build_instance("b", 1, false)];
+        let (selected, last) = router.select_tiers(&instances);
+        assert!(selected.is_empty());
+        assert_eq!(None, last);
+    }
+}
@@ -27,8 +27,8 @@ pub fn route_traffic_match(ctx: &RouteContext, rule: &Route) -> bool {
         return false;
     }
 
-    let traffic_provider = ctx.route_info.traffic_label_provider;
-    let ext_provider = ctx.route_info.external_parameter_supplier;
+    let traffic_provider = &ctx.route_info.traffic_label_provider;
+    let ext_provider = &ctx.route_info.external_parameter_supplier;
 
     // 匹配流量标签
     for (_, ele) in rule.sources.iter().enumerate() {
@@ -49,7 +49,9 @@ pub fn route_traffic_match(ctx: &RouteContext, rule: &Route) -> bool {
                         }
                         traffic_type = ArgumentType::parse_from_str(label_prefix);
                     }
-                    actual_val = traffic_provider(traffic_type, match_key).unwrap_or(String::new());
+                    actual_val = traffic_provider
+                        .get_label(traffic_type, match_key)
+                        .unwrap_or_default();
                 }
                 // 匹配参数，那就直接从 traffic_labels 中获取
                 ValueType::Parameter => continue,
@@ -60,7 +62,7 @@ pub fn route_traffic_match(ctx: &RouteContext, rule: &Route) -> bool {
                         if err != VarError::NotPresent {
                             return false;
                         }
-                        match ext_provider(match_key) {
+                        match ext_provider.get_parameter(match_key) {
                             Some(v) => actual_val = v,
                             None => return false,
                         }
@@ -68,7 +70,7 @@ pub fn route_traffic_match(ctx: &RouteContext, rule: &Route) -> bool {
                 },
             }
 
-            if match_label_value(rule_value, actual_val) {
+            if !match_label_value(rule_value, actual_val) {
                 matched = false;
                 break;
             }
@@ -96,9 +98,17 @@ pub fn match_label_value(rule_value: &MatchString, actual_val: String) -> bool {
             return match_value != actual_val;
         }
         polaris_specification::v1::match_string::MatchStringType::Regex => {
-            return regex::Regex::new(&match_value)
-                .unwrap()
-                .is_match(&actual_val);
+            return match regex::Regex::new(&match_value) {
+                Ok(re) => re.is_match(&actual_val),
+                Err(e) => {
+                    crate::error!(
+                        "[polaris][router][rule] invalid regex {} in route rule: {}",
+                        match_value,
+                        e
+                    );
+                    false
+                }
+            };
         }
         polaris_specification::v1::match_string::MatchStringType::In => {
             return match_value.split(',').any(|x| x == actual_val);
@@ -111,10 +121,31 @@ pub fn match_label_value(rule_value: &MatchString, actual_val: String) -> bool {
             if parts.len() != 2 {
                 return false;
             }
-            let min = parts[0].parse::<i64>().unwrap();
-            let max = parts[1].parse::<i64>().unwrap();
-            let actual_val = actual_val.parse::<i64>().unwrap();
-            return actual_val >= min && actual_val <= max;
+            let (min, max) = match (
+                parts[0].trim().parse::<i64>(),
+                parts[1].trim().parse::<i64>(),
+            ) {
+                (Ok(min), Ok(max)) => (min, max),
+                _ => {
+                    crate::error!(
+                        "[polaris][router][rule] invalid range {} in route rule",
+                        match_value
+                    );
+                    return false;
+                }
+            };
+            // 请求中的标签值不是数字时视为不匹配
+            return match actual_val.trim().parse::<i64>() {
+                Ok(val) => val >= min && val <= max,
+                Err(_) => {
+                    crate::debug!(
+                        "[polaris][router][rule] label value {} is not a number, range {} not match",
+                        actual_val,
+                        match_value
+                    );
+                    false
+                }
+            };
         }
     }
 }
@@ -152,3 +183,76 @@ pub fn match_callee_caller(rctx: &RouteContext, rule: &Route) -> bool {
 pub fn is_match_all(s: &str) -> bool {
     s == WILDCARD
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use polaris_specification::v1::{match_string::MatchStringType, Source};
+
+    use super::*;
+    use crate::core::model::router::RouteInfo;
+
+    fn new_route_ctx(lane: &str) -> RouteContext {
+        let lane = lane.to_string();
+        RouteContext {
+            route_info: RouteInfo {
+                traffic_label_provider: Arc::new(move |_: ArgumentType, key: &str| {
+                    if key == "lane" {
+                        Some(lane.clone())
+                    } else {
+                        None
+                    }
+                }),
+                ..Default::default()
+            },
+            extensions: None,
+        }
+    }
+
+    fn new_match_string(match_type: MatchStringType, value: &str) -> MatchString {
+        MatchString {
+            r#type: match_type as i32,
+            value: Some(value.to_string()),
+            value_type: ValueType::Text as i32,
+        }
+    }
+
+    fn new_route(key: &str, value: MatchString) -> Route {
+        Route {
+            sources: vec![Source {
+                service: Some(WILDCARD.to_string()),
+                namespace: Some(WILDCARD.to_string()),
+                metadata: HashMap::from([(key.to_string(), value)]),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_route_traffic_match_label() {
+        let rule = new_route("lane", new_match_string(MatchStringType::Exact, "gray"));
+        // 标签值与规则一致时命中
+        assert!(route_traffic_match(&new_route_ctx("gray"), &rule));
+        // 标签值与规则不一致时不命中
+        assert!(!route_traffic_match(&new_route_ctx("base"), &rule));
+    }
+
+    #[test]
+    fn test_match_label_value_invalid_rule() {
+        // 非法的正则表达式不会 panic，直接视为不匹配
+        let rule = new_match_string(MatchStringType::Regex, "gr(ay");
+        assert!(!match_label_value(&rule, "gray".to_string()));
+        let rule = new_match_string(MatchStringType::Regex, "^gr.*$");
+        assert!(match_label_value(&rule, "gray".to_string()));
+
+        let rule = new_match_string(MatchStringType::Range, "10,20");
+        assert!(match_label_value(&rule, "15".to_string()));
+        assert!(!match_label_value(&rule, "21".to_string()));
+        // 请求中的标签值或者规则中的范围不是数字时视为不匹配
+        assert!(!match_label_value(&rule, "abc".to_string()));
+        let rule = new_match_string(MatchStringType::Range, "a,20");
+        assert!(!match_label_value(&rule, "15".to_string()));
+    }
+}
@@ -34,6 +34,9 @@ use crate::core::{
 use crate::warn;
 use super::helper::route_traffic_match;
 
+// KEY_ROUTE_RULE_ID 服务端下发 v1 格式路由时，在 extend_info 中携带的 v2 规则 ID
+static KEY_ROUTE_RULE_ID: &str = "__routing_v2_id__";
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Direction {
     Callee,
@@ -60,7 +63,7 @@ enum RuleStatus {
     SourceRuleFail,
 }
 
-pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceRouter> {
+pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError> {
     let mut policy = RouteFailoverPolicy::All;
     if let Some(opt) = _conf.options.clone() {
         let val = opt.get("failover");
@@ -70,9 +73,9 @@ pub fn new_service_router(_conf: &ServiceRouterPluginConfig) -> Box<dyn ServiceR
             }
         }
     }
-    Box::new(RuleRouter {
+    Ok(Box::new(RuleRouter {
         failover_policy: policy,
-    })
+    }))
 }
 
 pub struct RuleRouter {
@@ -81,7 +84,7 @@ pub struct RuleRouter {
 
 impl RuleRouter {
     pub fn builder() -> (
-        fn(&ServiceRouterPluginConfig) -> Box<dyn ServiceRouter>,
+        fn(&ServiceRouterPluginConfig) -> Result<Box<dyn ServiceRouter>, PolarisError>,
         String,
     ) {
         (new_service_router, DEFAULT_ROUTER_RULE.to_string())
@@ -155,16 +158,22 @@ impl RuleRouter {
         return Ok(rule.outbounds);
     }
 
+    /// filter_instances 按照路由规则筛选实例，返回命中的规则标识以及筛选后的实例
     fn filter_instances(
         &self,
         rctx: &RouteContext,
         instances: &ServiceInstances,
         rules: Vec<Route>,
-    ) -> Result<Vec<Instance>, PolarisError> {
-        for ele in rules {
+        dir: &Direction,
+    ) -> Result<(Option<String>, Vec<Instance>), PolarisError> {
+        for (index, ele) in rules.into_iter().enumerate() {
             if !route_traffic_match(rctx, &ele) {
                 continue;
             }
+            let rule_id = match ele.extend_info.get(KEY_ROUTE_RULE_ID) {
+                Some(id) => id.clone(),
+                None => format!("{:?}[{}]", dir, index),
+            };
             // 匹配实例分组
 
             let destination = filter_available_destinations(ele.destinations);
@@ -174,12 +183,69 @@ impl RuleRouter {
                     continue;
                 }
                 // 返回目标实例分组结果
-                return Ok(ret);
+                return Ok((Some(rule_id), ret));
             }
             // 没有符合的实例分组，需要看下兜底逻辑
         }
         // 返回空实例列表
-        Ok(vec![])
+        Ok((None, vec![]))
+    }
+
+    /// build_route_result 根据规则匹配状态构建路由结果，匹配失败时按照 failover 策略兜底
+    fn build_route_result(
+        &self,
+        route_ctx: &RouteContext,
+        status: RuleStatus,
+        instances: ServiceInstances,
+        filtered_ins: Option<Vec<Instance>>,
+        matched_rule: Option<String>,
+    ) -> RouteResult {
+        match status {
+            RuleStatus::NoRule => RouteResult::next(instances),
+            RuleStatus::DestRuleSucc | RuleStatus::SourceRuleSucc => {
+                let mut total_weight = 0 as u64;
+                let filtered_ins = filtered_ins.unwrap();
+                for ele in filtered_ins.iter() {
+                    total_weight += ele.weight as u64;
+                }
+                RouteResult {
+                    instances: ServiceInstances {
+                        service: instances.service.clone(),
+                        instances: filtered_ins,
+                        total_weight: total_weight,
+                    },
+                    state: RouteState::Next,
+                    matched_rule,
+                    failover: None,
+                }
+            }
+            _ => {
+                warn!(
+                    "[router][rule] route rule not match, rule status: {:?}, not matched callee:{:?} caller:{:?}",
+                    status,
+                    route_ctx.route_info.caller,
+                    route_ctx.route_info.callee,
+                );
+                match self.failover_policy {
+                    RouteFailoverPolicy::All => RouteResult {
+                        instances,
+                        state: RouteState::Next,
+                        matched_rule: None,
+                        failover: Some(format!("{:?}, failover all", status)),
+                    },
+                    RouteFailoverPolicy::None => RouteResult {
+                        instances: ServiceInstances {
+                            service: instances.service.clone(),
+                            instances: vec![],
+                            total_weight: 0,
+                        },
+                        state: RouteState::Next,
+                        matched_rule: None,
+                        failover: Some(format!("{:?}, failover none", status)),
+                    },
+                }
+            }
+        }
     }
 }
 
@@ -195,6 +261,7 @@ impl ServiceRouter for RuleRouter {
 
         // 匹配顺序 -> 先按照被调方路由规则匹配，然后再按照主调方规则进行匹配
         let mut filtered_ins = Option::<Vec<Instance>>::None;
+        let mut matched_rule = Option::<String>::None;
 
         let mut status = RuleStatus::NoRule;
         let callee_rules = self
@@ -202,11 +269,13 @@ impl ServiceRouter for RuleRouter {
             .await?;
         if !callee_rules.is_empty() {
             status = RuleStatus::DestRuleSucc;
-            let ret = self.filter_instances(&route_ctx, &instances, callee_rules)?;
+            let (rule_id, ret) =
+                self.filter_instances(&route_ctx, &instances, callee_rules, &Direction::Callee)?;
             if ret.is_empty() {
                 status = RuleStatus::DestRuleFail;
             } else {
                 filtered_ins = Some(ret);
+                matched_rule = rule_id;
             }
         }
 
@@ -217,59 +286,22 @@ impl ServiceRouter for RuleRouter {
                 .await?;
             if !caller_rules.is_empty() {
                 status = RuleStatus::SourceRuleSucc;
-                let ret = self.filter_instances(&route_ctx, &instances, caller_rules)?;
+                let (rule_id, ret) = self.filter_instances(
+                    &route_ctx,
+                    &instances,
+                    caller_rules,
+                    &Direction::Caller,
+                )?;
                 if ret.is_empty() {
                     status = RuleStatus::SourceRuleFail;
                 } else {
                     filtered_ins = Some(ret);
+                    matched_rule = rule_id;
                 }
             }
         }
 
-        match status {
-            RuleStatus::NoRule => Ok(RouteResult {
-                instances,
-                state: RouteState::Next,
-            }),
-            RuleStatus::DestRuleSucc | RuleStatus::SourceRuleSucc => {
-                let mut total_weight = 0 as u64;
-                let filtered_ins = filtered_ins.unwrap();
-                let ins = Vec::<Instance>::with_capacity(filtered_ins.capacity());
-                for ele in filtered_ins {
-                    total_weight += ele.weight as u64;
-                }
-                Ok(RouteResult {
-                    instances: ServiceInstances {
-                        service: instances.service.clone(),
-                        instances: ins,
-                        total_weight: total_weight,
-                    },
-                    state: RouteState::Next,
-                })
-            }
-            _ => {
-                warn!(
-                    "[router][rule] route rule not match, rule status: {:?}, not matched callee:{:?} caller:{:?}",
-                    status,
-                    route_ctx.route_info.caller,
-                    route_ctx.route_info.callee,
-                );
-                match self.failover_policy {
-                    RouteFailoverPolicy::All => Ok(RouteResult {
-                        instances,
-                        state: RouteState::Next,
-                    }),
-                    RouteFailoverPolicy::None => Ok(RouteResult {
-                        instances: ServiceInstances {
-                            service: instances.service.clone(),
-                            instances: vec![],
-                            total_weight: 0,
-                        },
-                        state: RouteState::Next,
-                    }),
-                }
-            }
-        }
+        Ok(self.build_route_result(&route_ctx, status, instances, filtered_ins, matched_rule))
     }
 
     /// enable 是否启用
@@ -332,3 +364,85 @@ fn filter_available_destinations(dests: Vec<Destination>) -> Vec<Destination> {
 
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::naming::ServiceInfo;
+    use crate::core::model::router::RouteInfo;
+
+    fn new_instance(id: &str, weight: u32) -> Instance {
+        Instance {
+            id: id.to_string(),
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn new_route_ctx() -> RouteContext {
+        RouteContext {
+            route_info: RouteInfo::default(),
+            extensions: None,
+        }
+    }
+
+    fn new_instances() -> ServiceInstances {
+        ServiceInstances::new(
+            ServiceInfo::default(),
+            vec![
+                new_instance("1", 100),
+                new_instance("2", 50),
+                new_instance("3", 10),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_build_route_result_matched() {
+        let router = RuleRouter {
+            failover_policy: RouteFailoverPolicy::All,
+        };
+        // 规则命中时返回命中的实例分组，而不是空列表
+        let ret = router.build_route_result(
+            &new_route_ctx(),
+            RuleStatus::DestRuleSucc,
+            new_instances(),
+            Some(vec![new_instance("1", 100), new_instance("2", 50)]),
+            Some("rule-1".to_string()),
+        );
+        let ids: Vec<&str> = ret.instances.instances.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(vec!["1", "2"], ids);
+        assert_eq!(150, ret.instances.total_weight);
+        assert_eq!(Some("rule-1".to_string()), ret.matched_rule);
+        assert_eq!(None, ret.failover);
+    }
+
+    #[test]
+    fn test_build_route_result_failover() {
+        let router = RuleRouter {
+            failover_policy: RouteFailoverPolicy::All,
+        };
+        let ret = router.build_route_result(
+            &new_route_ctx(),
+            RuleStatus::DestRuleFail,
+            new_instances(),
+            None,
+            None,
+        );
+        assert_eq!(3, ret.instances.instances.len());
+        assert!(ret.failover.is_some());
+
+        let router = RuleRouter {
+            failover_policy: RouteFailoverPolicy::None,
+        };
+        let ret = router.build_route_result(
+            &new_route_ctx(),
+            RuleStatus::SourceRuleFail,
+            new_instances(),
+            None,
+            None,
+        );
+        assert!(ret.instances.instances.is_empty());
+        assert!(ret.failover.is_some());
+    }
+}
@@ -14,6 +14,7 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::{context::SDKContext, model::error::PolarisError};
 
@@ -45,4 +46,7 @@ where
     Self: Send + Sync,
 {
     async fn get_quota(&self, req: QuotaRequest) -> Result<QuotaResponse, PolarisError>;
+
+    /// shutdown 关闭 RateLimitAPI 实例，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
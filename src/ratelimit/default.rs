@@ -14,6 +14,7 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::{context::SDKContext, flow::RatelimitFlow, model::error::PolarisError};
 
@@ -76,4 +77,11 @@ impl RateLimitAPI for DefaultRateLimitAPI {
 
         todo!()
     }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        if !self.manage_sdk {
+            return Ok(());
+        }
+        self.context.shutdown(timeout).await
+    }
 }
@@ -13,12 +13,17 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::fmt::{self, Debug};
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::core::model::{error::{ErrorCode, PolarisError}, ArgumentType};
+use crate::core::model::{
+    error::{ErrorCode, PolarisError},
+    label::{ExternalParameterSupplier, TrafficLabelProvider},
+};
 
 /// QuotaRequest 获取请求配额
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct QuotaRequest {
     pub flow_id: String,
     pub timeout: Duration,
@@ -29,9 +34,21 @@ pub struct QuotaRequest {
     // method 方法名
     pub method: String,
     // traffic_label_provider 流量标签提供者
-    pub traffic_label_provider: fn(ArgumentType, &str) -> Option<String>,
+    pub traffic_label_provider: Arc<dyn TrafficLabelProvider>,
     // 北极星内部治理规则执行时，会识别规则中的参数来源类别，如果发现规则中的参数来源指定为外部数据源时，会调用本接口进行获取
-    pub external_parameter_supplier: fn(&str) -> Option<String>,
+    pub external_parameter_supplier: Arc<dyn ExternalParameterSupplier>,
+}
+
+impl Debug for QuotaRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuotaRequest")
+            .field("flow_id", &self.flow_id)
+            .field("timeout", &self.timeout)
+            .field("service", &self.service)
+            .field("namespace", &self.namespace)
+            .field("method", &self.method)
+            .finish()
+    }
 }
 
 impl QuotaRequest {
@@ -14,10 +14,15 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::{
-    core::{context::SDKContext, model::error::PolarisError},
+    core::{
+        context::SDKContext,
+        model::{error::PolarisError, loadbalance::InstanceWeight, naming::ServiceInstances},
+    },
     router::req::{
+        ProcessLoadBalanceCandidatesRequest, ProcessLoadBalanceCandidatesResponse,
         ProcessLoadBalanceRequest, ProcessLoadBalanceResponse, ProcessRouteRequest,
         ProcessRouteResponse,
     },
@@ -53,4 +58,16 @@ where
         &self,
         req: ProcessLoadBalanceRequest,
     ) -> Result<ProcessLoadBalanceResponse, PolarisError>;
+
+    // load_balance_candidates 执行负载均衡并按照顺序返回多个不同的实例，首选实例之后的实例用于重试
+    async fn load_balance_candidates(
+        &self,
+        req: ProcessLoadBalanceCandidatesRequest,
+    ) -> Result<ProcessLoadBalanceCandidatesResponse, PolarisError>;
+
+    // get_instance_weights 获取实例的动态权重计算结果，用于排查流量分布
+    async fn get_instance_weights(&self, instances: ServiceInstances) -> Vec<InstanceWeight>;
+
+    // shutdown 关闭 RouterAPI 实例，管理 SDKContext 生命周期时同时关闭 SDKContext
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError>;
 }
@@ -14,13 +14,20 @@
 // specific language governing permissions and limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::{
     context::SDKContext,
     flow::RouterFlow,
-    model::error::{ErrorCode, PolarisError},
+    model::{
+        error::{ErrorCode, PolarisError},
+        loadbalance::{Criteria, InstanceWeight},
+        naming::ServiceInstances,
+    },
+    plugin::loadbalance::LoadBalancer,
 };
 use crate::debug;
+use crate::discovery::req::ServiceCallResult;
 use super::{api::RouterAPI, req::ProcessRouteResponse};
 
 pub struct DefaultRouterAPI {
@@ -48,6 +55,36 @@ impl DefaultRouterAPI {
             flow: Arc::new(RouterFlow::new(extensions)),
         }
     }
+
+    // get_loadbalancer 查找负载均衡器，criteria 未指定负载均衡策略时使用默认策略
+    async fn get_loadbalancer(
+        &self,
+        criteria: &Criteria,
+    ) -> Result<Arc<Box<dyn LoadBalancer>>, PolarisError> {
+        let mut lb_policy = criteria.policy.clone();
+        if lb_policy.is_empty() {
+            lb_policy.clone_from(&self.context.conf.consumer.load_balancer.default_policy);
+        }
+
+        match self.flow.lookup_loadbalancer(&lb_policy).await {
+            Some(lb) => Ok(lb),
+            None => {
+                crate::error!(
+                    "[polaris][router_api] load balancer {} not found",
+                    lb_policy
+                );
+                Err(PolarisError::new(
+                    ErrorCode::PluginError,
+                    format!("load balancer {} not found", lb_policy),
+                ))
+            }
+        }
+    }
+
+    /// report_call_result 上报实例调用结果
+    pub(crate) async fn report_call_result(&self, result: &ServiceCallResult) {
+        self.flow.report_call_result(result).await;
+    }
 }
 
 impl Drop for DefaultRouterAPI {
@@ -79,12 +116,18 @@ impl RouterAPI for DefaultRouterAPI {
 
         let ret = self
             .flow
-            .choose_instances(req.route_info.clone(), req.service_instances)
+            .choose_instances(
+                req.route_info.clone(),
+                req.service_instances,
+                req.chain_override,
+                req.trace,
+            )
             .await;
 
         match ret {
-            Ok(result) => Ok(ProcessRouteResponse {
+            Ok((result, traces)) => Ok(ProcessRouteResponse {
                 service_instances: result,
+                traces,
             }),
             Err(e) => Err(e),
         }
@@ -96,32 +139,41 @@ impl RouterAPI for DefaultRouterAPI {
     ) -> Result<super::req::ProcessLoadBalanceResponse, PolarisError> {
         debug!("[polaris][router_api] load_balance request {:?}", req);
 
-        let criteria = req.criteria.clone();
-        let mut lb_policy = criteria.policy.clone();
+        let lb = self.get_loadbalancer(&req.criteria).await?;
+        let result = self
+            .flow
+            .choose_instance(&lb, req.criteria, req.service_instances);
 
-        if lb_policy.is_empty() {
-            lb_policy.clone_from(&self.context.conf.consumer.load_balancer.default_policy);
+        match result {
+            Ok(instance) => Ok(super::req::ProcessLoadBalanceResponse { instance }),
+            Err(e) => Err(e),
         }
+    }
 
-        let lb = self.flow.lookup_loadbalancer(&lb_policy).await;
-
-        if lb.is_none() {
-            crate::error!(
-                "[polaris][router_api] load balancer {} not found",
-                lb_policy
-            );
-            return Err(PolarisError::new(
-                ErrorCode::PluginError,
-                format!("load balancer {} not found", lb_policy),
-            ));
-        }
+    async fn load_balance_candidates(
+        &self,
+        req: super::req::ProcessLoadBalanceCandidatesRequest,
+    ) -> Result<super::req::ProcessLoadBalanceCandidatesResponse, PolarisError> {
+        debug!(
+            "[polaris][router_api] load_balance_candidates request {:?}",
+            req
+        );
+
+        let lb = self.get_loadbalancer(&req.criteria).await?;
+        let instances =
+            self.flow
+                .choose_candidates(&lb, req.criteria, req.service_instances, req.count)?;
+        Ok(super::req::ProcessLoadBalanceCandidatesResponse { instances })
+    }
 
-        let lb = lb.unwrap();
-        let result = lb.choose_instance(req.criteria, req.service_instances);
+    async fn get_instance_weights(&self, instances: ServiceInstances) -> Vec<InstanceWeight> {
+        self.flow.get_instance_weights(&instances)
+    }
 
-        match result {
-            Ok(instance) => Ok(super::req::ProcessLoadBalanceResponse { instance }),
-            Err(e) => Err(e),
+    async fn shutdown(&self, timeout: Duration) -> Result<(), PolarisError> {
+        if !self.manage_sdk {
+            return Ok(());
         }
+        self.context.shutdown(timeout).await
     }
 }
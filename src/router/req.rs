@@ -15,7 +15,7 @@
 
 use crate::core::model::loadbalance::Criteria;
 use crate::core::model::naming::{Instance, ServiceInstances};
-use crate::core::model::router::RouteInfo;
+use crate::core::model::router::{RouteInfo, RouterChain, RouterTraceItem};
 
 // 负载均衡相关请求
 #[derive(Debug)]
@@ -28,13 +28,33 @@ pub struct ProcessLoadBalanceResponse {
     pub instance: Instance,
 }
 
+// 负载均衡选择多个候选实例请求，用于调用失败后的重试
+#[derive(Debug)]
+pub struct ProcessLoadBalanceCandidatesRequest {
+    pub service_instances: ServiceInstances,
+    pub criteria: Criteria,
+    // count 最多返回的候选实例个数
+    pub count: usize,
+}
+
+pub struct ProcessLoadBalanceCandidatesResponse {
+    // instances 按照优先级排序的不同实例，第一个为首选实例
+    pub instances: Vec<Instance>,
+}
+
 // 路由相关请求
 #[derive(Debug)]
 pub struct ProcessRouteRequest {
     pub service_instances: ServiceInstances,
     pub route_info: RouteInfo,
+    // chain_override 本次请求使用的路由链，为 None 时按照配置选择路由链
+    pub chain_override: Option<RouterChain>,
+    // trace 是否记录路由链中每个路由插件的执行情况
+    pub trace: bool,
 }
 
 pub struct ProcessRouteResponse {
     pub service_instances: ServiceInstances,
+    // traces 路由链执行记录，仅在请求开启 trace 时返回
+    pub traces: Vec<RouterTraceItem>,
 }